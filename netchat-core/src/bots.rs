@@ -0,0 +1,292 @@
+//! Bot commands: a chat message starting with `!name` is a command for the
+//! bot that claimed `name`, the rest of the line its arguments. Several bots
+//! share a [`Node`](crate::node::Node) through a [`Dispatcher`], which hands
+//! each command to its bot and answers `!help` for all of them.
+//!
+//! ```
+//! use netchat_core::bots::{Bot, Command, Dispatcher};
+//! use netchat_core::messages::{Channel, Header};
+//! use netchat_core::{Clock, Msg};
+//!
+//! struct Dice;
+//!
+//! impl Bot for Dice {
+//!     fn commands(&self) -> Vec<Command> {
+//!         vec![Command::new("roll", "<sides>", "roll a die")]
+//!     }
+//!
+//!     fn handle(&mut self, _command: &str, args: &str, _msg: &Msg) -> Option<String> {
+//!         Some(format!("rolled a {}-sided die: 4", args))
+//!     }
+//! }
+//!
+//! let mut bots = Dispatcher::default();
+//! bots.add(Box::new(Dice)).unwrap();
+//! assert!(bots.add(Box::new(Dice)).is_err(), "!roll is taken");
+//!
+//! let roll = Header::Public(Channel::default(), "!roll 6".to_owned());
+//! let msg = Msg::new(1, "alice".to_owned(), roll, Clock::new("alice".to_owned()));
+//! let reply = Header::Public(Channel::default(), "rolled a 6-sided die: 4".to_owned());
+//! assert_eq!(bots.dispatch(&msg), Some(reply));
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use crate::messages::Header;
+use crate::Msg;
+
+/// A command a bot claims
+#[derive(Clone, Debug, PartialEq)]
+pub struct Command {
+    /// Without its `!`
+    pub name: String,
+    /// What it takes, shown after the name by `!help`
+    pub args: String,
+    /// What it does
+    pub help: String,
+}
+
+impl Command {
+    /// `!name args`, which does `help`
+    pub fn new(name: &str, args: &str, help: &str) -> Command {
+        Command {
+            name: name.to_owned(),
+            args: args.to_owned(),
+            help: help.to_owned(),
+        }
+    }
+}
+
+/// Answers the commands it claims
+pub trait Bot {
+    /// The commands it claims, asked once when it is added
+    fn commands(&self) -> Vec<Command>;
+
+    /// The answer to `command`, one of those claimed, sent with `args` in
+    /// `msg`. None to stay silent.
+    fn handle(&mut self, command: &str, args: &str, msg: &Msg) -> Option<String>;
+
+    /// Content type of the answers to `command`, one of
+    /// [`CONTENT_TYPES`](crate::messages::CONTENT_TYPES) for the app to render
+    /// them. Plain text by default.
+    fn content_type(&self, _command: &str) -> Option<String> {
+        None
+    }
+}
+
+/// The command of `text`, if it is one: its name without the `!`, and its
+/// arguments
+pub fn parse(text: &str) -> Option<(&str, &str)> {
+    let line = text.strip_prefix('!')?;
+    let (name, args) = match line.find(char::is_whitespace) {
+        Some(end) => (&line[..end], line[end..].trim()),
+        None => (line, ""),
+    };
+    Some((name, args)).filter(|(name, _)| !name.is_empty())
+}
+
+/// A bot claimed a command another one had, or `help`
+#[derive(Debug, PartialEq)]
+pub struct Clash(pub String);
+
+impl fmt::Display for Clash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "!{} is already claimed", self.0)
+    }
+}
+
+impl Error for Clash {}
+
+/// Hands each command to the bot that claimed it
+#[derive(Default)]
+pub struct Dispatcher {
+    bots: Vec<Box<dyn Bot>>,
+    commands: Vec<Command>,
+    claimed: HashMap<String, usize>, // Bot of each command
+}
+
+impl Dispatcher {
+    /// Adds `bot`, refused whole if it claims a command already claimed
+    pub fn add(&mut self, bot: Box<dyn Bot>) -> Result<(), Clash> {
+        let commands = bot.commands();
+        for (i, command) in commands.iter().enumerate() {
+            let again = commands[..i].iter().any(|c| c.name == command.name);
+            if command.name == "help" || again || self.claimed.contains_key(&command.name) {
+                return Err(Clash(command.name.clone()));
+            }
+        }
+        for command in &commands {
+            self.claimed.insert(command.name.clone(), self.bots.len());
+        }
+        self.commands.extend(commands);
+        self.bots.push(bot);
+        Ok(())
+    }
+
+    /// What `!help` answers: every command in the order the bots were added,
+    /// or only the one asked for
+    pub fn help(&self, name: Option<&str>) -> Option<String> {
+        let usage = |c: &Command| match c.args.as_str() {
+            "" => format!("!{}: {}", c.name, c.help),
+            args => format!("!{} {}: {}", c.name, args, c.help),
+        };
+        match name {
+            Some(name) => {
+                let name = name.trim_start_matches('!');
+                self.commands.iter().find(|c| c.name == name).map(usage)
+            }
+            None if self.commands.is_empty() => None,
+            None => Some(
+                self.commands
+                    .iter()
+                    .map(usage)
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            ),
+        }
+    }
+
+    /// The reply to the command `msg` carries, in its channel or privately to
+    /// its sender. None when it is not a command claimed here, other nodes
+    /// may have claimed it.
+    pub fn dispatch(&mut self, msg: &Msg) -> Option<Header> {
+        self.answer(msg).map(|(reply, _)| reply)
+    }
+
+    /// The reply of [`dispatch`](Dispatcher::dispatch), with the content type
+    /// the bot gave it, to send with [`Node::send_as`](crate::node::Node::send_as)
+    pub fn answer(&mut self, msg: &Msg) -> Option<(Header, Option<String>)> {
+        let text = match &msg.header {
+            Header::Public(_, text) | Header::Private(_, text) => text,
+            _ => return None,
+        };
+        let (answer, content_type) = match parse(text)? {
+            ("help", "") => (self.help(None)?, None),
+            ("help", name) => (self.help(Some(name))?, None),
+            (name, args) => {
+                let bot = &mut self.bots[*self.claimed.get(name)?];
+                (bot.handle(name, args, msg)?, bot.content_type(name))
+            }
+        };
+        let reply = match &msg.header {
+            Header::Public(channel, _) => Header::Public(channel.clone(), answer),
+            _ => Header::Private(msg.sender_id.clone(), answer),
+        };
+        Some((reply, content_type))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Channel;
+    use crate::Clock;
+
+    struct Echo(Vec<Command>);
+
+    impl Bot for Echo {
+        fn commands(&self) -> Vec<Command> {
+            self.0.clone()
+        }
+
+        fn handle(&mut self, command: &str, args: &str, _msg: &Msg) -> Option<String> {
+            Some(format!("{} {}", command, args)).filter(|_| args != "quiet")
+        }
+    }
+
+    fn said(header: Header) -> Msg {
+        Msg::new(
+            1,
+            "alice".to_owned(),
+            header,
+            Clock::new("alice".to_owned()),
+        )
+    }
+
+    #[test]
+    fn commands_go_to_the_bot_that_claimed_them() {
+        assert_eq!(parse("!roll  2d6 "), Some(("roll", "2d6")));
+        assert_eq!(parse("!"), None);
+        assert_eq!(parse("hi !roll"), None);
+
+        let mut bots = Dispatcher::default();
+        let echo = |names: &[&str]| {
+            let commands = names
+                .iter()
+                .map(|n| Command::new(n, "", "echoes"))
+                .collect();
+            Box::new(Echo(commands))
+        };
+        assert!(bots.add(echo(&["roll", "flip"])).is_ok());
+        assert_eq!(
+            bots.add(echo(&["weather", "flip"])),
+            Err(Clash("flip".to_owned()))
+        );
+        assert_eq!(bots.add(echo(&["help"])), Err(Clash("help".to_owned())));
+        assert_eq!(bots.add(echo(&["a", "a"])), Err(Clash("a".to_owned())));
+        assert!(
+            bots.add(echo(&["weather"])).is_ok(),
+            "the clashing bot added nothing"
+        );
+
+        let ops = Channel::parse("#ops").unwrap();
+        let public = said(Header::Public(ops.clone(), "!flip a coin".to_owned()));
+        assert_eq!(
+            bots.dispatch(&public),
+            Some(Header::Public(ops.clone(), "flip a coin".to_owned()))
+        );
+        let private = said(Header::Private("bot".to_owned(), "!weather".to_owned()));
+        assert_eq!(
+            bots.dispatch(&private),
+            Some(Header::Private("alice".to_owned(), "weather ".to_owned()))
+        );
+        let unclaimed = said(Header::Public(ops.clone(), "!deploy".to_owned()));
+        assert_eq!(bots.dispatch(&unclaimed), None);
+        let quiet = said(Header::Public(ops.clone(), "!roll quiet".to_owned()));
+        assert_eq!(bots.dispatch(&quiet), None);
+
+        let help = said(Header::Public(ops.clone(), "!help".to_owned()));
+        let all = "!roll: echoes\n!flip: echoes\n!weather: echoes".to_owned();
+        assert_eq!(bots.dispatch(&help), Some(Header::Public(ops.clone(), all)));
+        assert_eq!(bots.help(Some("!flip")), Some("!flip: echoes".to_owned()));
+        assert_eq!(bots.help(Some("deploy")), None);
+    }
+
+    struct Status;
+
+    impl Bot for Status {
+        fn commands(&self) -> Vec<Command> {
+            vec![Command::new("status", "", "the state of the build")]
+        }
+
+        fn handle(&mut self, _command: &str, _args: &str, _msg: &Msg) -> Option<String> {
+            Some(r#"{"build":"green"}"#.to_owned())
+        }
+
+        fn content_type(&self, _command: &str) -> Option<String> {
+            Some("application/json".to_owned())
+        }
+    }
+
+    #[test]
+    fn answers_carry_the_content_type_of_their_bot() {
+        let mut bots = Dispatcher::default();
+        bots.add(Box::new(Status)).unwrap();
+        let general = Channel::default();
+        let status = said(Header::Public(general.clone(), "!status".to_owned()));
+        let (reply, content_type) = bots.answer(&status).unwrap();
+        assert_eq!(content_type.as_deref(), Some("application/json"));
+        let help = said(Header::Public(general, "!help".to_owned()));
+        assert_eq!(bots.answer(&help).unwrap().1, None, "help is plain text");
+
+        let mut node = crate::node::Node::new("bot".to_owned());
+        while node.poll_frame().is_some() {}
+        let sent = node.send_as(reply, "application/json");
+        let frame = node.poll_frame().unwrap();
+        let received = crate::messages::parse(&frame).unwrap();
+        assert_eq!(received.content_type, sent.content_type);
+        assert_eq!(received.content_type.as_deref(), Some("application/json"));
+    }
+}
@@ -0,0 +1,303 @@
+//! The subset of CBOR (RFC 8949) messages need: integers, floats, booleans,
+//! null, text, arrays and maps with text keys. Messages go through a
+//! [`serde_json::Value`], so they encode to the same thing as in json.
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+
+use serde_json::{Map, Number, Value};
+
+use crate::messages::MAX_DEPTH;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const SIMPLE: u8 = 7;
+
+const FALSE: u8 = 20;
+const TRUE: u8 = 21;
+const NULL: u8 = 22;
+const F32: u8 = 26;
+const F64: u8 = 27;
+
+/// Items reserved at most before they are read: a length is only bounded by
+/// the bytes left, which each nested array would reserve again
+const MAX_RESERVED: usize = 1024;
+
+/// Why some bytes are not a value this module reads
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// Ends in the middle of a value
+    Truncated,
+    /// Nested deeper than [`MAX_DEPTH`]
+    TooDeep,
+    /// Initial byte of an item outside the subset, such as byte strings,
+    /// tags or indefinite lengths
+    Unsupported(u8),
+    /// Text which is not UTF-8
+    InvalidUtf8,
+    /// Float json has no number for, infinite or NaN
+    NotANumber,
+    /// Bytes left after the value
+    Trailing(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "truncated cbor"),
+            DecodeError::TooDeep => write!(f, "cbor nested too deeply"),
+            DecodeError::Unsupported(byte) => write!(f, "unsupported cbor item {:#04x}", byte),
+            DecodeError::InvalidUtf8 => write!(f, "cbor text is not UTF-8"),
+            DecodeError::NotANumber => write!(f, "cbor float is not a json number"),
+            DecodeError::Trailing(len) => write!(f, "{} bytes after the cbor value", len),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Appends `value` to `out`
+pub fn encode(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(SIMPLE << 5 | NULL),
+        Value::Bool(false) => out.push(SIMPLE << 5 | FALSE),
+        Value::Bool(true) => out.push(SIMPLE << 5 | TRUE),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => head(UNSIGNED, n, out),
+            (None, Some(n)) => head(NEGATIVE, !n as u64, out),
+            (None, None) => {
+                out.push(SIMPLE << 5 | F64);
+                out.extend_from_slice(&n.as_f64().unwrap_or_default().to_be_bytes());
+            }
+        },
+        Value::String(text) => {
+            head(TEXT, text.len() as u64, out);
+            out.extend_from_slice(text.as_bytes());
+        }
+        Value::Array(items) => {
+            head(ARRAY, items.len() as u64, out);
+            for item in items {
+                encode(item, out);
+            }
+        }
+        Value::Object(map) => {
+            head(MAP, map.len() as u64, out);
+            for (key, item) in map {
+                head(TEXT, key.len() as u64, out);
+                out.extend_from_slice(key.as_bytes());
+                encode(item, out);
+            }
+        }
+    }
+}
+
+/// Major type and argument, in as few bytes as possible
+fn head(major: u8, n: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match n {
+        0..=23 => out.push(major | n as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, n as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(n as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend_from_slice(&(n as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend_from_slice(&n.to_be_bytes());
+        }
+    }
+}
+
+/// Decodes the single value `input` holds
+///
+/// Never panics, and never allocates much more than the input size: lengths
+/// are only trusted as far as there are bytes left to back them, and arrays
+/// grow as their items are read past the first thousand.
+pub fn decode(input: &[u8]) -> Result<Value, DecodeError> {
+    let mut decoder = Decoder { input, pos: 0 };
+    let value = decoder.value(0)?;
+    match input.len() - decoder.pos {
+        0 => Ok(value),
+        left => Err(DecodeError::Trailing(left)),
+    }
+}
+
+struct Decoder<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        let bytes = self
+            .input
+            .get(self.pos..)
+            .and_then(|rest| rest.get(..len))
+            .ok_or(DecodeError::Truncated)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn left(&self) -> usize {
+        self.input.len() - self.pos
+    }
+
+    /// Initial byte, and the argument following it
+    fn head(&mut self) -> Result<(u8, u8, u64), DecodeError> {
+        let initial = self.take(1)?[0];
+        let mut be = |len| -> Result<u64, DecodeError> {
+            Ok(self
+                .take(len)?
+                .iter()
+                .fold(0, |n, &b| n << 8 | u64::from(b)))
+        };
+        let n = match initial & 0x1f {
+            n @ 0..=23 => u64::from(n),
+            24 => be(1)?,
+            25 => be(2)?,
+            26 => be(4)?,
+            27 => be(8)?,
+            _ => return Err(DecodeError::Unsupported(initial)),
+        };
+        Ok((initial, initial >> 5, n))
+    }
+
+    /// A length, at most what is left to read
+    fn len(&self, n: u64) -> Result<usize, DecodeError> {
+        usize::try_from(n)
+            .ok()
+            .filter(|&n| n <= self.left())
+            .ok_or(DecodeError::Truncated)
+    }
+
+    fn text(&mut self, n: u64) -> Result<String, DecodeError> {
+        let len = self.len(n)?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| DecodeError::InvalidUtf8)
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, DecodeError> {
+        let (initial, major, n) = self.head()?;
+        let nested = || match depth {
+            MAX_DEPTH => Err(DecodeError::TooDeep),
+            _ => Ok(depth + 1),
+        };
+        Ok(match major {
+            UNSIGNED => Value::from(n),
+            NEGATIVE => match i64::try_from(n) {
+                Ok(n) => Value::from(-1 - n),
+                Err(_) => float(-1.0 - n as f64)?,
+            },
+            TEXT => Value::String(self.text(n)?),
+            ARRAY => {
+                let depth = nested()?;
+                let len = self.len(n)?;
+                let mut items = Vec::with_capacity(len.min(MAX_RESERVED));
+                for _ in 0..len {
+                    items.push(self.value(depth)?);
+                }
+                Value::Array(items)
+            }
+            MAP => {
+                let depth = nested()?;
+                let len = self.len(n)?;
+                let mut map = Map::new();
+                for _ in 0..len {
+                    let key = match self.head()? {
+                        (_, TEXT, n) => self.text(n)?,
+                        (initial, _, _) => return Err(DecodeError::Unsupported(initial)),
+                    };
+                    map.insert(key, self.value(depth)?);
+                }
+                Value::Object(map)
+            }
+            SIMPLE => match initial & 0x1f {
+                FALSE => Value::Bool(false),
+                TRUE => Value::Bool(true),
+                NULL => Value::Null,
+                F32 => float(f64::from(f32::from_bits(n as u32)))?,
+                F64 => float(f64::from_bits(n))?,
+                _ => return Err(DecodeError::Unsupported(initial)),
+            },
+            _ => return Err(DecodeError::Unsupported(initial)),
+        })
+    }
+}
+
+fn float(f: f64) -> Result<Value, DecodeError> {
+    Number::from_f64(f)
+        .map(Value::Number)
+        .ok_or(DecodeError::NotANumber)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn values_round_trip() {
+        let value = json!({
+            "id": 18_446_744_073_709_551_615u64,
+            "neg": -300,
+            "min": i64::MIN,
+            "f": 1.5,
+            "text": "héllo",
+            "list": [null, true, false, [], {}],
+            "empty": "",
+        });
+        let mut out = Vec::new();
+        encode(&value, &mut out);
+        assert_eq!(decode(&out).unwrap(), value);
+
+        // RFC 8949 appendix A
+        let mut out = Vec::new();
+        encode(&json!([23, 24, 1000, -1, -1000, "a"]), &mut out);
+        assert_eq!(
+            out,
+            [0x86, 0x17, 0x18, 0x18, 0x19, 0x03, 0xe8, 0x20, 0x39, 0x03, 0xe7, 0x61, 0x61]
+        );
+    }
+
+    #[test]
+    fn bad_input_is_refused() {
+        assert_eq!(decode(&[]), Err(DecodeError::Truncated));
+        // An array claiming more items than there are bytes
+        assert_eq!(
+            decode(&[0x9b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(decode(&[0x41, 0x00]), Err(DecodeError::Unsupported(0x41)));
+        assert_eq!(decode(&[0x61, 0xff]), Err(DecodeError::InvalidUtf8));
+        assert_eq!(
+            decode(&[0xa1, 0x01, 0x01]),
+            Err(DecodeError::Unsupported(0x01))
+        );
+        assert_eq!(decode(&[0x01, 0x01]), Err(DecodeError::Trailing(1)));
+        assert_eq!(decode(&[0x9f]), Err(DecodeError::Unsupported(0x9f)));
+        assert_eq!(decode(&[0x81; MAX_DEPTH + 1]), Err(DecodeError::TooDeep));
+    }
+
+    #[test]
+    fn nested_huge_arrays_are_refused() {
+        // Each array claims as many items as there are bytes after its head,
+        // the innermost one all the zeros, so its parent lacks a second item
+        let len = 1024 * 1024;
+        let mut input = Vec::with_capacity(len);
+        for level in 0..MAX_DEPTH - 1 {
+            let claimed = (len - 5 * (level + 1)) as u32;
+            input.push(0x9a);
+            input.extend_from_slice(&claimed.to_be_bytes());
+        }
+        input.resize(len, 0x00);
+        assert_eq!(decode(&input), Err(DecodeError::Truncated));
+    }
+}
@@ -0,0 +1,92 @@
+//! Vector clocks, which order the messages of several apps
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use shrinkwraprs::Shrinkwrap;
+
+use crate::messages::Date;
+use crate::AppId;
+
+/// Vector clock: the last date known of each app
+#[derive(Shrinkwrap, Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[shrinkwrap(mutable)]
+pub struct Clock(pub HashMap<AppId, Date>);
+
+// Vector Clock implementation
+impl Clock {
+    /// Clock of a new app, which knows only itself
+    pub fn new(app_id: AppId) -> Self {
+        let mut map = HashMap::new();
+        map.insert(app_id.to_owned(), 0);
+        Clock(map)
+    }
+
+    /// Keeps the latest date of each app from both clocks
+    pub fn merge(&mut self, clock: &Self) {
+        self.merge_without(clock, &HashSet::new());
+    }
+
+    /// Same as [`merge`](Self::merge), but for the apps in `skipped`, which
+    /// are not taken from `clock`
+    pub fn merge_without(&mut self, clock: &Self, skipped: &HashSet<AppId>) {
+        for (id, date) in clock.0.iter().filter(|(id, _)| !skipped.contains(*id)) {
+            match self.get(id) {
+                // Clock is updated only if it contains an older date
+                Some(local_date) if local_date >= date => {}
+                _ => {
+                    self.insert(id.to_owned(), date.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Causal order of two clocks, None when they are concurrent.
+    /// Apps missing from a clock are at date 0.
+    pub fn compare(&self, other: &Self) -> Option<Ordering> {
+        let date = |clock: &Self, id| clock.get(id).copied().unwrap_or(0);
+        let (mut before, mut after) = (false, false);
+        for id in self.keys().chain(other.keys()) {
+            match date(self, id).cmp(&date(other, id)) {
+                Ordering::Less => before = true,
+                Ordering::Greater => after = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (before, after) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(dates: &[(&str, Date)]) -> Clock {
+        Clock(dates.iter().map(|(id, d)| ((*id).to_owned(), *d)).collect())
+    }
+
+    #[test]
+    fn clocks_are_partially_ordered() {
+        let a = clock(&[("alice", 2), ("bob", 1)]);
+        let mut b = clock(&[("alice", 2), ("bob", 0), ("carol", 0)]);
+        assert_eq!(a.compare(&b), Some(Ordering::Greater));
+        assert_eq!(b.compare(&a), Some(Ordering::Less));
+
+        b.insert("carol".to_owned(), 1);
+        assert_eq!(a.compare(&b), None);
+        b.merge(&a);
+        assert_eq!(b, clock(&[("alice", 2), ("bob", 1), ("carol", 1)]));
+        assert_eq!(b.compare(&b.clone()), Some(Ordering::Equal));
+
+        let skipped = std::iter::once("dave".to_owned()).collect();
+        b.merge_without(&clock(&[("carol", 2), ("dave", 4)]), &skipped);
+        assert_eq!(b, clock(&[("alice", 2), ("bob", 1), ("carol", 2)]));
+    }
+}
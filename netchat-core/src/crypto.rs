@@ -0,0 +1,1110 @@
+//! Minimal cryptographic primitives (SHA-512, Ed25519 and X25519), ported from
+//! TweetNaCl, and XChaCha20-Poly1305 for sealing frames with a pre-shared key
+//! and private messages with a X25519 shared secret
+//!
+//! The field arithmetic works on 16 limbs of 16 bits stored in `i64`s, which
+//! is slow but small and easy to audit against the reference implementation.
+
+use std::fmt;
+
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use rand::{thread_rng, Rng};
+
+// SHA-512
+//--------
+
+const K: [u64; 80] = [
+    0x428a2f98d728ae22,
+    0x7137449123ef65cd,
+    0xb5c0fbcfec4d3b2f,
+    0xe9b5dba58189dbbc,
+    0x3956c25bf348b538,
+    0x59f111f1b605d019,
+    0x923f82a4af194f9b,
+    0xab1c5ed5da6d8118,
+    0xd807aa98a3030242,
+    0x12835b0145706fbe,
+    0x243185be4ee4b28c,
+    0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f,
+    0x80deb1fe3b1696b1,
+    0x9bdc06a725c71235,
+    0xc19bf174cf692694,
+    0xe49b69c19ef14ad2,
+    0xefbe4786384f25e3,
+    0x0fc19dc68b8cd5b5,
+    0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275,
+    0x4a7484aa6ea6e483,
+    0x5cb0a9dcbd41fbd4,
+    0x76f988da831153b5,
+    0x983e5152ee66dfab,
+    0xa831c66d2db43210,
+    0xb00327c898fb213f,
+    0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2,
+    0xd5a79147930aa725,
+    0x06ca6351e003826f,
+    0x142929670a0e6e70,
+    0x27b70a8546d22ffc,
+    0x2e1b21385c26c926,
+    0x4d2c6dfc5ac42aed,
+    0x53380d139d95b3df,
+    0x650a73548baf63de,
+    0x766a0abb3c77b2a8,
+    0x81c2c92e47edaee6,
+    0x92722c851482353b,
+    0xa2bfe8a14cf10364,
+    0xa81a664bbc423001,
+    0xc24b8b70d0f89791,
+    0xc76c51a30654be30,
+    0xd192e819d6ef5218,
+    0xd69906245565a910,
+    0xf40e35855771202a,
+    0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8,
+    0x1e376c085141ab53,
+    0x2748774cdf8eeb99,
+    0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63,
+    0x4ed8aa4ae3418acb,
+    0x5b9cca4f7763e373,
+    0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc,
+    0x78a5636f43172f60,
+    0x84c87814a1f0ab72,
+    0x8cc702081a6439ec,
+    0x90befffa23631e28,
+    0xa4506cebde82bde9,
+    0xbef9a3f7b2c67915,
+    0xc67178f2e372532b,
+    0xca273eceea26619c,
+    0xd186b8c721c0c207,
+    0xeada7dd6cde0eb1e,
+    0xf57d4f7fee6ed178,
+    0x06f067aa72176fba,
+    0x0a637dc5a2c898a6,
+    0x113f9804bef90dae,
+    0x1b710b35131c471b,
+    0x28db77f523047d84,
+    0x32caab7b40c72493,
+    0x3c9ebe0a15c9bebc,
+    0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6,
+    0x597f299cfc657e2a,
+    0x5fcb6fab3ad6faec,
+    0x6c44198c4a475817,
+];
+
+const IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+fn sha512_block(state: &mut [u64; 8], block: &[u8]) {
+    let mut w = [0u64; 80];
+    for (i, chunk) in block.chunks(8).enumerate() {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(chunk);
+        w[i] = u64::from_be_bytes(word);
+    }
+    for i in 16..80 {
+        let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+        let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let mut v = *state;
+    for i in 0..80 {
+        let s1 = v[4].rotate_right(14) ^ v[4].rotate_right(18) ^ v[4].rotate_right(41);
+        let ch = (v[4] & v[5]) ^ (!v[4] & v[6]);
+        let t1 = v[7]
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = v[0].rotate_right(28) ^ v[0].rotate_right(34) ^ v[0].rotate_right(39);
+        let maj = (v[0] & v[1]) ^ (v[0] & v[2]) ^ (v[1] & v[2]);
+        let t2 = s0.wrapping_add(maj);
+        v[7] = v[6];
+        v[6] = v[5];
+        v[5] = v[4];
+        v[4] = v[3].wrapping_add(t1);
+        v[3] = v[2];
+        v[2] = v[1];
+        v[1] = v[0];
+        v[0] = t1.wrapping_add(t2);
+    }
+    for (s, x) in state.iter_mut().zip(v.iter()) {
+        *s = s.wrapping_add(*x);
+    }
+}
+
+/// SHA-512 digest of `data`
+pub fn sha512(data: &[u8]) -> [u8; 64] {
+    let mut state = IV;
+    let mut chunks = data.chunks_exact(128);
+    for block in &mut chunks {
+        sha512_block(&mut state, block);
+    }
+
+    // Padding: 0x80, zeroes, then the message length in bits on 128 bits
+    let rest = chunks.remainder();
+    let mut last = [0u8; 256];
+    last[..rest.len()].copy_from_slice(rest);
+    last[rest.len()] = 0x80;
+    let blocks = if rest.len() < 112 { 1 } else { 2 };
+    let bit_len = (data.len() as u128) << 3;
+    last[blocks * 128 - 16..blocks * 128].copy_from_slice(&bit_len.to_be_bytes());
+    for block in last[..blocks * 128].chunks(128) {
+        sha512_block(&mut state, block);
+    }
+
+    let mut out = [0u8; 64];
+    for (chunk, s) in out.chunks_mut(8).zip(state.iter()) {
+        chunk.copy_from_slice(&s.to_be_bytes());
+    }
+    out
+}
+
+// GF(2^255 - 19) arithmetic
+//--------------------------
+
+type Gf = [i64; 16];
+
+const GF0: Gf = [0; 16];
+const GF1: Gf = [1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+const D: Gf = [
+    0x78a3, 0x1359, 0x4dca, 0x75eb, 0xd8ab, 0x4141, 0x0a4d, 0x0070, 0xe898, 0x7779, 0x4079, 0x8cc7,
+    0xfe73, 0x2b6f, 0x6cee, 0x5203,
+];
+const D2: Gf = [
+    0xf159, 0x26b2, 0x9b94, 0xebd6, 0xb156, 0x8283, 0x149a, 0x00e0, 0xd130, 0xeef3, 0x80f2, 0x198e,
+    0xfce7, 0x56df, 0xd9dc, 0x2406,
+];
+const X: Gf = [
+    0xd51a, 0x8f25, 0x2d60, 0xc956, 0xa7b2, 0x9525, 0xc760, 0x692c, 0xdc5c, 0xfdd6, 0xe231, 0xc0a4,
+    0x53fe, 0xcd6e, 0x36d3, 0x2169,
+];
+const Y: Gf = [
+    0x6658, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666, 0x6666,
+    0x6666, 0x6666, 0x6666, 0x6666,
+];
+const I: Gf = [
+    0xa0b0, 0x4a0e, 0x1b27, 0xc4ee, 0xe478, 0xad2f, 0x1806, 0x2f43, 0xd7a7, 0x3dfb, 0x0099, 0x2b4d,
+    0xdf0b, 0x4fc1, 0x2480, 0x2b83,
+];
+
+fn car25519(o: &mut Gf) {
+    for i in 0..16 {
+        o[i] += 1 << 16;
+        let c = o[i] >> 16;
+        if i < 15 {
+            o[i + 1] += c - 1;
+        } else {
+            o[0] += 38 * (c - 1);
+        }
+        o[i] -= c << 16;
+    }
+}
+
+/// Constant time swap of `p` and `q` if `b` is 1
+fn sel25519(p: &mut Gf, q: &mut Gf, b: i64) {
+    let c = !(b - 1);
+    for i in 0..16 {
+        let t = c & (p[i] ^ q[i]);
+        p[i] ^= t;
+        q[i] ^= t;
+    }
+}
+
+fn pack25519(n: &Gf) -> [u8; 32] {
+    let mut t = *n;
+    car25519(&mut t);
+    car25519(&mut t);
+    car25519(&mut t);
+    for _ in 0..2 {
+        let mut m = GF0;
+        m[0] = t[0] - 0xffed;
+        for i in 1..15 {
+            m[i] = t[i] - 0xffff - ((m[i - 1] >> 16) & 1);
+            m[i - 1] &= 0xffff;
+        }
+        m[15] = t[15] - 0x7fff - ((m[14] >> 16) & 1);
+        let b = (m[15] >> 16) & 1;
+        m[14] &= 0xffff;
+        sel25519(&mut t, &mut m, 1 - b);
+    }
+    let mut o = [0u8; 32];
+    for i in 0..16 {
+        o[2 * i] = (t[i] & 0xff) as u8;
+        o[2 * i + 1] = (t[i] >> 8) as u8;
+    }
+    o
+}
+
+fn neq25519(a: &Gf, b: &Gf) -> bool {
+    !verify_32(&pack25519(a), &pack25519(b))
+}
+
+fn par25519(a: &Gf) -> u8 {
+    pack25519(a)[0] & 1
+}
+
+fn unpack25519(n: &[u8; 32]) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = i64::from(n[2 * i]) + (i64::from(n[2 * i + 1]) << 8);
+    }
+    o[15] &= 0x7fff;
+    o
+}
+
+fn add_gf(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] + b[i];
+    }
+    o
+}
+
+fn sub_gf(a: &Gf, b: &Gf) -> Gf {
+    let mut o = GF0;
+    for i in 0..16 {
+        o[i] = a[i] - b[i];
+    }
+    o
+}
+
+fn mul_gf(a: &Gf, b: &Gf) -> Gf {
+    let mut t = [0i64; 31];
+    for i in 0..16 {
+        for j in 0..16 {
+            t[i + j] += a[i] * b[j];
+        }
+    }
+    for i in 0..15 {
+        t[i] += 38 * t[i + 16];
+    }
+    let mut o = GF0;
+    o.copy_from_slice(&t[..16]);
+    car25519(&mut o);
+    car25519(&mut o);
+    o
+}
+
+fn square_gf(a: &Gf) -> Gf {
+    mul_gf(a, a)
+}
+
+fn inv25519(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..=253).rev() {
+        c = square_gf(&c);
+        if a != 2 && a != 4 {
+            c = mul_gf(&c, i);
+        }
+    }
+    c
+}
+
+fn pow2523(i: &Gf) -> Gf {
+    let mut c = *i;
+    for a in (0..=250).rev() {
+        c = square_gf(&c);
+        if a != 1 {
+            c = mul_gf(&c, i);
+        }
+    }
+    c
+}
+
+fn verify_32(x: &[u8], y: &[u8]) -> bool {
+    x.len() == y.len() && x.iter().zip(y).fold(0, |d, (a, b)| d | (a ^ b)) == 0
+}
+
+// Ed25519
+//--------
+
+/// Extended coordinates (X, Y, Z, T) of a point on the curve
+type Point = [Gf; 4];
+
+fn point_add(p: &mut Point, q: &Point) {
+    let a = mul_gf(&sub_gf(&p[1], &p[0]), &sub_gf(&q[1], &q[0]));
+    let b = mul_gf(&add_gf(&p[0], &p[1]), &add_gf(&q[0], &q[1]));
+    let c = mul_gf(&mul_gf(&p[3], &q[3]), &D2);
+    let d = mul_gf(&p[2], &q[2]);
+    let d = add_gf(&d, &d);
+    let e = sub_gf(&b, &a);
+    let f = sub_gf(&d, &c);
+    let g = add_gf(&d, &c);
+    let h = add_gf(&b, &a);
+
+    p[0] = mul_gf(&e, &f);
+    p[1] = mul_gf(&h, &g);
+    p[2] = mul_gf(&g, &f);
+    p[3] = mul_gf(&e, &h);
+}
+
+fn cswap(p: &mut Point, q: &mut Point, b: u8) {
+    for i in 0..4 {
+        sel25519(&mut p[i], &mut q[i], i64::from(b));
+    }
+}
+
+fn pack_point(p: &Point) -> [u8; 32] {
+    let zi = inv25519(&p[2]);
+    let tx = mul_gf(&p[0], &zi);
+    let ty = mul_gf(&p[1], &zi);
+    let mut r = pack25519(&ty);
+    r[31] ^= par25519(&tx) << 7;
+    r
+}
+
+fn scalarmult(q: &mut Point, s: &[u8]) -> Point {
+    let mut p = [GF0, GF1, GF1, GF0];
+    for i in (0..256).rev() {
+        let b = (s[i / 8] >> (i & 7)) & 1;
+        cswap(&mut p, q, b);
+        point_add(q, &p);
+        let pp = p;
+        point_add(&mut p, &pp);
+        cswap(&mut p, q, b);
+    }
+    p
+}
+
+fn scalarbase(s: &[u8]) -> Point {
+    let mut q = [X, Y, GF1, mul_gf(&X, &Y)];
+    scalarmult(&mut q, s)
+}
+
+const L: [i64; 32] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+/// Reduces a 512 bits little endian number modulo the group order
+fn mod_l(x: &mut [i64; 64]) -> [u8; 32] {
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        let mut j = i - 32;
+        while j < i - 12 {
+            x[j] += carry - 16 * x[i] * L[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+            j += 1;
+        }
+        x[j] += carry;
+        x[i] = 0;
+    }
+    let mut carry = 0;
+    for j in 0..32 {
+        x[j] += carry - (x[31] >> 4) * L[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..32 {
+        x[j] -= carry * L[j];
+    }
+    let mut r = [0u8; 32];
+    for i in 0..32 {
+        x[i + 1] += x[i] >> 8;
+        r[i] = (x[i] & 255) as u8;
+    }
+    r
+}
+
+fn reduce(h: &[u8; 64]) -> [u8; 32] {
+    let mut x = [0i64; 64];
+    for i in 0..64 {
+        x[i] = i64::from(h[i]);
+    }
+    mod_l(&mut x)
+}
+
+fn sha512_concat(parts: &[&[u8]]) -> [u8; 64] {
+    sha512(&parts.concat())
+}
+
+/// Expands a 32 bytes seed into the clamped secret scalar and the nonce prefix
+fn expand_seed(seed: &[u8; 32]) -> [u8; 64] {
+    let mut d = sha512(seed);
+    d[0] &= 248;
+    d[31] &= 127;
+    d[31] |= 64;
+    d
+}
+
+/// Decodes a public key as the negation of the point it encodes
+fn unpackneg(p: &[u8; 32]) -> Option<Point> {
+    let mut r = [GF0, unpack25519(p), GF1, GF0];
+    let num = square_gf(&r[1]);
+    let den = mul_gf(&num, &D);
+    let num = sub_gf(&num, &r[2]);
+    let den = add_gf(&r[2], &den);
+
+    let den2 = square_gf(&den);
+    let den4 = square_gf(&den2);
+    let den6 = mul_gf(&den4, &den2);
+    let mut t = mul_gf(&mul_gf(&den6, &num), &den);
+
+    t = pow2523(&t);
+    t = mul_gf(&mul_gf(&mul_gf(&t, &num), &den), &den);
+    r[0] = mul_gf(&t, &den);
+
+    let chk = mul_gf(&square_gf(&r[0]), &den);
+    if neq25519(&chk, &num) {
+        r[0] = mul_gf(&r[0], &I);
+    }
+    let chk = mul_gf(&square_gf(&r[0]), &den);
+    if neq25519(&chk, &num) {
+        return None;
+    }
+    if par25519(&r[0]) == (p[31] >> 7) {
+        r[0] = sub_gf(&GF0, &r[0]);
+    }
+    r[3] = mul_gf(&r[0], &r[1]);
+    Some(r)
+}
+
+// X25519
+//-------
+
+const GF121665: Gf = [0xdb41, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+
+/// u coordinate of the base point
+const BASE_U: [u8; 32] = [
+    9, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+/// Montgomery ladder multiplying the point `p` by the clamped scalar `n`
+fn x25519(n: &[u8; 32], p: &[u8; 32]) -> [u8; 32] {
+    let mut z = *n;
+    z[31] = (n[31] & 127) | 64;
+    z[0] &= 248;
+    let x = unpack25519(p);
+    let (mut a, mut b, mut c, mut d) = (GF1, x, GF0, GF1);
+    for i in (0..255).rev() {
+        let r = i64::from((z[i >> 3] >> (i & 7)) & 1);
+        sel25519(&mut a, &mut b, r);
+        sel25519(&mut c, &mut d, r);
+        let e = add_gf(&a, &c);
+        a = sub_gf(&a, &c);
+        c = add_gf(&b, &d);
+        b = sub_gf(&b, &d);
+        d = square_gf(&e);
+        let f = square_gf(&a);
+        a = mul_gf(&c, &a);
+        c = mul_gf(&b, &e);
+        let e = add_gf(&a, &c);
+        a = sub_gf(&a, &c);
+        b = square_gf(&a);
+        c = sub_gf(&d, &f);
+        a = mul_gf(&c, &GF121665);
+        a = add_gf(&a, &d);
+        c = mul_gf(&c, &a);
+        a = mul_gf(&d, &f);
+        d = mul_gf(&b, &x);
+        b = square_gf(&e);
+        sel25519(&mut a, &mut b, r);
+        sel25519(&mut c, &mut d, r);
+    }
+    pack25519(&mul_gf(&a, &inv25519(&c)))
+}
+
+// XChaCha20-Poly1305
+//-------------------
+
+const SIGMA: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+fn le32(b: &[u8]) -> u32 {
+    u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn le64(b: &[u8]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&b[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+/// The 20 rounds, without the final addition of the input
+fn chacha_rounds(state: &[u32; 16]) -> [u32; 16] {
+    let mut s = *state;
+    for _ in 0..10 {
+        quarter_round(&mut s, 0, 4, 8, 12);
+        quarter_round(&mut s, 1, 5, 9, 13);
+        quarter_round(&mut s, 2, 6, 10, 14);
+        quarter_round(&mut s, 3, 7, 11, 15);
+        quarter_round(&mut s, 0, 5, 10, 15);
+        quarter_round(&mut s, 1, 6, 11, 12);
+        quarter_round(&mut s, 2, 7, 8, 13);
+        quarter_round(&mut s, 3, 4, 9, 14);
+    }
+    s
+}
+
+/// Constants, key, then the 4 words of `input`: counter and nonce
+fn chacha_state(key: &[u8; 32], input: [u32; 4]) -> [u32; 16] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&SIGMA);
+    for i in 0..8 {
+        state[4 + i] = le32(&key[4 * i..]);
+    }
+    state[12..].copy_from_slice(&input);
+    state
+}
+
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; 12]) -> [u8; 64] {
+    let state = chacha_state(
+        key,
+        [counter, le32(nonce), le32(&nonce[4..]), le32(&nonce[8..])],
+    );
+    let mixed = chacha_rounds(&state);
+    let mut block = [0u8; 64];
+    for i in 0..16 {
+        block[4 * i..4 * i + 4].copy_from_slice(&mixed[i].wrapping_add(state[i]).to_le_bytes());
+    }
+    block
+}
+
+/// Xors `data` with the key stream starting at block `counter`
+fn chacha20_xor(key: &[u8; 32], counter: u32, nonce: &[u8; 12], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let block = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        for (byte, k) in chunk.iter_mut().zip(block.iter()) {
+            *byte ^= k;
+        }
+    }
+}
+
+/// Subkey for the first 16 bytes of an extended nonce
+fn hchacha20(key: &[u8; 32], nonce: &[u8]) -> [u8; 32] {
+    let input = [
+        le32(nonce),
+        le32(&nonce[4..]),
+        le32(&nonce[8..]),
+        le32(&nonce[12..]),
+    ];
+    let mixed = chacha_rounds(&chacha_state(key, input));
+    let mut subkey = [0u8; 32];
+    for (i, word) in mixed[..4].iter().chain(mixed[12..].iter()).enumerate() {
+        subkey[4 * i..4 * i + 4].copy_from_slice(&word.to_le_bytes());
+    }
+    subkey
+}
+
+/// One time authenticator of `msg`, with 44 bit limbs as in poly1305-donna
+fn poly1305(key: &[u8; 32], msg: &[u8]) -> [u8; 16] {
+    const M44: u64 = 0xfff_ffff_ffff;
+    const M42: u64 = 0x3ff_ffff_ffff;
+    let (t0, t1) = (le64(key), le64(&key[8..]));
+    let r0 = t0 & 0xffc_0fff_ffff;
+    let r1 = ((t0 >> 44) | (t1 << 20)) & 0xfff_ffc0_ffff;
+    let r2 = (t1 >> 24) & 0x00f_ffff_fc0f;
+    let (s1, s2) = (r1 * 20, r2 * 20);
+    let (mut h0, mut h1, mut h2) = (0u64, 0u64, 0u64);
+
+    for chunk in msg.chunks(16) {
+        let mut block = [0u8; 16];
+        block[..chunk.len()].copy_from_slice(chunk);
+        let hibit = if chunk.len() == 16 {
+            1 << 40
+        } else {
+            block[chunk.len()] = 1;
+            0
+        };
+        let (t0, t1) = (le64(&block), le64(&block[8..]));
+        h0 += t0 & M44;
+        h1 += ((t0 >> 44) | (t1 << 20)) & M44;
+        h2 += ((t1 >> 24) & M42) | hibit;
+
+        let mul = |a: u64, b: u64| u128::from(a) * u128::from(b);
+        let d0 = mul(h0, r0) + mul(h1, s2) + mul(h2, s1);
+        let mut d1 = mul(h0, r1) + mul(h1, r0) + mul(h2, s2);
+        let mut d2 = mul(h0, r2) + mul(h1, r1) + mul(h2, r0);
+        h0 = d0 as u64 & M44;
+        d1 += d0 >> 44;
+        h1 = d1 as u64 & M44;
+        d2 += d1 >> 44;
+        h2 = d2 as u64 & M42;
+        h0 += (d2 >> 42) as u64 * 5;
+        h1 += h0 >> 44;
+        h0 &= M44;
+    }
+
+    // Full carry, then h - p if h >= p
+    for _ in 0..2 {
+        h2 += h1 >> 44;
+        h1 &= M44;
+        h0 += (h2 >> 42) * 5;
+        h2 &= M42;
+        h1 += h0 >> 44;
+        h0 &= M44;
+    }
+    let mut g0 = h0 + 5;
+    let mut g1 = h1 + (g0 >> 44);
+    g0 &= M44;
+    let g2 = (h2 + (g1 >> 44)).wrapping_sub(1 << 42);
+    g1 &= M44;
+    let keep_g = (g2 >> 63).wrapping_sub(1);
+    h0 = (h0 & !keep_g) | (g0 & keep_g);
+    h1 = (h1 & !keep_g) | (g1 & keep_g);
+    h2 = (h2 & !keep_g) | (g2 & keep_g);
+
+    let (t0, t1) = (le64(&key[16..]), le64(&key[24..]));
+    h0 += t0 & M44;
+    h1 += (((t0 >> 44) | (t1 << 20)) & M44) + (h0 >> 44);
+    h0 &= M44;
+    h2 += ((t1 >> 24) & M42) + (h1 >> 44);
+    h1 &= M44;
+    h2 &= M42;
+    let mut tag = [0u8; 16];
+    tag[..8].copy_from_slice(&(h0 | (h1 << 44)).to_le_bytes());
+    tag[8..].copy_from_slice(&((h1 >> 20) | (h2 << 24)).to_le_bytes());
+    tag
+}
+
+/// Tag of the ChaCha20-Poly1305 construction of RFC 8439
+fn aead_tag(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+    let mut otk = [0u8; 32];
+    otk.copy_from_slice(&chacha20_block(key, 0, nonce)[..32]);
+    let padding = |len: usize| (16 - len % 16) % 16;
+    let mut mac_data = Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+    mac_data.extend_from_slice(aad);
+    mac_data.resize(mac_data.len() + padding(aad.len()), 0);
+    mac_data.extend_from_slice(ciphertext);
+    mac_data.resize(mac_data.len() + padding(ciphertext.len()), 0);
+    mac_data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    mac_data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly1305(&otk, &mac_data)
+}
+
+fn aead_seal(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut sealed = plaintext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut sealed);
+    let tag = aead_tag(key, nonce, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+fn aead_open(key: &[u8; 32], nonce: &[u8; 12], aad: &[u8], sealed: &[u8]) -> Option<Vec<u8>> {
+    let split = sealed.len().checked_sub(16)?;
+    let (ciphertext, tag) = sealed.split_at(split);
+    // Constant time comparison of the tags
+    let expected = aead_tag(key, nonce, aad, ciphertext);
+    if expected
+        .iter()
+        .zip(tag)
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        != 0
+    {
+        return None;
+    }
+    let mut plaintext = ciphertext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut plaintext);
+    Some(plaintext)
+}
+
+/// The ChaCha20 key and nonce an extended 24 bytes nonce stands for
+fn xchacha_key(key: &[u8; 32], nonce: &[u8; 24]) -> ([u8; 32], [u8; 12]) {
+    let mut short = [0u8; 12];
+    short[4..].copy_from_slice(&nonce[16..]);
+    (hchacha20(key, &nonce[..16]), short)
+}
+
+/// Hexadecimal line of a random nonce followed by `plaintext` sealed with `key`
+fn xseal(key: &[u8; 32], aad: &[u8], plaintext: &[u8]) -> String {
+    let mut nonce = [0u8; 24];
+    thread_rng().fill(&mut nonce);
+    let (key, short) = xchacha_key(key, &nonce);
+    let mut line = to_hex(&nonce);
+    line.push_str(&to_hex(&aead_seal(&key, &short, aad, plaintext)));
+    line
+}
+
+/// What a line made by [`xseal`] with `key` and `aad` holds
+fn xopen(key: &[u8; 32], aad: &[u8], line: &str) -> Option<Vec<u8>> {
+    let bytes = from_hex(line)?;
+    if bytes.len() < SEAL_OVERHEAD {
+        return None;
+    }
+    let (nonce_bytes, sealed) = bytes.split_at(24);
+    let mut nonce = [0u8; 24];
+    nonce.copy_from_slice(nonce_bytes);
+    let (key, short) = xchacha_key(key, &nonce);
+    aead_open(&key, &short, aad, sealed)
+}
+
+// Public API
+//-----------
+
+/// Lowercase hexadecimal form of `bytes`
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Bytes of an hexadecimal string, None if it is not one
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect()
+}
+
+/// Implements hex (de)serialization and `Debug` for a fixed size byte newtype
+macro_rules! hex_bytes {
+    ($name:ident, $len:expr) => {
+        impl fmt::Debug for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}({})", stringify!($name), to_hex(&self.0))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&to_hex(&self.0))
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let hex = String::deserialize(deserializer)?;
+                let bytes = from_hex(&hex)
+                    .filter(|b| b.len() == $len)
+                    .ok_or_else(|| de::Error::custom(concat!("invalid ", stringify!($name))))?;
+                let mut array = [0u8; $len];
+                array.copy_from_slice(&bytes);
+                Ok($name(array))
+            }
+        }
+    };
+}
+
+/// Ed25519 public key
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PublicKey(pub [u8; 32]);
+hex_bytes!(PublicKey, 32);
+
+/// The 32 bytes seed the signing scalar is derived from
+#[derive(Clone, PartialEq)]
+pub struct SecretKey(pub [u8; 32]);
+hex_bytes!(SecretKey, 32);
+
+/// X25519 public key, private messages to its owner are encrypted for it
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EncryptionKey(pub [u8; 32]);
+hex_bytes!(EncryptionKey, 32);
+
+/// Ed25519 signature
+#[derive(Clone, Copy, PartialEq)]
+pub struct Signature(pub [u8; 64]);
+hex_bytes!(Signature, 64);
+
+impl PublicKey {
+    /// Short human readable form, meant to be compared out of band
+    pub fn fingerprint(&self) -> String {
+        to_hex(&sha512(&self.0)[..8])
+    }
+}
+
+impl SecretKey {
+    /// A new key from the system's random source
+    pub fn generate() -> Self {
+        let mut seed = [0u8; 32];
+        thread_rng().fill(&mut seed);
+        SecretKey(seed)
+    }
+
+    /// The public half of the key
+    pub fn public_key(&self) -> PublicKey {
+        let d = expand_seed(&self.0);
+        PublicKey(pack_point(&scalarbase(&d[..32])))
+    }
+
+    /// Signs `msg`
+    pub fn sign(&self, msg: &[u8]) -> Signature {
+        let d = expand_seed(&self.0);
+        let pk = self.public_key();
+
+        let r = reduce(&sha512_concat(&[&d[32..], msg]));
+        let big_r = pack_point(&scalarbase(&r));
+        let h = reduce(&sha512_concat(&[&big_r, &pk.0, msg]));
+
+        let mut x = [0i64; 64];
+        for i in 0..32 {
+            x[i] = i64::from(r[i]);
+        }
+        for i in 0..32 {
+            for j in 0..32 {
+                x[i + j] += i64::from(h[i]) * i64::from(d[j]);
+            }
+        }
+        let s = mod_l(&mut x);
+
+        let mut sig = [0u8; 64];
+        sig[..32].copy_from_slice(&big_r);
+        sig[32..].copy_from_slice(&s);
+        Signature(sig)
+    }
+
+    /// The X25519 key of the same seed: its scalar is the signing one, as in
+    /// libsodium's conversion of Ed25519 keys
+    pub fn encryption_key(&self) -> EncryptionKey {
+        EncryptionKey(x25519(&self.scalar(), &BASE_U))
+    }
+
+    fn scalar(&self) -> [u8; 32] {
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&expand_seed(&self.0)[..32]);
+        scalar
+    }
+
+    /// Key shared with the owner of `peer`, None for a low order point which
+    /// would give the same secret to everyone
+    fn shared_key(&self, peer: &EncryptionKey) -> Option<[u8; 32]> {
+        let shared = x25519(&self.scalar(), &peer.0);
+        if verify_32(&shared, &[0; 32]) {
+            return None;
+        }
+        Some(hchacha20(&shared, &[0; 16]))
+    }
+
+    /// Hexadecimal line of `plaintext` sealed for the owner of `peer`, who
+    /// opens it with [`open_from`](Self::open_from) and our encryption key
+    pub fn seal_for(&self, peer: &EncryptionKey, aad: &[u8], plaintext: &[u8]) -> Option<String> {
+        Some(xseal(&self.shared_key(peer)?, aad, plaintext))
+    }
+
+    /// What a line sealed by the owner of `peer` for us holds, None if it was
+    /// sealed by someone else, for someone else, or tampered with
+    pub fn open_from(&self, peer: &EncryptionKey, aad: &[u8], line: &str) -> Option<Vec<u8>> {
+        xopen(&self.shared_key(peer)?, aad, line)
+    }
+}
+
+/// Whether `sig` is the signature of `msg` by `key`
+pub fn verify(key: &PublicKey, msg: &[u8], sig: &Signature) -> bool {
+    let mut q = match unpackneg(&key.0) {
+        Some(q) => q,
+        None => return false,
+    };
+    let h = reduce(&sha512_concat(&[&sig.0[..32], &key.0, msg]));
+    let mut p = scalarmult(&mut q, &h);
+    let q = scalarbase(&sig.0[32..]);
+    point_add(&mut p, &q);
+    verify_32(&sig.0[..32], &pack_point(&p))
+}
+
+/// Pre-shared key sealing whole frames with XChaCha20-Poly1305, for pipes
+/// other local users can read
+#[derive(Clone)]
+pub struct FrameKey(pub [u8; 32]);
+hex_bytes!(FrameKey, 32);
+
+/// Random nonce and tag added to each sealed frame
+const SEAL_OVERHEAD: usize = 24 + 16;
+
+impl FrameKey {
+    /// Key written as 64 hexadecimal digits
+    pub fn from_hex(hex: &str) -> Option<Self> {
+        let bytes = from_hex(hex.trim()).filter(|b| b.len() == 32)?;
+        let mut key = [0u8; 32];
+        key.copy_from_slice(&bytes);
+        Some(FrameKey(key))
+    }
+
+    /// Hexadecimal line of a random nonce followed by the sealed `frame`
+    pub fn seal(&self, frame: &[u8]) -> String {
+        xseal(&self.0, &[], frame)
+    }
+
+    /// The frame a line made by [`seal`](Self::seal) with this key holds,
+    /// None if it was made with another key or tampered with
+    pub fn open(&self, line: &str) -> Option<Vec<u8>> {
+        xopen(&self.0, &[], line)
+    }
+
+    /// Length of the line sealing a frame of `len` bytes
+    pub fn sealed_len(len: usize) -> usize {
+        2 * (len + SEAL_OVERHEAD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex32(hex: &str) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        out.copy_from_slice(&from_hex(hex).unwrap());
+        out
+    }
+
+    #[test]
+    fn sha512_vectors() {
+        assert_eq!(
+            to_hex(&sha512(b"abc")),
+            "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+             2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f"
+        );
+        // Long enough for the padding to spill into a second block
+        assert_eq!(
+            to_hex(&sha512(&[b'a'; 112])),
+            "c01d080efd492776a1c43bd23dd99d0a2e626d481e16782e75d54c2503b5dc32\
+             bd05f0f1ba33e568b88fd2d970929b719ecbb152f58f130a407c8830604b70ca"
+        );
+    }
+
+    #[test]
+    fn ed25519_rfc8032_vector() {
+        let sk = SecretKey(hex32(
+            "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        ));
+        let pk = sk.public_key();
+        assert_eq!(
+            to_hex(&pk.0),
+            "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a"
+        );
+        let sig = sk.sign(b"");
+        assert_eq!(
+            to_hex(&sig.0),
+            "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555\
+             fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b"
+        );
+        assert!(verify(&pk, b"", &sig));
+        assert!(!verify(&pk, b"tampered", &sig));
+    }
+
+    #[test]
+    fn x25519_rfc7748_vectors() {
+        let scalar = hex32("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let point = hex32("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+        assert_eq!(
+            to_hex(&x25519(&scalar, &point)),
+            "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552"
+        );
+
+        let alice = hex32("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        assert_eq!(
+            to_hex(&x25519(&alice, &BASE_U)),
+            "8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a"
+        );
+    }
+
+    #[test]
+    fn private_messages_open_for_both_ends_only() {
+        let (alice, bob, eve) = (
+            SecretKey::generate(),
+            SecretKey::generate(),
+            SecretKey::generate(),
+        );
+        let line = alice
+            .seal_for(&bob.encryption_key(), b"alice\0bob", b"hi")
+            .unwrap();
+        let opened = bob.open_from(&alice.encryption_key(), b"alice\0bob", &line);
+        assert_eq!(opened.as_deref(), Some(&b"hi"[..]));
+        assert_eq!(
+            alice
+                .open_from(&bob.encryption_key(), b"alice\0bob", &line)
+                .as_deref(),
+            Some(&b"hi"[..])
+        );
+
+        assert_eq!(
+            eve.open_from(&alice.encryption_key(), b"alice\0bob", &line),
+            None
+        );
+        assert_eq!(
+            bob.open_from(&alice.encryption_key(), b"alice\0eve", &line),
+            None
+        );
+        assert_eq!(alice.seal_for(&EncryptionKey([0; 32]), b"", b"hi"), None);
+    }
+
+    #[test]
+    fn chacha20_poly1305_rfc8439_vectors() {
+        let key = hex32("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let mut nonce = [0u8; 12];
+        nonce.copy_from_slice(&from_hex("000000090000004a00000000").unwrap());
+        assert_eq!(
+            to_hex(&chacha20_block(&key, 1, &nonce)[..16]),
+            "10f1e7e4d13b5915500fdd1fa32071c4"
+        );
+
+        let poly_key = hex32("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b");
+        assert_eq!(
+            to_hex(&poly1305(&poly_key, b"Cryptographic Forum Research Group")),
+            "a8061dc1305136c6c22b8baf0c0127a9"
+        );
+
+        let key = hex32("808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f");
+        nonce.copy_from_slice(&from_hex("070000004041424344454647").unwrap());
+        let aad = from_hex("50515253c0c1c2c3c4c5c6c7").unwrap();
+        let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you \
+            only one tip for the future, sunscreen would be it.";
+        let sealed = aead_seal(&key, &nonce, &aad, plaintext);
+        assert_eq!(to_hex(&sealed[..16]), "d31a8d34648e60db7b86afbc53ef7ec2");
+        assert_eq!(
+            to_hex(&sealed[sealed.len() - 16..]),
+            "1ae10b594f09e26a7e902ecbd0600691"
+        );
+        assert_eq!(
+            aead_open(&key, &nonce, &aad, &sealed).as_deref(),
+            Some(plaintext)
+        );
+    }
+
+    #[test]
+    fn sealed_frames_open_with_the_same_key_only() {
+        let key = hex32("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f");
+        let nonce = from_hex("000000090000004a0000000031415927").unwrap();
+        assert_eq!(
+            to_hex(&hchacha20(&key, &nonce)),
+            "82413b4227b27bfed30e42508a877d73a0f9e4d58a74a853c12ec41326d3ecdc"
+        );
+
+        let key = FrameKey(key);
+        let line = key.seal(b"{\"id\":1}");
+        assert_eq!(line.len(), FrameKey::sealed_len(8));
+        assert_ne!(line, key.seal(b"{\"id\":1}"), "a new nonce each time");
+        assert_eq!(key.open(&line).as_deref(), Some(&b"{\"id\":1}"[..]));
+
+        let other = FrameKey::from_hex(&"11".repeat(32)).unwrap();
+        assert_eq!(other.open(&line), None);
+        let mut tampered = line.clone().into_bytes();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'0' { b'1' } else { b'0' };
+        assert_eq!(key.open(&String::from_utf8(tampered).unwrap()), None);
+        assert_eq!(key.open("{\"id\":1}"), None);
+    }
+}
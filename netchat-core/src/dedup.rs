@@ -0,0 +1,98 @@
+//! Duplicate detection, messages reach an app through several relays
+
+use std::collections::{HashSet, VecDeque};
+
+use crate::messages::{Date, MsgId};
+
+/// Ids are remembered by default for this many local dates
+pub const DEFAULT_HORIZON: Date = 100_000;
+
+/// Ids of the messages already handled, every message is relayed once.
+///
+/// Each id is stamped with the local date it was seen at, and forgotten once
+/// the local date is `horizon` past it: memory stays bounded however long the
+/// app runs. A copy of a forgotten message would be handled again, which
+/// takes a relay holding it back for the whole horizon.
+pub struct Seen {
+    ids: HashSet<MsgId>,
+    order: VecDeque<(Date, MsgId)>, // Oldest first
+    horizon: Date,
+    last: Date,
+}
+
+impl Default for Seen {
+    fn default() -> Seen {
+        Seen::with_horizon(DEFAULT_HORIZON)
+    }
+}
+
+impl Seen {
+    /// Forgets ids seen `horizon` local dates ago
+    pub fn with_horizon(horizon: Date) -> Seen {
+        Seen {
+            ids: HashSet::new(),
+            order: VecDeque::new(),
+            horizon: horizon.max(1),
+            last: 0,
+        }
+    }
+
+    /// Records `id`, returns whether it is the first time it is seen. Each
+    /// call counts as a local date.
+    pub fn insert(&mut self, id: MsgId) -> bool {
+        self.insert_at(id, self.last + 1)
+    }
+
+    /// Records `id` seen at local `date`, returns whether it is the first time
+    pub fn insert_at(&mut self, id: MsgId, date: Date) -> bool {
+        self.last = self.last.max(date);
+        while let Some(&(at, old)) = self.order.front() {
+            if at + self.horizon > self.last {
+                break;
+            }
+            self.order.pop_front();
+            self.ids.remove(&old);
+        }
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back((self.last, id));
+        true
+    }
+
+    /// Whether `id` was seen, without recording it
+    pub fn contains(&self, id: MsgId) -> bool {
+        self.ids.contains(&id)
+    }
+
+    /// Ids remembered
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether no id is remembered
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_forgotten_past_the_horizon() {
+        let mut seen = Seen::with_horizon(10);
+        assert!(seen.insert_at(1, 1));
+        assert!(!seen.insert_at(1, 5));
+        assert!(seen.contains(1) && !seen.contains(2));
+        assert!(seen.insert_at(2, 10));
+        assert_eq!(seen.len(), 2);
+
+        // Date 11 is 10 past the first id only
+        assert!(seen.insert_at(3, 11));
+        assert_eq!(seen.len(), 2);
+        assert!(seen.insert_at(1, 12), "forgotten, new again");
+        assert!(!seen.insert_at(2, 12));
+    }
+}
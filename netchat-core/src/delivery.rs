@@ -0,0 +1,379 @@
+//! Order in which the received chat messages are handed over
+//!
+//! Messages are relayed as soon as they arrive, a [`DeliveryPolicy`] then
+//! decides when each chat message is shown: on arrival, in the order its
+//! sender sent them, after the messages it depends on, or in the same order on
+//! every app. To order them, chat messages carry how many chat messages of each
+//! app their sender had handed over, counted by [`Delivered`].
+//!
+//! The order of an app starts with the first of its messages seen, and
+//! messages which never come (cancelled in the outbox of their sender, sent
+//! before this app joined) hold back at most [`HOLD_LIMIT`] others. Calling
+//! [`DeliveryPolicy::expire`] every timeout also hands over the messages held
+//! for longer than it.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::messages::{Date, Header, MsgId};
+use crate::{AppId, Clock, Msg};
+
+/// Most messages held back, the oldest is handed over past it
+pub const HOLD_LIMIT: usize = 64;
+
+/// Names of the policies, for [`by_name`]
+pub const POLICIES: &[&str] = &["arrival", "fifo", "causal", "total"];
+
+/// Chooses when received chat messages are handed over
+pub trait DeliveryPolicy: Send {
+    /// Takes a chat message received for the first time, gives back the ones
+    /// to hand over now, in order
+    fn receive(&mut self, msg: Msg) -> Vec<Msg>;
+
+    /// `app_id` left the mesh, gives back the messages no longer waiting for it
+    fn left(&mut self, _app_id: &str) -> Vec<Msg> {
+        Vec::new()
+    }
+
+    /// Gives back the messages already held at the previous call, to call
+    /// every timeout: they were held between one and two timeouts
+    fn expire(&mut self) -> Vec<Msg> {
+        Vec::new()
+    }
+}
+
+/// The policy called `name`, one of [`POLICIES`]
+pub fn by_name(name: &str) -> Option<Box<dyn DeliveryPolicy>> {
+    match name {
+        "arrival" => Some(Box::new(Arrival)),
+        "fifo" => Some(Box::new(Fifo::default())),
+        "causal" => Some(Box::new(Causal::default())),
+        "total" => Some(Box::new(Total::default())),
+        _ => None,
+    }
+}
+
+/// Whether the message goes through the delivery policy
+pub fn is_chat(header: &Header) -> bool {
+    matches!(
+        header,
+        Header::Public(..) | Header::Private(..) | Header::Encrypted(..) | Header::Announcement(..)
+    )
+}
+
+/// Chat messages handed over or sent, from each app
+#[derive(Default)]
+pub struct Delivered(HashMap<AppId, Date>);
+
+impl Delivered {
+    /// Counts a chat message handed over
+    pub fn count(&mut self, msg: &Msg) {
+        *self.0.entry(msg.sender_id.clone()).or_insert(0) += 1;
+    }
+
+    /// Counts a chat message about to be sent and stamps it with the counts
+    pub fn stamp(&mut self, msg: &mut Msg) {
+        self.count(msg);
+        msg.delivered = Some(Box::new(Clock(self.0.clone())));
+    }
+}
+
+/// Rank of a stamped message among those of its sender
+fn number(msg: &Msg) -> Option<Date> {
+    msg.delivered.as_ref()?.get(&msg.sender_id).copied()
+}
+
+/// Hands messages over as they come, nothing is held back
+pub struct Arrival;
+
+impl DeliveryPolicy for Arrival {
+    fn receive(&mut self, msg: Msg) -> Vec<Msg> {
+        vec![msg]
+    }
+}
+
+/// Messages held until a condition on the counts of handed over ones holds
+#[derive(Default)]
+struct HoldBack {
+    delivered: HashMap<AppId, Date>,
+    held: Vec<(u64, Msg)>, // In arrival order, with the expiry period they came in
+    period: u64,
+}
+
+type Ready = fn(&HashMap<AppId, Date>, &Msg) -> bool;
+
+impl HoldBack {
+    fn receive(&mut self, msg: Msg, ready: Ready) -> Vec<Msg> {
+        let nth = match number(&msg) {
+            Some(number) => number,
+            None => return vec![msg],
+        };
+        self.delivered
+            .entry(msg.sender_id.clone())
+            .or_insert_with(|| nth.saturating_sub(1));
+        self.held.push((self.period, msg));
+        self.release(ready)
+    }
+
+    /// Hands over what came before the current period, then what it unblocks
+    fn expire(&mut self, ready: Ready) -> Vec<Msg> {
+        let mut released = Vec::new();
+        while self
+            .held
+            .first()
+            .is_some_and(|(period, _)| *period < self.period)
+        {
+            let (_, msg) = self.held.remove(0);
+            self.hand_over(&msg);
+            released.push(msg);
+        }
+        self.period += 1;
+        released.extend(self.release(ready));
+        released
+    }
+
+    fn release(&mut self, ready: Ready) -> Vec<Msg> {
+        let mut released = Vec::new();
+        loop {
+            let next = match self
+                .held
+                .iter()
+                .position(|(_, m)| ready(&self.delivered, m))
+            {
+                Some(i) => i,
+                None if self.held.len() > HOLD_LIMIT => 0,
+                None => break,
+            };
+            let (_, msg) = self.held.remove(next);
+            self.hand_over(&msg);
+            released.push(msg);
+        }
+        released
+    }
+
+    fn hand_over(&mut self, msg: &Msg) {
+        let date = self.delivered.entry(msg.sender_id.clone()).or_insert(0);
+        *date = (*date).max(number(msg).unwrap_or(0));
+    }
+}
+
+/// Next of its sender, or late
+fn in_sender_order(delivered: &HashMap<AppId, Date>, msg: &Msg) -> bool {
+    let last = delivered.get(&msg.sender_id).copied().unwrap_or(0);
+    number(msg).is_none_or(|n| n <= last + 1)
+}
+
+/// Every message its sender had handed over was, apps never seen don't count
+fn after_dependencies(delivered: &HashMap<AppId, Date>, msg: &Msg) -> bool {
+    let dependencies = match &msg.delivered {
+        Some(dependencies) => dependencies,
+        None => return true,
+    };
+    in_sender_order(delivered, msg)
+        && dependencies.iter().all(|(app_id, date)| {
+            *app_id == msg.sender_id || delivered.get(app_id).is_none_or(|d| date <= d)
+        })
+}
+
+/// Hands the messages of each sender over in the order they were sent
+#[derive(Default)]
+pub struct Fifo(HoldBack);
+
+impl DeliveryPolicy for Fifo {
+    fn receive(&mut self, msg: Msg) -> Vec<Msg> {
+        self.0.receive(msg, in_sender_order)
+    }
+
+    fn expire(&mut self) -> Vec<Msg> {
+        self.0.expire(in_sender_order)
+    }
+}
+
+/// Hands a message over after the ones its sender had seen
+#[derive(Default)]
+pub struct Causal(HoldBack);
+
+impl DeliveryPolicy for Causal {
+    fn receive(&mut self, msg: Msg) -> Vec<Msg> {
+        self.0.receive(msg, after_dependencies)
+    }
+
+    fn expire(&mut self) -> Vec<Msg> {
+        self.0.expire(after_dependencies)
+    }
+}
+
+/// Sum of the counts then sender: a message ranks after its dependencies
+type Rank = (Date, AppId, MsgId);
+
+fn rank(msg: &Msg) -> Rank {
+    let sum = msg.delivered.iter().flat_map(|d| d.values()).sum();
+    (sum, msg.sender_id.clone(), msg.id)
+}
+
+/// Causal order, and the same order on every app: a message is handed over
+/// once every app which already spoke sent one ranking after it. Apps which
+/// have not sent anything yet are not waited for, and the sent messages are
+/// shown at once by their sender.
+#[derive(Default)]
+pub struct Total {
+    causal: HoldBack,
+    ordered: BTreeMap<Rank, (u64, Msg)>, // With the expiry period they came in
+    latest: HashMap<AppId, Rank>,        // Of the last message of each app
+}
+
+impl Total {
+    fn order(&mut self, messages: Vec<Msg>, came: u64) {
+        for msg in messages {
+            let rank = rank(&msg);
+            self.latest.insert(msg.sender_id.clone(), rank.clone());
+            self.ordered.insert(rank, (came, msg));
+        }
+    }
+
+    /// Hands over in order, up to the first message someone may still answer
+    /// before, unless it came before `period`
+    fn release(&mut self, period: u64) -> Vec<Msg> {
+        let mut released = Vec::new();
+        while let Some((first, (came, _))) = self.ordered.iter().next() {
+            let everyone_moved_on = self
+                .latest
+                .iter()
+                .all(|(app_id, latest)| *app_id == first.1 || latest > first);
+            if !everyone_moved_on && self.ordered.len() <= HOLD_LIMIT && *came >= period {
+                break;
+            }
+            let first = first.clone();
+            released.extend(self.ordered.remove(&first).map(|(_, msg)| msg));
+        }
+        released
+    }
+}
+
+impl DeliveryPolicy for Total {
+    fn receive(&mut self, msg: Msg) -> Vec<Msg> {
+        if msg.delivered.is_none() {
+            return vec![msg];
+        }
+        let causal = self.causal.receive(msg, after_dependencies);
+        self.order(causal, self.causal.period);
+        self.release(0)
+    }
+
+    fn left(&mut self, app_id: &str) -> Vec<Msg> {
+        self.latest.remove(app_id);
+        self.release(0)
+    }
+
+    fn expire(&mut self) -> Vec<Msg> {
+        let period = self.causal.period;
+        // Held long enough already, they do not wait for the next period
+        let causal = self.causal.expire(after_dependencies);
+        self.order(causal, 0);
+        self.release(period)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Channel;
+
+    /// Public `text` from `sender`, stamped like `sender` would
+    fn send(sender: &mut Delivered, app_id: &str, text: &str) -> Msg {
+        let header = Header::Public(Channel::default(), text.to_owned());
+        let mut msg = Msg::new(
+            rand::random(),
+            app_id.to_owned(),
+            header,
+            Clock::new(app_id.to_owned()),
+        );
+        sender.stamp(&mut msg);
+        msg
+    }
+
+    fn texts(messages: Vec<Msg>) -> Vec<String> {
+        messages
+            .into_iter()
+            .map(|m| match m.header {
+                Header::Public(_, text) => text,
+                _ => String::new(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn answers_wait_for_the_question() {
+        let (mut alice, mut bob) = (Delivered::default(), Delivered::default());
+        let question = send(&mut alice, "alice", "cat?");
+        let again = send(&mut alice, "alice", "anyone?");
+        bob.count(&question);
+        bob.count(&again);
+        let answer = send(&mut bob, "bob", "under the bed");
+
+        assert_eq!(
+            texts(Arrival.receive(answer.clone())),
+            vec!["under the bed"]
+        );
+
+        let mut fifo = Fifo::default();
+        assert_eq!(texts(fifo.receive(question.clone())), vec!["cat?"]);
+        assert_eq!(texts(fifo.receive(answer.clone())), vec!["under the bed"]);
+        assert_eq!(texts(fifo.receive(again.clone())), vec!["anyone?"]);
+
+        let mut causal = Causal::default();
+        assert_eq!(texts(causal.receive(question)), vec!["cat?"]);
+        assert!(causal.receive(answer).is_empty());
+        assert_eq!(
+            texts(causal.receive(again)),
+            vec!["anyone?", "under the bed"]
+        );
+    }
+
+    #[test]
+    fn total_order_is_the_same_everywhere() {
+        let (mut alice, mut bob) = (Delivered::default(), Delivered::default());
+        let hi = send(&mut alice, "alice", "hi");
+        let hey = send(&mut bob, "bob", "hey");
+        let so = send(&mut alice, "alice", "so");
+        let well = send(&mut bob, "bob", "well");
+
+        let (mut carol, mut dave) = (Total::default(), Total::default());
+        let mut seen_by_carol = Vec::new();
+        let mut seen_by_dave = Vec::new();
+        for msg in &[&hi, &hey, &so, &well] {
+            seen_by_carol.extend(carol.receive((*msg).clone()));
+        }
+        for msg in &[&hi, &hey, &well, &so] {
+            seen_by_dave.extend(dave.receive((*msg).clone()));
+        }
+        assert_eq!(texts(seen_by_carol), vec!["hi", "hey", "so"]);
+        assert_eq!(texts(seen_by_dave), vec!["hi", "hey", "so"]);
+
+        // Nothing comes after "well" until alice speaks or leaves
+        assert_eq!(texts(dave.left("alice")), vec!["well"]);
+    }
+
+    #[test]
+    fn stragglers_are_handed_over_after_a_timeout() {
+        let (mut alice, mut bob) = (Delivered::default(), Delivered::default());
+        let question = send(&mut alice, "alice", "cat?");
+        let lost = send(&mut alice, "alice", "anyone?");
+        bob.count(&question);
+        bob.count(&lost);
+        let answer = send(&mut bob, "bob", "under the bed");
+
+        let mut causal = Causal::default();
+        assert_eq!(texts(causal.receive(question)), vec!["cat?"]);
+        assert!(causal.receive(answer).is_empty());
+        assert!(causal.expire().is_empty(), "held for less than a timeout");
+        assert_eq!(texts(causal.expire()), vec!["under the bed"]);
+
+        let (mut carol, mut dave) = (Delivered::default(), Delivered::default());
+        let mut total = Total::default();
+        let _ = total.receive(send(&mut carol, "carol", "hey"));
+        assert!(total.receive(send(&mut dave, "dave", "yo")).is_empty());
+        assert!(total.receive(send(&mut dave, "dave", "well")).is_empty());
+        assert!(total.expire().is_empty(), "carol may answer first");
+        assert_eq!(texts(total.expire()), vec!["yo", "well"]);
+    }
+}
@@ -0,0 +1,268 @@
+//! Splitting the input stream into frames, and the size limits of messages
+//!
+//! A frame is a json line, or a binary frame: [`BINARY_MARKER`], the length
+//! of the payload in 4 big endian bytes, the payload and a newline.
+
+use std::io::{self, BufRead, Read};
+use std::str;
+use std::sync::{Arc, Mutex};
+
+/// Longest line read from the input pipe, anything longer is skipped
+pub const MAX_FRAME_LEN: usize = 4 * 1024 * 1024;
+
+/// First byte of a binary frame, no line of text starts with it
+pub const BINARY_MARKER: u8 = 0;
+
+/// Payload of a whole binary `frame`, without its newline, None if it is not one
+pub fn binary_payload(frame: &[u8]) -> Option<&[u8]> {
+    match frame {
+        [BINARY_MARKER, len @ ..] if len.len() >= 4 => {
+            let (len, payload) = len.split_at(4);
+            let len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+            Some(payload).filter(|payload| payload.len() == len)
+        }
+        _ => None,
+    }
+}
+
+/// Default limit on the characters of a chat message
+pub const DEFAULT_MAX_TEXT_LEN: usize = 4096;
+
+/// Size limits, set from the command line
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    /// Longest serialized message sent or read, in bytes
+    pub max_frame_len: usize,
+    /// Longest chat message typed by the user, in characters
+    pub max_text_len: usize,
+    /// Split longer messages instead of refusing them
+    pub split_long_text: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_frame_len: MAX_FRAME_LEN,
+            max_text_len: DEFAULT_MAX_TEXT_LEN,
+            split_long_text: false,
+        }
+    }
+}
+
+/// Cuts `text` in pieces of at most `max_len` characters, at a space when possible
+pub fn split_text(text: &str, max_len: usize) -> Vec<String> {
+    let max_len = max_len.max(1);
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.chars().count() > max_len {
+        let end = rest
+            .char_indices()
+            .nth(max_len)
+            .map_or(rest.len(), |(i, _)| i);
+        let cut = match rest[..end].rfind(' ') {
+            _ if rest[end..].starts_with(' ') => end,
+            Some(space) if space > 0 => space,
+            _ => end,
+        };
+        pieces.push(rest[..cut].to_owned());
+        rest = rest[cut..].trim_start_matches(' ');
+    }
+    pieces.push(rest.to_owned());
+    pieces
+}
+
+/// Lines longer than this are not worth keeping in the pool
+const MAX_POOLED_LEN: usize = 64 * 1024;
+
+/// Line buffers handed back by the decoding stage to be filled again
+#[derive(Clone, Default)]
+pub struct Pool(Arc<Mutex<Vec<String>>>);
+
+impl Pool {
+    /// An empty line, reusing a returned one if any
+    pub fn take(&self) -> String {
+        self.0.lock().unwrap().pop().unwrap_or_default()
+    }
+
+    /// Hands a line back to be reused
+    pub fn give(&self, mut line: String) {
+        if line.capacity() <= MAX_POOLED_LEN {
+            line.clear();
+            self.0.lock().unwrap().push(line);
+        }
+    }
+}
+
+/// A line of the input pipe
+#[derive(Debug, PartialEq)]
+pub enum Frame {
+    /// Complete line, invalid UTF-8 is replaced
+    Line(String),
+    /// Payload of a binary frame
+    Binary(Vec<u8>),
+    /// Frame longer than the limit, with its length, its content was dropped
+    TooLong(usize),
+}
+
+/// Splits a stream into frames without ever buffering more than `max_len` bytes
+pub struct FrameReader<R> {
+    reader: R,
+    buf: Vec<u8>,
+    max_len: usize,
+    pool: Pool,
+}
+
+impl<R: BufRead> FrameReader<R> {
+    /// Frames of `reader`, with lines taken from `pool`
+    pub fn new(reader: R, max_len: usize, pool: Pool) -> Self {
+        FrameReader {
+            reader,
+            buf: Vec::new(),
+            max_len,
+            pool,
+        }
+    }
+
+    /// Next frame, None at the end of the stream
+    pub fn next_frame(&mut self) -> io::Result<Option<Frame>> {
+        self.buf.clear();
+        let mut len = 0;
+        loop {
+            match self.reader.fill_buf() {
+                Ok([BINARY_MARKER, ..]) => return self.binary_frame().map(Some),
+                Ok(_) => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        loop {
+            let (consumed, done) = {
+                let available = match self.reader.fill_buf() {
+                    Ok(available) => available,
+                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                if available.is_empty() {
+                    return Ok(if len == 0 {
+                        None
+                    } else {
+                        Some(self.frame(len))
+                    });
+                }
+                let end = available.iter().position(|&b| b == b'\n');
+                let chunk = &available[..end.unwrap_or(available.len())];
+                if len + chunk.len() <= self.max_len {
+                    self.buf.extend_from_slice(chunk);
+                }
+                len += chunk.len();
+                (chunk.len() + end.map_or(0, |_| 1), end.is_some())
+            };
+            self.reader.consume(consumed);
+            if done {
+                return Ok(Some(self.frame(len)));
+            }
+        }
+    }
+
+    fn binary_frame(&mut self) -> io::Result<Frame> {
+        let mut head = [0; 5];
+        self.reader.read_exact(&mut head)?;
+        let len = u32::from_be_bytes([head[1], head[2], head[3], head[4]]) as usize;
+        let frame = if len > self.max_len {
+            io::copy(&mut (&mut self.reader).take(len as u64), &mut io::sink())?;
+            Frame::TooLong(len)
+        } else {
+            let mut payload = vec![0; len];
+            self.reader.read_exact(&mut payload)?;
+            Frame::Binary(payload)
+        };
+        if let [b'\n', ..] = self.reader.fill_buf()? {
+            self.reader.consume(1);
+        }
+        Ok(frame)
+    }
+
+    fn frame(&mut self, len: usize) -> Frame {
+        if len > self.max_len {
+            return Frame::TooLong(len);
+        }
+        if self.buf.last() == Some(&b'\r') {
+            self.buf.pop();
+        }
+        match str::from_utf8(&self.buf) {
+            Ok(valid) => {
+                let mut line = self.pool.take();
+                line.push_str(valid);
+                Frame::Line(line)
+            }
+            Err(_) => Frame::Line(String::from_utf8_lossy(&self.buf).into_owned()),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for FrameReader<R> {
+    type Item = io::Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_frame().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_at_spaces() {
+        assert_eq!(split_text("hello world", 20), vec!["hello world"]);
+        assert_eq!(split_text("hello big world", 9), vec!["hello big", "world"]);
+        assert_eq!(split_text("ééééé", 2), vec!["éé", "éé", "é"]);
+    }
+
+    #[test]
+    fn long_lines_are_skipped() {
+        let input: &[u8] = b"short\r\nwaytoolong\n\xffok\nlast";
+        let frames: Vec<Frame> =
+            FrameReader::new(io::BufReader::with_capacity(3, input), 6, Pool::default())
+                .map(Result::unwrap)
+                .collect();
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Line("short".to_owned()),
+                Frame::TooLong(10),
+                Frame::Line("\u{fffd}ok".to_owned()),
+                Frame::Line("last".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn binary_frames_are_read_whole() {
+        let mut input = b"line\n".to_vec();
+        input.extend_from_slice(&[BINARY_MARKER, 0, 0, 0, 3, b'a', b'\n', b'b', b'\n']);
+        input.extend_from_slice(&[BINARY_MARKER, 0, 0, 0, 7]);
+        input.extend_from_slice(b"toolong\n");
+        input.extend_from_slice(&[BINARY_MARKER, 0, 0, 0, 0, b'\n']);
+        input.extend_from_slice(b"last");
+        let frames: Vec<Frame> = FrameReader::new(
+            io::BufReader::with_capacity(3, &input[..]),
+            6,
+            Pool::default(),
+        )
+        .map(Result::unwrap)
+        .collect();
+        assert_eq!(
+            frames,
+            vec![
+                Frame::Line("line".to_owned()),
+                Frame::Binary(b"a\nb".to_vec()),
+                Frame::TooLong(7),
+                Frame::Binary(Vec::new()),
+                Frame::Line("last".to_owned()),
+            ]
+        );
+        assert_eq!(binary_payload(&input[5..13]), Some(&b"a\nb"[..]));
+        assert_eq!(binary_payload(&input[5..12]), None);
+    }
+}
@@ -0,0 +1,826 @@
+//! Long term identity keys and the contact book of peer keys we trust
+use crate::AppId;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{self, EncryptionKey, PublicKey, SecretKey, Signature};
+use crate::messages::{Channel, Msg};
+
+/// The local signing key, persisted so the identity survives restarts
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Identity {
+    /// Key the others verify our messages with
+    pub public: PublicKey,
+    secret: SecretKey,
+}
+
+impl Identity {
+    /// A new random identity
+    pub fn generate() -> Self {
+        let secret = SecretKey::generate();
+        Identity {
+            public: secret.public_key(),
+            secret,
+        }
+    }
+
+    /// Loads the identity stored at `path`, creating a new one if there is none
+    pub fn load_or_generate(path: &Path, app_id: &AppId) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+                let identity = Identity::generate();
+                identity.save(path, app_id)?;
+                Ok(identity)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the key pair to `path` and its revocation certificate next to it,
+    /// the latter is meant to be copied somewhere safe
+    pub fn save(&self, path: &Path, app_id: &AppId) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_private(path, &format!("{}\n", json))?;
+        self.revocation_certificate(app_id)
+            .save(&path.with_extension("revocation"))
+    }
+
+    /// Certificate revoking this key, to be kept somewhere safe
+    pub fn revocation_certificate(&self, app_id: &AppId) -> RevocationCertificate {
+        RevocationCertificate {
+            app_id: app_id.to_owned(),
+            key: self.public,
+            signature: self.sign(&revocation_payload(app_id, &self.public)),
+        }
+    }
+
+    /// Signs `data` with the identity key
+    pub fn sign(&self, data: &[u8]) -> Signature {
+        self.secret.sign(data)
+    }
+
+    /// Key the others encrypt our private messages for, announced signed over
+    /// [`encryption_payload`]
+    pub fn encryption_key(&self) -> EncryptionKey {
+        self.secret.encryption_key()
+    }
+
+    /// `text` from `sender` to `recipient`, encrypted so that only the owner
+    /// of `key` and us can read it
+    pub fn encrypt(
+        &self,
+        sender: &str,
+        recipient: &str,
+        key: &EncryptionKey,
+        text: &str,
+    ) -> Option<String> {
+        self.secret
+            .seal_for(key, &private_payload(sender, recipient), text.as_bytes())
+    }
+
+    /// Text of a private message [`encrypt`](Self::encrypt)ed by the owner of
+    /// `key` for us, or by us for them
+    pub fn decrypt(
+        &self,
+        sender: &str,
+        recipient: &str,
+        key: &EncryptionKey,
+        sealed: &str,
+    ) -> Option<String> {
+        self.secret
+            .open_from(key, &private_payload(sender, recipient), sealed)
+            .and_then(|text| String::from_utf8(text).ok())
+    }
+
+    /// Chunk `chunk_index` of file `name`, from `sender` to `recipient`,
+    /// encrypted like a private message. The chunk is bound to its place in
+    /// the file, it cannot be passed off as another.
+    pub fn encrypt_chunk(
+        &self,
+        sender: &str,
+        recipient: &str,
+        key: &EncryptionKey,
+        name: &str,
+        chunk_index: u32,
+        data: &[u8],
+    ) -> Option<String> {
+        let payload = chunk_payload(sender, recipient, name, chunk_index);
+        self.secret.seal_for(key, &payload, data)
+    }
+
+    /// Bytes of a chunk [`encrypt_chunk`](Self::encrypt_chunk)ed by the owner
+    /// of `key` for us, or by us for them
+    pub fn decrypt_chunk(
+        &self,
+        sender: &str,
+        recipient: &str,
+        key: &EncryptionKey,
+        name: &str,
+        chunk_index: u32,
+        sealed: &str,
+    ) -> Option<Vec<u8>> {
+        let payload = chunk_payload(sender, recipient, name, chunk_index);
+        self.secret.open_from(key, &payload, sealed)
+    }
+
+    /// Past `messages` from `sender` for `recipient`, a linked device of the
+    /// same identity, encrypted like a private message
+    pub fn encrypt_history(
+        &self,
+        sender: &str,
+        recipient: &str,
+        key: &EncryptionKey,
+        messages: &[Msg],
+    ) -> Option<String> {
+        let json = serde_json::to_vec(messages).ok()?;
+        self.secret
+            .seal_for(key, &history_payload(sender, recipient), &json)
+    }
+
+    /// Messages of a history [`encrypt_history`](Self::encrypt_history)ed by
+    /// the owner of `key` for us
+    pub fn decrypt_history(
+        &self,
+        sender: &str,
+        recipient: &str,
+        key: &EncryptionKey,
+        sealed: &str,
+    ) -> Option<Vec<Msg>> {
+        self.secret
+            .open_from(key, &history_payload(sender, recipient), sealed)
+            .and_then(|json| serde_json::from_slice(&json).ok())
+    }
+
+    /// Replaces the key pair by a fresh one and returns the new public key
+    /// along with its endorsement by the previous key
+    pub fn rotate(&mut self, app_id: &AppId) -> (PublicKey, Signature) {
+        let next = Identity::generate();
+        let signature = self.sign(&rotation_payload(app_id, &self.public, &next.public));
+        *self = next;
+        (self.public, signature)
+    }
+}
+
+/// Writes `text` to `path`, readable by its owner only whatever the umask,
+/// and a file there before with it
+fn write_private(path: &Path, text: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(text.as_bytes())
+}
+
+/// Bytes signed by the old key to endorse `new_key` as the successor of `old_key`
+pub fn rotation_payload(app_id: &AppId, old_key: &PublicKey, new_key: &PublicKey) -> Vec<u8> {
+    [
+        b"netchat key rotation".as_ref(),
+        &old_key.0,
+        &new_key.0,
+        app_id.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Bytes signed by `app_id` to announce its encryption key
+pub fn encryption_payload(app_id: &AppId, key: &EncryptionKey) -> Vec<u8> {
+    [
+        b"netchat encryption key".as_ref(),
+        &key.0,
+        app_id.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Authenticated along with an encrypted private message, so that it cannot be
+/// passed off as sent by or to someone else
+fn private_payload(sender: &str, recipient: &str) -> Vec<u8> {
+    [sender.as_bytes(), b"\0", recipient.as_bytes()].concat()
+}
+
+/// Authenticated along with an encrypted file chunk: who sends it to whom, and
+/// where it goes in which file
+fn chunk_payload(sender: &str, recipient: &str, name: &str, chunk_index: u32) -> Vec<u8> {
+    [
+        b"netchat file chunk".as_ref(),
+        &chunk_index.to_be_bytes(),
+        &private_payload(sender, recipient),
+        b"\0",
+        name.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Authenticated along with an encrypted history, so it cannot be passed off
+/// as a private message
+fn history_payload(sender: &str, recipient: &str) -> Vec<u8> {
+    [
+        b"netchat history".as_ref(),
+        &private_payload(sender, recipient),
+    ]
+    .concat()
+}
+
+/// Bytes signed by `key` to declare it compromised
+pub fn revocation_payload(app_id: &AppId, key: &PublicKey) -> Vec<u8> {
+    [
+        b"netchat key revocation".as_ref(),
+        &key.0,
+        app_id.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Bytes signed by `app_id` to announce `text`
+pub fn announcement_payload(app_id: &AppId, text: &str) -> Vec<u8> {
+    [
+        b"netchat announcement".as_ref(),
+        app_id.as_bytes(),
+        b"\0",
+        text.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Bytes signed by the sender of `msg`: its id, time, sender, header and
+/// content type, not the clock relays change. The header goes through a json
+/// value, whose maps are sorted, so the bytes do not depend on the order of hash
+/// maps. The content type is only appended when there is one, messages without
+/// keep the bytes older apps check.
+pub fn message_payload(msg: &Msg) -> Vec<u8> {
+    let header = serde_json::to_value(&msg.header)
+        .and_then(|header| serde_json::to_vec(&header))
+        .unwrap_or_default();
+    let mut payload = [
+        b"netchat message".as_ref(),
+        &msg.id.to_be_bytes(),
+        &msg.sent_at.unwrap_or_default().to_be_bytes(),
+        msg.sender_id.as_bytes(),
+        b"\0",
+        &header,
+    ]
+    .concat();
+    if let Some(content_type) = &msg.content_type {
+        payload.extend_from_slice(b"\0content type\0");
+        payload.extend_from_slice(content_type.as_bytes());
+    }
+    payload
+}
+
+/// Bytes signed by `app_id` to set the slow mode of `channel` to `seconds`
+pub fn slow_mode_payload(app_id: &AppId, channel: &Channel, seconds: u64) -> Vec<u8> {
+    [
+        b"netchat slow mode".as_ref(),
+        &seconds.to_be_bytes(),
+        app_id.as_bytes(),
+        b"\0",
+        channel.as_str().as_bytes(),
+    ]
+    .concat()
+}
+
+/// Self signed statement that a key must no longer be trusted. It can be
+/// broadcast by anyone holding it, which covers losing the device with the key.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct RevocationCertificate {
+    /// App whose key is revoked
+    pub app_id: AppId,
+    /// The revoked key
+    pub key: PublicKey,
+    /// Made with the revoked key itself
+    pub signature: Signature,
+}
+
+impl RevocationCertificate {
+    /// Reads a certificate saved with [`save`](Self::save)
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Writes the certificate to `path`, as json
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        write_private(path, &format!("{}\n", json))
+    }
+
+    /// Whether the certificate was signed by the key it revokes
+    pub fn is_valid(&self) -> bool {
+        crypto::verify(
+            &self.key,
+            &revocation_payload(&self.app_id, &self.key),
+            &self.signature,
+        )
+    }
+}
+
+/// Bytes signed by the owner's key to vouch for one of its devices
+pub fn link_payload(owner: &AppId, device: &AppId, device_key: &PublicKey) -> Vec<u8> {
+    [
+        b"netchat device link".as_ref(),
+        &device_key.0,
+        owner.as_bytes(),
+        b"\0",
+        device.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Cross signature binding the key of the `device` app to the `owner` identity
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DeviceLink {
+    /// Identity the device belongs to
+    pub owner: AppId,
+    /// The linked app
+    pub device: AppId,
+    /// Key of the linked app
+    pub device_key: PublicKey,
+    /// Made by the owner's key over [`link_payload`]
+    pub signature: Signature,
+}
+
+impl DeviceLink {
+    /// Link of `device` signed by its `owner`
+    pub fn new(owner: &Identity, owner_id: &AppId, device: &AppId, device_key: PublicKey) -> Self {
+        DeviceLink {
+            owner: owner_id.to_owned(),
+            device: device.to_owned(),
+            device_key,
+            signature: owner.sign(&link_payload(owner_id, device, &device_key)),
+        }
+    }
+}
+
+/// What we know of a peer
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Contact {
+    /// Key currently trusted
+    pub key: PublicKey,
+    /// Keys we trusted before following rotations, oldest first
+    #[serde(default)]
+    pub previous_keys: Vec<PublicKey>,
+    /// Set once a valid revocation certificate for one of the keys was received
+    #[serde(default)]
+    pub revoked: bool,
+    /// Identity this app is a linked device of
+    #[serde(default)]
+    pub owner: Option<AppId>,
+    /// Labels given by the user, which the peer panel groups by
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+}
+
+impl Contact {
+    fn new(key: PublicKey) -> Self {
+        Contact {
+            key,
+            previous_keys: Vec::new(),
+            revoked: false,
+            owner: None,
+            tags: BTreeSet::new(),
+        }
+    }
+}
+
+/// Outcome of learning a key for a contact
+#[derive(Debug, PartialEq)]
+pub enum KeyStatus {
+    /// First key seen for this contact, trusted on first use
+    New,
+    /// Matches the key we already trust
+    Known,
+    /// Differs from the key we trust, which is kept
+    Mismatch,
+    /// The previous key endorsed the new one
+    Rotated,
+    /// The rotation or revocation was not signed by the key we trust
+    Rejected,
+    /// The identity was compromised, nothing it says can be trusted anymore
+    Revoked,
+    /// A revocation of a key not trusted yet, kept aside and applied if that
+    /// key ever is
+    SetAside,
+}
+
+/// Revocations kept aside at most, those past it are dropped
+const MAX_SET_ASIDE: usize = 1024;
+
+/// Known peer keys, saved to disk after each change when a path is given
+#[derive(Default)]
+pub struct Contacts {
+    path: Option<PathBuf>,
+    entries: HashMap<AppId, Contact>,
+    /// Keys revoked before we trusted them, by app, not saved
+    set_aside: HashMap<AppId, Vec<PublicKey>>,
+}
+
+impl Contacts {
+    /// Contacts saved at `path`, none if it cannot be read
+    pub fn load(path: PathBuf) -> Self {
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Contacts {
+            path: Some(path),
+            entries,
+            set_aside: HashMap::new(),
+        }
+    }
+
+    /// Whether `app_id`, or the identity it is a device of, was revoked
+    pub fn is_revoked(&self, app_id: &str) -> bool {
+        let revoked = |id: &str| self.entries.get(id).is_some_and(|c| c.revoked);
+        revoked(app_id) || revoked(self.identity_of(app_id))
+    }
+
+    /// Whether `signature` of `data` was made with the key we trust for
+    /// `app_id`, never for an unknown or revoked one
+    pub fn is_signed_by(&self, app_id: &str, data: &[u8], signature: &Signature) -> bool {
+        !self.is_revoked(app_id)
+            && self
+                .entries
+                .get(app_id)
+                .is_some_and(|c| crypto::verify(&c.key, data, signature))
+    }
+
+    /// Key trusted for `app_id`, None for an unknown or revoked one
+    pub fn trusted_key(&self, app_id: &str) -> Option<&PublicKey> {
+        match self.entries.get(app_id) {
+            Some(contact) if !self.is_revoked(app_id) => Some(&contact.key),
+            _ => None,
+        }
+    }
+
+    /// Apps whose key was revoked
+    pub fn revoked(&self) -> impl Iterator<Item = &AppId> {
+        self.entries
+            .iter()
+            .filter(|(_, c)| c.revoked)
+            .map(|(id, _)| id)
+    }
+
+    /// Tags of each contact which has some
+    pub fn tags(&self) -> impl Iterator<Item = (&AppId, &BTreeSet<String>)> {
+        self.entries
+            .iter()
+            .filter(|(_, c)| !c.tags.is_empty())
+            .map(|(app_id, c)| (app_id, &c.tags))
+    }
+
+    /// Adds `tag` to `app_id`, or removes it. Returns false if its key is not
+    /// known, tags are only kept for contacts.
+    pub fn set_tag(&mut self, app_id: &str, tag: &str, on: bool) -> bool {
+        let tags = match self.entries.get_mut(app_id) {
+            Some(contact) => &mut contact.tags,
+            None => return false,
+        };
+        let changed = match on {
+            true => tags.insert(tag.to_owned()),
+            false => tags.remove(tag),
+        };
+        if changed {
+            self.save();
+        }
+        true
+    }
+
+    /// Logical identity behind `app_id`: its owner if it is a linked device
+    pub fn identity_of<'a>(&'a self, app_id: &'a str) -> &'a str {
+        self.entries
+            .get(app_id)
+            .and_then(|c| c.owner.as_ref())
+            .map_or(app_id, |owner| owner.as_str())
+    }
+
+    /// Whether some apps are known to be devices of `identity`
+    pub fn has_devices(&self, identity: &str) -> bool {
+        self.devices(identity).next().is_some()
+    }
+
+    /// Apps known to be devices of `identity`, besides the owner itself
+    pub fn devices<'a>(&'a self, identity: &'a str) -> impl Iterator<Item = &'a AppId> {
+        self.entries
+            .iter()
+            .filter(move |(_, c)| c.owner.as_deref() == Some(identity))
+            .map(|(app_id, _)| app_id)
+    }
+
+    /// Records a device link if it was signed by the key we trust for its owner
+    pub fn link(&mut self, link: &DeviceLink) -> KeyStatus {
+        let owner_key = match self.entries.get(&link.owner) {
+            Some(owner) if owner.revoked => return KeyStatus::Revoked,
+            Some(owner) => owner.key,
+            None => return KeyStatus::Rejected,
+        };
+        let payload = link_payload(&link.owner, &link.device, &link.device_key);
+        if !crypto::verify(&owner_key, &payload, &link.signature) {
+            return KeyStatus::Rejected;
+        }
+        if !self.entries.contains_key(&link.device) {
+            let contact = self.first_contact(&link.device, link.device_key);
+            self.entries.insert(link.device.to_owned(), contact);
+        }
+        let device = self.entries.get_mut(&link.device).expect("just inserted");
+        if device.revoked {
+            return KeyStatus::Revoked;
+        }
+        if device.key != link.device_key {
+            return KeyStatus::Mismatch;
+        }
+        let status = if device.owner.as_ref() == Some(&link.owner) {
+            KeyStatus::Known
+        } else {
+            device.owner = Some(link.owner.to_owned());
+            KeyStatus::New
+        };
+        self.save();
+        status
+    }
+
+    /// Records a key announced by `app_id`
+    pub fn observe(&mut self, app_id: &AppId, key: PublicKey) -> KeyStatus {
+        match self.entries.get(app_id) {
+            Some(contact) if contact.revoked => KeyStatus::Revoked,
+            Some(contact) if contact.key == key => KeyStatus::Known,
+            Some(_) => KeyStatus::Mismatch,
+            None => {
+                let contact = self.first_contact(app_id, key);
+                let revoked = contact.revoked;
+                self.entries.insert(app_id.to_owned(), contact);
+                self.save();
+                match revoked {
+                    true => KeyStatus::Revoked,
+                    false => KeyStatus::New,
+                }
+            }
+        }
+    }
+
+    /// Follows a key rotation if it was signed by the key we currently trust
+    pub fn rotate(
+        &mut self,
+        app_id: &AppId,
+        new_key: PublicKey,
+        signature: &Signature,
+    ) -> KeyStatus {
+        let status = match self.entries.get_mut(app_id) {
+            // A stolen key must not be able to endorse a key of the thief's choosing
+            Some(contact) if contact.revoked => return KeyStatus::Revoked,
+            Some(contact) if contact.key == new_key => return KeyStatus::Known,
+            Some(contact) => {
+                let payload = rotation_payload(app_id, &contact.key, &new_key);
+                if !crypto::verify(&contact.key, &payload, signature) {
+                    return KeyStatus::Rejected;
+                }
+                let old_key = std::mem::replace(&mut contact.key, new_key);
+                contact.previous_keys.push(old_key);
+                KeyStatus::Rotated
+            }
+            None => {
+                // Nothing to check the endorsement against, we treat it as a first contact
+                let contact = self.first_contact(app_id, new_key);
+                let revoked = contact.revoked;
+                self.entries.insert(app_id.to_owned(), contact);
+                match revoked {
+                    true => KeyStatus::Revoked,
+                    false => KeyStatus::New,
+                }
+            }
+        };
+        self.save();
+        status
+    }
+
+    /// Marks the identity as compromised if `certificate` is valid. Certificates
+    /// for keys that were since rotated also apply, the thief may have rotated first.
+    pub fn revoke(&mut self, certificate: &RevocationCertificate) -> KeyStatus {
+        if !certificate.is_valid() {
+            return KeyStatus::Rejected;
+        }
+        let app_id = &certificate.app_id;
+        match self.entries.get_mut(app_id) {
+            Some(contact) if contact.revoked => return KeyStatus::Revoked,
+            Some(contact)
+                if contact.key == certificate.key
+                    || contact.previous_keys.contains(&certificate.key) =>
+            {
+                contact.revoked = true;
+            }
+            // Revoking a key we never trusted for this identity proves nothing
+            Some(_) => return KeyStatus::Rejected,
+            // Anyone can make a key and revoke it in the name of an app we
+            // have not met, only the key it comes with is told apart
+            None => {
+                let total: usize = self.set_aside.values().map(Vec::len).sum();
+                let keys = self.set_aside.entry(app_id.to_owned()).or_default();
+                if !keys.contains(&certificate.key) && total < MAX_SET_ASIDE {
+                    keys.push(certificate.key);
+                }
+                return KeyStatus::SetAside;
+            }
+        }
+        self.save();
+        KeyStatus::Revoked
+    }
+
+    /// Contact of `app_id` on its first key, revoked if a revocation of that
+    /// very key was kept aside
+    fn first_contact(&mut self, app_id: &str, key: PublicKey) -> Contact {
+        let mut contact = Contact::new(key);
+        contact.revoked = self
+            .set_aside
+            .remove(app_id)
+            .is_some_and(|keys| keys.contains(&key));
+        contact
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            let saved = serde_json::to_string_pretty(&self.entries)
+                .map_err(io::Error::from)
+                .and_then(|json| fs::write(path, format!("{}\n", json)));
+            if let Err(e) = saved {
+                log::error!("Could not save contacts to {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_types_are_signed() {
+        use crate::messages::Header;
+        use crate::Clock;
+
+        let text = Header::Public(Channel::default(), "see [this](https://x)".to_owned());
+        let mut msg = Msg::new(1, "alice".to_owned(), text, Clock::new("alice".to_owned()));
+        let plain = message_payload(&msg);
+        assert!(
+            !String::from_utf8_lossy(&plain).contains("content type"),
+            "unchanged without a content type"
+        );
+
+        let identity = Identity::generate();
+        let signature = identity.sign(&plain);
+        msg.content_type = Some("text/markdown".to_owned());
+        assert!(
+            !crypto::verify(&identity.public, &message_payload(&msg), &signature),
+            "a relay cannot label a signed message"
+        );
+        let signature = identity.sign(&message_payload(&msg));
+        assert!(crypto::verify(
+            &identity.public,
+            &message_payload(&msg),
+            &signature
+        ));
+    }
+
+    #[test]
+    fn rotation_continues_trust() {
+        let app_id = "alice".to_owned();
+        let mut identity = Identity::generate();
+        let mut contacts = Contacts::default();
+        assert_eq!(contacts.observe(&app_id, identity.public), KeyStatus::New);
+
+        let (new_key, signature) = identity.rotate(&app_id);
+        assert_eq!(
+            contacts.rotate(&app_id, new_key, &signature),
+            KeyStatus::Rotated
+        );
+        assert_eq!(contacts.observe(&app_id, identity.public), KeyStatus::Known);
+
+        // An endorsement by someone else's key is refused
+        let (forged_key, forged_signature) = Identity::generate().rotate(&app_id);
+        assert_eq!(
+            contacts.rotate(&app_id, forged_key, &forged_signature),
+            KeyStatus::Rejected
+        );
+        assert_eq!(contacts.observe(&app_id, new_key), KeyStatus::Known);
+    }
+
+    #[test]
+    fn revocation_of_a_rotated_key() {
+        let app_id = "alice".to_owned();
+        let mut identity = Identity::generate();
+        let certificate = identity.revocation_certificate(&app_id);
+        let mut contacts = Contacts::default();
+        contacts.observe(&app_id, identity.public);
+
+        // The thief rotates the stolen key before the owner broadcasts the certificate
+        let (new_key, signature) = identity.rotate(&app_id);
+        contacts.rotate(&app_id, new_key, &signature);
+
+        let mut forged = certificate.clone();
+        forged.app_id = "bob".to_owned();
+        assert_eq!(contacts.revoke(&forged), KeyStatus::Rejected);
+
+        assert_eq!(contacts.revoke(&certificate), KeyStatus::Revoked);
+        assert!(contacts.is_revoked(&app_id));
+        let (next_key, signature) = identity.rotate(&app_id);
+        assert_eq!(
+            contacts.rotate(&app_id, next_key, &signature),
+            KeyStatus::Revoked
+        );
+    }
+
+    #[test]
+    fn revocations_of_apps_not_met_are_set_aside() {
+        let bob = "bob".to_owned();
+        let identity = Identity::generate();
+        let mallory = Identity::generate();
+        let mut contacts = Contacts::default();
+
+        // Mallory revokes a key of their own in the name of bob
+        let forged = mallory.revocation_certificate(&bob);
+        assert_eq!(contacts.revoke(&forged), KeyStatus::SetAside);
+        assert!(!contacts.is_revoked(&bob));
+        assert_eq!(contacts.observe(&bob, identity.public), KeyStatus::New);
+        assert!(!contacts.is_revoked(&bob));
+
+        // The certificate of the key carol then announces, sent before she was met
+        let carol = "carol".to_owned();
+        let certificate = identity.revocation_certificate(&carol);
+        assert_eq!(contacts.revoke(&certificate), KeyStatus::SetAside);
+        assert_eq!(
+            contacts.observe(&carol, identity.public),
+            KeyStatus::Revoked
+        );
+        assert!(contacts.is_revoked(&carol));
+    }
+
+    #[test]
+    fn device_links() {
+        let (alice, phone) = ("alice".to_owned(), "alice-phone".to_owned());
+        let identity = Identity::generate();
+        let phone_key = Identity::generate().public;
+        let mut contacts = Contacts::default();
+
+        let link = DeviceLink::new(&identity, &alice, &phone, phone_key);
+        // The owner's key is needed to check the cross signature
+        assert_eq!(contacts.link(&link), KeyStatus::Rejected);
+
+        contacts.observe(&alice, identity.public);
+        let mut forged = link.clone();
+        forged.device = "mallory".to_owned();
+        assert_eq!(contacts.link(&forged), KeyStatus::Rejected);
+
+        assert_eq!(contacts.link(&link), KeyStatus::New);
+        assert_eq!(contacts.identity_of(&phone), "alice");
+        assert_eq!(contacts.identity_of("bob"), "bob");
+
+        contacts.revoke(&identity.revocation_certificate(&alice));
+        assert!(contacts.is_revoked(&phone));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keys_are_kept_to_their_owner() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("netchat-test-{}-me.key", std::process::id()));
+        // Left shared by an older version, or another umask
+        fs::write(&path, "").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        Identity::generate().save(&path, &"me".to_owned()).unwrap();
+        let revocation = path.with_extension("revocation");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        let modes = (mode(&path), mode(&revocation));
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&revocation).unwrap();
+        assert_eq!(modes, (0o600, 0o600));
+    }
+
+    #[test]
+    fn tags_are_saved() {
+        let path =
+            std::env::temp_dir().join(format!("netchat-test-{}-tags.json", std::process::id()));
+        let app_id = "alice".to_owned();
+        let mut contacts = Contacts::load(path.clone());
+        assert!(!contacts.set_tag(&app_id, "ops", true), "unknown key");
+        contacts.observe(&app_id, Identity::generate().public);
+        assert!(contacts.set_tag(&app_id, "ops", true));
+        assert!(contacts.set_tag(&app_id, "lab", true));
+        assert!(contacts.set_tag(&app_id, "lab", false));
+
+        let contacts = Contacts::load(path.clone());
+        fs::remove_file(&path).unwrap();
+        let tags: Vec<_> = contacts.tags().collect();
+        assert_eq!(
+            tags,
+            [(&app_id, &std::iter::once("ops".to_owned()).collect())]
+        );
+    }
+}
@@ -0,0 +1,56 @@
+#![warn(missing_docs)]
+//! The netchat protocol, without the terminal UI nor the pipes
+//!
+//! Apps exchange [`Msg`]s, one json line each, stamped with a vector
+//! [`Clock`]. Every app relays what it receives to the next one, [`dedup::Seen`]
+//! makes sure each message is handled and relayed only once.
+//!
+//! - [`messages`]: the messages, their headers and the decoding of untrusted lines
+//! - [`framing`]: splitting a byte stream into lines, and the size limits
+//! - [`cbor`]: the binary encoding apps switch to when they all read it
+//! - [`lz`]: the compression of large payloads
+//! - [`clock`]: vector clocks
+//! - [`crypto`] and [`identity`]: the keys apps sign their messages with
+//! - [`node`]: an app of the mesh for programs bringing their own input and output
+//! - [`bots`]: the `!command` convention, and sharing a node between bots
+//! - [`delivery`]: the order chat messages are handed over in
+//! - [`store`]: where chat messages are kept across sessions
+//!
+//! Messages are handed over in the order they arrive by default, the clocks are
+//! merged but nothing is held back waiting for a causally earlier message, see
+//! [`delivery`] for the other orders.
+//!
+//! ```
+//! use netchat_core::dedup::Seen;
+//! use netchat_core::messages::{self, Channel, Header};
+//! use netchat_core::{Clock, Msg};
+//!
+//! let msg = Msg::new(1, "alice".to_owned(), Header::Public(Channel::default(), "hi".to_owned()), Clock::new("alice".to_owned()));
+//! let mut line = Vec::new();
+//! msg.encode_into(&mut line).unwrap();
+//!
+//! // On the other side, untrusted input is decoded with `parse`
+//! let received = messages::parse(&line).unwrap();
+//! let mut seen = Seen::default();
+//! assert!(seen.insert(received.id));
+//! assert!(!seen.insert(msg.id), "relayed only once");
+//! ```
+
+pub mod bots;
+pub mod cbor;
+pub mod clock;
+pub mod crypto;
+pub mod dedup;
+pub mod delivery;
+pub mod framing;
+pub mod identity;
+pub mod lz;
+pub mod messages;
+pub mod node;
+pub mod store;
+
+pub use clock::Clock;
+pub use messages::Msg;
+
+/// Name of an app, chosen by the user or random
+pub type AppId = String;
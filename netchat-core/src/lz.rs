@@ -0,0 +1,168 @@
+//! A small LZ77 compressor for large payloads, long pastes and file chunks
+//! mostly: the length of the input in 4 big endian bytes, then groups of a
+//! flag byte and up to 8 items, each a literal byte for a clear bit, or a
+//! copy of earlier output for a set bit: its distance back in 2 big endian
+//! bytes and its length minus [`MIN_MATCH`] in one.
+
+use std::error::Error;
+use std::fmt;
+
+/// Shortest copy, shorter repeats are cheaper as literals
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = MIN_MATCH + u8::MAX as usize;
+const MAX_DISTANCE: usize = u16::MAX as usize;
+
+/// Slots of the table of the last positions of 3-byte prefixes
+const HASH_BITS: u32 = 12;
+
+/// Why some bytes do not decompress
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// Ends in the middle of an item, or before the length given
+    Truncated,
+    /// Says it decompresses to more than the limit, with its length
+    TooLong(usize),
+    /// A copy reaching before the start, or past the length given
+    InvalidCopy,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Truncated => write!(f, "truncated compressed payload"),
+            DecodeError::TooLong(len) => write!(f, "decompresses to {} bytes, too many", len),
+            DecodeError::InvalidCopy => write!(f, "invalid copy in a compressed payload"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+fn hash(bytes: &[u8]) -> usize {
+    let prefix = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+    (prefix.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+/// Appends `input` compressed to `out`
+pub fn compress(input: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(input.len() as u32).to_be_bytes());
+    // Last position + 1 of each prefix, 0 for none
+    let mut last = vec![0usize; 1 << HASH_BITS];
+    let (mut flags_at, mut items) = (out.len(), 8);
+    let mut i = 0;
+    while i < input.len() {
+        if items == 8 {
+            flags_at = out.len();
+            out.push(0);
+            items = 0;
+        }
+        let mut len = 0;
+        let mut distance = 0;
+        if i + MIN_MATCH <= input.len() {
+            let slot = hash(&input[i..]);
+            if let Some(start) = last[slot].checked_sub(1) {
+                distance = i - start;
+                let longest = (input.len() - i).min(MAX_MATCH);
+                len = (0..longest)
+                    .take_while(|k| input[start + k] == input[i + k])
+                    .count();
+            }
+            last[slot] = i + 1;
+        }
+        if len >= MIN_MATCH && distance <= MAX_DISTANCE {
+            out[flags_at] |= 1 << items;
+            out.extend_from_slice(&(distance as u16).to_be_bytes());
+            out.push((len - MIN_MATCH) as u8);
+            for j in i + 1..(i + len).min(input.len() + 1 - MIN_MATCH) {
+                last[hash(&input[j..])] = j + 1;
+            }
+            i += len;
+        } else {
+            out.push(input[i]);
+            i += 1;
+        }
+        items += 1;
+    }
+}
+
+/// Decompresses `input`, refusing to grow past `max_len` bytes
+pub fn decompress(input: &[u8], max_len: usize) -> Result<Vec<u8>, DecodeError> {
+    if input.len() < 4 {
+        return Err(DecodeError::Truncated);
+    }
+    let len = u32::from_be_bytes([input[0], input[1], input[2], input[3]]) as usize;
+    if len > max_len {
+        return Err(DecodeError::TooLong(len));
+    }
+    // The length comes from the sender, it only grows past what is likely
+    let mut out = Vec::with_capacity(len.min(input.len().saturating_mul(4)));
+    let mut bytes = input[4..].iter().copied();
+    let mut next = || bytes.next().ok_or(DecodeError::Truncated);
+    while out.len() < len {
+        let flags = next()?;
+        for item in 0..8 {
+            if out.len() == len {
+                break;
+            }
+            if flags & 1 << item == 0 {
+                out.push(next()?);
+                continue;
+            }
+            let distance = usize::from(u16::from_be_bytes([next()?, next()?]));
+            let copied = usize::from(next()?) + MIN_MATCH;
+            if distance == 0 || distance > out.len() || out.len() + copied > len {
+                return Err(DecodeError::InvalidCopy);
+            }
+            // Byte by byte, a copy may overlap what it writes
+            let start = out.len() - distance;
+            for k in 0..copied {
+                out.push(out[start + k]);
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> usize {
+        let mut packed = Vec::new();
+        compress(input, &mut packed);
+        assert_eq!(decompress(&packed, input.len()).unwrap(), input);
+        packed.len()
+    }
+
+    #[test]
+    fn what_is_compressed_decompresses_back() {
+        assert_eq!(round_trip(b""), 4);
+        round_trip(b"ab");
+        round_trip(b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+        let paste = "fn main() {\n    println!(\"hello\");\n}\n".repeat(200);
+        assert!(round_trip(paste.as_bytes()) < paste.len() / 10);
+        let noise: Vec<u8> = (0..70_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        round_trip(&noise);
+        let far: Vec<u8> = [&noise[..], &noise[..100]].concat();
+        round_trip(&far);
+    }
+
+    #[test]
+    fn bad_input_is_refused() {
+        let mut packed = Vec::new();
+        compress("abcabcabcabc".as_bytes(), &mut packed);
+        assert_eq!(decompress(&packed, 11), Err(DecodeError::TooLong(12)));
+        assert_eq!(
+            decompress(&packed[..packed.len() - 1], 12),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(decompress(&[0, 0], 12), Err(DecodeError::Truncated));
+        let copy_before_start = [0, 0, 0, 5, 0b1, 0, 1, 2];
+        assert_eq!(
+            decompress(&copy_before_start, 12),
+            Err(DecodeError::InvalidCopy)
+        );
+    }
+}
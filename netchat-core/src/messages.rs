@@ -0,0 +1,998 @@
+//! Messages exchanged by the apps and their decoding
+
+use crate::AppId;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cbor;
+use crate::crypto::{EncryptionKey, PublicKey, Signature};
+use crate::framing::BINARY_MARKER;
+use crate::identity::{DeviceLink, RevocationCertificate};
+use crate::lz;
+use crate::Clock;
+
+use Header::*;
+
+/// Random id of a message, the same for every copy relayed
+pub type MsgId = u64;
+/// Number of messages an app has sent, the unit of vector clocks
+pub type Date = u64;
+
+/// Version of the wire format, bumped on incompatible changes
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Deepest nesting of arrays and objects accepted in a message
+pub const MAX_DEPTH: usize = 64;
+
+/// Optional protocol features this build understands
+pub const FEATURES: &[&str] = &[
+    "identity-keys",
+    "revocation",
+    "device-links",
+    "history-sync",
+    "heartbeats",
+    "ordered-delivery",
+    "announcements",
+    "services",
+    "encryption",
+    "acks",
+    "nicks",
+    "files",
+    "slow-mode",
+    "typing",
+    "channels",
+    "cbor",
+    "receipts",
+    "anti-entropy",
+    "live-lines",
+    "compression",
+    "edits",
+    "content-types",
+    "encrypted-files",
+    "file-offers",
+    "encrypted-history",
+];
+
+/// Content types of chat messages the netchat app renders, see
+/// [`Msg::content_type`]
+pub const CONTENT_TYPES: &[&str] = &[
+    "text/plain",
+    "text/markdown",
+    "application/json",
+    "image/png-ref",
+];
+
+/// Longest nickname, in characters
+pub const MAX_NICK_LEN: usize = 32;
+
+/// Longest channel name, in characters, with its `#`
+pub const MAX_CHANNEL_LEN: usize = 32;
+
+/// Room public messages are sent to, `#general` by default. Apps relay the
+/// messages of every channel, and only show those of the channels they joined.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(transparent)]
+pub struct Channel(String);
+
+impl Channel {
+    /// `name` if it is a `#` followed by one word of printable characters,
+    /// [`MAX_CHANNEL_LEN`] at most
+    pub fn parse(name: &str) -> Option<Channel> {
+        let valid = name.len() > 1
+            && name.starts_with('#')
+            && name.chars().count() <= MAX_CHANNEL_LEN
+            && !name.chars().any(|c| c.is_whitespace() || c.is_control());
+        Some(Channel(name.to_owned())).filter(|_| valid)
+    }
+
+    /// Name, with its `#`
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether it is `#general`, where everyone is
+    pub fn is_general(&self) -> bool {
+        self.0 == "#general"
+    }
+}
+
+impl Default for Channel {
+    fn default() -> Channel {
+        Channel("#general".to_owned())
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Header(Content)
+/// Defines message type
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Header {
+    /// Chat message for one app, or all the devices of an identity
+    Private(AppId, String),
+    /// Chat message for everyone in a channel
+    Public(Channel, String),
+    /// Chat message for everyone, shown above the others, signed over
+    /// [`announcement_payload`](crate::identity::announcement_payload)
+    Announcement(String, Signature),
+    /// The sender joined
+    Connection,
+    /// The sender left
+    Disconnection,
+    /// Asks everyone for the messages they saved, with the requester
+    SnapshotRequest(AppId),
+    /// Saved messages of the sender, for the requester
+    SnapshotResponse(AppId, Vec<Msg>),
+    /// Identity key of the sender
+    KeyAnnouncement(PublicKey),
+    /// Key private messages to the sender are encrypted for, signed over
+    /// [`encryption_payload`](crate::identity::encryption_payload)
+    EncryptionAnnouncement(EncryptionKey, Signature),
+    /// Private message only the recipient can read, see
+    /// [`Identity::encrypt`](crate::identity::Identity::encrypt)
+    Encrypted(AppId, String),
+    /// New key, endorsed by the previous one
+    KeyRotation(PublicKey, Signature),
+    /// A key is not to be trusted anymore
+    Revocation(RevocationCertificate),
+    /// Owner identity to link to, key of the requesting device
+    LinkRequest(AppId, PublicKey),
+    /// An owner vouching for one of its devices
+    DeviceLink(DeviceLink),
+    /// Past messages, for the linked device given
+    HistorySync(AppId, Vec<Msg>),
+    /// A `HistorySync` only the linked device given can read, its private
+    /// messages decrypted, see
+    /// [`Identity::encrypt_history`](crate::identity::Identity::encrypt_history)
+    EncryptedHistory(AppId, String),
+    /// What the sender runs
+    Hello(VersionInfo),
+    /// Apps vouching to be alive, several once merged by a relay
+    Heartbeat(Vec<AppId>),
+    /// What the sender offers the others, one short description each
+    Services(Vec<String>),
+    /// The sender received this chat message
+    Ack(MsgId),
+    /// The sender showed this private message to its user
+    Receipt(MsgId),
+    /// New text of a public message of the sender. Only public messages are
+    /// edited, the text of a private one would go in clear.
+    Edit(MsgId, String),
+    /// The sender takes back one of its public messages
+    Delete(MsgId),
+    /// Rank of the last chat message of each app the sender has without a
+    /// gap, for its neighbours to send back what it lacks. Never relayed.
+    SyncSummary(Clock),
+    /// Chat messages the app given lacks, as they were sent. Never relayed,
+    /// the messages are.
+    SyncMissing(AppId, Vec<Msg>),
+    /// Name the sender wants to be shown as, see [`is_valid_nick`]
+    Nick(String),
+    /// Seconds each sender waits between two public messages in a channel, 0
+    /// for none, signed over [`slow_mode_payload`](crate::identity::slow_mode_payload)
+    SlowMode(Channel, u64, Signature),
+    /// The sender joined a channel, and shows its public messages
+    ChannelJoin(Channel),
+    /// The sender left a channel
+    ChannelLeave(Channel),
+    /// The sender is composing a message. Forgotten once relayed: it is
+    /// neither saved nor counted in the clocks.
+    Typing,
+    /// The line the sender is typing in its live line mode, as a `Private` or
+    /// an `Encrypted` header for the app it talks with. Like `Typing`, it is
+    /// neither saved nor counted in the clocks.
+    LiveLine(Box<Header>),
+    /// Chunk `chunk_index` of the `total_chunks` of a file for `to`, hex encoded
+    File {
+        /// Recipient app or identity
+        to: AppId,
+        /// File name, without its directory
+        name: String,
+        /// From 0
+        chunk_index: u32,
+        /// Chunks of the file, an empty one has one empty chunk
+        total_chunks: u32,
+        /// Bytes of the chunk, hex encoded
+        data: String,
+    },
+    /// A `File` chunk only the recipient can read, see
+    /// [`Identity::encrypt_chunk`](crate::identity::Identity::encrypt_chunk)
+    EncryptedFile {
+        /// Recipient app
+        to: AppId,
+        /// File name, without its directory
+        name: String,
+        /// From 0
+        chunk_index: u32,
+        /// Chunks of the file, an empty one has one empty chunk
+        total_chunks: u32,
+        /// Bytes of the chunk, sealed
+        sealed: String,
+    },
+    /// A file the sender holds for everyone in a channel, fetched by those
+    /// who want it rather than pushed to all
+    FileOffer {
+        /// Where it is offered
+        channel: Channel,
+        /// File name, without its directory
+        name: String,
+        /// Bytes of the file
+        size: u64,
+        /// Of the content, see `blobs::hash_of` in the netchat app
+        hash: String,
+    },
+    /// Asks everyone who holds the file of this hash to say so
+    BlobWanted(String),
+    /// The sender holds the file of this hash, for the app which wanted it
+    BlobHeld(AppId, String),
+    /// Asks the app given, the first to hold the file of this hash, for its
+    /// chunks. They come as `File` chunks named after the hash.
+    BlobRequest(AppId, String),
+}
+
+impl Header {
+    /// One line description, for places where the whole content does not fit
+    pub fn summary(&self) -> String {
+        match self {
+            Private(app_id, content) => format!("to {}: {}", app_id, content),
+            Encrypted(app_id, _) => format!("encrypted, to {}", app_id),
+            Public(channel, content) if channel.is_general() => content.to_owned(),
+            Public(channel, content) => format!("{}: {}", channel, content),
+            Announcement(content, _) => format!("announcement: {}", content),
+            SnapshotRequest(_) => "snapshot request".to_owned(),
+            SnapshotResponse(app_id, _) => format!("snapshot for {}", app_id),
+            HistorySync(app_id, _) | EncryptedHistory(app_id, _) => {
+                format!("history for {}", app_id)
+            }
+            SyncMissing(app_id, messages) => {
+                format!("{} missed messages for {}", messages.len(), app_id)
+            }
+            File {
+                to,
+                name,
+                chunk_index,
+                total_chunks,
+                ..
+            }
+            | EncryptedFile {
+                to,
+                name,
+                chunk_index,
+                total_chunks,
+                ..
+            } => format!(
+                "file {} for {}, chunk {}/{}",
+                name,
+                to,
+                chunk_index + 1,
+                total_chunks
+            ),
+            FileOffer { channel, name, .. } => format!("file {} offered in {}", name, channel),
+            header => format!("{:?}", header)
+                .split('(')
+                .next()
+                .unwrap_or_default()
+                .to_owned(),
+        }
+    }
+}
+
+/// What a peer runs, exchanged on connection
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct VersionInfo {
+    /// Wire format version, see [`PROTOCOL_VERSION`]
+    pub protocol: u32,
+    /// Version of the program
+    pub version: String,
+    /// Optional features understood, see [`FEATURES`]
+    pub features: Vec<String>,
+}
+
+impl VersionInfo {
+    /// What this build runs
+    pub fn local() -> Self {
+        VersionInfo {
+            protocol: PROTOCOL_VERSION,
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            features: FEATURES.iter().map(|f| (*f).to_owned()).collect(),
+        }
+    }
+
+    /// The same without `feature`, for programs which do not implement it
+    pub fn without(mut self, feature: &str) -> Self {
+        self.features.retain(|f| f != feature);
+        self
+    }
+
+    /// One line report on running alongside `app_id`, None when fully compatible
+    pub fn compatibility(&self, app_id: &str) -> Option<String> {
+        if self.protocol != PROTOCOL_VERSION {
+            return Some(format!(
+                "{} runs {} with protocol {} (ours is {}), its messages may not decode",
+                app_id, self.version, self.protocol, PROTOCOL_VERSION
+            ));
+        }
+        let unsupported: Vec<&str> = FEATURES
+            .iter()
+            .filter(|f| !self.features.iter().any(|g| g == *f))
+            .cloned()
+            .collect();
+        let unknown: Vec<&str> = self
+            .features
+            .iter()
+            .filter(|f| !FEATURES.contains(&f.as_str()))
+            .map(String::as_str)
+            .collect();
+        match (unsupported.is_empty(), unknown.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(format!(
+                "{} runs {}, {} unsupported",
+                app_id,
+                self.version,
+                unsupported.join(", ")
+            )),
+            (true, false) => Some(format!(
+                "{} runs {}, we do not support {}",
+                app_id,
+                self.version,
+                unknown.join(", ")
+            )),
+            (false, false) => Some(format!(
+                "{} runs {}, {} unsupported, we do not support {}",
+                app_id,
+                self.version,
+                unsupported.join(", "),
+                unknown.join(", ")
+            )),
+        }
+    }
+}
+
+/// Whether `nick` can be shown in place of an app id: one word of printable
+/// characters, [`MAX_NICK_LEN`] at most
+pub fn is_valid_nick(nick: &str) -> bool {
+    !nick.is_empty()
+        && nick.chars().count() <= MAX_NICK_LEN
+        && !nick.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Sender and header name of a line that does not decode as a Msg, if it looks like one
+pub fn undecodable(json: &str) -> Option<(AppId, String)> {
+    let value: serde_json::Value = serde_json::from_str(json).ok()?;
+    let sender_id = value.get("sender_id")?.as_str()?.to_owned();
+    let header = match value.get("header")? {
+        serde_json::Value::String(name) => name.to_owned(),
+        serde_json::Value::Object(variant) => variant.keys().next()?.to_owned(),
+        _ => return None,
+    };
+    Some((sender_id, header))
+}
+
+/// A message, as sent on the wire
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Msg {
+    /// Id used to relay the message only once
+    pub id: MsgId,
+    /// App which created the message
+    pub sender_id: AppId,
+    /// What the message is about
+    pub header: Header,
+    /// Clock of the last app which relayed it
+    pub clock: Clock,
+    /// Chat messages of each app the sender had handed over, on chat messages,
+    /// what [`delivery`](crate::delivery) policies order them by. Boxed, most
+    /// messages go without
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delivered: Option<Box<Clock>>,
+    /// Seconds since the Unix epoch on the wall clock of the sender when it
+    /// made the message, on chat messages and hellos
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sent_at: Option<u64>,
+    /// How the text of a chat message is meant to be shown, one of
+    /// [`CONTENT_TYPES`] or any other, plain text when None. Signed with the
+    /// rest, so that a relay cannot change how a message is shown.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Made by the identity key of the sender over
+    /// [`message_payload`](crate::identity::message_payload)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<Signature>,
+    /// Whether the signature was checked against the key trusted for the
+    /// sender, set on reception and never sent
+    #[serde(skip)]
+    pub verified: bool,
+    /// Names of the transports it goes out on, all of them if None, set by
+    /// its sender for itself and never sent
+    #[serde(skip)]
+    pub via: Option<Vec<String>>,
+}
+
+impl Msg {
+    /// A message with the given parts
+    pub fn new(id: MsgId, sender_id: AppId, header: Header, clock: Clock) -> Self {
+        Msg {
+            id,
+            sender_id,
+            header,
+            clock,
+            delivered: None,
+            sent_at: None,
+            content_type: None,
+            signature: None,
+            verified: false,
+            via: None,
+        }
+    }
+    /// Sets [`sent_at`](Msg::sent_at) to the time of the system clock
+    pub fn stamp_time(&mut self) {
+        self.sent_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|since| since.as_secs());
+    }
+
+    /// The message as a json line, without the newline
+    pub fn serialize(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
+    /// Serializes the message and a newline into `frame`, reusing its allocation
+    pub fn encode_into(&self, frame: &mut Vec<u8>) -> serde_json::Result<()> {
+        frame.clear();
+        serde_json::to_writer(&mut *frame, self)?;
+        frame.push(b'\n');
+        Ok(())
+    }
+
+    /// Folds the `later` heartbeat into this one, `later` is given back if one
+    /// of them is not a heartbeat. The result is unsigned.
+    pub fn merge_heartbeat(&mut self, later: Msg) -> Option<Msg> {
+        match (&mut self.header, later.header) {
+            (Heartbeat(apps), Heartbeat(later_apps)) => {
+                for app in later_apps {
+                    if !apps.contains(&app) {
+                        apps.push(app);
+                    }
+                }
+                self.id = later.id;
+                self.sender_id = later.sender_id;
+                self.clock.merge(&later.clock);
+                // Signed by none of the senders, it goes on unverified
+                self.signature = None;
+                None
+            }
+            (_, header) => Some(Msg { header, ..later }),
+        }
+    }
+}
+
+/// Names of the codecs, see [`Codec::by_name`]
+pub const CODECS: &[&str] = &["json", "cbor"];
+
+/// How messages are encoded on the wire. Every app reads both, cbor is only
+/// sent to apps advertising the `cbor` feature.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Codec {
+    /// One json line
+    #[default]
+    Json,
+    /// Cbor in a binary frame, see [`BINARY_MARKER`], smaller for large clocks
+    Cbor,
+}
+
+impl Codec {
+    /// The codec called `name`, one of [`CODECS`]
+    pub fn by_name(name: &str) -> Option<Codec> {
+        match name {
+            "json" => Some(Codec::Json),
+            "cbor" => Some(Codec::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Encodes `msg` as a frame into `frame`, newline included, reusing its allocation
+    pub fn encode_into(self, msg: &Msg, frame: &mut Vec<u8>) -> serde_json::Result<()> {
+        match self {
+            Codec::Json => msg.encode_into(frame),
+            Codec::Cbor => {
+                let value = serde_json::to_value(msg)?;
+                frame.clear();
+                frame.push(BINARY_MARKER);
+                frame.extend_from_slice(&[0; 4]);
+                cbor::encode(&value, frame);
+                let len = u32::try_from(frame.len() - 5).map_err(serde::ser::Error::custom)?;
+                frame[1..5].copy_from_slice(&len.to_be_bytes());
+                frame.push(b'\n');
+                Ok(())
+            }
+        }
+    }
+}
+
+impl FromStr for Msg {
+    type Err = ParseError;
+
+    fn from_str(json: &str) -> Result<Msg, ParseError> {
+        parse(json.as_bytes())
+    }
+}
+
+/// Why some input is not a message
+#[derive(Debug)]
+pub enum ParseError {
+    /// Longer than [`MAX_FRAME_LEN`](crate::framing::MAX_FRAME_LEN) or the
+    /// limit in use, with its length
+    TooLong(usize),
+    /// Nested deeper than [`MAX_DEPTH`]
+    TooDeep,
+    /// Not a message this version knows
+    Json(serde_json::Error),
+    /// Binary frame which is not cbor
+    Cbor(cbor::DecodeError),
+    /// Compressed payload which does not decompress
+    Compressed(lz::DecodeError),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::TooLong(len) => write!(f, "message of {} bytes is too long", len),
+            ParseError::TooDeep => write!(f, "message is nested too deeply"),
+            ParseError::Json(e) => write!(f, "{}", e),
+            ParseError::Cbor(e) => write!(f, "{}", e),
+            ParseError::Compressed(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Parses a message from untrusted bytes
+///
+/// Never panics and never allocates much more than the input size, whatever
+/// the input: this is the entry point to fuzz.
+pub fn parse(input: &[u8]) -> Result<Msg, ParseError> {
+    if input.len() > crate::framing::MAX_FRAME_LEN {
+        return Err(ParseError::TooLong(input.len()));
+    }
+    if depth(input) > MAX_DEPTH {
+        return Err(ParseError::TooDeep);
+    }
+    let json = String::from_utf8_lossy(input);
+    serde_json::from_str(&json).map_err(ParseError::Json)
+}
+
+/// Parses a message from the untrusted payload of a binary frame, same
+/// guarantees as [`parse`]. Neither the payload nor what it decompresses to
+/// may be longer than `max_len`, the
+/// [`max_frame_len`](crate::framing::Limits::max_frame_len) in use.
+pub fn parse_binary(payload: &[u8], max_len: usize) -> Result<Msg, ParseError> {
+    if payload.len() > max_len {
+        return Err(ParseError::TooLong(payload.len()));
+    }
+    match payload {
+        [COMPRESSED, packed @ ..] => {
+            let inner = lz::decompress(packed, max_len).map_err(ParseError::Compressed)?;
+            if inner.len() > max_len {
+                return Err(ParseError::TooLong(inner.len()));
+            }
+            match inner.first() {
+                Some(b'{') => parse(&inner),
+                _ => parse_cbor(&inner),
+            }
+        }
+        _ => parse_cbor(payload),
+    }
+}
+
+fn parse_cbor(payload: &[u8]) -> Result<Msg, ParseError> {
+    let value = cbor::decode(payload).map_err(ParseError::Cbor)?;
+    serde_json::from_value(value).map_err(ParseError::Json)
+}
+
+/// First byte of a compressed binary payload, no cbor value starts with it.
+/// It is followed by a json line or a cbor payload, compressed by [`lz`].
+pub const COMPRESSED: u8 = 0xff;
+
+/// Writes the frame encoded in `frame`, newline included, as a binary frame
+/// with its json or cbor compressed into `out`. Returns whether it is smaller,
+/// `out` is to be dropped otherwise.
+pub fn compress_frame(frame: &[u8], out: &mut Vec<u8>) -> bool {
+    let encoded = &frame[..frame.len().saturating_sub(1)];
+    let inner = crate::framing::binary_payload(encoded).unwrap_or(encoded);
+    out.clear();
+    out.push(BINARY_MARKER);
+    out.extend_from_slice(&[0; 4]);
+    out.push(COMPRESSED);
+    lz::compress(inner, out);
+    let len = match u32::try_from(out.len() - 5) {
+        Ok(len) => len,
+        Err(_) => return false,
+    };
+    out[1..5].copy_from_slice(&len.to_be_bytes());
+    out.push(b'\n');
+    out.len() < frame.len()
+}
+
+/// A message with only its id decoded, enough for a relay to skip duplicates
+#[derive(Deserialize)]
+pub struct Envelope<'a> {
+    /// Id of the message
+    pub id: MsgId,
+    #[serde(borrow)]
+    sender_id: &'a RawValue,
+    #[serde(borrow)]
+    header: &'a RawValue,
+    #[serde(borrow)]
+    clock: &'a RawValue,
+    #[serde(borrow, default)]
+    delivered: Option<&'a RawValue>,
+    #[serde(default)]
+    sent_at: Option<u64>,
+    #[serde(borrow, default)]
+    content_type: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    signature: Option<&'a RawValue>,
+}
+
+impl<'a> Envelope<'a> {
+    /// Decodes the rest of the message
+    pub fn open(&self) -> Result<Msg, ParseError> {
+        Ok(Msg {
+            id: self.id,
+            sender_id: serde_json::from_str(self.sender_id.get()).map_err(ParseError::Json)?,
+            header: serde_json::from_str(self.header.get()).map_err(ParseError::Json)?,
+            clock: serde_json::from_str(self.clock.get()).map_err(ParseError::Json)?,
+            delivered: match self.delivered {
+                Some(delivered) => {
+                    serde_json::from_str(delivered.get()).map_err(ParseError::Json)?
+                }
+                None => None,
+            },
+            sent_at: self.sent_at,
+            content_type: match self.content_type {
+                Some(content_type) => {
+                    serde_json::from_str(content_type.get()).map_err(ParseError::Json)?
+                }
+                None => None,
+            },
+            signature: match self.signature {
+                Some(signature) => {
+                    serde_json::from_str(signature.get()).map_err(ParseError::Json)?
+                }
+                None => None,
+            },
+            verified: false,
+            via: None,
+        })
+    }
+}
+
+/// Same checks as `parse`, without decoding more than the message id
+pub fn parse_envelope(input: &str) -> Result<Envelope<'_>, ParseError> {
+    if input.len() > crate::framing::MAX_FRAME_LEN {
+        return Err(ParseError::TooLong(input.len()));
+    }
+    if depth(input.as_bytes()) > MAX_DEPTH {
+        return Err(ParseError::TooDeep);
+    }
+    serde_json::from_str(input).map_err(ParseError::Json)
+}
+
+/// Deepest nesting of arrays and objects, ignoring brackets inside strings
+fn depth(json: &[u8]) -> usize {
+    let (mut depth, mut deepest) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for &b in json {
+        match b {
+            _ if escaped => escaped = false,
+            b'\\' if in_string => escaped = true,
+            b'"' => in_string = !in_string,
+            b'[' | b'{' if !in_string => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            b']' | b'}' if !in_string => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    deepest
+}
+
+#[cfg(test)]
+mod tests {
+    // Note this useful idiom: importing names from outer (for mod tests) scope.
+    use super::*;
+    use crate::framing::MAX_FRAME_LEN;
+
+    #[test]
+    fn message_serde() {
+        let msg = Msg {
+            id: 1,
+            sender_id: "asdasdw".to_owned(),
+            header: Header::Private("42".to_string(), "I like trains !".to_string()),
+            clock: Clock(
+                [("1".to_string(), 2), ("3".to_string(), 4)]
+                    .iter()
+                    .cloned()
+                    .collect(),
+            ),
+            delivered: None,
+            sent_at: Some(1_792_000_000),
+            content_type: Some("text/markdown".to_owned()),
+            signature: None,
+            verified: false,
+            via: None,
+        };
+
+        let serialized = msg.serialize().expect("failed to serialize");
+        println!("serialized = {}", serialized);
+
+        // Convert the JSON string back to a Msg.
+        let deserialized = Msg::from_str(&serialized).expect("failed to deserialize");
+
+        println!("deserialized = {:?}", deserialized);
+
+        assert_eq!(msg, deserialized);
+    }
+
+    #[test]
+    fn nicks_are_one_short_printable_word() {
+        assert!(is_valid_nick("alice"));
+        assert!(is_valid_nick("Zoé_42"));
+        for nick in &[
+            "",
+            "alice bob",
+            "al\u{1b}[31mice",
+            "alice\n",
+            &"a".repeat(33),
+        ] {
+            assert!(!is_valid_nick(nick), "{:?}", nick);
+        }
+    }
+
+    #[test]
+    fn channels_are_one_word_after_a_hash() {
+        let rust = Channel::parse("#rust").unwrap();
+        assert_eq!(serde_json::to_string(&rust).unwrap(), r##""#rust""##);
+        for name in &[
+            "",
+            "#",
+            "rust",
+            "#rust lang",
+            &format!("#{}", "a".repeat(32)),
+        ] {
+            assert_eq!(Channel::parse(name), None, "{:?}", name);
+        }
+        assert!(Channel::default().is_general());
+    }
+
+    #[test]
+    fn version_compatibility() {
+        assert_eq!(VersionInfo::local().compatibility("bob"), None);
+
+        let mut old = VersionInfo::local();
+        old.version = "0.0.9".to_owned();
+        old.features.retain(|f| f != "device-links");
+        old.features.push("reactions".to_owned());
+        assert_eq!(
+            old.compatibility("bob").unwrap(),
+            "bob runs 0.0.9, device-links unsupported, we do not support reactions"
+        );
+
+        old.protocol = PROTOCOL_VERSION + 1;
+        assert!(old.compatibility("bob").unwrap().contains("may not decode"));
+
+        let future = r#"{"id":1,"sender_id":"bob","header":{"React":[3,"+1"]},"clock":{}}"#;
+        assert_eq!(
+            undecodable(future),
+            Some(("bob".to_owned(), "React".to_owned()))
+        );
+        assert_eq!(undecodable("garbage"), None);
+    }
+
+    #[test]
+    fn heartbeats_merge() {
+        let clock = |dates: &[(&str, Date)]| {
+            Clock(dates.iter().map(|(id, d)| (id.to_string(), *d)).collect())
+        };
+        let mut merged = Msg::new(
+            1,
+            "carol".to_owned(),
+            Heartbeat(vec!["alice".to_owned()]),
+            clock(&[("alice", 3), ("carol", 5)]),
+        );
+        let later = Msg::new(
+            2,
+            "carol".to_owned(),
+            Heartbeat(vec!["bob".to_owned(), "alice".to_owned()]),
+            clock(&[("bob", 1), ("carol", 6)]),
+        );
+        assert_eq!(merged.merge_heartbeat(later), None);
+        assert_eq!(merged.id, 2);
+        assert_eq!(
+            merged.header,
+            Heartbeat(vec!["alice".to_owned(), "bob".to_owned()])
+        );
+        assert_eq!(
+            merged.clock,
+            clock(&[("alice", 3), ("bob", 1), ("carol", 6)])
+        );
+
+        let chat = Msg::new(
+            3,
+            "bob".to_owned(),
+            Public(Channel::default(), "hi".to_owned()),
+            clock(&[]),
+        );
+        assert_eq!(merged.merge_heartbeat(chat.clone()), Some(chat));
+    }
+
+    #[test]
+    fn envelope_opens_to_the_same_message() {
+        let mut msg = Msg::new(
+            7,
+            "bob".to_owned(),
+            Public(Channel::default(), "hi \"there\"".to_owned()),
+            Clock([("bob".to_owned(), 3)].iter().cloned().collect()),
+        );
+        msg.content_type = Some("application/json".to_owned());
+        let line = msg.serialize().unwrap();
+        let envelope = parse_envelope(&line).unwrap();
+        assert_eq!(envelope.id, 7);
+        assert_eq!(envelope.open().unwrap(), msg);
+    }
+
+    #[test]
+    fn cbor_frames_are_smaller() {
+        let clock = Clock((0..20).map(|i| (format!("app{}", i), i * 1000)).collect());
+        let msg = Msg::new(
+            u64::MAX,
+            "bob".to_owned(),
+            Public(Channel::default(), "hi".to_owned()),
+            clock,
+        );
+        let (mut json, mut binary) = (Vec::new(), Vec::new());
+        Codec::Json.encode_into(&msg, &mut json).unwrap();
+        Codec::Cbor.encode_into(&msg, &mut binary).unwrap();
+        assert!(
+            binary.len() < json.len() * 3 / 4,
+            "{} {}",
+            binary.len(),
+            json.len()
+        );
+
+        let payload = crate::framing::binary_payload(&binary[..binary.len() - 1]).unwrap();
+        assert_eq!(parse_binary(payload, MAX_FRAME_LEN).unwrap(), msg);
+        assert!(matches!(
+            parse_binary(&payload[..payload.len() - 1], MAX_FRAME_LEN),
+            Err(ParseError::Cbor(_))
+        ));
+    }
+
+    #[test]
+    fn long_pastes_are_compressed() {
+        let paste = "let x = 1;\n".repeat(1000);
+        let clock = Clock::new("bob".to_owned());
+        let msg = Msg::new(
+            1,
+            "bob".to_owned(),
+            Public(Channel::default(), paste),
+            clock,
+        );
+        for codec in [Codec::Json, Codec::Cbor].iter() {
+            let (mut frame, mut packed) = (Vec::new(), Vec::new());
+            codec.encode_into(&msg, &mut frame).unwrap();
+            assert!(compress_frame(&frame, &mut packed));
+            assert!(packed.len() < frame.len() / 10);
+            let payload = crate::framing::binary_payload(&packed[..packed.len() - 1]).unwrap();
+            assert_eq!(parse_binary(payload, MAX_FRAME_LEN).unwrap(), msg);
+            assert!(
+                matches!(
+                    parse_binary(payload, payload.len() + 100),
+                    Err(ParseError::Compressed(lz::DecodeError::TooLong(_)))
+                ),
+                "expands past the limit in use"
+            );
+        }
+
+        let hi = Public(Channel::default(), "hi".to_owned());
+        let hi = Msg::new(2, "bob".to_owned(), hi, Clock::new("bob".to_owned()));
+        let (mut frame, mut packed) = (Vec::new(), Vec::new());
+        hi.encode_into(&mut frame).unwrap();
+        assert!(!compress_frame(&frame, &mut packed), "not worth it");
+    }
+
+    /// `cargo test --release -- --ignored --nocapture codec_benchmark`
+    #[test]
+    #[ignore]
+    fn codec_benchmark() {
+        use std::time::Instant;
+
+        let clock = Clock((0..20).map(|i| (format!("app{}", i), i)).collect());
+        let history = (0..50)
+            .map(|i| {
+                Msg::new(
+                    i,
+                    "bob".to_owned(),
+                    Public(Channel::default(), "hello world".repeat(4)),
+                    clock.clone(),
+                )
+            })
+            .collect();
+        let lines: Vec<String> = [
+            Public(Channel::default(), "hello world".to_owned()),
+            HistorySync("bob".to_owned(), history),
+        ]
+        .iter()
+        .map(|header| {
+            Msg::new(1, "alice".to_owned(), header.clone(), clock.clone())
+                .serialize()
+                .unwrap()
+        })
+        .collect();
+
+        for line in &lines {
+            let rounds = 10_000;
+            let start = Instant::now();
+            for _ in 0..rounds {
+                Msg::from_str(line).unwrap();
+            }
+            let full = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..rounds {
+                parse_envelope(line).unwrap();
+            }
+            let envelope = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..rounds {
+                parse_envelope(line).unwrap().open().unwrap();
+            }
+            let opened = start.elapsed();
+            println!(
+                "{} bytes: serde_json {:?}, envelope only (duplicate) {:?}, envelope then open {:?}",
+                line.len(),
+                full / rounds,
+                envelope / rounds,
+                opened / rounds
+            );
+        }
+    }
+
+    #[test]
+    fn parse_never_panics() {
+        use rand::{Rng, SeedableRng};
+
+        let valid =
+            r#"{"id":1,"sender_id":"a","header":{"SnapshotResponse":["b",[]]},"clock":{"a":1}}"#;
+        assert!(parse(valid.as_bytes()).is_ok());
+
+        let deep = "[".repeat(MAX_DEPTH + 1);
+        assert!(matches!(parse(deep.as_bytes()), Err(ParseError::TooDeep)));
+
+        // Random mutations of a valid message
+        let mut rng = rand::rngs::SmallRng::seed_from_u64(42);
+        for _ in 0..10_000 {
+            let mut input = valid.as_bytes().to_vec();
+            for _ in 0..rng.gen_range(1, 8) {
+                let i = rng.gen_range(0, input.len());
+                match rng.gen_range(0, 3) {
+                    0 => input[i] = rng.gen(),
+                    1 => {
+                        input.remove(i);
+                    }
+                    _ => input.insert(i, b"[{\"\\:,0"[rng.gen_range(0, 7)]),
+                }
+            }
+            let _ = parse(&input);
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! An app of the mesh without any input nor output of its own
+
+use std::collections::VecDeque;
+
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, Rng, SeedableRng};
+
+use crate::dedup::Seen;
+use crate::delivery::{self, Delivered, DeliveryPolicy};
+use crate::messages::{self, Header, ParseError, VersionInfo};
+use crate::{AppId, Clock, Msg};
+
+/// Relays and stamps messages like the netchat server: the caller feeds it the
+/// lines read from the mesh, writes the frames it gives out to the next app and
+/// handles the messages it received.
+pub struct Node {
+    app_id: AppId,
+    clock: Clock,
+    seen: Seen,
+    rng: SmallRng,
+    frames: VecDeque<Vec<u8>>, // To write to the output, newline included
+    events: VecDeque<Msg>,     // Received messages meant for this node
+    delivery: Box<dyn DeliveryPolicy>,
+    delivered: Delivered,
+}
+
+impl Node {
+    /// A node named `app_id`, its connection frames are queued
+    pub fn new(app_id: AppId) -> Self {
+        Node::with_rng(app_id, SmallRng::from_entropy())
+    }
+
+    /// A node drawing the same message ids on every run
+    pub fn with_seed(app_id: AppId, seed: u64) -> Self {
+        Node::with_rng(app_id, SmallRng::seed_from_u64(seed))
+    }
+
+    fn with_rng(app_id: AppId, rng: SmallRng) -> Self {
+        let mut node = Node {
+            clock: Clock::new(app_id.clone()),
+            app_id,
+            seen: Seen::default(),
+            rng,
+            frames: VecDeque::new(),
+            events: VecDeque::new(),
+            delivery: Box::new(delivery::Arrival),
+            delivered: Delivered::default(),
+        };
+        node.send(Header::Connection);
+        node.send(Header::Hello(Node::version()));
+        node
+    }
+
+    /// What the node runs: it reads lines, so no cbor frames are sent to it
+    fn version() -> VersionInfo {
+        VersionInfo::local().without("cbor").without("compression")
+    }
+
+    /// Hands the chat messages over in the order of `policy`, on arrival by default
+    pub fn set_delivery(&mut self, policy: Box<dyn DeliveryPolicy>) {
+        self.delivery = policy;
+    }
+
+    /// Name of the node
+    pub fn app_id(&self) -> &AppId {
+        &self.app_id
+    }
+
+    /// What the node knows of everyone's date
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn increment_clock(&mut self) {
+        *self.clock.entry(self.app_id.clone()).or_insert(0) += 1;
+    }
+
+    /// Stamps a new message and queues it, returns it
+    pub fn send(&mut self, header: Header) -> Msg {
+        self.send_with(header, None)
+    }
+
+    /// Sends a chat message whose text is meant to be shown as `content_type`,
+    /// see [`Msg::content_type`]
+    pub fn send_as(&mut self, header: Header, content_type: &str) -> Msg {
+        self.send_with(header, Some(content_type.to_owned()))
+    }
+
+    fn send_with(&mut self, header: Header, content_type: Option<String>) -> Msg {
+        let id = self.rng.gen();
+        self.seen.insert(id);
+        self.increment_clock();
+        let mut msg = Msg::new(id, self.app_id.clone(), header, self.clock.clone());
+        if delivery::is_chat(&msg.header) {
+            self.delivered.stamp(&mut msg);
+            msg.stamp_time();
+        } else if let Header::Hello(_) = msg.header {
+            msg.stamp_time();
+        }
+        msg.content_type = content_type;
+        self.push_frame(&msg);
+        msg
+    }
+
+    /// Handles a line of the input, returns whether it carried a new message
+    pub fn feed(&mut self, line: &[u8]) -> Result<bool, ParseError> {
+        let mut msg = messages::parse(line)?;
+        if !self.seen.insert(msg.id) {
+            return Ok(false);
+        }
+        self.increment_clock();
+        self.clock.merge(&msg.clock);
+        msg.clock.clone_from(&self.clock);
+        self.push_frame(&msg);
+
+        match &msg.header {
+            // The newcomer missed our version
+            Header::Connection => {
+                self.send(Header::Hello(Node::version()));
+            }
+            header if delivery::is_chat(header) => {
+                let for_me = match header {
+                    Header::Private(app_id, _) | Header::Encrypted(app_id, _) => {
+                        *app_id == self.app_id
+                    }
+                    _ => true,
+                };
+                if for_me {
+                    self.send(Header::Ack(msg.id));
+                }
+                let released = self.delivery.receive(msg);
+                self.hand_over(released);
+                return Ok(true);
+            }
+            Header::Disconnection => {
+                let released = self.delivery.left(&msg.sender_id);
+                self.events.push_back(msg);
+                self.hand_over(released);
+                return Ok(true);
+            }
+            _ => {}
+        }
+        self.events.push_back(msg);
+        Ok(true)
+    }
+
+    /// Queues the chat messages released by the delivery policy
+    fn hand_over(&mut self, messages: Vec<Msg>) {
+        for msg in messages {
+            self.delivered.count(&msg);
+            match &msg.header {
+                Header::Private(app_id, _) | Header::Encrypted(app_id, _)
+                    if *app_id != self.app_id => {}
+                _ => self.events.push_back(msg),
+            }
+        }
+    }
+
+    /// Next frame to write to the mesh, newline included
+    pub fn poll_frame(&mut self) -> Option<Vec<u8>> {
+        self.frames.pop_front()
+    }
+
+    /// Next message received, the private ones for other apps are left out
+    pub fn poll_event(&mut self) -> Option<Msg> {
+        self.events.pop_front()
+    }
+
+    fn push_frame(&mut self, msg: &Msg) {
+        let mut frame = Vec::new();
+        if msg.encode_into(&mut frame).is_ok() {
+            self.frames.push_back(frame);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Channel;
+
+    /// Frames `from` gave out, fed to `to`, with whether each was new
+    fn forward(from: &mut Node, to: &mut Node) -> Vec<bool> {
+        let mut new = Vec::new();
+        while let Some(frame) = from.poll_frame() {
+            new.push(to.feed(&frame).unwrap());
+        }
+        new
+    }
+
+    #[test]
+    fn messages_are_relayed_once() {
+        let (mut alice, mut bob) = (
+            Node::with_seed("alice".to_owned(), 1),
+            Node::new("bob".to_owned()),
+        );
+        let hi = alice.send(Header::Public(Channel::default(), "hi".to_owned()));
+        assert_eq!(forward(&mut alice, &mut bob), [true, true, true]);
+        let mut again = Vec::new();
+        hi.encode_into(&mut again).unwrap();
+        assert!(!bob.feed(&again).unwrap(), "seen");
+
+        // Back to alice: the copies of her own are not new, bob's ack is
+        while let Some(frame) = bob.poll_frame() {
+            let msg = messages::parse(&frame).unwrap();
+            assert_eq!(alice.feed(&frame).unwrap(), msg.sender_id == "bob");
+        }
+        let events: Vec<_> = std::iter::from_fn(|| alice.poll_event()).collect();
+        assert!(events.iter().any(|msg| msg.header == Header::Ack(hi.id)));
+    }
+
+    #[test]
+    fn private_messages_for_others_are_relayed_not_handed_over() {
+        let (mut alice, mut bob) = (Node::new("alice".to_owned()), Node::new("bob".to_owned()));
+        alice.send(Header::Private("carol".to_owned(), "psst".to_owned()));
+        alice.send(Header::Private("bob".to_owned(), "hi".to_owned()));
+        forward(&mut alice, &mut bob);
+        let texts: Vec<_> = std::iter::from_fn(|| bob.poll_event())
+            .filter_map(|msg| match msg.header {
+                Header::Private(_, text) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, ["hi"]);
+
+        // Bob relays both, acknowledges his own only
+        let frames: Vec<_> = std::iter::from_fn(|| bob.poll_frame()).collect();
+        let sent: Vec<_> = frames
+            .iter()
+            .filter_map(|frame| messages::parse(frame).ok())
+            .collect();
+        assert_eq!(
+            sent.iter()
+                .filter(|msg| matches!(msg.header, Header::Private(..)))
+                .count(),
+            2
+        );
+        assert_eq!(
+            sent.iter()
+                .filter(|msg| matches!(msg.header, Header::Ack(_)))
+                .count(),
+            1
+        );
+    }
+}
@@ -0,0 +1,198 @@
+//! Where chat messages are kept across sessions
+//!
+//! [`FileStore`] appends them to a file, a json line each, [`MemoryStore`]
+//! keeps them in memory. Programs embedding netchat can bring their own
+//! backend by implementing [`Store`].
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::messages::Msg;
+
+/// Messages in the order they were appended, each at a position from 0
+pub trait Store: Send {
+    /// Adds `msg` after the others
+    fn append(&mut self, msg: &Msg) -> io::Result<()>;
+
+    /// How many messages are kept
+    fn len(&self) -> io::Result<usize>;
+
+    /// Whether no message is kept
+    fn is_empty(&self) -> io::Result<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Messages at the positions in `range`, oldest first, without those past the end
+    fn range(&self, range: Range<usize>) -> io::Result<Vec<Msg>>;
+
+    /// Messages whose [`summary`](crate::messages::Header::summary) contains
+    /// `text`, oldest first
+    fn search(&self, text: &str) -> io::Result<Vec<Msg>> {
+        let mut found = self.range(0..self.len()?)?;
+        found.retain(|msg| msg.header.summary().contains(text));
+        Ok(found)
+    }
+
+    /// Forgets all but the `keep` newest messages
+    fn compact(&mut self, keep: usize) -> io::Result<()>;
+
+    /// The `n` newest messages, oldest first
+    fn last(&self, n: usize) -> io::Result<Vec<Msg>> {
+        let len = self.len()?;
+        self.range(len.saturating_sub(n)..len)
+    }
+}
+
+/// Keeps messages in memory, for tests and short-lived programs
+#[derive(Default)]
+pub struct MemoryStore {
+    messages: Vec<Msg>,
+}
+
+impl Store for MemoryStore {
+    fn append(&mut self, msg: &Msg) -> io::Result<()> {
+        self.messages.push(msg.clone());
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<usize> {
+        Ok(self.messages.len())
+    }
+
+    fn range(&self, range: Range<usize>) -> io::Result<Vec<Msg>> {
+        let end = range.end.min(self.messages.len());
+        Ok(self.messages[range.start.min(end)..end].to_vec())
+    }
+
+    fn compact(&mut self, keep: usize) -> io::Result<()> {
+        let excess = self.messages.len().saturating_sub(keep);
+        self.messages.drain(..excess);
+        Ok(())
+    }
+}
+
+/// Appends messages to a file, one json line each. Lines that do not
+/// decode are skipped, and dropped by [`compact`](Store::compact).
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    len: usize,
+}
+
+impl FileStore {
+    /// Appends to `path`, created if needed
+    pub fn open(path: &Path) -> io::Result<FileStore> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut store = FileStore {
+            path: path.to_owned(),
+            file,
+            len: 0,
+        };
+        store.len = store.read()?.len();
+        Ok(store)
+    }
+
+    fn read(&self) -> io::Result<Vec<Msg>> {
+        let mut messages = Vec::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            match line?.parse::<Msg>() {
+                Ok(msg) => messages.push(msg),
+                Err(e) => log::error!("Skipped a line of {:?}: {}", self.path, e),
+            }
+        }
+        Ok(messages)
+    }
+}
+
+fn line(msg: &Msg) -> io::Result<String> {
+    Ok(format!("{}\n", msg.serialize()?))
+}
+
+impl Store for FileStore {
+    fn append(&mut self, msg: &Msg) -> io::Result<()> {
+        self.file.write_all(line(msg)?.as_bytes())?;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn len(&self) -> io::Result<usize> {
+        Ok(self.len)
+    }
+
+    fn range(&self, range: Range<usize>) -> io::Result<Vec<Msg>> {
+        let count = range.end.saturating_sub(range.start);
+        Ok(self
+            .read()?
+            .into_iter()
+            .skip(range.start)
+            .take(count)
+            .collect())
+    }
+
+    fn compact(&mut self, keep: usize) -> io::Result<()> {
+        let kept = self.last(keep)?;
+        // Written aside then renamed, the file is never left half written
+        let mut aside = self.path.clone().into_os_string();
+        aside.push(".compacting");
+        let aside = PathBuf::from(aside);
+        let mut content = String::new();
+        for msg in &kept {
+            content.push_str(&line(msg)?);
+        }
+        fs::write(&aside, content)?;
+        fs::rename(&aside, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        self.len = kept.len();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::Channel;
+    use crate::messages::Header::Public;
+    use crate::Clock;
+
+    fn texts(messages: Vec<Msg>) -> Vec<String> {
+        messages.iter().map(|msg| msg.header.summary()).collect()
+    }
+
+    fn check(store: &mut dyn Store) {
+        assert!(store.is_empty().unwrap());
+        for (id, text) in ["one", "two", "three", "four"].iter().enumerate() {
+            let clock = Clock::new("alice".to_owned());
+            let msg = Msg::new(
+                id as u64,
+                "alice".to_owned(),
+                Public(Channel::default(), text.to_string()),
+                clock,
+            );
+            store.append(&msg).unwrap();
+        }
+        assert_eq!(store.len().unwrap(), 4);
+        assert_eq!(texts(store.range(1..3).unwrap()), ["two", "three"]);
+        assert_eq!(texts(store.range(3..9).unwrap()), ["four"]);
+        assert!(store.range(7..9).unwrap().is_empty());
+        assert_eq!(texts(store.search("o").unwrap()), ["one", "two", "four"]);
+
+        store.compact(2).unwrap();
+        assert_eq!(texts(store.last(5).unwrap()), ["three", "four"]);
+    }
+
+    #[test]
+    fn backends_keep_append_order() {
+        check(&mut MemoryStore::default());
+
+        let path =
+            std::env::temp_dir().join(format!("netchat-test-{}-store.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        check(&mut FileStore::open(&path).unwrap());
+        // What was compacted is what is read back
+        let reopened = texts(FileStore::open(&path).unwrap().last(5).unwrap());
+        fs::remove_file(&path).unwrap();
+        assert_eq!(reopened, ["three", "four"]);
+    }
+}
@@ -0,0 +1,81 @@
+//! Allocations made on the hot path, counted by an allocator of its own, in a
+//! test binary of its own so that no other test is run with it
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use netchat_core::framing::{Frame, FrameReader, Pool, MAX_FRAME_LEN};
+use netchat_core::messages::{Channel, Header};
+use netchat_core::{Clock, Msg};
+
+/// Counts the allocations made by each thread
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|a| a.set(a.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn allocations_per_call(rounds: usize, mut f: impl FnMut()) -> f64 {
+    let before = ALLOCATIONS.with(Cell::get);
+    for _ in 0..rounds {
+        f();
+    }
+    (ALLOCATIONS.with(Cell::get) - before) as f64 / rounds as f64
+}
+
+#[test]
+fn hot_path_allocations() {
+    let clock = Clock((0..5).map(|i| (format!("app{}", i), i)).collect());
+    let msg = Msg::new(
+        1,
+        "app0".to_owned(),
+        Header::Public(Channel::default(), "hello".to_owned()),
+        clock,
+    );
+
+    let formatted = allocations_per_call(1000, || {
+        let line = format!("{}\n", msg.serialize().unwrap());
+        assert!(!line.is_empty());
+    });
+    let mut frame = Vec::new();
+    let encoded = allocations_per_call(1000, || msg.encode_into(&mut frame).unwrap());
+
+    let line = String::from_utf8(frame.clone()).unwrap();
+    let input = line.repeat(2000);
+    let pool = Pool::default();
+    let mut reader = FrameReader::new(input.as_bytes(), MAX_FRAME_LEN, pool.clone());
+    let read = allocations_per_call(1000, || match reader.next_frame() {
+        Ok(Some(Frame::Line(line))) => pool.give(line),
+        _ => panic!("expected a line"),
+    });
+
+    assert!(
+        formatted >= 2.0,
+        "serialize + format allocates {} times per message",
+        formatted
+    );
+    assert!(
+        encoded < 0.01,
+        "encode_into allocates {} times per message",
+        encoded
+    );
+    assert!(
+        read < 0.01,
+        "a pooled read allocates {} times per message",
+        read
+    );
+}
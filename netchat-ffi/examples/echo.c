@@ -0,0 +1,51 @@
+/* Echo bot: answers every public message of the mesh with the same text.
+ *
+ *   cargo build -p netchat-ffi
+ *   cc netchat-ffi/examples/echo.c -Inetchat-ffi/include -Ltarget/debug -lnetchat_ffi -o echo
+ *   LD_LIBRARY_PATH=target/debug ./echo <name> < input_pipe > output_pipe
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "netchat.h"
+
+static void flush_frames(NetchatNode *node) {
+    char *frame;
+    while ((frame = netchat_node_poll_frame(node)) != NULL) {
+        fputs(frame, stdout);
+        netchat_string_free(frame);
+    }
+    fflush(stdout);
+}
+
+int main(int argc, char **argv) {
+    NetchatNode *node = netchat_node_new(argc > 1 ? argv[1] : "echo");
+    char *line = NULL;
+    size_t capacity = 0;
+    ssize_t len;
+
+    flush_frames(node);
+    while ((len = getline(&line, &capacity, stdin)) > 0) {
+        netchat_node_feed(node, (const uint8_t *)line, (size_t)len);
+
+        char *event;
+        while ((event = netchat_node_poll_event(node)) != NULL) {
+            /* Crude but enough for a demo: {"id":..,"header":{"Public":"<text>"},.. */
+            const char *public = strstr(event, "\"header\":{\"Public\":\"");
+            if (public != NULL) {
+                char text[1024];
+                public += strlen("\"header\":{\"Public\":\"");
+                size_t n = strcspn(public, "\"");
+                snprintf(text, sizeof text, "echo: %.*s", (int)n, public);
+                netchat_node_send(node, NULL, text);
+            }
+            netchat_string_free(event);
+        }
+        flush_frames(node);
+    }
+    free(line);
+    netchat_node_free(node);
+    return 0;
+}
@@ -0,0 +1,55 @@
+/* C API of netchat-ffi, kept in sync with src/lib.rs by hand.
+ *
+ * A node reads nothing and writes nothing by itself: feed it the lines read
+ * from the mesh, write the frames it polls out to the next app, and poll the
+ * messages it received. Strings returned by the library are freed with
+ * netchat_string_free. */
+
+#ifndef NETCHAT_H
+#define NETCHAT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct NetchatNode NetchatNode;
+
+/* Creates a node named app_id and queues its connection frames,
+ * NULL if app_id is not valid UTF-8. */
+NetchatNode *netchat_node_new(const char *app_id);
+
+void netchat_node_free(NetchatNode *node);
+
+/* Feeds a line read from the mesh, the newline is optional. Returns 1 for a
+ * new message, 0 for one already seen, -1 if it is not a message. */
+int netchat_node_feed(NetchatNode *node, const uint8_t *frame, size_t len);
+
+/* Sends text to everyone, or only to recipient when it is not NULL.
+ * Returns 0, or -1 if a string is not valid UTF-8. */
+int netchat_node_send(NetchatNode *node, const char *recipient, const char *text);
+
+/* Same, with the text meant to be shown as content_type, such as
+ * "application/json", or as plain text when it is NULL. */
+int netchat_node_send_as(NetchatNode *node, const char *recipient, const char *text,
+                         const char *content_type);
+
+/* Next frame to write to the mesh, newline included, NULL when there is none. */
+char *netchat_node_poll_frame(NetchatNode *node);
+
+/* Next message received by the node, as a json object, NULL when there is none. */
+char *netchat_node_poll_event(NetchatNode *node);
+
+/* Decodes an untrusted line, returns the message as a json object or NULL if
+ * it is not one. */
+char *netchat_msg_decode(const uint8_t *frame, size_t len);
+
+/* Merges two json clocks, NULL if one is not a clock. */
+char *netchat_clock_merge(const char *a, const char *b);
+
+/* Causal order of two json clocks: -1 if a happened before b, 0 if they are
+ * equal, 1 if a happened after, 2 if they are concurrent, -2 if one is not a
+ * clock. */
+int netchat_clock_compare(const char *a, const char *b);
+
+void netchat_string_free(char *s);
+
+#endif
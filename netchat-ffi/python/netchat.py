@@ -0,0 +1,149 @@
+"""Python bindings of netchat, over the C API of netchat-ffi
+
+Build the library first with `cargo build -p netchat-ffi`, this module looks
+for it in target/debug, target/release or at the path in $NETCHAT_LIB.
+
+    node = netchat.Node("bot")
+    for line in input_pipe:
+        node.feed(line)
+        for msg in node.events():
+            header = msg["header"]
+            if isinstance(header, dict) and "Public" in header:
+                channel, text = header["Public"]
+                node.send("echo: " + text)
+        output_pipe.writelines(node.frames())
+
+Headers without content, such as "Connection", are plain strings, the others
+dicts of one key. test_netchat.py runs this bot, `cargo test -p netchat-ffi`
+runs it.
+"""
+
+import ctypes
+import json
+import os
+
+_ROOT = os.path.join(os.path.dirname(os.path.abspath(__file__)), "..", "..")
+
+
+def _load():
+    candidates = [os.environ.get("NETCHAT_LIB")] + [
+        os.path.join(_ROOT, "target", profile, "libnetchat_ffi.so")
+        for profile in ("debug", "release")
+    ]
+    for path in candidates:
+        if path and os.path.exists(path):
+            return ctypes.CDLL(path)
+    raise OSError("libnetchat_ffi.so not found, run `cargo build -p netchat-ffi`")
+
+
+_lib = _load()
+_lib.netchat_node_new.restype = ctypes.c_void_p
+_lib.netchat_node_new.argtypes = [ctypes.c_char_p]
+_lib.netchat_node_free.argtypes = [ctypes.c_void_p]
+_lib.netchat_node_feed.argtypes = [ctypes.c_void_p, ctypes.c_char_p, ctypes.c_size_t]
+_lib.netchat_node_send.argtypes = [ctypes.c_void_p, ctypes.c_char_p, ctypes.c_char_p]
+_lib.netchat_node_send_as.argtypes = [
+    ctypes.c_void_p, ctypes.c_char_p, ctypes.c_char_p, ctypes.c_char_p
+]
+for _name in ("netchat_node_poll_frame", "netchat_node_poll_event"):
+    getattr(_lib, _name).restype = ctypes.c_void_p
+    getattr(_lib, _name).argtypes = [ctypes.c_void_p]
+_lib.netchat_msg_decode.restype = ctypes.c_void_p
+_lib.netchat_msg_decode.argtypes = [ctypes.c_char_p, ctypes.c_size_t]
+_lib.netchat_clock_merge.restype = ctypes.c_void_p
+_lib.netchat_clock_merge.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+_lib.netchat_clock_compare.argtypes = [ctypes.c_char_p, ctypes.c_char_p]
+_lib.netchat_string_free.argtypes = [ctypes.c_void_p]
+
+
+def _take(pointer):
+    """Copies a string returned by the library and frees it"""
+    if not pointer:
+        return None
+    try:
+        return ctypes.string_at(pointer).decode()
+    finally:
+        _lib.netchat_string_free(pointer)
+
+
+def _bytes(line):
+    return line if isinstance(line, bytes) else line.encode()
+
+
+def decode(line):
+    """The message carried by an untrusted line, as a dict, None if it is not one"""
+    line = _bytes(line)
+    msg = _take(_lib.netchat_msg_decode(line, len(line)))
+    return json.loads(msg) if msg is not None else None
+
+
+def encode(msg):
+    """The line carrying `msg`, newline included, ValueError if it is not a message"""
+    line = json.dumps(msg, separators=(",", ":"))
+    if decode(line) is None:
+        raise ValueError("not a netchat message")
+    return line + "\n"
+
+
+def merge(a, b):
+    """Latest date of each app from both clocks"""
+    merged = _take(_lib.netchat_clock_merge(json.dumps(a).encode(), json.dumps(b).encode()))
+    if merged is None:
+        raise ValueError("not a clock")
+    return json.loads(merged)
+
+
+BEFORE, EQUAL, AFTER, CONCURRENT = -1, 0, 1, 2
+
+
+def compare(a, b):
+    """Causal order of two clocks: BEFORE, EQUAL, AFTER or CONCURRENT"""
+    order = _lib.netchat_clock_compare(json.dumps(a).encode(), json.dumps(b).encode())
+    if order == -2:
+        raise ValueError("not a clock")
+    return order
+
+
+class Node:
+    """An app of the mesh, which reads and writes nothing by itself"""
+
+    def __init__(self, app_id):
+        self._node = _lib.netchat_node_new(app_id.encode())
+        if not self._node:
+            raise ValueError("invalid app id")
+
+    def __del__(self):
+        if getattr(self, "_node", None):
+            _lib.netchat_node_free(self._node)
+            self._node = None
+
+    def feed(self, line):
+        """Handles a line read from the mesh, returns whether it was a new message"""
+        line = _bytes(line)
+        result = _lib.netchat_node_feed(self._node, line, len(line))
+        if result < 0:
+            raise ValueError("not a netchat message")
+        return result == 1
+
+    def send(self, text, recipient=None, content_type=None):
+        """Sends `text` to everyone, or only to `recipient`, shown as
+        `content_type` such as "application/json", plain text by default"""
+        recipient = recipient.encode() if recipient is not None else None
+        content_type = content_type.encode() if content_type is not None else None
+        _lib.netchat_node_send_as(self._node, recipient, text.encode(), content_type)
+
+    def frames(self):
+        """Lines to write to the mesh, newline included"""
+        while True:
+            frame = _take(_lib.netchat_node_poll_frame(self._node))
+            if frame is None:
+                return
+            yield frame
+
+    def events(self):
+        """Messages received by the node, as dicts"""
+        while True:
+            event = _take(_lib.netchat_node_poll_event(self._node))
+            if event is None:
+                return
+            yield json.loads(event)
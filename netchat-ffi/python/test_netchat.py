@@ -0,0 +1,56 @@
+"""Smoke test of the bindings, run by `cargo test -p netchat-ffi` or with
+`python3 -m unittest` in this directory once the library is built"""
+
+import unittest
+
+import netchat
+
+
+def forward(sender, receiver):
+    """Feeds every frame `sender` has to write to `receiver`"""
+    for frame in sender.frames():
+        receiver.feed(frame)
+
+
+def texts(node):
+    """Texts of the public messages `node` received"""
+    return [
+        msg["header"]["Public"][1]
+        for msg in node.events()
+        if isinstance(msg["header"], dict) and "Public" in msg["header"]
+    ]
+
+
+class NetchatTest(unittest.TestCase):
+    def test_the_bot_of_the_docstring_echoes(self):
+        alice, bot = netchat.Node("alice"), netchat.Node("bot")
+        alice.send("hi")
+        for line in list(alice.frames()):
+            bot.feed(line)
+            for msg in bot.events():
+                header = msg["header"]
+                if isinstance(header, dict) and "Public" in header:
+                    channel, text = header["Public"]
+                    bot.send("echo: " + text)
+        forward(bot, alice)
+        self.assertEqual(texts(alice), ["echo: hi"])
+
+    def test_messages_and_clocks(self):
+        alice = netchat.Node("alice")
+        alice.send("hi")
+        line = list(alice.frames())[-1]
+        msg = netchat.decode(line)
+        self.assertEqual(msg["header"]["Public"][1], "hi")
+        self.assertEqual(netchat.decode(netchat.encode(msg)), msg)
+        self.assertIsNone(netchat.decode("{"))
+        with self.assertRaises(ValueError):
+            netchat.encode({"not": "a message"})
+
+        a, b = {"alice": 2, "bob": 1}, {"alice": 1, "carol": 1}
+        self.assertEqual(netchat.merge(a, b), {"alice": 2, "bob": 1, "carol": 1})
+        self.assertEqual(netchat.compare(a, b), netchat.CONCURRENT)
+        self.assertEqual(netchat.compare(b, b), netchat.EQUAL)
+
+
+if __name__ == "__main__":
+    unittest.main()
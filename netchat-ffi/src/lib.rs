@@ -0,0 +1,277 @@
+//! C API over `netchat-core`, for programs which are not written in rust
+//!
+//! Wraps [`netchat_core::node::Node`]: the caller feeds it the lines it reads
+//! from the mesh, writes the frames it polls out to the next app, and polls the
+//! messages meant for it. The declarations are in `include/netchat.h`.
+
+use std::cmp::Ordering;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::slice;
+
+use netchat_core::messages::{self, Channel, Header};
+use netchat_core::node::Node;
+use netchat_core::Clock;
+
+unsafe fn to_str<'a>(s: *const c_char) -> Option<&'a str> {
+    if s.is_null() {
+        return None;
+    }
+    CStr::from_ptr(s).to_str().ok()
+}
+
+fn into_c_string(bytes: Vec<u8>) -> *mut c_char {
+    // Json never contains a nul byte, it is escaped
+    CString::new(bytes).map_or(ptr::null_mut(), CString::into_raw)
+}
+
+/// Creates a node named `app_id` and queues its connection frames,
+/// NULL if `app_id` is not valid UTF-8
+///
+/// # Safety
+///
+/// `app_id` is a nul terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_node_new(app_id: *const c_char) -> *mut Node {
+    match to_str(app_id) {
+        Some(app_id) => Box::into_raw(Box::new(Node::new(app_id.to_owned()))),
+        None => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `node` comes from `netchat_node_new` and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_node_free(node: *mut Node) {
+    if !node.is_null() {
+        drop(Box::from_raw(node));
+    }
+}
+
+/// Feeds a line read from the mesh, the newline is optional.
+/// Returns 1 for a new message, 0 for one already seen, -1 if it is not a message.
+///
+/// # Safety
+///
+/// `node` is alive and `frame` points to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_node_feed(node: *mut Node, frame: *const u8, len: usize) -> c_int {
+    let node = match node.as_mut() {
+        Some(node) if !frame.is_null() => node,
+        _ => return -1,
+    };
+    match node.feed(slice::from_raw_parts(frame, len)) {
+        Ok(true) => 1,
+        Ok(false) => 0,
+        Err(_) => -1,
+    }
+}
+
+/// Sends `text` to everyone in `#general`, or only to `recipient` when it is not NULL.
+/// Returns 0, or -1 if a string is not valid UTF-8.
+///
+/// # Safety
+///
+/// `node` is alive, `recipient` and `text` are nul terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_node_send(
+    node: *mut Node,
+    recipient: *const c_char,
+    text: *const c_char,
+) -> c_int {
+    netchat_node_send_as(node, recipient, text, ptr::null())
+}
+
+/// Like `netchat_node_send`, with the text meant to be shown as `content_type`,
+/// such as `application/json`, or as plain text when it is NULL.
+///
+/// # Safety
+///
+/// `node` is alive, `recipient`, `text` and `content_type` are nul terminated
+/// strings.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_node_send_as(
+    node: *mut Node,
+    recipient: *const c_char,
+    text: *const c_char,
+    content_type: *const c_char,
+) -> c_int {
+    let (node, text) = match (node.as_mut(), to_str(text)) {
+        (Some(node), Some(text)) => (node, text.to_owned()),
+        _ => return -1,
+    };
+    let header = if recipient.is_null() {
+        Header::Public(Channel::default(), text)
+    } else {
+        match to_str(recipient) {
+            Some(recipient) => Header::Private(recipient.to_owned(), text),
+            None => return -1,
+        }
+    };
+    if content_type.is_null() {
+        node.send(header);
+    } else {
+        match to_str(content_type) {
+            Some(content_type) => node.send_as(header, content_type),
+            None => return -1,
+        };
+    }
+    0
+}
+
+/// Next frame to write to the mesh, newline included, NULL when there is none.
+/// Free it with `netchat_string_free`.
+///
+/// # Safety
+///
+/// `node` is alive.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_node_poll_frame(node: *mut Node) -> *mut c_char {
+    match node.as_mut().and_then(Node::poll_frame) {
+        Some(frame) => into_c_string(frame),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Next message received by the node, as a json object, NULL when there is none.
+/// Free it with `netchat_string_free`.
+///
+/// # Safety
+///
+/// `node` is alive.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_node_poll_event(node: *mut Node) -> *mut c_char {
+    match node.as_mut().and_then(Node::poll_event) {
+        Some(msg) => serde_json::to_vec(&msg).map_or(ptr::null_mut(), into_c_string),
+        None => ptr::null_mut(),
+    }
+}
+
+/// Decodes an untrusted line, returns the message as a json object or NULL if
+/// it is not one. Free it with `netchat_string_free`.
+///
+/// # Safety
+///
+/// `frame` points to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_msg_decode(frame: *const u8, len: usize) -> *mut c_char {
+    if frame.is_null() {
+        return ptr::null_mut();
+    }
+    match messages::parse(slice::from_raw_parts(frame, len)) {
+        Ok(msg) => serde_json::to_vec(&msg).map_or(ptr::null_mut(), into_c_string),
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+unsafe fn to_clock(json: *const c_char) -> Option<Clock> {
+    serde_json::from_str(to_str(json)?).ok()
+}
+
+/// Merges two json clocks, NULL if one is not a clock.
+/// Free it with `netchat_string_free`.
+///
+/// # Safety
+///
+/// `a` and `b` are nul terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_clock_merge(a: *const c_char, b: *const c_char) -> *mut c_char {
+    match (to_clock(a), to_clock(b)) {
+        (Some(mut a), Some(b)) => {
+            a.merge(&b);
+            serde_json::to_vec(&a).map_or(ptr::null_mut(), into_c_string)
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Causal order of two json clocks: -1 if `a` happened before `b`, 0 if they
+/// are equal, 1 if `a` happened after, 2 if they are concurrent, -2 if one is
+/// not a clock.
+///
+/// # Safety
+///
+/// `a` and `b` are nul terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_clock_compare(a: *const c_char, b: *const c_char) -> c_int {
+    match (to_clock(a), to_clock(b)) {
+        (Some(a), Some(b)) => match a.compare(&b) {
+            Some(Ordering::Less) => -1,
+            Some(Ordering::Equal) => 0,
+            Some(Ordering::Greater) => 1,
+            None => 2,
+        },
+        _ => -2,
+    }
+}
+
+/// # Safety
+///
+/// `s` comes from this library and is not used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn netchat_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netchat_core::Msg;
+
+    unsafe fn take(s: *mut c_char) -> Option<String> {
+        if s.is_null() {
+            return None;
+        }
+        let owned = CStr::from_ptr(s).to_str().unwrap().to_owned();
+        netchat_string_free(s);
+        Some(owned)
+    }
+
+    /// Feeds every frame `from` has to write to `to`, returns the results
+    unsafe fn forward(from: *mut Node, to: *mut Node) -> Vec<c_int> {
+        let mut results = Vec::new();
+        while let Some(frame) = take(netchat_node_poll_frame(from)) {
+            results.push(netchat_node_feed(to, frame.as_ptr(), frame.len()));
+        }
+        results
+    }
+
+    #[test]
+    fn nodes_chat_through_the_c_api() {
+        unsafe {
+            let alice = netchat_node_new(b"alice\0".as_ptr() as *const c_char);
+            let bob = netchat_node_new(b"bob\0".as_ptr() as *const c_char);
+            netchat_node_send(alice, ptr::null(), b"hi\0".as_ptr() as *const c_char);
+            netchat_node_send(
+                alice,
+                b"carol\0".as_ptr() as *const c_char,
+                b"secret\0".as_ptr() as *const c_char,
+            );
+
+            // Connection, Hello, the public and the private message
+            assert_eq!(forward(alice, bob), vec![1, 1, 1, 1]);
+            let mut events = Vec::new();
+            while let Some(event) = take(netchat_node_poll_event(bob)) {
+                events.push(serde_json::from_str::<Msg>(&event).unwrap().header);
+            }
+            assert_eq!(
+                events[2],
+                Header::Public(Channel::default(), "hi".to_owned())
+            );
+            assert_eq!(events.len(), 3, "the private message is for carol");
+
+            // Bob relays everything back, alice already saw it all
+            let relayed = forward(bob, alice);
+            assert_eq!(relayed.iter().filter(|r| **r == 0).count(), 4);
+            assert!(take(netchat_node_poll_event(alice)).is_some());
+
+            assert_eq!(netchat_node_feed(bob, b"{".as_ptr(), 1), -1);
+            netchat_node_free(alice);
+            netchat_node_free(bob);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Runs the smoke test of the Python bindings against the library just built
+
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn python_bindings_work() {
+    // target/<profile>/deps/python-<hash>, the library is built next to it
+    let deps = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_owned();
+    let lib = [deps.clone(), deps.parent().unwrap().to_owned()]
+        .iter()
+        .map(|dir| dir.join("libnetchat_ffi.so"))
+        .find(|lib| lib.exists())
+        .expect("libnetchat_ffi.so built for the tests");
+    let bindings = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("python");
+    let run = Command::new("python3")
+        .args(["-m", "unittest", "test_netchat"])
+        .current_dir(&bindings)
+        .env("NETCHAT_LIB", &lib)
+        .output();
+    let output = match run {
+        Ok(output) => output,
+        // Not everywhere the crate builds
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => panic!("could not run python3: {}", e),
+    };
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
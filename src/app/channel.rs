@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+
+use super::events::{Event, Policy};
+
+/// Events waiting for the UI, beyond which droppable ones are discarded
+pub const CAPACITY: usize = 1024;
+
+/// Bounded queue of events for the UI, the UI stalling never blocks the senders
+pub fn channel(capacity: usize) -> (Sender, Receiver) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(Queue {
+            events: VecDeque::new(),
+            capacity,
+            dropped: 0,
+            disconnected: false,
+        }),
+        ready: Condvar::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver { shared },
+    )
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+}
+
+struct Queue {
+    events: VecDeque<Event>,
+    capacity: usize,
+    dropped: usize,
+    disconnected: bool, // The receiver is gone
+}
+
+impl Queue {
+    fn push(&mut self, event: Event) {
+        // Only the last state is worth displaying
+        if let Policy::Latest(..) = event.policy() {
+            if let Some(queued) = self
+                .events
+                .iter_mut()
+                .find(|queued| queued.policy() == event.policy())
+            {
+                *queued = event;
+                return;
+            }
+        }
+
+        if self.events.len() >= self.capacity {
+            let droppable = self
+                .events
+                .iter()
+                .position(|e| e.policy() == Policy::Droppable);
+            if let Some(i) = droppable {
+                self.events.remove(i);
+                self.dropped += 1;
+                log::warn!("the UI is late, {} events dropped so far", self.dropped);
+            }
+        }
+        self.events.push_back(event);
+    }
+}
+
+#[derive(Clone)]
+pub struct Sender {
+    shared: Arc<Shared>,
+}
+
+impl Sender {
+    pub fn send(&self, event: Event) -> Result<(), mpsc::SendError<()>> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.disconnected {
+            return Err(mpsc::SendError(()));
+        }
+        queue.push(event);
+        self.shared.ready.notify_one();
+        Ok(())
+    }
+}
+
+pub struct Receiver {
+    shared: Arc<Shared>,
+}
+
+impl Receiver {
+    /// Another sender into this queue
+    pub fn sender(&self) -> Sender {
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// Next event if one is already waiting
+    pub fn try_recv(&self) -> Option<Event> {
+        self.shared.queue.lock().unwrap().events.pop_front()
+    }
+
+    /// Blocks until an event is available
+    pub fn recv(&self) -> Event {
+        let mut queue = self.shared.queue.lock().unwrap();
+        loop {
+            if let Some(event) = queue.events.pop_front() {
+                return event;
+            }
+            queue = self.shared.ready.wait(queue).unwrap();
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().disconnected = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::notice::Notice;
+    use crate::server::reconnect::{Change, State};
+
+    #[test]
+    fn chat_survives_a_stalled_ui() {
+        let (tx, rx) = channel(3);
+        let connection = |state| {
+            Event::Connection(Change {
+                name: "out".to_owned(),
+                state,
+            })
+        };
+        let notice = |text: &str| Event::Notice(Notice::info("test", text.to_owned()));
+        tx.send(connection(State::Disconnected)).unwrap();
+        tx.send(Event::Tick).unwrap();
+        tx.send(notice("1")).unwrap();
+        tx.send(connection(State::Connected)).unwrap();
+        tx.send(notice("2")).unwrap();
+        tx.send(notice("3")).unwrap();
+
+        // The tick made room, the connection state was coalesced
+        match rx.recv() {
+            Event::Connection(change) => assert_eq!(change.state, State::Connected),
+            _ => panic!("expected the connection state"),
+        }
+        for expected in &["1", "2", "3"] {
+            match rx.recv() {
+                Event::Notice(n) => assert_eq!(n.text, *expected),
+                _ => panic!("expected a notice"),
+            }
+        }
+
+        drop(rx);
+        assert!(tx.send(Event::Tick).is_err());
+    }
+}
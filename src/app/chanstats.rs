@@ -0,0 +1,134 @@
+//! `/chanstats [#channel]`: who spoke how much in a channel, at what hours and
+//! how long their messages were, out of the message history
+
+use std::collections::BTreeMap;
+
+use super::daylog::LocalTime;
+use super::AppId;
+use crate::server::messages::{Channel, Header, Msg};
+
+/// Width of the longest bar
+const BAR_WIDTH: usize = 20;
+
+/// Busiest hours shown
+const TOP_HOURS: usize = 5;
+
+/// Public messages of a channel, counted
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChannelStats {
+    /// Messages of each sender
+    pub senders: BTreeMap<AppId, usize>,
+    /// Messages sent at each hour of the day, local time, of those which
+    /// tell when they were sent
+    pub hours: [usize; 24],
+    /// Characters of all the messages
+    pub chars: usize,
+}
+
+impl ChannelStats {
+    /// Counts the public messages of `channel` among `messages`
+    pub fn collect<'a>(messages: impl IntoIterator<Item = &'a Msg>, channel: &Channel) -> Self {
+        let mut stats = ChannelStats::default();
+        for msg in messages {
+            let text = match &msg.header {
+                Header::Public(c, text) if c == channel => text,
+                _ => continue,
+            };
+            *stats.senders.entry(msg.sender_id.clone()).or_insert(0) += 1;
+            stats.chars += text.chars().count();
+            if let Some(secs) = msg.sent_at {
+                stats.hours[LocalTime::at(secs as libc::time_t).hour as usize % 24] += 1;
+            }
+        }
+        stats
+    }
+
+    pub fn messages(&self) -> usize {
+        self.senders.values().sum()
+    }
+
+    /// Lines shown for `channel`, with the senders named by `name`
+    pub fn render<'a>(
+        &'a self,
+        channel: &Channel,
+        name: impl Fn(&'a str) -> &'a str,
+    ) -> Vec<String> {
+        let messages = self.messages();
+        if messages == 0 {
+            return vec![format!("Nothing said in {} yet", channel)];
+        }
+        let mut lines = vec![format!(
+            "{}: {} messages, {} characters long on average",
+            channel,
+            messages,
+            self.chars / messages
+        )];
+
+        let mut senders: Vec<(&str, usize)> =
+            self.senders.iter().map(|(id, n)| (name(id), *n)).collect();
+        senders.sort_by_key(|(name, n)| (std::cmp::Reverse(*n), *name));
+        let width = senders
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let most = senders[0].1;
+        for (name, n) in senders {
+            lines.push(format!("  {:<w$} {} {}", name, bar(n, most), n, w = width));
+        }
+
+        let mut hours: Vec<(usize, usize)> = (0..24)
+            .map(|hour| (hour, self.hours[hour]))
+            .filter(|(_, n)| *n > 0)
+            .collect();
+        hours.sort_by_key(|(hour, n)| (std::cmp::Reverse(*n), *hour));
+        hours.truncate(TOP_HOURS);
+        if let Some(&(_, most)) = hours.first() {
+            lines.push("  Busiest hours:".to_owned());
+            for (hour, n) in hours {
+                lines.push(format!("  {:02}:00 {} {}", hour, bar(n, most), n));
+            }
+        }
+        lines
+    }
+}
+
+/// `n` out of `most` as a bar, at least one block wide
+fn bar(n: usize, most: usize) -> String {
+    let len = (n * BAR_WIDTH / most.max(1)).max(1);
+    "█".repeat(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Clock;
+
+    #[test]
+    fn messages_of_the_channel_are_counted() {
+        let rust = Channel::parse("#rust").unwrap();
+        let msg = |sender: &str, channel: &Channel, text: &str| {
+            let header = Header::Public(channel.clone(), text.to_owned());
+            let mut msg = Msg::new(1, sender.to_owned(), header, Clock::new(sender.to_owned()));
+            msg.sent_at = Some(1_792_000_000);
+            msg
+        };
+        let messages = [
+            msg("bob", &rust, "hi"),
+            msg("bob", &rust, "borrowck"),
+            msg("carol", &rust, "hello"),
+            msg("carol", &Channel::default(), "not in rust"),
+        ];
+        let stats = ChannelStats::collect(&messages, &rust);
+        assert_eq!(stats.messages(), 3);
+        assert_eq!(stats.chars, 15);
+        assert_eq!(stats.hours.iter().sum::<usize>(), 3);
+
+        let lines = stats.render(&rust, |id| if id == "carol" { "c" } else { id });
+        assert_eq!(lines[0], "#rust: 3 messages, 5 characters long on average");
+        assert_eq!(lines[1], format!("  bob {} 2", "█".repeat(BAR_WIDTH)));
+        assert_eq!(lines[2], format!("  c   {} 1", "█".repeat(BAR_WIDTH / 2)));
+        assert_eq!(lines[3], "  Busiest hours:");
+        assert_eq!(lines.len(), 5);
+    }
+}
@@ -0,0 +1,980 @@
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::time::SystemTime;
+
+use super::mutes::parse_duration;
+use super::peers::{Grouping, Sort, GROUPINGS, SORTS};
+use super::{
+    filters, observing, push_announcement, send_chat, send_chat_via, send_to_server, App, Away,
+    Message::System,
+};
+use crate::server::acceptance::{self, POLICIES};
+use crate::server::events::Event as ServerEvent;
+use crate::server::messages::{is_valid_nick, Channel, MAX_CHANNEL_LEN, MAX_NICK_LEN};
+
+/// A command of the input field, what it takes and what it does
+pub struct Spec {
+    pub name: &'static str,
+    /// Shown after the name in its usage
+    pub args: &'static str,
+    pub help: &'static str,
+    /// Fewest and most words after the name
+    arity: (usize, usize),
+}
+
+/// Words a command takes at the least and at the most, the last may hold spaces
+const ANY: usize = usize::MAX;
+
+/// Every command, `/help` lists them in this order
+pub const COMMANDS: &[Spec] = &[
+    spec(
+        "/help",
+        "[<command>]",
+        (0, 1),
+        "list the commands, or tell how one is used",
+    ),
+    spec("/msg", "<app> <text>", (2, ANY), "send a private message"),
+    spec(
+        "/edit",
+        "<text>",
+        (1, ANY),
+        "replace the text of your last message in the tab",
+    ),
+    spec(
+        "/delete",
+        "",
+        (0, 0),
+        "take back your last message in the tab",
+    ),
+    spec(
+        "/announce",
+        "<text>",
+        (1, ANY),
+        "send an announcement, see --operator",
+    ),
+    spec(
+        "/nick",
+        "<name>",
+        (1, 1),
+        "be shown as name instead of your id",
+    ),
+    spec(
+        "/join",
+        "#<name>",
+        (1, 1),
+        "join a channel and show it in a new tab",
+    ),
+    spec(
+        "/leave",
+        "[#<channel>]",
+        (0, 1),
+        "leave the channel shown, or the one given",
+    ),
+    spec(
+        "/send",
+        "<path> [@<app>]",
+        (1, ANY),
+        "send a file to an app, or offer it in the channel shown",
+    ),
+    spec(
+        "/files",
+        "[get <hash> | accept <file> | reject <file> | policy <app> <auto | prompt | reject | default>]",
+        (0, ANY),
+        "list the files offered and in quarantine, fetch one offered, move one to the downloads or delete it, or choose what becomes of the files of an app",
+    ),
+    spec(
+        "/slow",
+        "[<seconds>]",
+        (0, 1),
+        "hold back public messages sent closer than that, 0 turns it off",
+    ),
+    spec(
+        "/via",
+        "[<transport>[,<transport>...] [<text>] | all]",
+        (0, ANY),
+        "send public messages through some transports only",
+    ),
+    spec(
+        "/mentions",
+        "[<number> | clear]",
+        (0, 1),
+        "list the mentions missed, or jump to one",
+    ),
+    spec(
+        "/alias",
+        "[<name> [<line>]]",
+        (0, ANY),
+        "make /name stand for line, list or remove aliases",
+    ),
+    spec(
+        "/away",
+        "",
+        (0, 0),
+        "count the messages received until /back",
+    ),
+    spec("/back", "", (0, 0), "sum up what was received since /away"),
+    spec("/read", "", (0, 0), "mark every message as read"),
+    spec(
+        "/mute",
+        "[[#<channel>] <duration>]",
+        (0, 2),
+        "silence a channel for 90s, 30m, 2h or 1d, the one shown by default, or list those muted",
+    ),
+    spec(
+        "/unmute",
+        "[#<channel>]",
+        (0, 1),
+        "hear from a muted channel again",
+    ),
+    spec(
+        "/peers",
+        "[group <grouping> | sort <order>]",
+        (0, 2),
+        "toggle the peer panel, or change how it groups and sorts",
+    ),
+    spec(
+        "/tag",
+        "<app> <tag>",
+        (2, 2),
+        "tag an app in the contacts, again to remove it",
+    ),
+    spec(
+        "/translate",
+        "[<app> | auto <app>]",
+        (0, 2),
+        "translate the last message received, or those of an app as they come",
+    ),
+    spec(
+        "/speak",
+        "",
+        (0, 0),
+        "stop or start reading messages out loud",
+    ),
+    spec(
+        "/outbox",
+        "[retry [<number>] | cancel <number>]",
+        (0, 2),
+        "show or hide the messages waiting to be sent, or act on them",
+    ),
+    spec(
+        "/connect",
+        "[<app> | <address>]",
+        (0, 1),
+        "chat over TCP with an app found on the local network, or list them",
+    ),
+    spec(
+        "/reconnect",
+        "[<transport>]",
+        (0, 1),
+        "retry a broken transport now",
+    ),
+    spec(
+        "/partition",
+        "[<transport>]",
+        (0, 1),
+        "stop writing to and reading from a transport",
+    ),
+    spec(
+        "/heal",
+        "[<transport>]",
+        (0, 1),
+        "let what a partition held back through",
+    ),
+    spec(
+        "/live",
+        "[<app>]",
+        (0, 1),
+        "send the line typed to an app as it changes, or stop",
+    ),
+    spec(
+        "/watch",
+        "[<regex> | clear]",
+        (0, ANY),
+        "put the messages matching in the watch panel, or show or hide it",
+    ),
+    spec("/services", "", (0, 0), "list what the apps offer"),
+    spec(
+        "/chanstats",
+        "[#<channel>]",
+        (0, 1),
+        "count the messages of a channel in the history",
+    ),
+    spec("/clock", "", (0, 0), "show the clock of the server"),
+    spec(
+        "/stats",
+        "",
+        (0, 0),
+        "count the messages the server sent, relayed, received and dropped",
+    ),
+    spec(
+        "/metrics",
+        "dump <file>",
+        (2, ANY),
+        "write the counters and queues of the server to a file as JSON",
+    ),
+    spec(
+        "/jobs",
+        "",
+        (0, 0),
+        "list the long-running operations of the server",
+    ),
+    spec("/cancel", "<job>", (1, 1), "stop a long-running operation"),
+    spec("/quit", "", (0, 0), "leave, like Ctrl+c"),
+];
+
+const fn spec(
+    name: &'static str,
+    args: &'static str,
+    arity: (usize, usize),
+    help: &'static str,
+) -> Spec {
+    Spec {
+        name,
+        args,
+        help,
+        arity,
+    }
+}
+
+impl Spec {
+    pub fn usage(&self) -> String {
+        format!("{} {}", self.name, self.args).trim_end().to_owned()
+    }
+}
+
+/// The command named `name`, with its `/`
+pub fn find(name: &str) -> Option<&'static Spec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+/// Runs a command typed in the input field, `line` starts with a `/`
+pub fn execute(app: &mut App, line: &str, server_tx: &mpsc::Sender<ServerEvent>) {
+    let expanded = expand(app, line);
+    let line = expanded.as_deref().unwrap_or(line);
+    if !line.starts_with('/') {
+        send_chat(app, None, line, server_tx);
+        return;
+    }
+    let args: Vec<&str> = line.split_whitespace().collect();
+    let spec = match find(args[0]) {
+        Some(spec) => spec,
+        None => {
+            app.messages.push(System(format!(
+                "Unknown command: {}, /help lists them",
+                args[0]
+            )));
+            return;
+        }
+    };
+    let (fewest, most) = spec.arity;
+    if args.len() - 1 < fewest || args.len() - 1 > most {
+        return usage(app, &spec.usage());
+    }
+    match args.as_slice() {
+        ["/help"] => {
+            let mut lines = vec!["Commands, Tab completes their names:".to_owned()];
+            lines.extend(
+                COMMANDS
+                    .iter()
+                    .map(|spec| format!("{}  {}", spec.usage(), spec.help)),
+            );
+            if !app.aliases.is_empty() {
+                let aliases: Vec<String> = app.aliases.keys().map(|a| format!("/{}", a)).collect();
+                lines.push(format!("Aliases: {}", aliases.join(" ")));
+            }
+            app.messages.push(System(lines.join("\n")));
+        }
+        ["/help", name] => {
+            let name = format!("/{}", name.trim_start_matches('/'));
+            let notice = match (find(&name), app.aliases.get(&name[1..])) {
+                (Some(spec), _) => format!("Usage: {}, {}", spec.usage(), spec.help),
+                (None, Some(expansion)) => format!("{} -> {}", name, expansion),
+                (None, None) => format!("Unknown command: {}, /help lists them", name),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/msg", to, _, ..] => send_chat(app, Some(to), after_words(line, 2), server_tx),
+        ["/announce", _, ..] => announce(app, after_words(line, 1), server_tx),
+        ["/edit", _, ..] => change_last(app, Some(after_words(line, 1)), server_tx),
+        ["/delete"] => change_last(app, None, server_tx),
+        ["/nick", _] | ["/send", ..] | ["/slow", _] if observing(app) => {}
+        ["/nick", nick] if is_valid_nick(nick) => {
+            app.messages
+                .push(System(format!("You are now known as {}", nick)));
+            send_to_server(ServerEvent::UserNick((*nick).to_owned()), server_tx);
+        }
+        ["/nick", ..] => usage(
+            app,
+            &format!(
+                "/nick <name>, one word of {} characters at most",
+                MAX_NICK_LEN
+            ),
+        ),
+        ["/send", _, .., to] if to.starts_with('@') => {
+            let path = after_words(line, 1);
+            let path = path[..path.len() - to.len()].trim_end();
+            app.messages
+                .push(System(format!("Sending {} to {}", path, &to[1..])));
+            let event = ServerEvent::UserFile(PathBuf::from(path), to[1..].to_owned());
+            send_to_server(event, server_tx);
+        }
+        ["/send", _, ..] => {
+            let path = after_words(line, 1);
+            let channel = app.tabs.channel().clone();
+            app.messages
+                .push(System(format!("Offering {} in {}", path, channel)));
+            let event = ServerEvent::UserOffer(PathBuf::from(path), channel);
+            send_to_server(event, server_tx);
+        }
+        ["/files"] => send_to_server(ServerEvent::ListFiles, server_tx),
+        ["/files", "get", hash] => {
+            app.messages.push(System(format!("Fetching {}", hash)));
+            send_to_server(ServerEvent::GetFile((*hash).to_owned()), server_tx);
+        }
+        ["/files", "accept", _, ..] => {
+            let name = after_words(line, 2).to_owned();
+            send_to_server(ServerEvent::AcceptFile(name), server_tx);
+        }
+        ["/files", "reject", _, ..] => {
+            let name = after_words(line, 2).to_owned();
+            send_to_server(ServerEvent::RejectFile(name), server_tx);
+        }
+        ["/files", "policy", app_id, name] => {
+            let policy = acceptance::Policy::by_name(name);
+            if policy.is_some() || *name == "default" {
+                app.messages.push(System(format!(
+                    "Files of {}: {}",
+                    app_id,
+                    policy.map_or("as --accept-files", |policy| policy.name())
+                )));
+                let event = ServerEvent::FilePolicy((*app_id).to_owned(), policy);
+                send_to_server(event, server_tx);
+            } else {
+                let policies = POLICIES.join(" | ");
+                usage(
+                    app,
+                    &format!("/files policy <app> <{} | default>", policies),
+                );
+            }
+        }
+        ["/slow"] => {
+            let channel = app.tabs.channel();
+            let notice = match app.slow.interval(channel) {
+                Some(interval) => format!(
+                    "Slow mode in {}: one public message every {} seconds",
+                    channel,
+                    interval.as_secs()
+                ),
+                None => format!("No slow mode in {}", channel),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/slow", seconds] => match seconds.parse() {
+            Ok(seconds) => {
+                let channel = app.tabs.channel().clone();
+                let notice = match seconds {
+                    0 => format!("Slow mode off in {}", channel),
+                    _ => format!(
+                        "Slow mode in {}: one public message every {} seconds",
+                        channel, seconds
+                    ),
+                };
+                app.messages.push(System(format!(
+                    "{}, for the apps started with --operator <your id>",
+                    notice
+                )));
+                app.slow.set(channel.clone(), seconds);
+                send_to_server(ServerEvent::UserSlowMode(channel, seconds), server_tx);
+            }
+            Err(_) => usage(app, "/slow <seconds>, 0 turns it off"),
+        },
+        ["/join", name] => match Channel::parse(name) {
+            Some(channel) => {
+                let index = app.tabs.open(channel.clone());
+                app.show_tab(index);
+                app.messages.push(System(format!(
+                    "Joined {}, Alt+{} shows it",
+                    channel,
+                    index + 1
+                )));
+                send_to_server(ServerEvent::JoinChannel(channel), server_tx);
+            }
+            None => usage(
+                app,
+                &format!("/join #<name>, {} characters at most", MAX_CHANNEL_LEN),
+            ),
+        },
+        ["/leave", ..] => {
+            let channel = match args.get(1) {
+                Some(name) => Channel::parse(name),
+                None => Some(app.tabs.channel().clone()),
+            };
+            // #general comes back with its draft
+            if channel.as_ref() == Some(app.tabs.channel()) {
+                app.show_tab(0);
+            }
+            match channel {
+                Some(channel) if channel.is_general() => app
+                    .messages
+                    .push(System("Everyone stays in #general".to_owned())),
+                Some(channel)
+                    if app.tabs.close(
+                        &channel,
+                        &mut app.messages,
+                        &mut app.first_display_message_id,
+                    ) =>
+                {
+                    app.messages.push(System(format!("Left {}", channel)));
+                    app.mentions.forget(&channel);
+                    app.drafts.forget(&channel);
+                    send_to_server(ServerEvent::LeaveChannel(channel), server_tx);
+                }
+                _ => usage(app, "/leave [#<channel joined>]"),
+            }
+        }
+        ["/mentions"] if app.mentions.is_empty() => {
+            app.messages.push(System("No mentions".to_owned()));
+        }
+        ["/mentions"] => {
+            let mut lines = vec![format!(
+                "{} mentions, /mentions <number> jumps to one, /mentions clear forgets them",
+                app.mentions.len()
+            )];
+            for (n, mention) in app.mentions.iter() {
+                let first_line = mention.line.lines().next().unwrap_or_default();
+                lines.push(format!("{}. {} {}", n, mention.channel, first_line));
+            }
+            for line in lines {
+                app.messages.push(System(line));
+            }
+        }
+        ["/mentions", "clear"] => app.mentions.clear(),
+        ["/mentions", n] => match n.parse().ok().and_then(|n| app.mentions.get(n)) {
+            Some(mention) => {
+                let position = mention.position;
+                if let Some(tab) = app.tabs.position(&mention.channel) {
+                    app.show_tab(tab);
+                }
+                // The mention at the bottom, the messages after it hidden until scrolled down
+                let skip = app.messages.len().saturating_sub(position + 1);
+                app.scroll_to(skip, 0);
+            }
+            None => usage(app, "/mentions [<number> | clear]"),
+        },
+        ["/alias"] if app.aliases.is_empty() => {
+            app.messages.push(System(
+                "No alias, /alias <name> <line> defines one".to_owned(),
+            ));
+        }
+        ["/alias"] => {
+            for (name, expansion) in &app.aliases {
+                app.messages
+                    .push(System(format!("/{} -> {}", name, expansion)));
+            }
+        }
+        ["/alias", name] => {
+            let name = name.trim_start_matches('/');
+            if app.aliases.remove(name).is_some() {
+                app.messages.push(System(format!("Removed /{}", name)));
+            }
+        }
+        ["/alias", name, ..] => {
+            let name = name.trim_start_matches('/').to_owned();
+            let expansion = after_words(line, 2).to_owned();
+            app.messages
+                .push(System(format!("/{} -> {}", name, expansion)));
+            app.aliases.insert(name, expansion);
+        }
+        ["/outbox"] => {
+            app.show_outbox = !app.show_outbox;
+        }
+        ["/outbox", "retry"] => {
+            send_to_server(ServerEvent::RetryOutbox(None), server_tx);
+        }
+        ["/outbox", "retry", seq] => match seq.trim_start_matches('#').parse() {
+            Ok(seq) => send_to_server(ServerEvent::RetryOutbox(Some(seq)), server_tx),
+            Err(_) => usage(app, "/outbox retry [<number>]"),
+        },
+        ["/outbox", "cancel", seq] => match seq.trim_start_matches('#').parse() {
+            Ok(seq) => send_to_server(ServerEvent::CancelOutbox(seq), server_tx),
+            Err(_) => usage(app, "/outbox cancel <number>"),
+        },
+        ["/connect"] => {
+            let notice = match app.discovered.is_empty() {
+                true => "No app found on the local network, is --discover on?".to_owned(),
+                false => app
+                    .discovered
+                    .iter()
+                    .map(|(app_id, addr)| format!("{} at {}", app_id, addr))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/connect", to] => {
+            let addr = match app.discovered.get(*to) {
+                Some(addr) => Some(addr.to_string()),
+                None => Some((*to).to_owned()).filter(|to| to.contains(':')),
+            };
+            let notice = match addr {
+                Some(addr) => {
+                    send_to_server(ServerEvent::Connect(addr.clone()), server_tx);
+                    format!("Connecting to {}", addr)
+                }
+                None => format!("{} was not found on the local network", to),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/reconnect"] => {
+            send_to_server(ServerEvent::Reconnect(None), server_tx);
+        }
+        ["/reconnect", name] => {
+            send_to_server(ServerEvent::Reconnect(Some(name.to_string())), server_tx);
+        }
+        ["/partition"] => send_to_server(ServerEvent::Partition(None), server_tx),
+        ["/partition", name] => {
+            send_to_server(ServerEvent::Partition(Some(name.to_string())), server_tx);
+        }
+        ["/heal"] => send_to_server(ServerEvent::Heal(None), server_tx),
+        ["/heal", name] => {
+            send_to_server(ServerEvent::Heal(Some(name.to_string())), server_tx);
+        }
+        ["/peers"] => app.peers.shown = !app.peers.shown,
+        ["/peers", "group", grouping] => match Grouping::by_name(grouping) {
+            Some(grouping) => {
+                app.peers.grouping = grouping;
+                app.peers.shown = true;
+            }
+            None => usage(app, &format!("/peers group <{}>", GROUPINGS.join(" | "))),
+        },
+        ["/peers", "sort", sort] => match Sort::by_name(sort) {
+            Some(sort) => {
+                app.peers.sort = sort;
+                app.peers.shown = true;
+            }
+            None => usage(app, &format!("/peers sort <{}>", SORTS.join(" | "))),
+        },
+        ["/tag", app_id, tag] => {
+            let on = app.peers.toggle_tag(app_id, tag);
+            let verb = if on { "Tagged" } else { "Untagged" };
+            app.messages
+                .push(System(format!("{} {} {}", verb, app_id, tag)));
+            let event = ServerEvent::Tag(app_id.to_string(), tag.to_string(), on);
+            send_to_server(event, server_tx);
+        }
+        ["/translate", ..] if app.translator.is_none() => app.messages.push(System(
+            "No translation, start the app with --translate-command <command>".to_owned(),
+        )),
+        ["/translate", "auto", sender] => {
+            let on = app
+                .translator
+                .as_mut()
+                .is_some_and(|t| t.toggle_auto(sender));
+            let notice = match on {
+                true => format!("The messages of {} are translated as they come", sender),
+                false => format!("The messages of {} are no longer translated", sender),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/translate"] | ["/translate", _] => {
+            let sender = args.get(1).copied();
+            if !app
+                .translator
+                .as_ref()
+                .is_some_and(|t| t.translate_last(sender))
+            {
+                let notice = match sender {
+                    Some(sender) => format!("Nothing received from {}", sender),
+                    None => "Nothing received yet".to_owned(),
+                };
+                app.messages.push(System(notice));
+            }
+        }
+        ["/speak"] => {
+            let notice = match &mut app.speaker {
+                Some(speaker) => {
+                    speaker.muted = !speaker.muted;
+                    match speaker.muted {
+                        true => "Messages are no longer read out loud",
+                        false => "Messages are read out loud again",
+                    }
+                }
+                None => "No speech, start the app with --speak-command <command>",
+            };
+            app.messages.push(System(notice.to_owned()));
+        }
+        ["/via"] => {
+            let channel = app.tabs.channel();
+            let notice = match app.via.get(channel) {
+                Some(via) => format!("Public messages of {} go via {}", channel, via.join(", ")),
+                None => format!("Public messages of {} go through every transport", channel),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/via", "all"] => {
+            let channel = app.tabs.channel().clone();
+            app.via.remove(&channel);
+            app.messages.push(System(format!(
+                "Public messages of {} go through every transport",
+                channel
+            )));
+        }
+        ["/via", names, ..] => {
+            let via: Vec<String> = names.split(',').map(str::to_owned).collect();
+            let unknown: Vec<&str> = via
+                .iter()
+                .filter(|name| !app.connections.iter().any(|c| c.name == **name))
+                .map(String::as_str)
+                .collect();
+            if !unknown.is_empty() {
+                let known: Vec<&str> = app.connections.iter().map(|c| c.name.as_str()).collect();
+                app.messages.push(System(format!(
+                    "No transport named {}, there are {}",
+                    unknown.join(", "),
+                    known.join(", ")
+                )));
+                return;
+            }
+            match after_words(line, 2) {
+                "" => {
+                    let channel = app.tabs.channel().clone();
+                    app.messages.push(System(format!(
+                        "Public messages of {} go via {} from now on, /via all undoes it",
+                        channel,
+                        via.join(", ")
+                    )));
+                    app.via.insert(channel, via);
+                }
+                text => send_chat_via(app, None, text, Some(via), server_tx),
+            }
+        }
+        ["/away"] => away(app),
+        ["/back"] => back(app),
+        ["/read"] => app.unread.mark_read(),
+        ["/services"] => send_to_server(ServerEvent::GetServices, server_tx),
+        ["/chanstats"] => {
+            let channel = app.tabs.channel().clone();
+            send_to_server(ServerEvent::GetChannelStats(channel), server_tx);
+        }
+        ["/chanstats", name] => match Channel::parse(name) {
+            Some(channel) => send_to_server(ServerEvent::GetChannelStats(channel), server_tx),
+            None => usage(app, "/chanstats [#<channel>]"),
+        },
+        ["/jobs"] if app.jobs.is_empty() => app.messages.push(System("No jobs".to_owned())),
+        ["/jobs"] => {
+            for (op_id, job) in app.jobs.iter() {
+                app.messages.push(System(format!(
+                    "{}: {} {}/{}",
+                    op_id, job.label, job.done, job.total
+                )));
+            }
+        }
+        ["/cancel", op_id] => {
+            app.jobs.hide(op_id);
+            send_to_server(ServerEvent::CancelJob((*op_id).to_owned()), server_tx);
+        }
+        ["/live"] => {
+            let notice = match app.live.stop() {
+                Some(to) => {
+                    send_to_server(
+                        ServerEvent::UserLiveLine(to.clone(), String::new()),
+                        server_tx,
+                    );
+                    format!("The line typed is no longer sent to {}", to)
+                }
+                None => "Live line mode is off, /live <app> turns it on".to_owned(),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/live", to] if app.revoked.contains(*to) => {
+            app.messages.push(System(format!(
+                "Not sent: the identity of {} was revoked",
+                to
+            )));
+        }
+        ["/live", _] if observing(app) => {}
+        ["/live", to] => {
+            if let Some(before) = app.live.stop() {
+                send_to_server(ServerEvent::UserLiveLine(before, String::new()), server_tx);
+            }
+            app.live.start((*to).to_owned());
+            app.messages.push(System(format!(
+                "The line typed is sent to {} as it changes, /live stops it",
+                to
+            )));
+        }
+        ["/watch"] => {
+            let patterns: Vec<&str> = app.watches.patterns().collect();
+            let notice = match patterns.is_empty() {
+                true => "Nothing watched, /watch <regex> adds an expression".to_owned(),
+                false => format!("Watching {}", patterns.join(", ")),
+            };
+            app.watches.shown = !app.watches.shown;
+            app.messages.push(System(notice));
+        }
+        ["/watch", "clear"] => {
+            app.watches.clear();
+            app.messages
+                .push(System("Nothing watched anymore".to_owned()));
+        }
+        ["/watch", ..] => {
+            let pattern = after_words(line, 1);
+            let notice = match app.watches.add(pattern) {
+                Ok(()) => format!("Messages matching {} go to the watch panel", pattern),
+                Err(e) => format!("Not a regex: {}", e),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/mute"] => {
+            let now = SystemTime::now();
+            let muted: Vec<String> = app
+                .mutes
+                .iter(now)
+                .filter(|(_, left)| !left.is_zero())
+                .map(|(channel, left)| format!("{} for {} min", channel, left.as_secs() / 60 + 1))
+                .collect();
+            let notice = match muted.is_empty() {
+                true => "No channel muted, /mute <duration> mutes the one shown".to_owned(),
+                false => format!("Muted: {}", muted.join(", ")),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/mute", duration] => {
+            let channel = app.tabs.channel().clone();
+            mute(app, channel, duration);
+        }
+        ["/mute", channel, duration] => match Channel::parse(channel) {
+            Some(channel) => mute(app, channel, duration),
+            None => usage(app, "/mute [[#<channel>] <duration>]"),
+        },
+        ["/unmute", ..] => {
+            let channel = match args.get(1) {
+                Some(channel) => Channel::parse(channel),
+                None => Some(app.tabs.channel().clone()),
+            };
+            let notice = match channel {
+                Some(channel) if app.mutes.unmute(&channel) => {
+                    format!("{} is not muted anymore", channel)
+                }
+                Some(channel) => format!("{} is not muted", channel),
+                None => "Not a channel, give it as #<name>".to_owned(),
+            };
+            app.messages.push(System(notice));
+        }
+        ["/clock"] => send_to_server(ServerEvent::GetClock, server_tx),
+        ["/stats"] => send_to_server(ServerEvent::GetStats, server_tx),
+        ["/metrics", "dump", ..] => {
+            let path = PathBuf::from(after_words(line, 2));
+            send_to_server(ServerEvent::DumpMetrics(path), server_tx);
+        }
+        ["/quit"] => app.quit = true,
+        _ => usage(app, &spec.usage()),
+    }
+}
+
+/// Completes the command name typed in the input field: to the only one it
+/// starts, or as far as those it starts agree, which are then listed
+pub fn complete(app: &mut App) {
+    let typed = app.input.as_str();
+    let mut names: Vec<String> = COMMANDS
+        .iter()
+        .map(|spec| spec.name.to_owned())
+        .chain(app.aliases.keys().map(|alias| format!("/{}", alias)))
+        .filter(|name| name.starts_with(typed))
+        .collect();
+    names.sort();
+    names.dedup();
+    match names.as_slice() {
+        [] => {}
+        [name] => app.input = format!("{} ", name),
+        [first, others @ ..] => {
+            let common = others.iter().fold(first.as_str(), |common, name| {
+                let len = common
+                    .chars()
+                    .zip(name.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a.len_utf8())
+                    .sum();
+                &common[..len]
+            });
+            if common.len() > typed.len() {
+                app.input = common.to_owned();
+            } else {
+                app.messages.push(System(names.join(" ")));
+            }
+        }
+    }
+}
+
+/// `line` with the alias it starts with replaced, the arguments are kept.
+/// The expansion is not expanded again.
+fn expand(app: &App, line: &str) -> Option<String> {
+    let (name, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let expansion = app.aliases.get(name.strip_prefix('/')?)?;
+    Some(format!("{} {}", expansion, rest).trim_end().to_owned())
+}
+
+/// What follows the `n` first words of `line`
+fn after_words(line: &str, n: usize) -> &str {
+    let mut rest = line.trim_start();
+    for _ in 0..n {
+        rest = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .map_or("", |(_, rest)| rest);
+    }
+    rest.trim_start()
+}
+
+/// Sends `text` as an announcement, never split: it is shown as one banner
+fn announce(app: &mut App, text: &str, server_tx: &mpsc::Sender<ServerEvent>) {
+    if observing(app) {
+        return;
+    }
+    let text = filters::apply(&app.filters, text);
+    if text.chars().count() > app.limits.max_text_len {
+        app.messages.push(System(format!(
+            "Not sent: the announcement is {} characters long, the limit is {}",
+            text.chars().count(),
+            app.limits.max_text_len
+        )));
+        return;
+    }
+    app.unread.mark_read();
+    push_announcement(&mut app.messages, "You announce: ".to_owned(), &text);
+    send_to_server(ServerEvent::UserAnnouncement(text), server_tx);
+}
+
+/// Edits our last public message in the tab with `text`, or deletes it for
+/// None. It is changed once the server sent the change.
+fn change_last(app: &mut App, text: Option<&str>, server_tx: &mpsc::Sender<ServerEvent>) {
+    if observing(app) {
+        return;
+    }
+    let channel = app.tabs.channel().clone();
+    let id = match app.edits.last_ours(&channel) {
+        Some(id) => id,
+        None => {
+            let what = if text.is_some() { "edit" } else { "delete" };
+            app.messages.push(System(format!(
+                "No message of yours to {} in {}",
+                what, channel
+            )));
+            return;
+        }
+    };
+    let event = match text {
+        Some(text) => {
+            let text = filters::apply(&app.filters, text);
+            if text.chars().count() > app.limits.max_text_len {
+                app.messages.push(System(format!(
+                    "Not changed: the message is {} characters long, the limit is {}",
+                    text.chars().count(),
+                    app.limits.max_text_len
+                )));
+                return;
+            }
+            ServerEvent::UserEdit(id, text)
+        }
+        None => ServerEvent::UserDelete(id),
+    };
+    send_to_server(event, server_tx);
+}
+
+/// Counts the messages received until `/back`
+pub fn away(app: &mut App) {
+    app.away = Some(Away::default());
+    app.messages.push(System(
+        "Away, messages received until /back are counted".to_owned(),
+    ));
+}
+
+/// Sums up what was received since `/away`
+pub fn back(app: &mut App) {
+    let notice = match app.away.take() {
+        None => "You were not away".to_owned(),
+        Some(away) if away.senders.is_empty() => "Back, nothing was received meanwhile".to_owned(),
+        Some(away) => {
+            let senders: Vec<_> = away
+                .senders
+                .iter()
+                .map(|(sender, count)| format!("{} from {}", count, sender))
+                .collect();
+            format!(
+                "*** While you were away: {}, Ctrl+u jumps to the first unread message ***",
+                senders.join(", ")
+            )
+        }
+    };
+    app.messages.push(System(notice));
+}
+
+fn mute(app: &mut App, channel: Channel, duration: &str) {
+    let notice = match parse_duration(duration) {
+        Some(length) => {
+            app.mutes.mute(channel.clone(), length, SystemTime::now());
+            format!(
+                "{} is muted for {}, /unmute {} ends it",
+                channel, duration, channel
+            )
+        }
+        None => format!("Not a duration: {}, expected 90s, 30m, 2h or 1d", duration),
+    };
+    app.messages.push(System(notice));
+}
+
+fn usage(app: &mut App, usage: &str) {
+    app.messages.push(System(format!("Usage: {}", usage)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_keep_their_arguments() {
+        let mut app = App::default();
+        app.aliases
+            .insert("std".to_owned(), "/msg bob standup in 5".to_owned());
+        assert_eq!(
+            expand(&app, "/std sharp").as_deref(),
+            Some("/msg bob standup in 5 sharp")
+        );
+        assert_eq!(expand(&app, "/outbox"), None);
+        assert_eq!(expand(&app, "std"), None, "only commands are aliases");
+        assert_eq!(after_words("/msg  bob  ship it", 2), "ship it");
+    }
+
+    #[test]
+    fn commands_are_checked_then_completed() {
+        let mut app = App::default();
+        let (server_tx, _server_rx) = mpsc::channel();
+        let last = |app: &mut App, line: &str| {
+            execute(app, line, &server_tx);
+            app.messages.window(0, 1)[0].str().to_owned()
+        };
+        assert_eq!(last(&mut app, "/tag bob"), "Usage: /tag <app> <tag>");
+        assert_eq!(last(&mut app, "/read now"), "Usage: /read");
+        assert_eq!(
+            last(&mut app, "/nope"),
+            "Unknown command: /nope, /help lists them"
+        );
+        assert!(last(&mut app, "/help tag").starts_with("Usage: /tag <app> <tag>, "));
+        assert_eq!(last(&mut app, "/help").lines().count(), COMMANDS.len() + 1);
+
+        let completed = |app: &mut App, typed: &str| {
+            app.input = typed.to_owned();
+            complete(app);
+            app.input.clone()
+        };
+        assert_eq!(completed(&mut app, "/hel"), "/help ");
+        assert_eq!(completed(&mut app, "/j"), "/jo", "/jobs and /join");
+        app.aliases
+            .insert("std".to_owned(), "/msg bob standup".to_owned());
+        assert_eq!(completed(&mut app, "/st"), "/st", "/stats and /std");
+        assert_eq!(app.messages.window(0, 1)[0], "/stats /std");
+        assert_eq!(completed(&mut app, "/std"), "/std ");
+        assert_eq!(completed(&mut app, "/re"), "/re");
+        assert_eq!(app.messages.window(0, 1)[0], "/read /reconnect");
+    }
+}
@@ -0,0 +1,114 @@
+//! Chat history written to one file per day, irssi style, so that
+//! `grep decided history/2026-10-*.log` finds what was said when
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Date and time in the local time zone
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalTime {
+    pub year: i32,
+    /// From 1 to 12
+    pub month: u32,
+    pub day: u32,
+    /// From 0, Sunday, to 6
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+}
+
+impl LocalTime {
+    pub fn now() -> LocalTime {
+        LocalTime::at(unsafe { libc::time(std::ptr::null_mut()) })
+    }
+
+    /// The local time `secs` seconds after the Unix epoch
+    pub fn at(secs: libc::time_t) -> LocalTime {
+        let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+        unsafe {
+            libc::localtime_r(&secs, &mut tm);
+        }
+        LocalTime {
+            year: tm.tm_year + 1900,
+            month: tm.tm_mon as u32 + 1,
+            day: tm.tm_mday as u32,
+            weekday: tm.tm_wday as u32,
+            hour: tm.tm_hour as u32,
+            minute: tm.tm_min as u32,
+        }
+    }
+
+    /// `2026-10-14`, the name of the day's file
+    pub fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+
+    /// `Wednesday 14 October 2026`
+    pub fn long_date(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            WEEKDAYS[self.weekday as usize % 7],
+            self.day,
+            MONTHS[(self.month as usize + 11) % 12],
+            self.year
+        )
+    }
+
+    /// `09:05`
+    pub fn time(&self) -> String {
+        format!("{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+/// Appends lines to `<dir>/<date>.log`, the file changes with the day
+pub struct DayLog {
+    dir: PathBuf,
+    file: Option<(String, File)>, // Date of the open file
+}
+
+impl DayLog {
+    pub fn open(dir: PathBuf) -> io::Result<DayLog> {
+        fs::create_dir_all(&dir)?;
+        Ok(DayLog { dir, file: None })
+    }
+
+    pub fn write(&mut self, at: &LocalTime, line: &str) -> io::Result<()> {
+        let date = at.date();
+        if self.file.as_ref().map(|(d, _)| d) != Some(&date) {
+            let path = self.dir.join(format!("{}.log", date));
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            self.file = Some((date, file));
+        }
+        let (_, file) = self.file.as_mut().expect("opened above");
+        // Every line of a multi-line message gets the time, for grep
+        for line in line.split('\n') {
+            writeln!(file, "{} {}", at.time(), line)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+//! What is left in the input field, a draft per tab: switching tabs puts the
+//! draft of the tab left aside and brings back the one of the tab shown. The
+//! drafts are saved on exit, those of channels not joined yet come back with
+//! their tab.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::PathBuf;
+
+use crate::server::messages::Channel;
+
+#[derive(Default)]
+pub struct Drafts {
+    path: Option<PathBuf>,
+    /// Drafts of the tabs not shown, never empty
+    by_channel: BTreeMap<Channel, String>,
+}
+
+impl Drafts {
+    /// Reads the drafts saved in `path`, they are saved there on exit
+    pub fn load(path: PathBuf) -> Self {
+        let by_channel = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Drafts {
+            path: Some(path),
+            by_channel,
+        }
+    }
+
+    /// The draft of `channel`, kept by the input field from now on
+    pub fn take(&mut self, channel: &Channel) -> String {
+        self.by_channel.remove(channel).unwrap_or_default()
+    }
+
+    /// Keeps `input` as the draft of `from`, replacing it with the draft of `to`
+    pub fn switch(&mut self, from: &Channel, to: &Channel, input: &mut String) {
+        let left = mem::replace(input, self.take(to));
+        if !left.is_empty() {
+            self.by_channel.insert(from.clone(), left);
+        }
+    }
+
+    /// Drops the draft of `channel`, once left
+    pub fn forget(&mut self, channel: &Channel) {
+        self.by_channel.remove(channel);
+    }
+
+    /// Saves the drafts, with `input` as the one of `shown`
+    pub fn save(&self, shown: &Channel, input: &str) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut drafts = self.by_channel.clone();
+        if !input.is_empty() {
+            drafts.insert(shown.clone(), input.to_owned());
+        }
+        let saved = if drafts.is_empty() {
+            fs::remove_file(path).or_else(|e| match e.kind() {
+                io::ErrorKind::NotFound => Ok(()),
+                _ => Err(e.to_string()),
+            })
+        } else {
+            serde_json::to_string(&drafts)
+                .map_err(|e| e.to_string())
+                .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()))
+        };
+        if let Err(e) = saved {
+            log::error!("Could not save the drafts: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_tab_gets_its_draft_back_across_runs() {
+        let path = std::env::temp_dir().join(format!("netchat-test-{}.drafts", std::process::id()));
+        let general = Channel::default();
+        let rust = Channel::parse("#rust").unwrap();
+        let mut drafts = Drafts::load(path.clone());
+        let mut input = "half a sentence".to_owned();
+        drafts.switch(&general, &rust, &mut input);
+        assert_eq!(input, "");
+        input.push_str("about lifetimes");
+        drafts.switch(&rust, &general, &mut input);
+        assert_eq!(input, "half a sentence");
+        drafts.save(&general, &input);
+
+        // Restarted in #general, #rust joined again later
+        let mut drafts = Drafts::load(path.clone());
+        let mut input = drafts.take(&general);
+        assert_eq!(input, "half a sentence");
+        input.clear();
+        drafts.switch(&general, &rust, &mut input);
+        assert_eq!(input, "about lifetimes");
+        drafts.switch(&rust, &general, &mut input);
+        assert_eq!(input, "", "an empty draft is not kept");
+        drafts.forget(&rust);
+        drafts.save(&general, &input);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,112 @@
+//! Public messages shown, by id: where they are, for their edits and
+//! deletions to change them in place. Ours are found once the server gave
+//! them an id, `/edit` and `/delete` change the last one of the tab.
+
+use std::collections::VecDeque;
+
+use super::receipts::Place;
+use crate::server::messages::{Channel, MsgId};
+
+/// Messages kept above this, the oldest are not changed anymore
+const MAX_SHOWN: usize = 1024;
+
+struct Shown {
+    id: MsgId,
+    place: Place,
+    prefix: String, // Shown before the text, kept by an edit
+    ours: bool,
+}
+
+#[derive(Default)]
+pub struct Edits {
+    /// Ours, shown as typed before the server gave them an id: text, place
+    /// and prefix
+    unsent: VecDeque<(String, Place, String)>,
+    shown: VecDeque<Shown>,
+}
+
+impl Edits {
+    /// Public message `id` was shown at `place`, after `prefix`
+    pub fn shown(&mut self, id: MsgId, place: Place, prefix: String) {
+        self.keep(Shown {
+            id,
+            place,
+            prefix,
+            ours: false,
+        });
+    }
+
+    /// A public message of ours with `text` was shown at `place`
+    pub fn typed(&mut self, text: &str, place: Place, prefix: String) {
+        if self.unsent.len() >= MAX_SHOWN {
+            self.unsent.pop_front();
+        }
+        self.unsent.push_back((text.to_owned(), place, prefix));
+    }
+
+    /// The server sent our public message of `text` in `channel` as `id`
+    pub fn sent(&mut self, id: MsgId, channel: &Channel, text: &str) {
+        let typed = self
+            .unsent
+            .iter()
+            .position(|(t, (c, _), _)| t == text && c == channel);
+        if let Some((_, place, prefix)) = typed.and_then(|i| self.unsent.remove(i)) {
+            self.keep(Shown {
+                id,
+                place,
+                prefix,
+                ours: true,
+            });
+        }
+    }
+
+    /// Our last public message in the tab of `channel`
+    pub fn last_ours(&self, channel: &Channel) -> Option<MsgId> {
+        self.shown
+            .iter()
+            .rev()
+            .find(|shown| shown.ours && shown.place.0 == *channel)
+            .map(|shown| shown.id)
+    }
+
+    /// Where message `id` is shown, with the prefix of its text
+    pub fn place(&self, id: MsgId) -> Option<(&Place, &str)> {
+        let shown = self.shown.iter().find(|shown| shown.id == id)?;
+        Some((&shown.place, &shown.prefix))
+    }
+
+    /// Message `id` was deleted, it is not changed anymore
+    pub fn forget(&mut self, id: MsgId) {
+        self.shown.retain(|shown| shown.id != id);
+    }
+
+    fn keep(&mut self, shown: Shown) {
+        if self.shown.len() >= MAX_SHOWN {
+            self.shown.pop_front();
+        }
+        self.shown.push_back(shown);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn our_last_message_is_found_once_sent() {
+        let (general, rust) = (Channel::default(), Channel::parse("#rust").unwrap());
+        let mut edits = Edits::default();
+        edits.typed("hi", (general.clone(), 0), "You: ".to_owned());
+        edits.shown(7, (general.clone(), 1), "bob: ".to_owned());
+        assert_eq!(edits.last_ours(&general), None, "no id yet");
+
+        edits.sent(3, &general, "hi");
+        assert_eq!(edits.last_ours(&general), Some(3));
+        assert_eq!(edits.last_ours(&rust), None);
+        assert_eq!(edits.place(7), Some((&(general.clone(), 1), "bob: ")));
+
+        edits.forget(3);
+        assert_eq!(edits.last_ours(&general), None);
+        assert_eq!(edits.place(3), None);
+    }
+}
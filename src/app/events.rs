@@ -1,32 +1,123 @@
+use std::collections::BTreeMap;
 use std::io;
-use std::sync::mpsc;
+use std::net::SocketAddr;
 use std::thread;
 use std::time::Duration;
 
-use termion::event::Key;
+use termion::event::{Event as TermEvent, Key};
 use termion::input::TermRead;
 
-use crate::server::messages::Msg;
+use crate::app::channel::Receiver;
+use crate::app::chanstats::ChannelStats;
+use crate::app::translate::Target;
+use crate::app::AppId;
+use crate::server::messages::{Channel, Msg, MsgId};
+use crate::server::notice::Notice;
 use crate::server::Clock;
+use crate::server::{outbox, reconnect};
 
 pub enum Event {
     /// User input (keypress)
     UserInput(Key),
+    /// Text pasted in the terminal, in one piece
+    Paste(String),
     /// Message from another app (write in a file)
-    DistantMessage(Msg),
-    /// Information from the server
-    ServerMessage(String),
+    DistantMessage(Box<Msg>),
+    /// Information, warning or error from the server
+    Notice(Notice),
+    /// A chat message nobody acknowledged, however many times it was sent
+    DeliveryFailed(String),
+    /// Id given to a private message typed, with its recipient and text
+    PrivateSent(MsgId, AppId, String),
+    /// The recipient of a private message was shown it
+    Receipt(MsgId),
+    /// Id given to a public message typed, with its channel and text
+    PublicSent(MsgId, Channel, String),
+    /// New text of a public message, from its sender
+    Edited(MsgId, String),
+    /// A public message was taken back by its sender
+    Deleted(MsgId),
     /// Periodically send tick a to refresh the UI
     Tick,
     /// Display vector clock
     DisplayClock(Clock),
+    /// Display what each app offers
+    DisplayServices(BTreeMap<AppId, Vec<String>>),
+    /// Display the counters of the server, by name
+    DisplayStats(Vec<(&'static str, u64)>),
+    /// Display who spoke how much in a channel
+    DisplayChannelStats(Channel, ChannelStats),
+    /// What `--translate-command` made of a message, or why it failed
+    Translated(Target, Result<String, String>),
+    /// The identity was declared compromised by its owner
+    IdentityRevoked(AppId),
+    /// An app was heard from, after it joined, left or went silent
+    PeerOnline(AppId),
+    /// An app left or was silent for too long
+    PeerOffline(AppId),
+    /// An app set the name it is shown as
+    Nick(AppId, String),
+    /// An operator set the seconds between two public messages of a sender in
+    /// a channel, 0 for none
+    SlowMode(AppId, Channel, u64),
+    /// An app is composing a message, shown until it sends it or a few seconds pass
+    Typing(AppId),
+    /// The line an app is typing for us in its live line mode
+    LiveLine(AppId, String),
+    /// Messages exchanged by another device of our identity
+    History(Vec<Msg>),
+    /// Current content of the outbox
+    Outbox(Vec<outbox::Item>),
+    /// An app listening on the local network, found by `--discover`
+    Discovered(AppId, SocketAddr),
+    /// A transport connected, disconnected or is being reconnected
+    Connection(reconnect::Change),
+    /// How far a long-running operation is, it ends once `done` reaches `total`
+    Progress {
+        op_id: String,
+        label: String,
+        done: u64,
+        total: u64,
+    },
 }
 
+/// What to do with an event when the UI falls behind
+#[derive(PartialEq)]
+pub enum Policy<'a> {
+    /// Never dropped: input, chat messages and notices
+    Keep,
+    /// Dropped, oldest first, once the queue is full
+    Droppable,
+    /// Replaces the queued event of the same kind and name
+    Latest(&'static str, &'a str),
+}
+
+impl Event {
+    pub fn policy(&self) -> Policy<'_> {
+        match self {
+            Event::Tick => Policy::Droppable,
+            Event::DisplayClock(_) => Policy::Latest("clock", ""),
+            Event::DisplayServices(_) => Policy::Latest("services", ""),
+            Event::DisplayStats(_) => Policy::Latest("stats", ""),
+            Event::DisplayChannelStats(..) => Policy::Latest("chanstats", ""),
+            Event::Outbox(_) => Policy::Latest("outbox", ""),
+            Event::Connection(change) => Policy::Latest("connection", &change.name),
+            Event::Progress { op_id, .. } => Policy::Latest("progress", op_id),
+            Event::Typing(app_id) => Policy::Latest("typing", app_id),
+            Event::LiveLine(app_id, _) => Policy::Latest("live-line", app_id),
+            _ => Policy::Keep,
+        }
+    }
+}
+
+/// Sent by the terminal around pasted text, once bracketed paste is enabled
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
 /// A small event handler that wraps termion input and tick events. Each event
-/// type is handled in its own thread and returned to a common `Receiver`
+/// type is handled in its own thread and queued with the server's events
 pub struct Events {
-    rx: mpsc::Receiver<Event>,
-    _server_handle: thread::JoinHandle<()>,
+    rx: Receiver,
     _input_handle: thread::JoinHandle<()>,
     _tick_handle: thread::JoinHandle<()>,
 }
@@ -47,53 +138,47 @@ impl Default for Config {
 }
 
 impl Events {
-    pub fn new(server_rx: mpsc::Receiver<Event>) -> Events {
+    pub fn new(server_rx: Receiver) -> Events {
         Events::with_config(server_rx, Config::default())
     }
 
-    pub fn with_config(server_rx: mpsc::Receiver<Event>, config: Config) -> Events {
-        let (tx, rx) = mpsc::channel();
-
-        // listen to the server
-        let _server_handle = {
-            let tx = tx.clone();
-            thread::spawn(move || loop {
-                if let Ok(event) = server_rx.recv() {
-                    // Forward server events
-                    tx.send(event).unwrap();
-                } else {
-                    log::info!("server disconnected");
-                    break;
-                }
-            })
-        };
-
+    pub fn with_config(rx: Receiver, config: Config) -> Events {
         // listen to stdin for user events
         let _input_handle = {
-            let tx = tx.clone();
+            let tx = rx.sender();
             thread::spawn(move || {
                 let stdin = io::stdin();
-                for evt in stdin.keys() {
-                    match evt {
-                        Ok(key) => {
-                            if let Err(_) = tx.send(Event::UserInput(key)) {
-                                return;
-                            }
-                            if key == config.exit_key {
-                                return;
-                            }
+                let mut paste: Option<String> = None;
+                for event in stdin.events().flatten() {
+                    let event = match (event, &mut paste) {
+                        (TermEvent::Unsupported(seq), _) if seq == PASTE_START => {
+                            paste = Some(String::new());
+                            continue;
+                        }
+                        (TermEvent::Unsupported(seq), Some(_)) if seq == PASTE_END => {
+                            Event::Paste(paste.take().unwrap_or_default())
+                        }
+                        (TermEvent::Key(Key::Char(c)), Some(text)) => {
+                            text.push(c);
+                            continue;
                         }
-                        Err(_) => {}
+                        (TermEvent::Key(key), None) => Event::UserInput(key),
+                        _ => continue,
+                    };
+                    let exit = matches!(event, Event::UserInput(key) if key == config.exit_key);
+                    if tx.send(event).is_err() || exit {
+                        return;
                     }
                 }
             })
         };
 
         let _tick_handle = {
-            let tx = tx.clone();
-            thread::spawn(move || loop {
-                tx.send(Event::Tick).unwrap();
-                thread::sleep(config.tick_rate);
+            let tx = rx.sender();
+            thread::spawn(move || {
+                while tx.send(Event::Tick).is_ok() {
+                    thread::sleep(config.tick_rate);
+                }
             })
         };
 
@@ -101,11 +186,14 @@ impl Events {
             rx,
             _input_handle,
             _tick_handle,
-            _server_handle,
         }
     }
 
-    pub fn next(&self) -> Result<Event, mpsc::RecvError> {
+    pub fn next(&self) -> Event {
         self.rx.recv()
     }
+
+    pub fn try_next(&self) -> Option<Event> {
+        self.rx.try_recv()
+    }
 }
@@ -0,0 +1,133 @@
+//! Transformations of the typed text before it is sent, chosen with `--filter`
+//!
+//! To add one, implement [`Filter`] and add it to [`by_name`].
+
+use std::collections::HashMap;
+use std::fs;
+
+/// Rewrites outgoing chat text, filters are applied in the order given
+pub trait Filter {
+    fn transform(&self, text: String) -> String;
+}
+
+/// Names accepted by [`by_name`]
+pub const FILTERS: &str = "strip-ansi, abbreviations:<file> or wrap-code";
+
+/// The filter described by `spec`
+pub fn by_name(spec: &str) -> Result<Box<dyn Filter>, String> {
+    match spec {
+        "strip-ansi" => Ok(Box::new(StripAnsi)),
+        "wrap-code" => Ok(Box::new(WrapCode)),
+        _ if spec.starts_with("abbreviations:") => Ok(Box::new(Abbreviations::load(
+            &spec["abbreviations:".len()..],
+        )?)),
+        _ => Err(format!("unknown filter {}, expected {}", spec, FILTERS)),
+    }
+}
+
+/// Applies every filter of `filters` to `text`
+pub fn apply(filters: &[Box<dyn Filter>], text: &str) -> String {
+    filters
+        .iter()
+        .fold(text.to_owned(), |text, filter| filter.transform(text))
+}
+
+/// Removes terminal escape sequences and control characters, often pasted
+/// along with the output of a command
+pub struct StripAnsi;
+
+impl Filter for StripAnsi {
+    fn transform(&self, text: String) -> String {
+        let mut stripped = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                // CSI sequences end with a byte from @ to ~
+                '\x1b' if chars.peek() == Some(&'[') => {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                '\x1b' => {
+                    chars.next();
+                }
+                '\n' | '\t' => stripped.push(c),
+                _ if c.is_control() => {}
+                _ => stripped.push(c),
+            }
+        }
+        stripped
+    }
+}
+
+/// Replaces words by what they stand for, read from a file of
+/// `<word> <expansion>` lines
+pub struct Abbreviations(HashMap<String, String>);
+
+impl Abbreviations {
+    fn load(path: &str) -> Result<Self, String> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("could not read {}: {}", path, e))?;
+        let expansions = content
+            .lines()
+            .filter_map(|line| {
+                let (word, expansion) = line.trim().split_once(char::is_whitespace)?;
+                Some((word.to_owned(), expansion.trim().to_owned()))
+            })
+            .collect();
+        Ok(Abbreviations(expansions))
+    }
+}
+
+impl Filter for Abbreviations {
+    fn transform(&self, text: String) -> String {
+        text.split(' ')
+            .map(|word| self.0.get(word).map_or(word, String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Puts messages of several lines in a code block, so they read as pasted
+pub struct WrapCode;
+
+impl Filter for WrapCode {
+    fn transform(&self, text: String) -> String {
+        if !text.contains('\n') || text.starts_with("```") {
+            return text;
+        }
+        format!("```\n{}\n```", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_apply_in_order() {
+        let path = std::env::temp_dir().join(format!("netchat-test-{}.abbr", std::process::id()));
+        fs::write(&path, "afaik as far as I know\nbrb be right back\n").unwrap();
+        let filters: Vec<_> = ["strip-ansi", &format!("abbreviations:{}", path.display())]
+            .iter()
+            .map(|spec| by_name(spec).unwrap())
+            .collect();
+
+        assert_eq!(
+            apply(&filters, "\x1b[1;31mafaik\x1b[0m it builds, brb"),
+            "as far as I know it builds, be right back"
+        );
+        assert_eq!(apply(&filters, "brb?"), "brb?", "only whole words");
+        assert_eq!(
+            by_name("wrap-code")
+                .unwrap()
+                .transform("fn main() {\n}".to_owned()),
+            "```\nfn main() {\n}\n```"
+        );
+        assert!(by_name("shout").is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
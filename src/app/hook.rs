@@ -0,0 +1,84 @@
+//! `--notify-command`: a shell command run on each notification, with the
+//! message as json on its standard input
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use crate::server::messages::Msg;
+
+pub struct NotifyCommand {
+    command: String,
+}
+
+impl NotifyCommand {
+    /// Runs `command` with `sh -c`
+    pub fn new(command: String) -> NotifyCommand {
+        NotifyCommand { command }
+    }
+
+    /// Runs the command for `msg` without waiting for it, its output is discarded
+    pub fn run(&self, msg: &Msg) {
+        let json = match msg.serialize() {
+            Ok(json) => json,
+            Err(e) => return log::error!("Could not serialize the notification: {}", e),
+        };
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => return log::error!("Could not run the notification command: {}", e),
+        };
+        // Waited for on the side, so that it does not linger as a zombie
+        thread::spawn(move || {
+            if let Some(mut stdin) = child.stdin.take() {
+                if let Err(e) = stdin.write_all(format!("{}\n", json).as_bytes()) {
+                    log::warn!("The notification command did not read the message: {}", e);
+                }
+            }
+            match child.wait() {
+                Ok(status) if !status.success() => {
+                    log::warn!("The notification command failed: {}", status)
+                }
+                Ok(_) => {}
+                Err(e) => log::error!("Could not wait for the notification command: {}", e),
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::{Channel, Header};
+    use crate::server::Clock;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn the_message_is_given_on_stdin() {
+        let path = std::env::temp_dir().join(format!("netchat-test-{}-notify", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let hook = NotifyCommand::new(format!("cat > {}.part && mv {0}.part {0}", path.display()));
+        let msg = Msg::new(
+            1,
+            "bob".to_owned(),
+            Header::Public(Channel::default(), "hi alice".to_owned()),
+            Clock::new("bob".to_owned()),
+        );
+        hook.run(&msg);
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !path.exists() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written.trim_end().parse::<Msg>().unwrap(), msg);
+    }
+}
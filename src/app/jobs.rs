@@ -0,0 +1,92 @@
+//! Long-running operations of the server, shown in the progress area
+
+use std::collections::{BTreeMap, HashSet};
+
+/// How far an operation is
+#[derive(Debug, Clone, PartialEq)]
+pub struct Job {
+    pub label: String,
+    pub done: u64,
+    pub total: u64,
+}
+
+impl Job {
+    /// `width` characters filled in proportion of what is done
+    pub fn bar(&self, width: usize) -> String {
+        let filled = (self.done.min(self.total) * width as u64)
+            .checked_div(self.total)
+            .unwrap_or(0) as usize;
+        format!("[{}{}]", "#".repeat(filled), ".".repeat(width - filled))
+    }
+}
+
+/// Operations in progress, by id
+#[derive(Default)]
+pub struct Jobs {
+    running: BTreeMap<String, Job>,
+    hidden: HashSet<String>, // Cancelled by the user, until they end
+}
+
+impl Jobs {
+    /// Takes a progress report, an operation ends once everything is done
+    pub fn progress(&mut self, op_id: String, job: Job) {
+        if job.done >= job.total {
+            self.running.remove(&op_id);
+            self.hidden.remove(&op_id);
+        } else if !self.hidden.contains(&op_id) {
+            self.running.insert(op_id, job);
+        }
+    }
+
+    /// Stops showing an operation, returns false if there is no such operation
+    pub fn hide(&mut self, op_id: &str) -> bool {
+        if self.running.remove(op_id).is_none() {
+            return false;
+        }
+        self.hidden.insert(op_id.to_owned());
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.running.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.running.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Job)> {
+        self.running.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(done: u64, total: u64) -> Job {
+        Job {
+            label: "Sending queued messages".to_owned(),
+            done,
+            total,
+        }
+    }
+
+    #[test]
+    fn jobs_show_until_done_or_hidden() {
+        let mut jobs = Jobs::default();
+        jobs.progress("outbox".to_owned(), job(1, 4));
+        assert_eq!(jobs.iter().next().unwrap().1.bar(8), "[##......]");
+        jobs.progress("outbox".to_owned(), job(4, 4));
+        assert!(jobs.is_empty());
+
+        jobs.progress("outbox".to_owned(), job(1, 4));
+        assert!(jobs.hide("outbox"));
+        assert!(!jobs.hide("outbox"));
+        jobs.progress("outbox".to_owned(), job(2, 4));
+        assert!(jobs.is_empty(), "hidden until it ends");
+        jobs.progress("outbox".to_owned(), job(4, 4));
+        jobs.progress("outbox".to_owned(), job(1, 4));
+        assert_eq!(jobs.len(), 1);
+    }
+}
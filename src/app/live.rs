@@ -0,0 +1,125 @@
+//! Live line mode, talk(1)-style: while it is on with an app, the line being
+//! typed is sent to it as it changes, and the line it types for us is shown
+//! as it comes, in the title bar
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use super::AppId;
+
+/// The line typed is sent at most this often, the last change on a tick
+const SEND_INTERVAL: Duration = Duration::from_millis(300);
+
+/// A live line stops being shown this long after it last changed
+const EXPIRY: Duration = Duration::from_secs(30);
+
+/// Characters of the end of a live line shown
+const SHOWN: usize = 40;
+
+#[derive(Default)]
+pub struct LiveLines {
+    with: Option<AppId>,
+    sent: Option<Instant>,
+    pending: bool, // Typed since the line was last sent
+    others: BTreeMap<AppId, (String, Instant)>,
+}
+
+impl LiveLines {
+    /// Sends the line typed to `app_id` from now on
+    pub fn start(&mut self, app_id: AppId) {
+        self.with = Some(app_id);
+        self.sent = None;
+        self.pending = false;
+    }
+
+    /// Stops sending the line typed, returns to whom it was sent
+    pub fn stop(&mut self) -> Option<AppId> {
+        self.with.take()
+    }
+
+    /// The app the line typed is sent to, if the mode is on
+    pub fn with(&self) -> Option<&AppId> {
+        self.with.as_ref()
+    }
+
+    /// The line typed changed, returns whether to send it now. Otherwise it
+    /// is sent by [`due`](LiveLines::due) once the interval passed.
+    pub fn typed(&mut self, now: Instant) -> bool {
+        if self.with.is_none() {
+            return false;
+        }
+        if self.sent.is_some_and(|sent| now < sent + SEND_INTERVAL) {
+            self.pending = true;
+            return false;
+        }
+        self.sent = Some(now);
+        self.pending = false;
+        true
+    }
+
+    /// Whether a change held back by [`typed`](LiveLines::typed) is to be sent now
+    pub fn due(&mut self, now: Instant) -> bool {
+        self.pending && self.typed(now)
+    }
+
+    /// `app_id` is typing `line` for us, an empty one is no line
+    pub fn received(&mut self, app_id: AppId, line: String, now: Instant) {
+        match line.is_empty() {
+            true => self.others.remove(&app_id),
+            false => self.others.insert(app_id, (line, now)),
+        };
+    }
+
+    /// `app_id` sent what it was typing
+    pub fn stopped(&mut self, app_id: &str) {
+        self.others.remove(app_id);
+    }
+
+    /// Forgets the lines which did not change for a while
+    pub fn expire(&mut self, now: Instant) {
+        self.others.retain(|_, (_, at)| now < *at + EXPIRY);
+    }
+
+    /// The end of the line of each app typing for us, in id order
+    pub fn lines(&self) -> impl Iterator<Item = (&AppId, &str)> {
+        self.others.iter().map(|(app_id, (line, _))| {
+            let start = line
+                .char_indices()
+                .rev()
+                .nth(SHOWN - 1)
+                .map_or(0, |(i, _)| i);
+            (app_id, &line[start..])
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn live_lines_are_throttled_and_shown_by_their_end() {
+        let start = Instant::now();
+        let mut live = LiveLines::default();
+        assert!(!live.typed(start), "the mode is off");
+        live.start("bob".to_owned());
+        assert!(live.typed(start));
+        assert!(!live.typed(start + Duration::from_millis(100)));
+        assert!(!live.due(start + Duration::from_millis(200)));
+        assert!(live.due(start + SEND_INTERVAL));
+        assert!(!live.due(start + SEND_INTERVAL * 2), "sent already");
+        assert_eq!(live.stop(), Some("bob".to_owned()));
+
+        live.received("bob".to_owned(), "é".repeat(50), start);
+        live.received("carol".to_owned(), "hi".to_owned(), start + EXPIRY);
+        let lines: Vec<(&AppId, &str)> = live.lines().collect();
+        assert_eq!(lines[0].1.chars().count(), SHOWN);
+        assert_eq!(lines[1].1, "hi");
+        live.expire(start + EXPIRY);
+        live.received("dave".to_owned(), "x".to_owned(), start + EXPIRY);
+        live.received("dave".to_owned(), String::new(), start + EXPIRY);
+        assert_eq!(live.lines().count(), 1);
+        live.stopped("carol");
+        assert_eq!(live.lines().count(), 0);
+    }
+}
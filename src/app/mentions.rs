@@ -0,0 +1,87 @@
+//! Mentions received while not looking: scrolled up, away, or in a tab not
+//! shown. `/mentions` lists them and jumps to one.
+
+use std::collections::VecDeque;
+
+use crate::server::messages::Channel;
+
+/// Mentions kept at most, the oldest are forgotten first
+const MAX_MENTIONS: usize = 100;
+
+pub struct Mention {
+    pub channel: Channel,
+    /// Position of the message in the scrollback of its tab
+    pub position: usize,
+    /// Sender and text, as shown
+    pub line: String,
+}
+
+#[derive(Default)]
+pub struct Mentions {
+    list: VecDeque<Mention>,
+}
+
+impl Mentions {
+    pub fn push(&mut self, mention: Mention) {
+        if self.list.len() == MAX_MENTIONS {
+            self.list.pop_front();
+        }
+        self.list.push_back(mention);
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Oldest first, numbered from 1
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Mention)> {
+        self.list.iter().enumerate().map(|(i, m)| (i + 1, m))
+    }
+
+    /// Mention number `n`, from 1
+    pub fn get(&self, n: usize) -> Option<&Mention> {
+        self.list.get(n.checked_sub(1)?)
+    }
+
+    /// Forgets the mentions in `channel`, its tab is gone
+    pub fn forget(&mut self, channel: &Channel) {
+        self.list.retain(|m| m.channel != *channel);
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mentions_are_numbered_from_the_oldest() {
+        let rust = Channel::parse("#rust").unwrap();
+        let mut mentions = Mentions::default();
+        for position in 0..MAX_MENTIONS + 2 {
+            let channel = match position % 2 {
+                0 => Channel::default(),
+                _ => rust.clone(),
+            };
+            mentions.push(Mention {
+                channel,
+                position,
+                line: String::new(),
+            });
+        }
+        assert_eq!(mentions.len(), MAX_MENTIONS);
+        assert_eq!(mentions.get(1).unwrap().position, 2);
+        assert!(mentions.get(0).is_none());
+
+        mentions.forget(&rust);
+        assert_eq!(mentions.len(), MAX_MENTIONS / 2);
+        assert!(mentions.iter().all(|(_, m)| m.channel.is_general()));
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io::{self, Write};
+use std::net::SocketAddr;
 use std::sync::mpsc;
+use std::time::{Instant, SystemTime};
 
 use unicode_width::UnicodeWidthStr;
 
@@ -15,20 +18,88 @@ use tui::style::{Color, Modifier, Style};
 use tui::widgets::{Block, Borders, List, Paragraph, Text, Widget};
 use tui::Terminal;
 
+use serde::{Deserialize, Serialize};
+
 use rand::distributions::Alphanumeric;
 use rand::{thread_rng, Rng};
 
+pub mod channel;
+pub mod chanstats;
+mod commands;
+pub mod daylog;
+pub mod drafts;
+use drafts::Drafts;
+mod edits;
+use edits::Edits;
+pub mod filters;
+use filters::Filter;
+pub mod hook;
+use hook::NotifyCommand;
+pub mod jobs;
+use jobs::{Job, Jobs};
+pub mod live;
+use live::LiveLines;
+pub mod mentions;
+use mentions::{Mention, Mentions};
+pub mod mutes;
+use mutes::Mutes;
+mod paste;
+pub mod peers;
+use peers::Peers;
+pub mod quiet;
+use quiet::QuietHours;
+mod receipts;
+use receipts::Receipts;
+pub mod render;
+use render::Renderers;
+pub mod scrollback;
+use scrollback::Scrollback;
+pub mod slow;
+use slow::SlowMode;
+pub mod speech;
+use speech::Speaker;
+pub mod status;
+use status::StatusBar;
+pub mod tabs;
+use tabs::Tabs;
+pub mod theme;
+use theme::Theme;
+pub mod translate;
+use translate::{Target, Translator};
+pub mod typing;
+use typing::Typing;
+pub mod unread;
+use unread::ReadMarker;
+pub mod watch;
+use watch::Watches;
+
 pub mod events;
 use events::{Event, Events};
 
 use crate::server::events::Event as ServerEvent;
-use crate::server::messages::Header::{Private, Public};
+use crate::server::framing::{split_text, Limits};
+use crate::server::messages::Header::{self, Private, Public};
+use crate::server::messages::{Channel, Msg, MsgId};
+use crate::server::notice::Severity;
+use crate::server::{outbox, reconnect};
+use daylog::LocalTime;
+use netchat_core::delivery::is_chat;
+
+pub use netchat_core::AppId;
 
-pub type AppId = String;
+/// Events handled between two redraws at most
+const MAX_BATCH: usize = 256;
 
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Message {
     System(String),
     User(String),
+    /// Shown as a banner
+    Announcement(String),
+    /// Shown in yellow
+    Warning(String),
+    /// Shown in red
+    Error(String),
 }
 use Message::*;
 
@@ -37,22 +108,120 @@ impl Message {
         match self {
             System(s) => s,
             User(s) => s,
+            Announcement(s) => s,
+            Warning(s) => s,
+            Error(s) => s,
         }
     }
 }
 
+impl PartialEq<&str> for Message {
+    fn eq(&self, other: &&str) -> bool {
+        self.str() == *other
+    }
+}
+
+/// Number of messages received from each sender since `/away`
+#[derive(Default)]
+struct Away {
+    senders: BTreeMap<AppId, usize>,
+}
+
 /// Holds the state of the application
 pub struct App {
     //Application id
     pub id: AppId,
     /// History of received/sent messages
-    pub messages: Vec<Message>,
+    pub messages: Scrollback,
     /// Current value of the input box
     input: String,
     /// Id of the first message to be displayed, used for scrolling
     first_display_message_id: usize,
+    /// Tab shown and its number of messages when last drawn: while scrolled
+    /// away from the newest, the view stays on the same messages as more come
+    drawn: (usize, usize),
     /// Id of the private message recipient
     private_recipient_id: AppId,
+    /// Identities whose key was revoked, their messages are flagged
+    revoked: HashSet<AppId>,
+    /// Apps listening on the local network, which `/connect` dials by id
+    discovered: BTreeMap<AppId, SocketAddr>,
+    /// Names the other apps are shown as, set by their `/nick`
+    nicks: HashMap<AppId, String>,
+    /// Set by an operator's `/slow`, holds back our public messages and flags theirs
+    slow: SlowMode,
+    /// One per channel joined, `#general` first
+    pub tabs: Tabs,
+    /// Received while scrolled up, away or in another tab, listed by `/mentions`
+    mentions: Mentions,
+    /// Apps composing a message, shown in the title bar
+    typing: Typing,
+    /// The line sent as typed in live line mode, and those typed for us
+    live: LiveLines,
+    /// Apps heard from, listed in the panel `/peers` toggles
+    pub peers: Peers,
+    /// Messages the server could not send yet
+    outbox: Vec<outbox::Item>,
+    /// Whether the outbox panel is displayed when it is not empty
+    show_outbox: bool,
+    /// Messages matching a `/watch` expression, in their panel
+    watches: Watches,
+    /// Last known state of each transport
+    connections: Vec<reconnect::Change>,
+    /// Apps on the clock of the last message received
+    clock_len: usize,
+    /// Segments around the key help, at the bottom
+    pub status: StatusBar,
+    /// Long-running operations of the server, in the progress area
+    jobs: Jobs,
+    /// How long typed messages can be
+    pub limits: Limits,
+    /// Set between `/away` and `/back`
+    away: Option<Away>,
+    /// First unread message, Ctrl+u scrolls to it
+    pub unread: ReadMarker,
+    /// Channels which neither notify nor count as unread, for a while
+    pub mutes: Mutes,
+    /// Input lines of the tabs not shown, saved on exit with the one shown
+    pub drafts: Drafts,
+    /// Long paste waiting for the user to choose how to send it
+    paste: Option<String>,
+    /// Applied to the typed text before it is sent
+    pub filters: Vec<Box<dyn Filter>>,
+    /// Commands standing for a longer line, without their `/`
+    pub aliases: BTreeMap<String, String>,
+    /// Keys typed since F3, until F4
+    recording: Option<Vec<Key>>,
+    /// Keys replayed by F4
+    keyboard_macro: Vec<Key>,
+    /// Whether private messages and mentions ring the terminal bell
+    pub bell: bool,
+    /// Set by `--theme`
+    pub theme: Theme,
+    /// When the bell is silent and the app away
+    pub quiet_hours: Option<QuietHours>,
+    /// Contacts whose private messages and mentions ring during quiet hours
+    pub quiet_override: HashSet<AppId>,
+    /// Whether the quiet hours made the app away
+    quiet: bool,
+    /// Run on what would ring the bell, even when it is off
+    pub notify_command: Option<NotifyCommand>,
+    /// Transports the public messages of a channel go through, all if unset
+    pub via: BTreeMap<Channel, Vec<String>>,
+    /// Set by `--speak-command`, reads messages out loud
+    pub speaker: Option<Speaker>,
+    /// Set by `--translate-command`, shows translations under the messages
+    pub translator: Option<Translator>,
+    /// Set by `--observe`, nothing typed is sent
+    pub observe: bool,
+    /// Set by `/quit`, the UI stops
+    quit: bool,
+    /// Private messages sent, marked once their recipient was shown them
+    receipts: Receipts,
+    /// Public messages shown, changed by their edits and deletions
+    edits: Edits,
+    /// Show chat messages after their content type
+    pub renderers: Renderers,
 }
 
 impl Default for App {
@@ -61,9 +230,330 @@ impl Default for App {
         App {
             id: (0..8).map(|_| rng.sample(Alphanumeric)).collect(),
             input: String::new(),
-            messages: Vec::new(),
+            messages: Scrollback::default(),
             first_display_message_id: 0,
+            drawn: (0, 0),
             private_recipient_id: "no one".to_owned(),
+            revoked: HashSet::new(),
+            discovered: BTreeMap::new(),
+            nicks: HashMap::new(),
+            slow: SlowMode::default(),
+            tabs: Tabs::default(),
+            mentions: Mentions::default(),
+            typing: Typing::default(),
+            live: LiveLines::default(),
+            peers: Peers::default(),
+            outbox: Vec::new(),
+            show_outbox: true,
+            watches: Watches::default(),
+            connections: Vec::new(),
+            clock_len: 0,
+            status: StatusBar::default(),
+            jobs: Jobs::default(),
+            limits: Limits::default(),
+            away: None,
+            unread: ReadMarker::default(),
+            mutes: Mutes::default(),
+            drafts: Drafts::default(),
+            paste: None,
+            filters: Vec::new(),
+            aliases: BTreeMap::new(),
+            recording: None,
+            keyboard_macro: Vec::new(),
+            bell: false,
+            theme: Theme::default(),
+            quiet_hours: None,
+            quiet_override: HashSet::new(),
+            quiet: false,
+            notify_command: None,
+            via: BTreeMap::new(),
+            speaker: None,
+            translator: None,
+            observe: false,
+            quit: false,
+            receipts: Receipts::default(),
+            edits: Edits::default(),
+            renderers: Renderers::default(),
+        }
+    }
+}
+
+impl App {
+    /// Puts the draft of the tab shown back in the input field, `drafts` are
+    /// saved on exit
+    pub fn keep_drafts(&mut self, mut drafts: Drafts) {
+        self.input = drafts.take(self.tabs.channel());
+        self.drafts = drafts;
+    }
+
+    /// Shows the tab at `index`, with its draft in the input field
+    fn show_tab(&mut self, index: usize) {
+        let left = self.tabs.channel().clone();
+        self.tabs.show(
+            index,
+            &mut self.messages,
+            &mut self.first_display_message_id,
+        );
+        if *self.tabs.channel() != left {
+            self.drafts
+                .switch(&left, self.tabs.channel(), &mut self.input);
+        }
+    }
+
+    /// Skips the `skip` newest messages, the top of the view, at most
+    /// those which leave `page` messages to show
+    fn scroll_to(&mut self, skip: usize, page: usize) {
+        self.first_display_message_id = skip.min(self.messages.len().saturating_sub(page));
+        self.drawn = (self.tabs.shown(), self.messages.len());
+    }
+
+    /// Keeps the view on the messages it showed, unless it shows the newest
+    fn keep_scroll_position(&mut self) {
+        let (tab, len) = self.drawn;
+        if tab == self.tabs.shown() && self.first_display_message_id > 0 {
+            self.first_display_message_id += self.messages.len().saturating_sub(len);
+        }
+        self.drawn = (self.tabs.shown(), self.messages.len());
+    }
+
+    /// What `app_id` is shown as: its nick, or the id itself
+    fn name<'a>(&'a self, app_id: &'a str) -> &'a str {
+        self.nicks.get(app_id).map_or(app_id, String::as_str)
+    }
+
+    /// Reads `msg` out loud if the speech rules want it, not during quiet
+    /// hours unless its sender overrides them
+    fn speak(&self, msg: &Msg) {
+        let speaker = match &self.speaker {
+            Some(speaker) => speaker,
+            None => return,
+        };
+        let mentions = matches!(&msg.header, Public(_, text) if self.mentioned(text));
+        if !speaker.wants(msg, mentions)
+            || (self.quiet && !self.quiet_override.contains(&msg.sender_id))
+        {
+            return;
+        }
+        let name = self.name(&msg.sender_id);
+        let text = match &msg.header {
+            Public(channel, text) if channel.is_general() => format!("{} says {}", name, text),
+            Public(channel, text) => format!("{} in {} says {}", name, channel, text),
+            Private(_, text) => format!("{} to you: {}", name, text),
+            Header::Announcement(text, _) => format!("{} announces {}", name, text),
+            _ => return,
+        };
+        speaker.say(text);
+    }
+
+    /// Tells the translator a message of `sender` was shown at `position` of
+    /// the tab of channel `tab`
+    fn offer_translation(&mut self, sender: &str, tab: Channel, position: usize, text: &str) {
+        if let Some(translator) = &mut self.translator {
+            let target = Target {
+                sender: sender.to_owned(),
+                tab,
+                position,
+            };
+            translator.received(target, text);
+        }
+    }
+
+    /// Shows the new text of public message `id`, or a tombstone for None
+    fn change(&mut self, id: MsgId, text: Option<&str>) {
+        let ((tab, position), prefix) = match self.edits.place(id) {
+            Some((place, prefix)) => (place.clone(), prefix.to_owned()),
+            None => return,
+        };
+        if text.is_none() {
+            self.edits.forget(id);
+        }
+        if let Some(messages) = self.tabs.messages(&tab, &mut self.messages) {
+            messages.amend(position, |message| {
+                *message = match text {
+                    Some(text) => chat(&prefix, &format!("{} (edited)", text)),
+                    None => User(format!("{}[deleted]", prefix)),
+                };
+            });
+        }
+    }
+
+    /// Puts `msg` in the watch panel if it matches a `/watch` expression
+    fn watch(&mut self, msg: &Msg) {
+        let name = self.name(&msg.sender_id);
+        let (source, text) = match &msg.header {
+            Public(channel, text) => (format!("{} {}", channel, name), text),
+            Private(_, text) => (format!("{} privately", name), text),
+            Header::Announcement(text, _) => (format!("{} announces", name), text),
+            _ => return,
+        };
+        self.watches.check(source, text);
+    }
+
+    /// Whether `text` mentions us
+    fn mentioned(&self, text: &str) -> bool {
+        text.contains(self.id.as_str())
+    }
+
+    /// Whether `msg` is notified: a private message or a mention outside a
+    /// muted channel, from a contact overriding the quiet hours if they
+    /// started, or an announcement
+    fn notifies(&self, msg: &Msg) -> bool {
+        let concerns_me = match &msg.header {
+            Header::Announcement(..) => return true,
+            Private(..) => true,
+            Public(channel, text) => {
+                self.mentioned(text) && !self.mutes.is_muted(channel, SystemTime::now())
+            }
+            _ => false,
+        };
+        concerns_me && (!self.quiet || self.quiet_override.contains(&msg.sender_id))
+    }
+
+    /// Sets the app away when the quiet hours start, and back when they end
+    fn check_quiet_hours(&mut self) {
+        let hours = match self.quiet_hours {
+            Some(hours) => hours,
+            None => return,
+        };
+        let quiet = hours.contains(&LocalTime::now());
+        if quiet == self.quiet {
+            return;
+        }
+        self.quiet = quiet;
+        if quiet {
+            self.messages.push(System(format!(
+                "Quiet hours until {}, the bell is silent",
+                hours.end()
+            )));
+            if self.away.is_none() {
+                commands::away(self);
+            }
+        } else if self.away.is_some() {
+            commands::back(self);
+        }
+    }
+}
+
+/// Most lines the input field grows to
+const MAX_INPUT_LINES: usize = 8;
+
+/// Most operations shown at once in the progress area, `/jobs` lists them all
+const MAX_JOB_LINES: usize = 3;
+
+/// Columns of the peer panel, borders included
+const PEER_PANEL_WIDTH: u16 = 24;
+
+/// Pushes a chat message, its lines after the first are aligned under it
+fn push_chat(messages: &mut Scrollback, prefix: String, text: &str) {
+    messages.push(chat(&prefix, text));
+}
+
+/// A chat message, its lines after the first aligned under the first
+fn chat(prefix: &str, text: &str) -> Message {
+    let indent = format!("\n{}", " ".repeat(prefix.width()));
+    User(format!("{}{}", prefix, text.replace('\n', &indent)))
+}
+
+/// Pushes an announcement, as a banner
+fn push_announcement(messages: &mut Scrollback, prefix: String, text: &str) {
+    let indent = format!("\n{}", " ".repeat(prefix.width()));
+    messages.push(Announcement(format!(
+        "{}{}",
+        prefix,
+        text.replace('\n', &indent)
+    )));
+}
+
+/// Whether the app only observes, the user is told that nothing is sent
+fn observing(app: &mut App) -> bool {
+    if app.observe {
+        app.messages
+            .push(System("Not sent: observing, see --observe".to_owned()));
+    }
+    app.observe
+}
+
+/// Sends the line typed in live line mode, empty while it is a command
+fn send_live_line(app: &App, server_tx: &mpsc::Sender<ServerEvent>) {
+    if let Some(to) = app.live.with() {
+        let line = match app.input.starts_with('/') {
+            true => String::new(),
+            false => app.input.clone(),
+        };
+        send_to_server(ServerEvent::UserLiveLine(to.clone(), line), server_tx);
+    }
+}
+
+/// Sends `message` to `to`, or to everyone, refused or split when it is too long
+fn send_chat(
+    app: &mut App,
+    to: Option<&str>,
+    message: &str,
+    server_tx: &mpsc::Sender<ServerEvent>,
+) {
+    send_chat_via(app, to, message, None, server_tx);
+}
+
+/// Same as [`send_chat`], a public message going only through the transports
+/// of `via`, or those set for the channel if None
+fn send_chat_via(
+    app: &mut App,
+    to: Option<&str>,
+    message: &str,
+    via: Option<Vec<String>>,
+    server_tx: &mpsc::Sender<ServerEvent>,
+) {
+    if observing(app) {
+        return;
+    }
+    if let Some(to) = to.filter(|to| app.revoked.contains(*to)) {
+        let notice = format!("Not sent: the identity of {} was revoked", to);
+        app.messages.push(System(notice));
+        return;
+    }
+    let now = Instant::now();
+    let channel = app.tabs.channel().clone();
+    if let Some(wait) = app.slow.wait(&channel, now).filter(|_| to.is_none()) {
+        app.messages.push(System(format!(
+            "Not sent: slow mode, {} more seconds to wait",
+            wait.as_secs() + 1
+        )));
+        return;
+    }
+    let message = &filters::apply(&app.filters, message);
+    if !app.limits.split_long_text && message.chars().count() > app.limits.max_text_len {
+        app.messages.push(System(format!(
+            "Not sent: the message is {} characters long, the limit is {}",
+            message.chars().count(),
+            app.limits.max_text_len
+        )));
+        return;
+    }
+    app.unread.mark_read();
+    if to.is_none() {
+        app.slow.sent(&channel, now);
+    }
+    let via = via.or_else(|| app.via.get(&channel).cloned());
+    for piece in split_text(message, app.limits.max_text_len) {
+        let (event, prefix) = match to {
+            Some(to) => (
+                ServerEvent::UserPrivateMessage(to.to_owned(), piece.clone()),
+                format!("You to {}: ", to),
+            ),
+            None => (
+                ServerEvent::UserPublicMessage(channel.clone(), piece.clone(), via.clone()),
+                match &via {
+                    Some(via) => format!("You via {}: ", via.join(", ")),
+                    None => "You: ".to_owned(),
+                },
+            ),
+        };
+        send_to_server(event, server_tx);
+        push_chat(&mut app.messages, prefix.clone(), &piece);
+        let place = (channel.clone(), app.messages.len() - 1);
+        match to {
+            Some(to) => app.receipts.shown(to, &piece, place),
+            None => app.edits.typed(&piece, place, prefix),
         }
     }
 }
@@ -76,7 +566,7 @@ pub fn send_to_server(msg: ServerEvent, server_tx: &mpsc::Sender<ServerEvent>) {
 
 pub fn run(
     mut app: App,
-    server_rx: mpsc::Receiver<Event>,
+    server_rx: channel::Receiver,
     server_tx: mpsc::Sender<ServerEvent>,
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Terminal initialization
@@ -85,6 +575,8 @@ pub fn run(
     let stdout = AlternateScreen::from(stdout);
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
+    // Pasted text comes between markers instead of as typed keys
+    write!(terminal.backend_mut(), "\x1b[?2004h")?;
 
     let events = Events::new(server_rx);
 
@@ -92,7 +584,19 @@ pub fn run(
 
     let mut msg_list_size: usize = 0;
 
-    loop {
+    'ui: loop {
+        app.keep_scroll_position();
+        // Only what fits on screen is read, possibly from disk
+        let visible = terminal.size()?.height.into();
+        let rows = app.messages.window(app.first_display_message_id, visible);
+        let input_lines: Vec<&str> = app.input.split('\n').collect();
+        let input_height = input_lines.len().min(MAX_INPUT_LINES) as u16 + 2;
+        let context = status::Context {
+            connections: &app.connections,
+            clock_len: app.clock_len,
+        };
+        app.status.update(&context, Instant::now());
+
         // Draw UI
         terminal.draw(|mut f| {
             let chunks = Layout::default()
@@ -100,153 +604,759 @@ pub fn run(
                 .constraints(
                     [
                         Constraint::Length(1),
-                        Constraint::Length(3),
+                        Constraint::Length(input_height),
+                        Constraint::Length(if app.tabs.len() > 1 { 1 } else { 0 }),
                         Constraint::Min(1),
+                        Constraint::Length(app.jobs.len().min(MAX_JOB_LINES) as u16),
                         Constraint::Length(1),
                     ]
                     .as_ref(),
                 )
                 .split(f.size());
 
-            msg_list_size = chunks[2].inner(1).height.into();
+            msg_list_size = chunks[3].inner(1).height.into();
+
+            let show_outbox = app.show_outbox && !app.outbox.is_empty();
+            let mut columns = vec![Constraint::Min(0)];
+            if show_outbox {
+                columns.push(Constraint::Percentage(30));
+            }
+            let show_watches = app.watches.shown && !app.watches.is_empty();
+            if show_watches {
+                columns.push(Constraint::Percentage(30));
+            }
+            if app.peers.shown {
+                columns.push(Constraint::Length(PEER_PANEL_WIDTH));
+            }
+            let body = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints(&columns[..])
+                .split(chunks[3]);
 
-            Paragraph::new([Text::raw("NetChat")].iter())
+            let mut title = vec![Text::raw("NetChat")];
+            for change in &app.connections {
+                let (state, color) = match change.state {
+                    reconnect::State::Connected => ("up".to_owned(), app.theme.ok),
+                    reconnect::State::Disconnected => ("down".to_owned(), app.theme.error),
+                    reconnect::State::Reconnecting { attempt, .. } => {
+                        (format!("retry {}", attempt), app.theme.warning)
+                    }
+                    reconnect::State::Partitioned => ("cut".to_owned(), app.theme.cut),
+                    reconnect::State::Left => ("left".to_owned(), app.theme.muted),
+                };
+                title.push(Text::raw(format!("  {} ", change.name)));
+                title.push(Text::styled(state, Style::default().fg(color)));
+            }
+            if !app.mentions.is_empty() {
+                title.push(Text::styled(
+                    format!("  {} mentions", app.mentions.len()),
+                    Style::default().fg(app.theme.warning),
+                ));
+            }
+            if let Some(away) = &app.away {
+                let received: usize = away.senders.values().sum();
+                title.push(Text::styled(
+                    format!("  away, {} received", received),
+                    Style::default().fg(app.theme.warning),
+                ));
+            }
+            let typing: Vec<&str> = app.typing.apps().map(|id| app.name(id)).collect();
+            if !typing.is_empty() {
+                let verb = if typing.len() == 1 { "is" } else { "are" };
+                title.push(Text::styled(
+                    format!("  {} {} typing…", typing.join(", "), verb),
+                    Style::default().fg(app.theme.muted),
+                ));
+            }
+            for (app_id, line) in app.live.lines() {
+                title.push(Text::styled(
+                    format!("  {} ✎ {}", app.name(app_id), line),
+                    Style::default().fg(app.theme.accent),
+                ));
+            }
+            Paragraph::new(title.iter())
                 .alignment(Alignment::Center)
                 .render(&mut f, chunks[0]);
 
+            // Counts down to the next public message in slow mode
+            let input_title = match app.slow.wait(app.tabs.channel(), Instant::now()) {
+                Some(wait) => format!(" Input, slow mode: {}s ", wait.as_secs() + 1),
+                None => " Input ".to_owned(),
+            };
             Paragraph::new([Text::raw(&app.input)].iter())
-                .style(Style::default().fg(Color::Cyan))
-                .block(Block::default().borders(Borders::ALL).title(" Input "))
+                .style(Style::default().fg(app.theme.accent))
+                .block(Block::default().borders(Borders::ALL).title(&input_title))
                 .render(&mut f, chunks[1]);
 
-            let messages = app
+            let mut tabs = Vec::new();
+            let now = SystemTime::now();
+            for (i, tab) in app.tabs.iter().enumerate() {
+                let style = match i == app.tabs.shown() {
+                    true => Style::default().modifier(Modifier::REVERSED),
+                    false => Style::default(),
+                };
+                let unseen = match tab.unseen {
+                    _ if app.mutes.is_muted(&tab.channel, now) => " (muted)".to_owned(),
+                    0 => String::new(),
+                    n => format!(" ({})", n),
+                };
+                tabs.push(Text::styled(
+                    format!(" {} {}{} ", i + 1, tab.channel, unseen),
+                    style,
+                ));
+                tabs.push(Text::raw(" "));
+            }
+            Paragraph::new(tabs.iter()).render(&mut f, chunks[2]);
+
+            // Newest first, the rule goes under the first unread message
+            let inner = chunks[3].inner(1);
+            let rule = format!("{:─^1$}", " new messages ", usize::from(inner.width));
+            let newest = app
                 .messages
+                .len()
+                .saturating_sub(1 + app.first_display_message_id);
+            let mut items = Vec::with_capacity(rows.len() + 1);
+            let banner = Style::default()
+                .fg(app.theme.warning)
+                .modifier(Modifier::BOLD | Modifier::REVERSED);
+            for (i, row) in rows.iter().enumerate() {
+                match row {
+                    Announcement(text) => {
+                        items.extend(text.split('\n').map(|line| Text::styled(line, banner)))
+                    }
+                    Warning(text) => items
+                        .extend(text.split('\n').map(|line| {
+                            Text::styled(line, Style::default().fg(app.theme.warning))
+                        })),
+                    Error(text) => items.extend(
+                        text.split('\n')
+                            .map(|line| Text::styled(line, Style::default().fg(app.theme.error))),
+                    ),
+                    _ => items.extend(row.str().split('\n').map(Text::raw)),
+                }
+                // The read marker counts the messages of #general
+                if app.tabs.shown() == 0 && app.unread.first_unread == Some(newest - i) {
+                    items.push(Text::styled(
+                        rule.as_str(),
+                        Style::default().fg(app.theme.error),
+                    ));
+                }
+            }
+            // Counted from the oldest, while scrolled away from the newest
+            let title = match app.first_display_message_id {
+                0 => " Messages ".to_owned(),
+                skip => format!(
+                    " Messages {}-{} of {}, Home for the newest ",
+                    (newest + 2)
+                        .saturating_sub(rows.len().min(msg_list_size))
+                        .max(1),
+                    app.messages.len() - skip,
+                    app.messages.len()
+                ),
+            };
+            List::new(items.into_iter())
+                .block(Block::default().borders(Borders::ALL).title(&title))
+                .render(&mut f, body[0]);
+
+            if show_outbox {
+                let items = app.outbox.iter().map(|item| {
+                    let (status, color) = match item.status {
+                        outbox::Status::Queued => ("queued".to_owned(), Color::Reset),
+                        outbox::Status::Retrying => {
+                            (format!("retrying ({})", item.attempts), app.theme.warning)
+                        }
+                        outbox::Status::Failed => ("failed".to_owned(), app.theme.error),
+                    };
+                    Text::styled(
+                        format!("#{} {} {}", item.seq, status, item.summary),
+                        Style::default().fg(color),
+                    )
+                });
+                List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(" Outbox "))
+                    .render(&mut f, body[1]);
+            }
+
+            if show_watches {
+                let items = app.watches.hits().map(|hit| {
+                    Text::raw(format!("{}: {}", hit.source, hit.text.replace('\n', " ")))
+                });
+                List::new(items)
+                    .block(Block::default().borders(Borders::ALL).title(" Watch "))
+                    .render(&mut f, body[1 + show_outbox as usize]);
+            }
+
+            if app.peers.shown {
+                let mut items = Vec::new();
+                for (title, ids) in app.peers.groups(|id| app.name(id)) {
+                    let title = match title {
+                        Some(title) => title,
+                        None if app.peers.grouping == peers::Grouping::None => String::new(),
+                        None => "others".to_owned(),
+                    };
+                    if !title.is_empty() {
+                        items.push(Text::styled(
+                            title,
+                            Style::default().modifier(Modifier::BOLD),
+                        ));
+                    }
+                    items.extend(ids.into_iter().map(|id| {
+                        let name = format!(" {}", app.name(id));
+                        match app.peers.is_online(id) {
+                            true => Text::raw(name),
+                            false => Text::styled(name, Style::default().fg(app.theme.muted)),
+                        }
+                    }));
+                }
+                let title = format!(" Peers ({}/{}) ", app.peers.online(), app.peers.len());
+                List::new(items.into_iter())
+                    .block(Block::default().borders(Borders::ALL).title(&title))
+                    .render(&mut f, body[body.len() - 1]);
+            }
+
+            let jobs: Vec<Text> = app
+                .jobs
                 .iter()
-                .rev()
-                .skip(app.first_display_message_id)
-                .map(|m| Text::raw(m.str()));
-            List::new(messages)
-                .block(Block::default().borders(Borders::ALL).title(" Messages "))
-                .render(&mut f, chunks[2]);
-
-            Paragraph::new(
-                [
-                    Text::styled("^C", Style::default().modifier(Modifier::REVERSED)),
-                    Text::raw(" Quit "),
-                    Text::styled("^H", Style::default().modifier(Modifier::REVERSED)),
-                    Text::raw(" Display clock "),
-                    Text::styled("^S", Style::default().modifier(Modifier::REVERSED)),
-                    Text::raw(" Snapshot "),
-                    Text::styled("^P", Style::default().modifier(Modifier::REVERSED)),
-                    Text::raw(" Send private message "),
-                    Text::styled("^R", Style::default().modifier(Modifier::REVERSED)),
-                    Text::raw(" Set pm recipient "),
-                    Text::raw(""),
-                ]
-                .iter(),
-            )
-            .render(&mut f, chunks[3]);
+                .map(|(op_id, job)| {
+                    Text::raw(format!(
+                        "{} {} {}/{} ({})\n",
+                        job.bar(20),
+                        job.label,
+                        job.done,
+                        job.total,
+                        op_id
+                    ))
+                })
+                .collect();
+            Paragraph::new(jobs.iter())
+                .style(Style::default().fg(app.theme.accent))
+                .render(&mut f, chunks[4]);
+
+            let segment =
+                |text| Text::styled(format!(" {} ", text), Style::default().fg(app.theme.accent));
+            let mut bar: Vec<Text> = app.status.left().map(segment).collect();
+            let keys = Style::default().modifier(Modifier::REVERSED);
+            bar.extend([
+                Text::styled("^C", keys),
+                Text::raw(" Quit "),
+                Text::styled("^H", keys),
+                Text::raw(" Display clock "),
+                Text::styled("^S", keys),
+                Text::raw(" Snapshot "),
+                Text::styled("^P", keys),
+                Text::raw(" Send private message "),
+                Text::styled("^R", keys),
+                Text::raw(" Set pm recipient "),
+            ]);
+            // The right segments keep their room, the key help is cut first
+            let right_width: usize = app.status.right().map(|text| text.width() + 2).sum();
+            let bottom = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(0), Constraint::Length(right_width as u16)].as_ref())
+                .split(chunks[5]);
+            Paragraph::new(bar.iter()).render(&mut f, bottom[0]);
+            let right: Vec<Text> = app.status.right().map(segment).collect();
+            Paragraph::new(right.iter()).render(&mut f, bottom[1]);
         })?;
 
         // Put the cursor back inside the input box
         write!(
             terminal.backend_mut(),
             "{}",
-            Goto(2 + app.input.width() as u16, 3)
+            Goto(
+                2 + input_lines.last().map_or(0, |l| l.width()) as u16,
+                input_height
+            )
         )?;
 
-        // Handle events
-        match events.next()? {
-            // Input from the user
-            Event::UserInput(input) => match input {
-                Key::Ctrl('c') => {
-                    break;
-                }
-                Key::Ctrl('h') => {
-                    send_to_server(ServerEvent::GetClock, &server_tx);
-                }
-                Key::Ctrl('s') => {
-                    send_to_server(ServerEvent::GetSnapshot, &server_tx);
-                }
-                Key::Char('\n') => {
-                    send_to_server(
-                        ServerEvent::UserPublicMessage(app.input.clone()),
-                        &server_tx,
-                    );
-                    let message: String = app.input.drain(..).collect();
-                    app.messages.push(User(format!("You: {}", message)));
-                }
-                // set the recipient id for private messages
-                Key::Ctrl('r') => {
-                    let private_recipient_id: String = app.input.drain(..).collect();
-                    if private_recipient_id.len() > 0 {
-                        app.private_recipient_id = private_recipient_id;
+        // Handle events, a burst is handled in a single redraw
+        let mut replayed = VecDeque::new(); // Keys of the macro being replayed
+        let mut ring = false;
+        let mut next = Some(events.next());
+        let mut handled = 0;
+        while let Some(event) = next.take() {
+            if let (Event::UserInput(key), Some(keys)) = (&event, &mut app.recording) {
+                if !matches!(key, Key::F(3) | Key::F(4)) {
+                    keys.push(*key);
+                }
+            }
+            match event {
+                // Input from the user
+                Event::UserInput(input) => match input {
+                    Key::Ctrl('c') => {
+                        break 'ui;
+                    }
+                    key if app.paste.is_some() => paste::choose(&mut app, key, &server_tx),
+                    Key::F(3) => {
+                        app.recording = Some(Vec::new());
+                        app.messages
+                            .push(System("Recording a macro, F4 to stop".to_owned()));
+                    }
+                    Key::F(4) => match app.recording.take() {
+                        Some(keys) => {
+                            app.messages.push(System(format!(
+                                "Recorded a macro of {} keys, F4 replays it",
+                                keys.len()
+                            )));
+                            app.keyboard_macro = keys;
+                        }
+                        None => replayed.extend(app.keyboard_macro.iter().copied()),
+                    },
+                    Key::Ctrl('h') => {
+                        send_to_server(ServerEvent::GetClock, &server_tx);
+                    }
+                    Key::Ctrl('s') => {
+                        send_to_server(ServerEvent::GetSnapshot, &server_tx);
+                    }
+                    Key::Ctrl('k') => {
+                        send_to_server(ServerEvent::RotateKey, &server_tx);
+                    }
+                    Key::Ctrl('l') => {
+                        send_to_server(ServerEvent::AcceptLink, &server_tx);
+                    }
+                    Key::Alt(c @ '1'..='9') => {
+                        let index = c as usize - '1' as usize;
+                        app.show_tab(index);
+                    }
+                    Key::Ctrl('u') => {
+                        // The read marker counts the messages of #general
+                        app.show_tab(0);
+                        match app.unread.first_unread {
+                            // Shown at the bottom, the messages after it above
+                            Some(first) => {
+                                let skip = app.messages.len().saturating_sub(first + msg_list_size);
+                                app.scroll_to(skip, 0);
+                            }
+                            None => app.messages.push(System("Nothing unread".to_owned())),
+                        }
+                    }
+                    // Alt+Enter starts a new line of the same message
+                    Key::Alt('\r') | Key::Alt('\n') => app.input.push('\n'),
+                    Key::Char('\n') if app.input.starts_with('/') => {
+                        let line: String = app.input.drain(..).collect();
+                        commands::execute(&mut app, &line, &server_tx);
+                        if app.quit {
+                            break 'ui;
+                        }
+                    }
+                    Key::Char('\t')
+                        if app.input.starts_with('/')
+                            && !app.input.contains(char::is_whitespace) =>
+                    {
+                        commands::complete(&mut app)
+                    }
+                    Key::Char('\n') | Key::Ctrl('p')
+                        if !app.limits.split_long_text
+                            && app.input.chars().count() > app.limits.max_text_len =>
+                    {
+                        app.messages.push(System(format!(
+                            "Not sent: the message is {} characters long, the limit is {}",
+                            app.input.chars().count(),
+                            app.limits.max_text_len
+                        )));
+                    }
+                    Key::Char('\n') => {
+                        let message: String = app.input.drain(..).collect();
+                        send_chat(&mut app, None, &message, &server_tx);
+                    }
+                    // set the recipient id for private messages
+                    Key::Ctrl('r') => {
+                        let private_recipient_id: String = app.input.drain(..).collect();
+                        if !private_recipient_id.is_empty() {
+                            app.private_recipient_id = private_recipient_id;
+                        } else {
+                            app.private_recipient_id = last_private_id.clone();
+                        }
+                        app.messages.push(System(format!(
+                            "Private recipient id set to: {}",
+                            app.private_recipient_id
+                        )));
+                    }
+                    Key::Ctrl('p') if app.revoked.contains(&app.private_recipient_id) => {
+                        app.messages.push(System(format!(
+                            "Not sent: the identity of {} was revoked",
+                            app.private_recipient_id
+                        )));
+                    }
+                    Key::Ctrl('p') => {
+                        let message: String = app.input.drain(..).collect();
+                        let to = app.private_recipient_id.clone();
+                        send_chat(&mut app, Some(&to), &message, &server_tx);
+                    }
+                    Key::Char(c) => {
+                        app.input.push(c);
+                        if !app.input.starts_with('/')
+                            && !app.observe
+                            && app.typing.typed(Instant::now())
+                        {
+                            send_to_server(ServerEvent::UserTyping, &server_tx);
+                        }
+                        if app.live.typed(Instant::now()) {
+                            send_live_line(&app, &server_tx);
+                        }
+                    }
+                    Key::Backspace => {
+                        app.input.pop();
+                        if app.live.typed(Instant::now()) {
+                            send_live_line(&app, &server_tx);
+                        }
+                    }
+                    // Newest at the top: up is newer, down is older
+                    Key::Up => {
+                        let skip = app.first_display_message_id.saturating_sub(1);
+                        app.scroll_to(skip, msg_list_size);
+                    }
+                    Key::Down => {
+                        let skip = app.first_display_message_id + 1;
+                        app.scroll_to(skip, msg_list_size);
+                    }
+                    // A page keeps a message of the previous one
+                    Key::PageUp => {
+                        let page = msg_list_size.saturating_sub(1).max(1);
+                        let skip = app.first_display_message_id.saturating_sub(page);
+                        app.scroll_to(skip, msg_list_size);
+                    }
+                    Key::PageDown => {
+                        let page = msg_list_size.saturating_sub(1).max(1);
+                        let skip = app.first_display_message_id + page;
+                        app.scroll_to(skip, msg_list_size);
+                    }
+                    Key::Home => app.scroll_to(0, msg_list_size),
+                    Key::End => app.scroll_to(usize::MAX, msg_list_size),
+                    _ => {}
+                },
+                // Input from a distant app
+                Event::DistantMessage(msg) => {
+                    app.clock_len = msg.clock.len();
+                    app.peers.seen(&msg.sender_id, &msg.header, Instant::now());
+                    if app.notifies(&msg) {
+                        ring |= app.bell;
+                        if let Some(command) = &app.notify_command {
+                            command.run(&msg);
+                        }
+                    }
+                    app.speak(&msg);
+                    app.watch(&msg);
+                    if let Some(away) = &mut app.away {
+                        if is_chat(&msg.header) {
+                            *away.senders.entry(msg.sender_id.clone()).or_insert(0) += 1;
+                        }
+                    }
+                    // Public messages of a channel not shown go to its tab
+                    let hidden = match &msg.header {
+                        Public(channel, _) => app
+                            .tabs
+                            .position(channel)
+                            .is_some_and(|tab| tab != app.tabs.shown()),
+                        _ => false,
+                    };
+                    let muted = match &msg.header {
+                        Public(channel, _) => app.mutes.is_muted(channel, SystemTime::now()),
+                        _ => false,
+                    };
+                    if is_chat(&msg.header) {
+                        if !hidden && !muted && app.tabs.shown() == 0 {
+                            app.unread.shown(&msg, app.messages.len());
+                        }
+                        app.typing.stopped(&msg.sender_id);
+                        app.live.stopped(&msg.sender_id);
+                    }
+                    let flag = if app.revoked.contains(&msg.sender_id) {
+                        "[revoked] "
+                    } else if !msg.verified {
+                        "[unverified] "
                     } else {
-                        app.private_recipient_id = last_private_id.clone();
+                        ""
+                    };
+                    let tab = match &msg.header {
+                        Public(channel, _) if hidden => channel.clone(),
+                        _ => app.tabs.channel().clone(),
+                    };
+                    match &msg.header {
+                        Public(channel, content) => {
+                            let now = Instant::now();
+                            let slow = if app.slow.received(channel, &msg.sender_id, now) {
+                                "[slow] "
+                            } else {
+                                ""
+                            };
+                            let prefix = format!("{}{}{}: ", flag, slow, app.name(&msg.sender_id));
+                            let line = format!("{}{}", prefix, content);
+                            let messages = match muted {
+                                true => app.tabs.muted(channel),
+                                false => app.tabs.hidden(channel),
+                            };
+                            let messages = messages.unwrap_or(&mut app.messages);
+                            let position = messages.len();
+                            let shown = app.renderers.render(msg.content_type.as_deref(), content);
+                            push_chat(messages, prefix.clone(), &shown);
+                            let shown_at = messages.len() - 1;
+                            app.edits.shown(msg.id, (tab.clone(), shown_at), prefix);
+                            app.offer_translation(&msg.sender_id, tab, shown_at, content);
+                            let looking =
+                                !hidden && app.first_display_message_id == 0 && app.away.is_none();
+                            if !looking && !muted && app.mentioned(content) {
+                                app.mentions.push(Mention {
+                                    channel: channel.clone(),
+                                    position,
+                                    line,
+                                });
+                            }
+                        }
+                        Private(_, content) => {
+                            let prefix = format!("{}{} to You: ", flag, app.name(&msg.sender_id));
+                            let shown = app.renderers.render(msg.content_type.as_deref(), content);
+                            push_chat(&mut app.messages, prefix, &shown);
+                            let shown_at = app.messages.len() - 1;
+                            app.offer_translation(&msg.sender_id, tab, shown_at, content);
+                            last_private_id = msg.sender_id;
+                        }
+                        Header::Announcement(content, _) => {
+                            let prefix =
+                                format!("{}{} announces: ", flag, app.name(&msg.sender_id));
+                            push_announcement(&mut app.messages, prefix, content);
+                            let shown_at = app.messages.len() - 1;
+                            app.offer_translation(&msg.sender_id, tab, shown_at, content);
+                        }
+                        _ => {}
                     }
+                }
+                Event::History(history) => {
+                    for msg in history {
+                        if is_chat(&msg.header) {
+                            app.unread.shown(&msg, app.messages.len());
+                        }
+                        match &msg.header {
+                            Public(channel, content) => {
+                                let prefix = match channel.is_general() {
+                                    true => format!("[history] {}: ", app.name(&msg.sender_id)),
+                                    false => format!(
+                                        "[history] {} {}: ",
+                                        channel,
+                                        app.name(&msg.sender_id)
+                                    ),
+                                };
+                                let shown =
+                                    app.renderers.render(msg.content_type.as_deref(), content);
+                                push_chat(&mut app.messages, prefix.clone(), &shown);
+                                let place = (app.tabs.channel().clone(), app.messages.len() - 1);
+                                app.edits.shown(msg.id, place, prefix);
+                            }
+                            Header::Edit(id, text) => app.change(*id, Some(text)),
+                            Header::Delete(id) => app.change(*id, None),
+                            Private(recipient, content) => {
+                                let prefix = format!(
+                                    "[history] {} to {}: ",
+                                    app.name(&msg.sender_id),
+                                    app.name(recipient)
+                                );
+                                let shown =
+                                    app.renderers.render(msg.content_type.as_deref(), content);
+                                push_chat(&mut app.messages, prefix, &shown);
+                            }
+                            Header::Announcement(content, _) => {
+                                let prefix =
+                                    format!("[history] {} announces: ", app.name(&msg.sender_id));
+                                push_announcement(&mut app.messages, prefix, content);
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+                Event::Paste(text) => paste::pasted(&mut app, text),
+                Event::Outbox(items) => {
+                    app.outbox = items;
+                }
+                Event::Progress {
+                    op_id,
+                    label,
+                    done,
+                    total,
+                } => app.jobs.progress(op_id, Job { label, done, total }),
+                Event::Connection(change) => {
+                    let previous = app.connections.iter_mut().find(|c| c.name == change.name);
+                    let notice = match (&previous, &change.state) {
+                        (Some(p), reconnect::State::Connected)
+                            if p.state != reconnect::State::Connected =>
+                        {
+                            Some(format!("{} connected", change.name))
+                        }
+                        (Some(p), reconnect::State::Disconnected)
+                            if p.state == reconnect::State::Connected =>
+                        {
+                            Some(format!("{} disconnected", change.name))
+                        }
+                        (_, reconnect::State::Partitioned) => Some(format!(
+                            "{} is cut off, what goes through it waits for /heal {}",
+                            change.name, change.name
+                        )),
+                        (Some(_), reconnect::State::Left) => Some(format!("{} left", change.name)),
+                        _ => None,
+                    };
+                    match previous {
+                        _ if change.state == reconnect::State::Left => {
+                            app.connections.retain(|c| c.name != change.name)
+                        }
+                        Some(previous) => *previous = change,
+                        None => app.connections.push(change),
+                    }
+                    if let Some(notice) = notice {
+                        app.messages.push(System(notice));
+                    }
+                }
+                Event::Discovered(app_id, addr) => {
                     app.messages.push(System(format!(
-                        "Private recipient id set to: {}",
-                        app.private_recipient_id
+                        "{} is on the local network at {}, /connect {} to chat with it",
+                        app_id, addr, app_id
                     )));
+                    app.discovered.insert(app_id, addr);
                 }
-                Key::Ctrl('p') => {
-                    send_to_server(
-                        ServerEvent::UserPrivateMessage(
-                            app.private_recipient_id.clone(),
-                            app.input.clone(),
-                        ),
-                        &server_tx,
-                    );
-                    let message: String = app.input.drain(..).collect();
-                    app.messages.push(User(format!(
-                        "You to {}: {}",
-                        app.private_recipient_id, message
+                Event::IdentityRevoked(app_id) => {
+                    app.messages.push(System(format!(
+                        "The identity of {} was revoked, its messages are flagged",
+                        app_id
                     )));
+                    app.revoked.insert(app_id);
                 }
-                Key::Char(c) => {
-                    app.input.push(c);
-                }
-                Key::Backspace => {
-                    app.input.pop();
+                Event::Nick(app_id, nick) => {
+                    if app.nicks.get(&app_id) != Some(&nick) {
+                        app.messages
+                            .push(System(format!("{} is now known as {}", app_id, nick)));
+                        app.nicks.insert(app_id, nick);
+                    }
                 }
-                Key::Up => {
-                    app.first_display_message_id = app.first_display_message_id.saturating_sub(1);
+                Event::PeerOnline(app_id) => app.peers.presence(&app_id, true, Instant::now()),
+                Event::PeerOffline(app_id) => app.peers.presence(&app_id, false, Instant::now()),
+                Event::Typing(app_id) => app.typing.started(app_id, Instant::now()),
+                Event::LiveLine(app_id, line) => app.live.received(app_id, line, Instant::now()),
+                Event::SlowMode(app_id, channel, seconds) => {
+                    app.messages.push(System(match seconds {
+                        0 => format!(
+                            "{} turned the slow mode of {} off",
+                            app.name(&app_id),
+                            channel
+                        ),
+                        _ => format!(
+                            "{} set the slow mode of {}: one public message every {} seconds",
+                            app.name(&app_id),
+                            channel,
+                            seconds
+                        ),
+                    }));
+                    app.slow.set(channel, seconds);
                 }
-                Key::Down => {
-                    app.first_display_message_id = app
-                        .messages
-                        .len()
-                        .saturating_sub(msg_list_size)
-                        .min(app.first_display_message_id + 1);
+                Event::DisplayClock(clock) => {
+                    for (id, date) in clock.0 {
+                        app.messages
+                            .push(System(format!("App {} date: {}", id, date)));
+                    }
                 }
-                _ => {}
-            },
-            // Input from a distant app
-            Event::DistantMessage(msg) => match &msg.header {
-                Public(content) => {
+                Event::DisplayServices(directory) if directory.is_empty() => {
                     app.messages
-                        .push(User(format!("{}: {}", msg.sender_id, content)));
+                        .push(System("No app offers any service".to_owned()));
                 }
-                Private(_, content) => {
-                    app.messages
-                        .push(User(format!("{} to You: {}", msg.sender_id, content)));
-                    last_private_id = msg.sender_id;
+                Event::DisplayServices(directory) => {
+                    for (id, services) in directory {
+                        for service in services {
+                            app.messages.push(System(format!("{}: {}", id, service)));
+                        }
+                    }
                 }
-                _ => {}
-            },
-            Event::DisplayClock(clock) => {
-                for (id, date) in clock.0 {
+                Event::DisplayStats(stats) => {
+                    let lines: Vec<String> = stats
+                        .iter()
+                        .map(|(name, value)| format!("  {}: {}", name.replace('_', " "), value))
+                        .collect();
                     app.messages
-                        .push(System(format!("App {} date: {}", id, date)));
+                        .push(System(format!("Server stats\n{}", lines.join("\n"))));
+                }
+                Event::DisplayChannelStats(channel, stats) => {
+                    // One message, so that the lines are not shown upside down
+                    let table = stats.render(&channel, |id| app.name(id)).join("\n");
+                    app.messages.push(System(table));
+                }
+                Event::Translated(target, Ok(translation)) => {
+                    let indent = "\n    ";
+                    let under = format!("  ↳ {}", translation.replace('\n', indent));
+                    let amended = app
+                        .tabs
+                        .messages(&target.tab, &mut app.messages)
+                        .is_some_and(|messages| {
+                            messages.amend(target.position, |message| {
+                                if let User(text) | Announcement(text) = message {
+                                    text.push('\n');
+                                    text.push_str(&under);
+                                }
+                            })
+                        });
+                    // Out of the scrollback kept in memory, or its tab was closed
+                    if !amended {
+                        let notice = format!("{} said{}", app.name(&target.sender), under);
+                        app.messages.push(System(notice));
+                    }
+                }
+                Event::Translated(target, Err(e)) => {
+                    app.messages.push(Warning(format!(
+                        "Could not translate the message of {}: {}",
+                        app.name(&target.sender),
+                        e
+                    )));
+                }
+                Event::Notice(notice) => {
+                    let text = notice.to_string();
+                    app.messages.push(match notice.severity {
+                        Severity::Info => System(text),
+                        Severity::Warning => Warning(text),
+                        Severity::Error => Error(text),
+                    });
+                }
+                Event::PrivateSent(id, to, text) => app.receipts.sent(id, &to, &text),
+                Event::PublicSent(id, channel, text) => app.edits.sent(id, &channel, &text),
+                Event::Edited(id, text) => app.change(id, Some(&text)),
+                Event::Deleted(id) => app.change(id, None),
+                Event::Receipt(id) => {
+                    if let Some((tab, position)) = app.receipts.received(id) {
+                        if let Some(messages) = app.tabs.messages(&tab, &mut app.messages) {
+                            messages.amend(position, |message| {
+                                if let User(text) = message {
+                                    text.push_str(" ✓");
+                                }
+                            });
+                        }
+                    }
+                }
+                Event::DeliveryFailed(what) => {
+                    app.messages.push(System(format!(
+                        "Not delivered, nobody acknowledged it: {}",
+                        what
+                    )));
+                }
+                Event::Tick => {
+                    app.check_quiet_hours();
+                    for channel in app.mutes.expire(SystemTime::now()) {
+                        app.messages
+                            .push(System(format!("{} is not muted anymore", channel)));
+                    }
+                    app.typing.expire(Instant::now());
+                    app.live.expire(Instant::now());
+                    if app.live.due(Instant::now()) {
+                        send_live_line(&app, &server_tx);
+                    }
                 }
             }
-            Event::ServerMessage(string) => {
-                app.messages.push(System(format!("Server: {}", string)));
+            handled += 1;
+            next = replayed.pop_front().map(Event::UserInput);
+            if next.is_none() && handled < MAX_BATCH {
+                next = events.try_next();
             }
-            Event::Tick => {}
+        }
+        if ring {
+            write!(terminal.backend_mut(), "\x07")?;
         }
     }
 
+    write!(terminal.backend_mut(), "\x1b[?2004l")?;
+    app.drafts.save(app.tabs.channel(), &app.input);
     send_to_server(ServerEvent::Shutdown, &server_tx);
 
     Ok(())
@@ -0,0 +1,139 @@
+//! Channels muted with `/mute` for a while: their messages neither notify nor
+//! count as unseen or unread until the mute ends. The end of each is saved on
+//! the system clock, a restart does not cut it short.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::server::messages::Channel;
+
+/// `90s`, `30m`, `2h` or `1d`
+pub fn parse_duration(spec: &str) -> Option<Duration> {
+    let unit = match spec.chars().last()? {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        _ => return None,
+    };
+    let count: u64 = spec[..spec.len() - 1].parse().ok()?;
+    Some(Duration::from_secs(count.checked_mul(unit)?)).filter(|d| !d.is_zero())
+}
+
+fn seconds(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[derive(Default)]
+pub struct Mutes {
+    path: Option<PathBuf>,
+    until: BTreeMap<Channel, u64>, // Seconds since the Unix epoch
+}
+
+impl Mutes {
+    /// Reads the mutes saved in `path`, they are saved there when changed
+    pub fn load(path: PathBuf) -> Self {
+        let until = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Mutes {
+            path: Some(path),
+            until,
+        }
+    }
+
+    /// Mutes `channel` for `duration` from `now`
+    pub fn mute(&mut self, channel: Channel, duration: Duration, now: SystemTime) {
+        self.until.insert(channel, seconds(now + duration));
+        self.save();
+    }
+
+    /// Returns whether `channel` was muted
+    pub fn unmute(&mut self, channel: &Channel) -> bool {
+        let muted = self.until.remove(channel).is_some();
+        self.save();
+        muted
+    }
+
+    pub fn is_muted(&self, channel: &Channel, now: SystemTime) -> bool {
+        self.until
+            .get(channel)
+            .is_some_and(|until| seconds(now) < *until)
+    }
+
+    /// Each channel muted, with how long it stays so from `now`
+    pub fn iter(&self, now: SystemTime) -> impl Iterator<Item = (&Channel, Duration)> {
+        let now = seconds(now);
+        self.until
+            .iter()
+            .map(move |(channel, until)| (channel, Duration::from_secs(until.saturating_sub(now))))
+    }
+
+    /// Forgets the mutes ended at `now`, returns their channels
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Channel> {
+        let now = seconds(now);
+        let ended: Vec<Channel> = self
+            .until
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(channel, _)| channel.clone())
+            .collect();
+        if !ended.is_empty() {
+            self.until.retain(|_, until| *until > now);
+            self.save();
+        }
+        ended
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            let saved = serde_json::to_string(&self.until)
+                .map_err(|e| e.to_string())
+                .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                log::error!("Could not save the muted channels: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mutes_end_on_time_across_runs() {
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("0m"), None);
+        assert_eq!(parse_duration("2"), None);
+        assert_eq!(parse_duration("h"), None);
+
+        let path = std::env::temp_dir().join(format!("netchat-test-{}.mutes", std::process::id()));
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let rust = Channel::parse("#rust").unwrap();
+        let mut mutes = Mutes::load(path.clone());
+        mutes.mute(rust.clone(), Duration::from_secs(7200), now);
+        assert!(mutes.is_muted(&rust, now));
+        assert!(!mutes.is_muted(&Channel::default(), now));
+
+        // Restarted an hour later
+        let later = now + Duration::from_secs(3600);
+        let mut mutes = Mutes::load(path.clone());
+        assert!(mutes.is_muted(&rust, later));
+        assert_eq!(
+            mutes.iter(later).next(),
+            Some((&rust, Duration::from_secs(3600)))
+        );
+        assert!(mutes.expire(later).is_empty());
+        let end = now + Duration::from_secs(7200);
+        assert!(!mutes.is_muted(&rust, end));
+        assert_eq!(mutes.expire(end), vec![rust.clone()]);
+        assert!(!mutes.unmute(&rust));
+        assert!(Mutes::load(path.clone()).iter(end).next().is_none());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+//! Pastes of many lines are not sent line by line without asking first
+
+use std::sync::mpsc;
+
+use termion::event::Key;
+
+use super::{send_chat, App, Message::System};
+use crate::server::events::Event as ServerEvent;
+
+/// Longer pastes ask how to send them, shorter ones go to the input field
+pub const PROMPT_LINES: usize = 5;
+
+/// Takes pasted `text`, asks what to do with it if it is long
+pub fn pasted(app: &mut App, text: String) {
+    let text = text.trim_end_matches('\n').to_owned();
+    let lines = text.lines().count();
+    if lines <= PROMPT_LINES {
+        app.input.push_str(&text);
+        return;
+    }
+    app.messages.push(System(format!(
+        "Pasted {} lines: send them as 1 message, as {} messages, \
+         as a code block, or cancel? [1/2/3/Esc]",
+        lines, lines
+    )));
+    app.paste = Some(text);
+}
+
+/// Answer to the question of `pasted`
+pub fn choose(app: &mut App, key: Key, server_tx: &mpsc::Sender<ServerEvent>) {
+    let text = match app.paste.take() {
+        Some(text) => text,
+        None => return,
+    };
+    match key {
+        Key::Char('1') => send_chat(app, None, &text, server_tx),
+        Key::Char('2') => {
+            for line in text.lines().filter(|l| !l.trim().is_empty()) {
+                send_chat(app, None, line, server_tx);
+            }
+        }
+        Key::Char('3') => send_chat(app, None, &format!("```\n{}\n```", text), server_tx),
+        _ => app.messages.push(System("Paste cancelled".to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_pastes_wait_for_a_choice() {
+        let (server_tx, server_rx) = mpsc::channel();
+        let mut app = App::default();
+        pasted(&mut app, "a\nb\n".to_owned());
+        assert_eq!(app.input, "a\nb");
+
+        let long: Vec<_> = (0..200).map(|i| i.to_string()).collect();
+        pasted(&mut app, long.join("\n"));
+        assert!(
+            server_rx.try_recv().is_err(),
+            "nothing is sent before choosing"
+        );
+        choose(&mut app, Key::Char('2'), &server_tx);
+        assert_eq!(server_rx.try_iter().count(), 200);
+
+        pasted(&mut app, long.join("\n"));
+        choose(&mut app, Key::Esc, &server_tx);
+        assert!(app.paste.is_none());
+        assert!(server_rx.try_recv().is_err());
+    }
+}
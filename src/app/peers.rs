@@ -0,0 +1,254 @@
+//! The peer panel, toggled with `/peers`: the apps heard from, grouped by
+//! channel or by tag and sorted by activity, name or last seen
+//!
+//! Tags are kept in the contact store, `/tag <app> <tag>` adds or removes one.
+//! Peers the server tells are offline stay listed, dimmed.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::time::Instant;
+
+use super::AppId;
+use crate::server::messages::{Channel, Header};
+use netchat_core::delivery::is_chat;
+
+/// Names accepted by [`Grouping::by_name`]
+pub const GROUPINGS: &[&str] = &["none", "channel", "tag"];
+
+/// Names accepted by [`Sort::by_name`]
+pub const SORTS: &[&str] = &["activity", "name", "seen"];
+
+/// What the panel puts together
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Grouping {
+    None,
+    /// Under each channel the peer spoke in
+    Channel,
+    /// Under each of its tags
+    Tag,
+}
+
+/// Order of the peers in a group
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Sort {
+    /// Most messages first
+    Activity,
+    /// By name shown
+    Name,
+    /// Last heard from first
+    Seen,
+}
+
+impl Grouping {
+    /// The grouping called `name`, one of [`GROUPINGS`]
+    pub fn by_name(name: &str) -> Option<Grouping> {
+        match name {
+            "none" => Some(Grouping::None),
+            "channel" => Some(Grouping::Channel),
+            "tag" => Some(Grouping::Tag),
+            _ => None,
+        }
+    }
+}
+
+impl Sort {
+    /// The order called `name`, one of [`SORTS`]
+    pub fn by_name(name: &str) -> Option<Sort> {
+        match name {
+            "activity" => Some(Sort::Activity),
+            "name" => Some(Sort::Name),
+            "seen" => Some(Sort::Seen),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Peer {
+    /// Channels it sent public messages in
+    channels: BTreeSet<Channel>,
+    tags: BTreeSet<String>,
+    /// Chat messages received from it
+    messages: usize,
+    /// None for a peer only known by its tags
+    last_seen: Option<Instant>,
+    online: bool,
+}
+
+/// Peers heard from, and how the panel shows them
+pub struct Peers {
+    peers: HashMap<AppId, Peer>,
+    pub grouping: Grouping,
+    pub sort: Sort,
+    /// Whether the panel is displayed
+    pub shown: bool,
+}
+
+impl Default for Peers {
+    fn default() -> Peers {
+        Peers {
+            peers: HashMap::new(),
+            grouping: Grouping::None,
+            sort: Sort::Activity,
+            shown: false,
+        }
+    }
+}
+
+impl Peers {
+    /// A message with `header` came from `app_id`
+    pub fn seen(&mut self, app_id: &str, header: &Header, now: Instant) {
+        let peer = self.peers.entry(app_id.to_owned()).or_default();
+        peer.last_seen = Some(now);
+        if is_chat(header) {
+            peer.messages += 1;
+        }
+        if let Header::Public(channel, _) = header {
+            peer.channels.insert(channel.clone());
+        }
+    }
+
+    /// The server told `app_id` is online, or offline
+    pub fn presence(&mut self, app_id: &str, online: bool, now: Instant) {
+        let peer = self.peers.entry(app_id.to_owned()).or_default();
+        if online {
+            peer.last_seen = Some(now);
+        }
+        peer.online = online;
+    }
+
+    pub fn is_online(&self, app_id: &str) -> bool {
+        self.peers.get(app_id).is_some_and(|peer| peer.online)
+    }
+
+    pub fn online(&self) -> usize {
+        self.peers.values().filter(|peer| peer.online).count()
+    }
+
+    pub fn len(&self) -> usize {
+        self.peers
+            .values()
+            .filter(|p| p.last_seen.is_some())
+            .count()
+    }
+
+    /// Adds `tag` to `app_id`, or removes it if it had it. Returns whether it has it.
+    pub fn toggle_tag(&mut self, app_id: &str, tag: &str) -> bool {
+        let tags = &mut self.peers.entry(app_id.to_owned()).or_default().tags;
+        !tags.remove(tag) && tags.insert(tag.to_owned())
+    }
+
+    pub fn tag(&mut self, app_id: &str, tag: &str) {
+        let peer = self.peers.entry(app_id.to_owned()).or_default();
+        peer.tags.insert(tag.to_owned());
+    }
+
+    /// Titles of the groups, in order, and their peers sorted. `name` is what
+    /// an app is shown as; a peer lands in every group it belongs to, and in
+    /// the last one, untitled, if it belongs to none.
+    pub fn groups<'a>(
+        &'a self,
+        name: impl Fn(&'a str) -> &'a str,
+    ) -> Vec<(Option<String>, Vec<&'a str>)> {
+        let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+        let mut others = Vec::new();
+        for (app_id, peer) in &self.peers {
+            if peer.last_seen.is_none() {
+                continue;
+            }
+            let titles: Vec<String> = match self.grouping {
+                Grouping::None => Vec::new(),
+                Grouping::Channel => peer.channels.iter().map(Channel::to_string).collect(),
+                Grouping::Tag => peer.tags.iter().cloned().collect(),
+            };
+            if titles.is_empty() {
+                others.push(app_id.as_str());
+            }
+            for title in titles {
+                groups.entry(title).or_default().push(app_id);
+            }
+        }
+        let mut groups: Vec<_> = groups
+            .into_iter()
+            .map(|(title, ids)| (Some(title), ids))
+            .collect();
+        if !others.is_empty() {
+            groups.push((None, others));
+        }
+        for (_, ids) in &mut groups {
+            match self.sort {
+                Sort::Activity => {
+                    ids.sort_by_key(|id| (std::cmp::Reverse(self.peers[*id].messages), name(id)))
+                }
+                Sort::Name => ids.sort_by_key(|id| (name(id), *id)),
+                Sort::Seen => {
+                    ids.sort_by_key(|id| (std::cmp::Reverse(self.peers[*id].last_seen), *id))
+                }
+            }
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn nick(id: &str) -> &str {
+        if id == "carol" {
+            "alice"
+        } else {
+            id
+        }
+    }
+
+    #[test]
+    fn peers_are_grouped_and_sorted() {
+        let rust = Channel::parse("#rust").unwrap();
+        let public = |channel: &Channel| Header::Public(channel.clone(), "hi".to_owned());
+        let start = Instant::now();
+        let mut peers = Peers::default();
+        peers.seen("carol", &public(&rust), start);
+        peers.seen("bob", &public(&Channel::default()), start);
+        peers.seen("bob", &public(&rust), start);
+        peers.seen(
+            "dave",
+            &Header::Heartbeat(Vec::new()),
+            start + Duration::from_secs(1),
+        );
+        peers.tag("mallory", "ops");
+        assert_eq!(peers.len(), 3, "mallory was never heard from");
+        peers.presence("bob", true, start);
+        peers.presence("carol", true, start);
+        peers.presence("carol", false, start);
+        assert!(peers.is_online("bob") && !peers.is_online("carol"));
+        assert_eq!(peers.online(), 1);
+
+        let groups = peers.groups(|id| id);
+        assert_eq!(groups, [(None, vec!["bob", "carol", "dave"])]);
+
+        peers.grouping = Grouping::Channel;
+        peers.sort = Sort::Seen;
+        assert_eq!(
+            peers.groups(nick),
+            [
+                (Some("#general".to_owned()), vec!["bob"]),
+                (Some("#rust".to_owned()), vec!["bob", "carol"]),
+                (None, vec!["dave"]),
+            ]
+        );
+
+        peers.grouping = Grouping::Tag;
+        peers.sort = Sort::Name;
+        assert!(peers.toggle_tag("dave", "ops"));
+        assert!(peers.toggle_tag("carol", "ops"));
+        assert!(!peers.toggle_tag("dave", "ops"));
+        assert_eq!(
+            peers.groups(nick),
+            [
+                (Some("ops".to_owned()), vec!["carol"]),
+                (None, vec!["bob", "dave"]),
+            ]
+        );
+    }
+}
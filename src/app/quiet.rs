@@ -0,0 +1,81 @@
+//! Hours during which received messages do not ring the bell
+
+use std::str::FromStr;
+
+use super::daylog::LocalTime;
+
+/// From `start` to `end`, minutes since midnight, across midnight if `end`
+/// comes first
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    start: u32,
+    end: u32,
+}
+
+fn minutes(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 23 || minutes > 59 {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, String> {
+        let error = || format!("expected <hh:mm>-<hh:mm>, got {}", spec);
+        let (start, end) = spec.split_once('-').ok_or_else(error)?;
+        Ok(QuietHours {
+            start: minutes(start).ok_or_else(error)?,
+            end: minutes(end).ok_or_else(error)?,
+        })
+    }
+}
+
+impl QuietHours {
+    pub fn contains(&self, at: &LocalTime) -> bool {
+        let now = at.hour * 60 + at.minute;
+        if self.start <= self.end {
+            self.start <= now && now < self.end
+        } else {
+            now >= self.start || now < self.end
+        }
+    }
+
+    /// `08:00`
+    pub fn end(&self) -> String {
+        format!("{:02}:{:02}", self.end / 60, self.end % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(hour: u32, minute: u32) -> LocalTime {
+        LocalTime {
+            year: 2026,
+            month: 10,
+            day: 14,
+            weekday: 3,
+            hour,
+            minute,
+        }
+    }
+
+    #[test]
+    fn quiet_hours_can_span_midnight() {
+        let night: QuietHours = "22:00-08:00".parse().unwrap();
+        assert!(night.contains(&at(23, 30)));
+        assert!(night.contains(&at(7, 59)));
+        assert!(!night.contains(&at(8, 0)));
+        assert!(!night.contains(&at(12, 0)));
+        let lunch: QuietHours = "12:00-13:30".parse().unwrap();
+        assert!(lunch.contains(&at(13, 0)));
+        assert!(!lunch.contains(&at(22, 0)));
+        assert!("22:00".parse::<QuietHours>().is_err());
+        assert!("25:00-08:00".parse::<QuietHours>().is_err());
+    }
+}
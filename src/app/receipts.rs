@@ -0,0 +1,74 @@
+//! Private messages sent, marked with a ✓ once their recipient was shown them
+
+use std::collections::VecDeque;
+
+use super::AppId;
+use crate::server::messages::{Channel, MsgId};
+
+/// Messages waiting for their receipt above this, the oldest are forgotten
+const MAX_WAITING: usize = 256;
+
+/// Where a private message sent is shown, the channel of its tab and its
+/// position in the tab
+pub type Place = (Channel, usize);
+
+#[derive(Default)]
+pub struct Receipts {
+    /// Shown as typed, before the server gave them an id: recipient and text
+    unsent: VecDeque<(AppId, String, Place)>,
+    /// Waiting for their receipt
+    sent: VecDeque<(MsgId, Place)>,
+}
+
+impl Receipts {
+    /// A private message of `text` for `to` was shown at `place`
+    pub fn shown(&mut self, to: &str, text: &str, place: Place) {
+        if self.unsent.len() >= MAX_WAITING {
+            self.unsent.pop_front();
+        }
+        self.unsent
+            .push_back((to.to_owned(), text.to_owned(), place));
+    }
+
+    /// The server sent the private message of `text` for `to` as `id`
+    pub fn sent(&mut self, id: MsgId, to: &str, text: &str) {
+        let shown = self
+            .unsent
+            .iter()
+            .position(|(t, x, _)| t == to && x == text);
+        if let Some((_, _, place)) = shown.and_then(|i| self.unsent.remove(i)) {
+            if self.sent.len() >= MAX_WAITING {
+                self.sent.pop_front();
+            }
+            self.sent.push_back((id, place));
+        }
+    }
+
+    /// Where message `id` is shown, if it is still waiting for its receipt
+    pub fn received(&mut self, id: MsgId) -> Option<Place> {
+        let i = self.sent.iter().position(|(sent, _)| *sent == id)?;
+        self.sent.remove(i).map(|(_, place)| place)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receipts_find_the_messages_shown() {
+        let general = Channel::default();
+        let mut receipts = Receipts::default();
+        receipts.shown("bob", "hi", (general.clone(), 3));
+        receipts.shown("carol", "hi", (general.clone(), 4));
+        receipts.shown("bob", "hi", (general.clone(), 5));
+        receipts.sent(10, "carol", "hi");
+        receipts.sent(11, "bob", "hi");
+        receipts.sent(12, "bob", "never shown");
+
+        assert_eq!(receipts.received(11), Some((general.clone(), 3)));
+        assert_eq!(receipts.received(11), None, "marked once");
+        assert_eq!(receipts.received(12), None);
+        assert_eq!(receipts.received(10), Some((general, 4)));
+    }
+}
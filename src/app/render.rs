@@ -0,0 +1,176 @@
+//! How chat messages are shown after their content type: bots send json,
+//! markdown or references to images, shown readable rather than raw. Texts of
+//! a type without a renderer, or one it fails on, are shown as they came.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+/// Shows a text of its content type, None if it is not one
+pub type Renderer = fn(&str) -> Option<String>;
+
+/// Renderer of each content type
+pub struct Renderers {
+    by_type: HashMap<String, Renderer>,
+}
+
+impl Default for Renderers {
+    fn default() -> Renderers {
+        let mut renderers = Renderers {
+            by_type: HashMap::new(),
+        };
+        renderers.register("text/markdown", markdown);
+        renderers.register("application/json", json);
+        renderers.register("image/png-ref", image_ref);
+        renderers
+    }
+}
+
+impl Renderers {
+    /// Shows the texts of `content_type` with `renderer`, in place of the one
+    /// it had
+    pub fn register(&mut self, content_type: &str, renderer: Renderer) {
+        self.by_type.insert(essence(content_type), renderer);
+    }
+
+    /// `text` as shown, plain text when `content_type` is None
+    pub fn render<'a>(&self, content_type: Option<&str>, text: &'a str) -> Cow<'a, str> {
+        let renderer = content_type.and_then(|c| self.by_type.get(&essence(c)));
+        match renderer.and_then(|render| render(text)) {
+            Some(shown) => Cow::Owned(shown),
+            None => Cow::Borrowed(text),
+        }
+    }
+}
+
+/// `text/markdown; charset=utf-8` is `text/markdown`
+fn essence(content_type: &str) -> String {
+    let end = content_type.find(';').unwrap_or(content_type.len());
+    content_type[..end].trim().to_ascii_lowercase()
+}
+
+/// Indented, one value a line
+fn json(text: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    serde_json::to_string_pretty(&value).ok()
+}
+
+/// The reference, then the description of the image on the lines after it
+fn image_ref(text: &str) -> Option<String> {
+    let (reference, about) = match text.find('\n') {
+        Some(end) => (text[..end].trim(), text[end..].trim()),
+        None => (text.trim(), ""),
+    };
+    if reference.is_empty() || reference.contains(char::is_whitespace) {
+        return None;
+    }
+    Some(match about {
+        "" => format!("[image] {}", reference),
+        about => format!("[image: {}] {}", about.replace('\n', " "), reference),
+    })
+}
+
+/// Without its markup: headings in capitals, bullets for list items, bars
+/// before quotes, code blocks indented and links followed by their target
+fn markdown(text: &str) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut code = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            code = !code;
+            continue;
+        }
+        if code {
+            lines.push(format!("    {}", line));
+            continue;
+        }
+        let heading = trimmed.trim_start_matches('#');
+        let shown = if heading.len() < trimmed.len() && heading.starts_with(' ') {
+            inline(heading.trim()).to_uppercase()
+        } else if let Some(item) = ["- ", "* ", "+ "]
+            .iter()
+            .find_map(|bullet| trimmed.strip_prefix(bullet))
+        {
+            let indent = &line[..line.len() - trimmed.len()];
+            format!("{}• {}", indent, inline(item))
+        } else if let Some(quote) = trimmed.strip_prefix('>') {
+            format!("│ {}", inline(quote.trim_start()))
+        } else {
+            inline(line)
+        };
+        lines.push(shown);
+    }
+    Some(lines.join("\n"))
+}
+
+/// A line without its bold markers nor code backticks, links as their text
+/// and target
+fn inline(line: &str) -> String {
+    let mut shown = String::new();
+    // Inside backticks, between odd pieces, the text is kept as is
+    for (i, piece) in line.split('`').enumerate() {
+        if i % 2 == 1 {
+            shown.push_str(piece);
+            continue;
+        }
+        let mut rest = piece.replace("**", "");
+        while let Some(start) = rest.find('[') {
+            let link = rest[start..].find("](").and_then(|middle| {
+                let end = rest[start + middle..].find(')')?;
+                Some((start + middle, start + middle + end))
+            });
+            let (middle, end) = match link {
+                Some(link) => link,
+                None => break,
+            };
+            shown.push_str(&rest[..start]);
+            shown.push_str(&format!(
+                "{} <{}>",
+                &rest[start + 1..middle],
+                &rest[middle + 2..end]
+            ));
+            rest = rest[end + 1..].to_owned();
+        }
+        shown.push_str(&rest);
+    }
+    shown
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads_are_shown_after_their_type() {
+        let renderers = Renderers::default();
+        assert_eq!(renderers.render(None, "**hi**"), "**hi**");
+        assert_eq!(
+            renderers.render(
+                Some("application/json"),
+                r#"{"build":"green","tests":[1,2]}"#
+            ),
+            "{\n  \"build\": \"green\",\n  \"tests\": [\n    1,\n    2\n  ]\n}"
+        );
+        assert_eq!(
+            renderers.render(Some("application/json"), "not json"),
+            "not json",
+            "shown as it came"
+        );
+        assert_eq!(
+            renderers.render(
+                Some("Text/Markdown; charset=utf-8"),
+                "# Build\n- **green** on `main`\n> see [the logs](https://ci/1)\n```\nlet x = 1;\n```"
+            ),
+            "BUILD\n• green on main\n│ see the logs <https://ci/1>\n    let x = 1;"
+        );
+        assert_eq!(
+            renderers.render(Some("image/png-ref"), "https://ci/graph.png\nBuild times"),
+            "[image: Build times] https://ci/graph.png"
+        );
+        assert_eq!(renderers.render(Some("image/x-other"), "raw"), "raw");
+
+        let mut renderers = renderers;
+        renderers.register("text/plain", |text| Some(text.to_uppercase()));
+        assert_eq!(renderers.render(Some("text/plain"), "hi"), "HI");
+    }
+}
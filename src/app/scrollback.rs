@@ -0,0 +1,259 @@
+use std::collections::VecDeque;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use super::daylog::{DayLog, LocalTime};
+use super::Message::{self, System};
+
+/// Default number of messages kept in memory
+pub const DEFAULT_CAPACITY: usize = 1000;
+
+/// Messages read back from disk at once
+const PAGE_LEN: usize = 100;
+
+/// Message history of the UI, older messages are moved to a file
+pub struct Scrollback {
+    recent: VecDeque<Message>,
+    capacity: usize,
+    spill: Option<Spill>,
+    day: Option<String>, // Date of the last message, a separator marks the next day
+    log: Option<DayLog>,
+}
+
+/// Messages moved out of memory, one json line each
+struct Spill {
+    path: PathBuf,
+    file: File,
+    len: usize,
+    page_offsets: Vec<u64>, // Offset of every PAGE_LEN-th line
+    end: u64,
+    page: Option<(usize, Vec<Message>)>, // Last page read back
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Scrollback {
+            recent: VecDeque::new(),
+            capacity: usize::MAX,
+            spill: None,
+            day: None,
+            log: None,
+        }
+    }
+}
+
+impl Scrollback {
+    /// Keeps `capacity` messages in memory, the older ones go to `path`
+    pub fn spill_to(&mut self, path: PathBuf, capacity: usize) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        self.capacity = capacity;
+        self.spill = Some(Spill {
+            path,
+            file,
+            len: 0,
+            page_offsets: Vec::new(),
+            end: 0,
+            page: None,
+        });
+        while self.recent.len() > self.capacity {
+            self.spill_oldest();
+        }
+        Ok(())
+    }
+
+    /// Also writes every message to a file per day in `dir`
+    pub fn log_to(&mut self, dir: PathBuf) -> io::Result<()> {
+        self.log = Some(DayLog::open(dir)?);
+        Ok(())
+    }
+
+    /// Number of messages, on disk included
+    pub fn len(&self) -> usize {
+        self.spill.as_ref().map_or(0, |s| s.len) + self.recent.len()
+    }
+
+    pub fn push(&mut self, message: Message) {
+        self.push_at(message, &LocalTime::now());
+    }
+
+    fn push_at(&mut self, message: Message, at: &LocalTime) {
+        let date = at.date();
+        if self.day.as_ref().is_some_and(|day| *day != date) {
+            let separator = format!("--- Day changed to {} ---", at.long_date());
+            self.append(System(separator));
+        }
+        self.day = Some(date);
+        if let Some(log) = &mut self.log {
+            if let Err(e) = log.write(at, message.str()) {
+                log::error!("Could not write the history: {}", e);
+            }
+        }
+        self.append(message);
+    }
+
+    fn append(&mut self, message: Message) {
+        self.recent.push_back(message);
+        if self.recent.len() > self.capacity {
+            self.spill_oldest();
+        }
+    }
+
+    /// Changes message `i` with `change`, unless it was moved to disk.
+    /// Returns whether it was changed.
+    pub fn amend(&mut self, i: usize, change: impl FnOnce(&mut Message)) -> bool {
+        let spilled = self.spill.as_ref().map_or(0, |s| s.len);
+        match i.checked_sub(spilled).and_then(|i| self.recent.get_mut(i)) {
+            Some(message) => {
+                change(message);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Up to `count` messages, newest first, skipping the `skip` newest ones
+    pub fn window(&mut self, skip: usize, count: usize) -> Vec<Message> {
+        let total = self.len();
+        let mut rows = Vec::with_capacity(count);
+        for i in (0..total.saturating_sub(skip)).rev().take(count) {
+            rows.push(self.get(i).unwrap_or_else(|| System(String::new())));
+        }
+        rows
+    }
+
+    fn get(&mut self, i: usize) -> Option<Message> {
+        let spilled = self.spill.as_ref().map_or(0, |s| s.len);
+        if i >= spilled {
+            return self.recent.get(i - spilled).cloned();
+        }
+        let spill = self.spill.as_mut()?;
+        let page = i / PAGE_LEN;
+        if spill.page.as_ref().map(|(p, _)| *p) != Some(page) {
+            spill.page = match spill.read_page(page) {
+                Ok(messages) => Some((page, messages)),
+                Err(e) => {
+                    log::error!("Could not read back the scrollback: {}", e);
+                    None
+                }
+            };
+        }
+        let (_, messages) = spill.page.as_ref()?;
+        messages.get(i % PAGE_LEN).cloned()
+    }
+
+    fn spill_oldest(&mut self) {
+        if let (Some(spill), Some(message)) = (self.spill.as_mut(), self.recent.pop_front()) {
+            if let Err(e) = spill.append(&message) {
+                log::error!("Could not write the scrollback: {}", e);
+            }
+        }
+    }
+}
+
+impl Spill {
+    fn append(&mut self, message: &Message) -> io::Result<()> {
+        let line = format!("{}\n", serde_json::to_string(message)?);
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(line.as_bytes())?;
+        if self.len.is_multiple_of(PAGE_LEN) {
+            self.page_offsets.push(self.end);
+        }
+        self.end += line.len() as u64;
+        self.len += 1;
+        // The cached page may have been partial
+        if self.page.as_ref().map(|(p, _)| *p) == Some((self.len - 1) / PAGE_LEN) {
+            self.page = None;
+        }
+        Ok(())
+    }
+
+    fn read_page(&mut self, page: usize) -> io::Result<Vec<Message>> {
+        let offset = self.page_offsets.get(page).cloned().unwrap_or(self.end);
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut messages = Vec::with_capacity(PAGE_LEN);
+        for line in BufReader::new(&self.file).lines().take(PAGE_LEN) {
+            messages.push(serde_json::from_str(&line?)?);
+        }
+        Ok(messages)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::Message::User;
+
+    #[test]
+    fn pages_back_spilled_messages() {
+        let path =
+            std::env::temp_dir().join(format!("netchat-test-{}.scrollback", std::process::id()));
+        let mut scrollback = Scrollback::default();
+        scrollback.push(User("0".to_owned()));
+        scrollback.spill_to(path.clone(), 10).unwrap();
+        for i in 1..250 {
+            scrollback.push(User(i.to_string()));
+        }
+
+        assert_eq!(scrollback.len(), 250);
+        assert_eq!(scrollback.recent.len(), 10);
+        assert_eq!(scrollback.window(0, 2), vec!["249", "248"]);
+        assert_eq!(scrollback.window(148, 3), vec!["101", "100", "99"]);
+        assert_eq!(scrollback.window(248, 5), vec!["1", "0"]);
+
+        // Reading back does not get in the way of spilling
+        scrollback.push(User("250".to_owned()));
+        assert_eq!(scrollback.window(11, 1), vec!["239"]);
+
+        drop(scrollback);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn days_get_a_separator_and_a_file() {
+        let dir = std::env::temp_dir().join(format!("netchat-test-{}.days", std::process::id()));
+        let mut scrollback = Scrollback::default();
+        scrollback.log_to(dir.clone()).unwrap();
+        let monday = LocalTime {
+            year: 2026,
+            month: 10,
+            day: 12,
+            weekday: 1,
+            hour: 23,
+            minute: 59,
+        };
+        let tuesday = LocalTime {
+            day: 13,
+            weekday: 2,
+            hour: 9,
+            minute: 5,
+            ..monday.clone()
+        };
+        scrollback.push_at(User("bob: ship it?".to_owned()), &monday);
+        scrollback.push_at(User("You: decided, we ship".to_owned()), &tuesday);
+
+        assert_eq!(
+            scrollback.window(0, 3),
+            vec![
+                "You: decided, we ship",
+                "--- Day changed to Tuesday 13 October 2026 ---",
+                "bob: ship it?"
+            ]
+        );
+        let tuesday_log = fs::read_to_string(dir.join("2026-10-13.log")).unwrap();
+        assert_eq!(tuesday_log, "09:05 You: decided, we ship\n");
+        assert!(dir.join("2026-10-12.log").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
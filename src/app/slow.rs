@@ -0,0 +1,86 @@
+//! Slow mode: in a channel, each sender waits a while between two public
+//! messages, set by an operator with `/slow`
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::AppId;
+use crate::server::messages::Channel;
+
+/// When we and the others last sent a public message in each channel, to
+/// hold ours back and flag theirs
+#[derive(Default)]
+pub struct SlowMode {
+    /// Channels without one are not in slow mode
+    intervals: HashMap<Channel, Duration>,
+    sent: HashMap<Channel, Instant>,
+    received: HashMap<(Channel, AppId), Instant>,
+}
+
+impl SlowMode {
+    /// Sets the wait in `channel` to `seconds`, 0 turns the slow mode off
+    pub fn set(&mut self, channel: Channel, seconds: u64) {
+        match seconds {
+            0 => self.intervals.remove(&channel),
+            _ => self.intervals.insert(channel, Duration::from_secs(seconds)),
+        };
+    }
+
+    pub fn interval(&self, channel: &Channel) -> Option<Duration> {
+        self.intervals.get(channel).copied()
+    }
+
+    /// How long until we may send a public message in `channel`, None if we may now
+    pub fn wait(&self, channel: &Channel, now: Instant) -> Option<Duration> {
+        let next = *self.sent.get(channel)? + self.interval(channel)?;
+        Some(next.saturating_duration_since(now)).filter(|d| !d.is_zero())
+    }
+
+    /// We sent a public message in `channel`
+    pub fn sent(&mut self, channel: &Channel, now: Instant) {
+        self.sent.insert(channel.clone(), now);
+    }
+
+    /// `sender` sent a public message in `channel`, returns whether it came
+    /// too soon after its previous one. Compared when received, a message
+    /// delayed on the way may be flagged.
+    pub fn received(&mut self, channel: &Channel, sender: &str, now: Instant) -> bool {
+        let key = (channel.clone(), sender.to_owned());
+        let previous = self.received.insert(key, now);
+        match (previous, self.interval(channel)) {
+            (Some(previous), Some(interval)) => now.duration_since(previous) < interval,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_too_close_are_held_back_or_flagged() {
+        let start = Instant::now();
+        let (general, rust) = (Channel::default(), Channel::parse("#rust").unwrap());
+        let mut slow = SlowMode::default();
+        slow.sent(&general, start);
+        assert_eq!(slow.wait(&general, start), None, "off by default");
+        assert!(!slow.received(&general, "bob", start));
+        assert!(!slow.received(&general, "bob", start));
+
+        slow.set(general.clone(), 10);
+        assert_eq!(
+            slow.wait(&general, start + Duration::from_secs(4)),
+            Some(Duration::from_secs(6))
+        );
+        assert_eq!(slow.wait(&general, start + Duration::from_secs(10)), None);
+        assert!(slow.received(&general, "bob", start + Duration::from_secs(5)));
+        assert!(!slow.received(&general, "bob", start + Duration::from_secs(15)));
+        assert!(!slow.received(&general, "carol", start + Duration::from_secs(16)));
+        assert!(!slow.received(&rust, "bob", start + Duration::from_secs(16)));
+
+        slow.set(general.clone(), 0);
+        assert_eq!(slow.interval(&general), None);
+        assert_eq!(slow.wait(&general, start), None);
+    }
+}
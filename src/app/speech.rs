@@ -0,0 +1,167 @@
+//! `--speak-command`: incoming messages read out loud by a text-to-speech
+//! command, such as `espeak` or `say`, given the text on its standard input.
+//! `--speak <rule>` chooses which ones, `/speak` turns it off and on.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+
+use super::AppId;
+use crate::server::messages::{Channel, Header, Msg};
+
+/// Rules accepted by `--speak`
+pub const RULES: &str = "all, private, mentions, announcements, from:<app> or in:#<channel>";
+
+/// Which messages are spoken
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rule {
+    /// Every chat message and announcement
+    All,
+    Private,
+    /// Public messages mentioning us
+    Mentions,
+    Announcements,
+    /// Everything an app sends
+    From(AppId),
+    /// The public messages of a channel
+    In(Channel),
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, String> {
+        let error = || format!("expected {}, got {}", RULES, spec);
+        Ok(match spec {
+            "all" => Rule::All,
+            "private" => Rule::Private,
+            "mentions" => Rule::Mentions,
+            "announcements" => Rule::Announcements,
+            _ => match spec.split_once(':') {
+                Some(("from", app_id)) if !app_id.is_empty() => Rule::From(app_id.to_owned()),
+                Some(("in", channel)) => Rule::In(Channel::parse(channel).ok_or_else(error)?),
+                _ => return Err(error()),
+            },
+        })
+    }
+}
+
+impl Rule {
+    /// Whether `msg`, which `mentions` us or not, is spoken
+    fn matches(&self, msg: &Msg, mentions: bool) -> bool {
+        let chat = matches!(
+            msg.header,
+            Header::Public(..) | Header::Private(..) | Header::Announcement(..)
+        );
+        match (self, &msg.header) {
+            (Rule::All, _) => chat,
+            (Rule::Private, Header::Private(..)) => true,
+            (Rule::Mentions, Header::Public(..)) => mentions,
+            (Rule::Announcements, Header::Announcement(..)) => true,
+            (Rule::From(app_id), _) => chat && *app_id == msg.sender_id,
+            (Rule::In(channel), Header::Public(c, _)) => c == channel,
+            _ => false,
+        }
+    }
+}
+
+/// Speaks one message at a time, in the order they came
+pub struct Speaker {
+    rules: Vec<Rule>,
+    tx: mpsc::Sender<String>,
+    /// Turned off by `/speak`
+    pub muted: bool,
+}
+
+impl Speaker {
+    /// Runs `command` with `sh -c` for the messages matching one of `rules`,
+    /// private messages and mentions if there are none
+    pub fn new(command: String, mut rules: Vec<Rule>) -> Speaker {
+        if rules.is_empty() {
+            rules = vec![Rule::Private, Rule::Mentions];
+        }
+        let (tx, rx) = mpsc::channel::<String>();
+        thread::spawn(move || {
+            for text in rx {
+                if let Err(e) = speak(&command, &text) {
+                    log::warn!("The speech command failed: {}", e);
+                }
+            }
+        });
+        Speaker {
+            rules,
+            tx,
+            muted: false,
+        }
+    }
+
+    /// Whether `msg` is spoken, it `mentions` us or not
+    pub fn wants(&self, msg: &Msg, mentions: bool) -> bool {
+        !self.muted && self.rules.iter().any(|rule| rule.matches(msg, mentions))
+    }
+
+    /// Queues `text`, spoken once what came before was
+    pub fn say(&self, text: String) {
+        if self.tx.send(text).is_err() {
+            log::error!("The speech thread is gone");
+        }
+    }
+}
+
+/// Runs `command` with `text` on its standard input until it is done
+fn speak(command: &str, text: &str) -> std::io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(format!("{}\n", text).as_bytes())?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(std::io::Error::other(status.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::Clock;
+
+    #[test]
+    fn rules_choose_the_messages_spoken() {
+        let rust = Channel::parse("#rust").unwrap();
+        let msg = |sender: &str, header: Header| {
+            Msg::new(1, sender.to_owned(), header, Clock::new(sender.to_owned()))
+        };
+        let public = msg("bob", Header::Public(rust.clone(), "hi".to_owned()));
+        let private = msg(
+            "carol",
+            Header::Private("alice".to_owned(), "psst".to_owned()),
+        );
+        let heartbeat = msg("bob", Header::Heartbeat(vec!["bob".to_owned()]));
+
+        let speaker = Speaker::new("true".to_owned(), Vec::new());
+        assert!(speaker.wants(&private, false));
+        assert!(!speaker.wants(&public, false));
+        assert!(speaker.wants(&public, true), "a mention");
+
+        let rules = ["from:bob", "in:#rust"].iter().map(|r| r.parse().unwrap());
+        let mut speaker = Speaker::new("true".to_owned(), rules.collect());
+        assert!(speaker.wants(&public, false));
+        assert!(!speaker.wants(&private, false));
+        assert!(!speaker.wants(&heartbeat, false));
+        speaker.muted = true;
+        assert!(!speaker.wants(&public, false));
+
+        assert!("all".parse::<Rule>().unwrap().matches(&private, false));
+        assert!("in:rust".parse::<Rule>().is_err());
+        assert!("from:".parse::<Rule>().is_err());
+    }
+}
@@ -0,0 +1,267 @@
+//! Segments of the status bar, chosen with `--status-left` and `--status-right`
+//!
+//! To add one, implement [`Segment`] and add it to [`by_name`].
+
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::daylog::LocalTime;
+use crate::server::reconnect;
+
+/// What segments read of the app
+pub struct Context<'a> {
+    pub connections: &'a [reconnect::Change],
+    /// Apps on the clock of the last message received
+    pub clock_len: usize,
+}
+
+/// A piece of the status bar, its text is asked for again every `interval`
+pub trait Segment {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+    /// What it shows, nothing when empty
+    fn text(&mut self, context: &Context) -> String;
+}
+
+/// Names accepted by [`by_name`]
+pub const SEGMENTS: &str = "peers, clock, time or cmd:<shell command>, followed by @<seconds> to \
+                            change how often it is updated";
+
+/// Commands are not run more often than this by default
+const COMMAND_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The segment described by `spec`
+pub fn by_name(spec: &str) -> Result<Box<dyn Segment>, String> {
+    let (name, interval) = match spec.rsplit_once('@') {
+        Some((name, secs)) => match secs.parse() {
+            Ok(secs) if secs > 0 => (name, Some(Duration::from_secs(secs))),
+            _ => (spec, None),
+        },
+        None => (spec, None),
+    };
+    let segment: Box<dyn Segment> = match name {
+        "peers" => Box::new(Peers),
+        "clock" => Box::new(ClockSize),
+        "time" => Box::new(Time),
+        _ if name.starts_with("cmd:") => {
+            Box::new(ShellCommand::new(name["cmd:".len()..].to_owned()))
+        }
+        _ => return Err(format!("unknown segment {}, expected {}", spec, SEGMENTS)),
+    };
+    Ok(match interval {
+        Some(interval) => Box::new(Every(interval, segment)),
+        None => segment,
+    })
+}
+
+/// A segment and what it last showed
+struct Slot {
+    segment: Box<dyn Segment>,
+    text: String,
+    updated: Option<Instant>,
+}
+
+/// Segments shown on the left and on the right of the status bar
+#[derive(Default)]
+pub struct StatusBar {
+    left: Vec<Slot>,
+    right: Vec<Slot>,
+}
+
+impl StatusBar {
+    pub fn push_left(&mut self, segment: Box<dyn Segment>) {
+        self.left.push(Slot::new(segment));
+    }
+
+    pub fn push_right(&mut self, segment: Box<dyn Segment>) {
+        self.right.push(Slot::new(segment));
+    }
+
+    /// Asks the segments whose interval passed for their text again
+    pub fn update(&mut self, context: &Context, now: Instant) {
+        for slot in self.left.iter_mut().chain(&mut self.right) {
+            if slot
+                .updated
+                .is_none_or(|at| now >= at + slot.segment.interval())
+            {
+                slot.text = slot.segment.text(context);
+                slot.updated = Some(now);
+            }
+        }
+    }
+
+    /// Texts of the left segments which are not empty
+    pub fn left(&self) -> impl Iterator<Item = &str> {
+        shown(&self.left)
+    }
+
+    pub fn right(&self) -> impl Iterator<Item = &str> {
+        shown(&self.right)
+    }
+}
+
+impl Slot {
+    fn new(segment: Box<dyn Segment>) -> Slot {
+        Slot {
+            segment,
+            text: String::new(),
+            updated: None,
+        }
+    }
+}
+
+fn shown(slots: &[Slot]) -> impl Iterator<Item = &str> {
+    slots
+        .iter()
+        .map(|slot| slot.text.as_str())
+        .filter(|text| !text.is_empty())
+}
+
+/// Another segment, updated every given interval
+struct Every(Duration, Box<dyn Segment>);
+
+impl Segment for Every {
+    fn interval(&self) -> Duration {
+        self.0
+    }
+
+    fn text(&mut self, context: &Context) -> String {
+        self.1.text(context)
+    }
+}
+
+/// Transports up, out of all of them
+pub struct Peers;
+
+impl Segment for Peers {
+    fn text(&mut self, context: &Context) -> String {
+        let up = context
+            .connections
+            .iter()
+            .filter(|change| change.state == reconnect::State::Connected)
+            .count();
+        format!("{}/{} up", up, context.connections.len())
+    }
+}
+
+/// Apps on the vector clock of the last message received
+pub struct ClockSize;
+
+impl Segment for ClockSize {
+    fn text(&mut self, context: &Context) -> String {
+        match context.clock_len {
+            0 => String::new(),
+            len => format!("clock {}", len),
+        }
+    }
+}
+
+/// Local time
+pub struct Time;
+
+impl Segment for Time {
+    fn text(&mut self, _: &Context) -> String {
+        LocalTime::now().time()
+    }
+}
+
+/// First line a shell command prints, such as `git branch --show-current`.
+/// It runs on its own thread, the bar shows its previous output meanwhile.
+pub struct ShellCommand {
+    command: String,
+    output: Arc<Mutex<String>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl ShellCommand {
+    pub fn new(command: String) -> ShellCommand {
+        ShellCommand {
+            command,
+            output: Arc::default(),
+            running: Arc::default(),
+        }
+    }
+}
+
+impl Segment for ShellCommand {
+    fn interval(&self) -> Duration {
+        COMMAND_INTERVAL
+    }
+
+    fn text(&mut self, _: &Context) -> String {
+        let mut running = self.running.lock().unwrap();
+        if !*running {
+            *running = true;
+            let (command, output) = (self.command.clone(), self.output.clone());
+            let running = self.running.clone();
+            thread::spawn(move || {
+                let printed = run(&command).unwrap_or_else(|e| {
+                    log::warn!("Status bar command `{}` failed: {}", command, e);
+                    String::new()
+                });
+                *output.lock().unwrap() = printed;
+                *running.lock().unwrap() = false;
+            });
+        }
+        self.output.lock().unwrap().clone()
+    }
+}
+
+/// First line printed by `command`, without control characters
+fn run(command: &str) -> std::io::Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut printed = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        // A line is plenty, the rest is not read
+        stdout.take(4096).read_to_string(&mut printed)?;
+    }
+    child.wait()?;
+    let line = printed.lines().next().unwrap_or_default();
+    Ok(line.chars().filter(|c| !c.is_control()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn segments_update_at_their_interval() {
+        let connections = [reconnect::Change {
+            name: "out".to_owned(),
+            state: reconnect::State::Connected,
+        }];
+        let mut context = Context {
+            connections: &connections,
+            clock_len: 0,
+        };
+        let mut bar = StatusBar::default();
+        bar.push_left(by_name("peers").unwrap());
+        bar.push_right(by_name("clock@5").unwrap());
+        bar.push_right(by_name("cmd:echo main; echo more").unwrap());
+        assert!(by_name("battery").is_err());
+
+        let start = Instant::now();
+        bar.update(&context, start);
+        assert_eq!(bar.left().collect::<Vec<_>>(), ["1/1 up"]);
+        assert_eq!(bar.right().count(), 0, "empty clock, command running");
+
+        context.clock_len = 3;
+        let (mut now, deadline) = (start, Instant::now() + Duration::from_secs(5));
+        while bar.right().count() < 2 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            now += COMMAND_INTERVAL;
+            bar.update(&context, now);
+        }
+        assert_eq!(bar.right().collect::<Vec<_>>(), ["clock 3", "main"]);
+    }
+}
@@ -0,0 +1,179 @@
+//! A tab per channel joined. The messages of the tab shown are in
+//! [`App::messages`](super::App::messages), those of the others wait here.
+
+use std::mem;
+
+use super::scrollback::Scrollback;
+use crate::server::messages::Channel;
+
+pub struct Tab {
+    pub channel: Channel,
+    /// Empty while the tab is shown
+    messages: Scrollback,
+    first_display_message_id: usize,
+    /// Chat messages received since the tab was last shown
+    pub unseen: usize,
+}
+
+impl Tab {
+    fn new(channel: Channel) -> Tab {
+        Tab {
+            channel,
+            messages: Scrollback::default(),
+            first_display_message_id: 0,
+            unseen: 0,
+        }
+    }
+}
+
+/// Always has a `#general` tab, first
+pub struct Tabs {
+    tabs: Vec<Tab>,
+    shown: usize,
+}
+
+impl Default for Tabs {
+    fn default() -> Tabs {
+        Tabs {
+            tabs: vec![Tab::new(Channel::default())],
+            shown: 0,
+        }
+    }
+}
+
+impl Tabs {
+    pub fn len(&self) -> usize {
+        self.tabs.len()
+    }
+
+    /// Position of the tab shown
+    pub fn shown(&self) -> usize {
+        self.shown
+    }
+
+    /// Channel of the tab shown, where typed messages go
+    pub fn channel(&self) -> &Channel {
+        &self.tabs[self.shown].channel
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Tab> {
+        self.tabs.iter()
+    }
+
+    pub fn position(&self, channel: &Channel) -> Option<usize> {
+        self.tabs.iter().position(|tab| tab.channel == *channel)
+    }
+
+    /// Adds a tab for `channel` if it has none, returns its position
+    pub fn open(&mut self, channel: Channel) -> usize {
+        self.position(&channel).unwrap_or_else(|| {
+            self.tabs.push(Tab::new(channel));
+            self.tabs.len() - 1
+        })
+    }
+
+    /// Shows tab `index`: `messages` and `first` are those of the tab shown,
+    /// they are put aside and replaced by those of tab `index`
+    pub fn show(&mut self, index: usize, messages: &mut Scrollback, first: &mut usize) {
+        if index >= self.tabs.len() || index == self.shown {
+            return;
+        }
+        for index in [self.shown, index] {
+            let tab = &mut self.tabs[index];
+            mem::swap(messages, &mut tab.messages);
+            mem::swap(first, &mut tab.first_display_message_id);
+        }
+        self.shown = index;
+        self.tabs[index].unseen = 0;
+    }
+
+    /// Removes the tab of `channel`, showing `#general` if it was shown.
+    /// Returns whether it had one, `#general` is never removed.
+    pub fn close(
+        &mut self,
+        channel: &Channel,
+        messages: &mut Scrollback,
+        first: &mut usize,
+    ) -> bool {
+        let index = match self.position(channel) {
+            Some(index) if index > 0 => index,
+            _ => return false,
+        };
+        if index == self.shown {
+            self.show(0, messages, first);
+        }
+        self.tabs.remove(index);
+        if self.shown > index {
+            self.shown -= 1;
+        }
+        true
+    }
+
+    /// Messages of the tab of `channel`, `shown` if it is the tab shown
+    pub fn messages<'a>(
+        &'a mut self,
+        channel: &Channel,
+        shown: &'a mut Scrollback,
+    ) -> Option<&'a mut Scrollback> {
+        match self.position(channel)? {
+            index if index == self.shown => Some(shown),
+            index => Some(&mut self.tabs[index].messages),
+        }
+    }
+
+    /// Messages of `channel` while its tab is not shown, counting one more unseen
+    pub fn hidden(&mut self, channel: &Channel) -> Option<&mut Scrollback> {
+        let tab = self.hidden_tab(channel)?;
+        tab.unseen += 1;
+        Some(&mut tab.messages)
+    }
+
+    /// Same as [`hidden`](Tabs::hidden), for a muted channel: nothing is unseen
+    pub fn muted(&mut self, channel: &Channel) -> Option<&mut Scrollback> {
+        self.hidden_tab(channel).map(|tab| &mut tab.messages)
+    }
+
+    fn hidden_tab(&mut self, channel: &Channel) -> Option<&mut Tab> {
+        let index = self
+            .position(channel)
+            .filter(|index| *index != self.shown)?;
+        Some(&mut self.tabs[index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Message::System;
+    use super::*;
+
+    #[test]
+    fn hidden_tabs_keep_their_messages() {
+        let rust = Channel::parse("#rust").unwrap();
+        let (mut messages, mut first) = (Scrollback::default(), 0);
+        let mut tabs = Tabs::default();
+        messages.push(System("in general".to_owned()));
+        assert_eq!(tabs.open(rust.clone()), 1);
+        assert_eq!(tabs.open(rust.clone()), 1);
+        assert!(tabs.hidden(&Channel::default()).is_none(), "shown");
+        tabs.hidden(&rust)
+            .unwrap()
+            .push(System("in rust".to_owned()));
+        assert_eq!(
+            tabs.iter().map(|tab| tab.unseen).collect::<Vec<_>>(),
+            [0, 1]
+        );
+
+        tabs.show(1, &mut messages, &mut first);
+        assert_eq!(tabs.channel(), &rust);
+        assert_eq!(messages.window(0, 5)[0], "in rust");
+        assert_eq!(
+            tabs.iter().map(|tab| tab.unseen).collect::<Vec<_>>(),
+            [0, 0]
+        );
+
+        assert!(!tabs.close(&Channel::default(), &mut messages, &mut first));
+        assert!(tabs.close(&rust, &mut messages, &mut first));
+        assert_eq!((tabs.len(), tabs.shown()), (1, 0));
+        assert_eq!(messages.window(0, 5)[0], "in general");
+    }
+}
@@ -0,0 +1,62 @@
+//! `--theme`: the colors of the interface, by what they tell
+
+use tui::style::Color;
+
+/// Names accepted by [`Theme::by_name`]
+pub const THEMES: &[&str] = &["dark", "light", "mono"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// Input line, jobs, status segments and live lines
+    pub accent: Color,
+    /// Warnings, mentions and transports retrying
+    pub warning: Color,
+    /// Errors, the unread rule and transports down
+    pub error: Color,
+    /// Who is typing, peers offline and transports left
+    pub muted: Color,
+    /// Transports up
+    pub ok: Color,
+    /// Transports cut by `/partition`
+    pub cut: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            accent: Color::Cyan,
+            warning: Color::Yellow,
+            error: Color::Red,
+            muted: Color::DarkGray,
+            ok: Color::Green,
+            cut: Color::Magenta,
+        }
+    }
+}
+
+impl Theme {
+    /// The theme called `name`, one of [`THEMES`]
+    pub fn by_name(name: &str) -> Option<Theme> {
+        match name {
+            "dark" => Some(Theme::default()),
+            // Yellow and cyan do not read on a white background
+            "light" => Some(Theme {
+                accent: Color::Blue,
+                warning: Color::Magenta,
+                error: Color::Red,
+                muted: Color::Gray,
+                ok: Color::Green,
+                cut: Color::Magenta,
+            }),
+            "mono" => Some(Theme {
+                accent: Color::Reset,
+                warning: Color::Reset,
+                error: Color::Reset,
+                muted: Color::Reset,
+                ok: Color::Reset,
+                cut: Color::Reset,
+            }),
+            _ => None,
+        }
+    }
+}
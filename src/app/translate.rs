@@ -0,0 +1,174 @@
+//! `--translate-command`: a shell command given the text of a message on its
+//! standard input, whose output is shown under the message as its translation.
+//! It can be a local tool or call an API, e.g. `trans -b :en`.
+//!
+//! `/translate [<app>]` translates the last message received, from `<app>` if
+//! given, and the messages of the apps `--translate-from` or
+//! `/translate auto <app>` selects are translated as they come.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::thread;
+
+use super::channel::Sender;
+use super::events::Event;
+use super::AppId;
+use crate::server::messages::Channel;
+
+/// Most bytes of the output read
+const MAX_OUTPUT_LEN: u64 = 16 * 1024;
+
+/// Where a message was shown
+#[derive(Clone, Debug, PartialEq)]
+pub struct Target {
+    pub sender: AppId,
+    /// Channel of the tab it is in
+    pub tab: Channel,
+    /// Position in the messages of the tab
+    pub position: usize,
+}
+
+pub struct Translator {
+    command: String,
+    tx: Sender,
+    /// Senders whose messages are translated as they come
+    pub auto: HashSet<AppId>,
+    /// Last message received from each sender, and where it is shown
+    last: HashMap<AppId, (Target, String)>,
+    latest: Option<AppId>,
+}
+
+impl Translator {
+    /// Runs `command` with `sh -c`, translations are sent back to the app on `tx`
+    pub fn new(command: String, tx: Sender) -> Translator {
+        Translator {
+            command,
+            tx,
+            auto: HashSet::new(),
+            last: HashMap::new(),
+            latest: None,
+        }
+    }
+
+    /// A chat message with `text` was shown at `target`, it is translated
+    /// if its sender is selected
+    pub fn received(&mut self, target: Target, text: &str) {
+        let sender = target.sender.clone();
+        if self.auto.contains(&sender) {
+            self.translate(target.clone(), text.to_owned());
+        }
+        self.last.insert(sender.clone(), (target, text.to_owned()));
+        self.latest = Some(sender);
+    }
+
+    /// Translates the last message received, from `sender` if given. Returns
+    /// false if there is none.
+    pub fn translate_last(&self, sender: Option<&str>) -> bool {
+        let sender = match sender.or(self.latest.as_deref()) {
+            Some(sender) => sender,
+            None => return false,
+        };
+        match self.last.get(sender) {
+            Some((target, text)) => {
+                self.translate(target.clone(), text.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Selects `sender` for automatic translation, or unselects it if it
+    /// was. Returns whether it is selected.
+    pub fn toggle_auto(&mut self, sender: &str) -> bool {
+        !self.auto.remove(sender) && self.auto.insert(sender.to_owned())
+    }
+
+    /// Runs the command on its own thread, the app gets an
+    /// [`Event::Translated`] for `target` once it is done
+    fn translate(&self, target: Target, text: String) {
+        let (command, tx) = (self.command.clone(), self.tx.clone());
+        thread::spawn(move || {
+            let translation = run(&command, &text).map_err(|e| e.to_string());
+            if tx.send(Event::Translated(target, translation)).is_err() {
+                log::warn!("The translation came after the app stopped");
+            }
+        });
+    }
+}
+
+/// What `command` prints for `text`, without control characters but the line ends
+fn run(command: &str, text: &str) -> std::io::Result<String> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(format!("{}\n", text).as_bytes())?;
+    }
+    let mut printed = String::new();
+    if let Some(stdout) = child.stdout.take() {
+        stdout.take(MAX_OUTPUT_LEN).read_to_string(&mut printed)?;
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "the command failed: {}",
+            status
+        )));
+    }
+    let printed: String = printed
+        .trim()
+        .chars()
+        .filter(|c| *c == '\n' || !c.is_control())
+        .collect();
+    if printed.is_empty() {
+        return Err(std::io::Error::other("the command printed nothing"));
+    }
+    Ok(printed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::channel::{channel, Receiver};
+    use std::time::{Duration, Instant};
+
+    fn next(rx: &Receiver) -> Option<(Target, Result<String, String>)> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            match rx.try_recv() {
+                Some(Event::Translated(target, text)) => return Some((target, text)),
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn selected_senders_are_translated() {
+        let (tx, rx) = channel(16);
+        let mut translator = Translator::new("tr a-z A-Z".to_owned(), tx);
+        let target = |sender: &str, position| Target {
+            sender: sender.to_owned(),
+            tab: Channel::default(),
+            position,
+        };
+        assert!(!translator.translate_last(None), "nothing received yet");
+        assert!(translator.toggle_auto("bob"));
+        translator.received(target("bob", 3), "hola");
+        translator.received(target("carol", 4), "salut");
+        assert!(!translator.translate_last(Some("dave")));
+
+        assert_eq!(next(&rx), Some((target("bob", 3), Ok("HOLA".to_owned()))));
+
+        assert!(translator.translate_last(None));
+        assert_eq!(
+            next(&rx),
+            Some((target("carol", 4), Ok("SALUT".to_owned())))
+        );
+    }
+}
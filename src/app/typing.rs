@@ -0,0 +1,73 @@
+//! Who is composing a message, told to the others while typing and shown in
+//! the title bar for a few seconds
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use super::AppId;
+
+/// Typing is told again at most this often
+const RESEND_INTERVAL: Duration = Duration::from_secs(3);
+
+/// An app stops being shown as typing this long after it last said so
+const EXPIRY: Duration = Duration::from_secs(6);
+
+#[derive(Default)]
+pub struct Typing {
+    told: Option<Instant>,
+    others: BTreeMap<AppId, Instant>,
+}
+
+impl Typing {
+    /// The user typed a key, returns whether to tell the others
+    pub fn typed(&mut self, now: Instant) -> bool {
+        if self.told.is_some_and(|told| now < told + RESEND_INTERVAL) {
+            return false;
+        }
+        self.told = Some(now);
+        true
+    }
+
+    /// `app_id` is typing
+    pub fn started(&mut self, app_id: AppId, now: Instant) {
+        self.others.insert(app_id, now);
+    }
+
+    /// `app_id` sent what it was typing
+    pub fn stopped(&mut self, app_id: &str) {
+        self.others.remove(app_id);
+    }
+
+    /// Forgets the apps which did not say they were typing for a while
+    pub fn expire(&mut self, now: Instant) {
+        self.others.retain(|_, at| now < *at + EXPIRY);
+    }
+
+    /// Apps typing, in id order
+    pub fn apps(&self) -> impl Iterator<Item = &AppId> {
+        self.others.keys()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_is_told_sparingly_and_expires() {
+        let start = Instant::now();
+        let mut typing = Typing::default();
+        assert!(typing.typed(start));
+        assert!(!typing.typed(start + Duration::from_secs(1)));
+        assert!(typing.typed(start + RESEND_INTERVAL));
+
+        typing.started("bob".to_owned(), start);
+        typing.started("carol".to_owned(), start + Duration::from_secs(2));
+        typing.started("dave".to_owned(), start);
+        typing.stopped("dave");
+        typing.expire(start + Duration::from_secs(1));
+        assert_eq!(typing.apps().count(), 2);
+        typing.expire(start + EXPIRY);
+        assert_eq!(typing.apps().collect::<Vec<_>>(), ["carol"]);
+    }
+}
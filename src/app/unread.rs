@@ -0,0 +1,111 @@
+use std::fs;
+use std::path::PathBuf;
+
+use crate::server::messages::Msg;
+use crate::server::Clock;
+
+/// Where reading stopped: the last date read from each sender, kept across
+/// runs so messages sent again by a linked device are not unread twice
+pub struct ReadMarker {
+    path: Option<PathBuf>,
+    read: Clock,
+    shown: Clock, // Last date shown from each sender, read or not
+    /// Position of the first unread message in the scrollback
+    pub first_unread: Option<usize>,
+}
+
+impl Default for ReadMarker {
+    fn default() -> Self {
+        ReadMarker {
+            path: None,
+            read: Clock(Default::default()),
+            shown: Clock(Default::default()),
+            first_unread: None,
+        }
+    }
+}
+
+impl ReadMarker {
+    /// Reads the marker saved in `path`, it is saved there when moved
+    pub fn load(path: PathBuf) -> Self {
+        let read = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_else(|| Clock(Default::default()));
+        ReadMarker {
+            path: Some(path),
+            read,
+            ..ReadMarker::default()
+        }
+    }
+
+    /// `msg` is shown at `position` in the scrollback
+    pub fn shown(&mut self, msg: &Msg, position: usize) {
+        let date = match msg.clock.get(&msg.sender_id) {
+            Some(date) => *date,
+            None => return,
+        };
+        if self
+            .read
+            .get(&msg.sender_id)
+            .is_none_or(|read| *read < date)
+        {
+            self.first_unread.get_or_insert(position);
+        }
+        let shown = self.shown.entry(msg.sender_id.clone()).or_insert(0);
+        *shown = (*shown).max(date);
+    }
+
+    /// Everything shown so far is read
+    pub fn mark_read(&mut self) {
+        if self.first_unread.take().is_none() {
+            return;
+        }
+        self.read.merge(&self.shown);
+        if let Some(path) = &self.path {
+            let saved = serde_json::to_string(&self.read)
+                .map_err(|e| e.to_string())
+                .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                log::error!("Could not save the read marker: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::{Channel, Header};
+
+    fn from_bob(date: u64) -> Msg {
+        let mut clock = Clock::new("bob".to_owned());
+        clock.insert("bob".to_owned(), date);
+        Msg::new(
+            date,
+            "bob".to_owned(),
+            Header::Public(Channel::default(), String::new()),
+            clock,
+        )
+    }
+
+    #[test]
+    fn read_messages_stay_read_across_runs() {
+        let path = std::env::temp_dir().join(format!("netchat-test-{}.read", std::process::id()));
+        let mut marker = ReadMarker::load(path.clone());
+        marker.shown(&from_bob(1), 10);
+        marker.shown(&from_bob(2), 11);
+        assert_eq!(marker.first_unread, Some(10));
+        marker.mark_read();
+        assert_eq!(marker.first_unread, None);
+
+        // bob 1 and 2 come back in the history of a linked device
+        let mut marker = ReadMarker::load(path.clone());
+        marker.shown(&from_bob(1), 0);
+        marker.shown(&from_bob(2), 1);
+        assert_eq!(marker.first_unread, None);
+        marker.shown(&from_bob(3), 2);
+        assert_eq!(marker.first_unread, Some(2));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Watch expressions: chat messages matching one of the regexes given with
+//! `/watch` go to the watch panel, from every tab, with where they came from
+
+use std::collections::VecDeque;
+
+use regex::Regex;
+
+/// Matches kept at most, the oldest are forgotten first
+const MAX_HITS: usize = 100;
+
+pub struct Hit {
+    /// Channel and sender, or how it was sent
+    pub source: String,
+    pub text: String,
+}
+
+pub struct Watches {
+    patterns: Vec<Regex>,
+    hits: VecDeque<Hit>,
+    /// Whether the panel is shown, once there is something in it
+    pub shown: bool,
+}
+
+impl Default for Watches {
+    fn default() -> Watches {
+        Watches {
+            patterns: Vec::new(),
+            hits: VecDeque::new(),
+            shown: true,
+        }
+    }
+}
+
+impl Watches {
+    /// Watches for `pattern` from now on
+    pub fn add(&mut self, pattern: &str) -> Result<(), regex::Error> {
+        self.patterns.push(Regex::new(pattern)?);
+        self.shown = true;
+        Ok(())
+    }
+
+    /// Stops watching, and forgets the matches
+    pub fn clear(&mut self) {
+        self.patterns.clear();
+        self.hits.clear();
+    }
+
+    pub fn patterns(&self) -> impl Iterator<Item = &str> {
+        self.patterns.iter().map(Regex::as_str)
+    }
+
+    /// Keeps `text` from `source` if it matches, returns whether it did
+    pub fn check(&mut self, source: String, text: &str) -> bool {
+        if !self.patterns.iter().any(|pattern| pattern.is_match(text)) {
+            return false;
+        }
+        if self.hits.len() == MAX_HITS {
+            self.hits.pop_front();
+        }
+        let text = text.to_owned();
+        self.hits.push_back(Hit { source, text });
+        true
+    }
+
+    /// Newest first
+    pub fn hits(&self) -> impl Iterator<Item = &Hit> {
+        self.hits.iter().rev()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_are_kept_with_their_source() {
+        let mut watches = Watches::default();
+        assert!(watches.add("deploy (failed|done)").is_ok());
+        assert!(watches.add("(unclosed").is_err());
+        assert!(!watches.check("#ops bot".to_owned(), "deploy started"));
+        assert!(watches.check("#ops bot".to_owned(), "deploy failed: disk full"));
+        assert!(watches.check("#general carol".to_owned(), "is the deploy done?"));
+        let sources: Vec<&str> = watches.hits().map(|hit| hit.source.as_str()).collect();
+        assert_eq!(sources, ["#general carol", "#ops bot"]);
+        assert_eq!(
+            watches.patterns().collect::<Vec<_>>(),
+            ["deploy (failed|done)"]
+        );
+
+        for _ in 0..MAX_HITS {
+            watches.check("#ops bot".to_owned(), "deploy done");
+        }
+        assert_eq!(watches.hits().count(), MAX_HITS);
+        watches.clear();
+        assert!(watches.is_empty());
+        assert!(!watches.check("#ops bot".to_owned(), "deploy done"));
+    }
+}
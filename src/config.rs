@@ -0,0 +1,553 @@
+//! `config.toml` in `$XDG_CONFIG_HOME/netchat`, or the file `--config` names:
+//! options read before those of the command line, which win over them
+//!
+//! Keys are the long options, `input = "lab-in"` stands for `--input lab-in`,
+//! `bell = true` for `--bell` and a list for the option repeated. Only this
+//! subset of TOML is read: comments, strings, integers, booleans and arrays,
+//! and for the other files read with [`document`], such as the scenarios of
+//! `netchat simulate`, tables, arrays of tables and inline tables.
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::dirs;
+
+/// Keys standing for another option
+const ALIASES: &[(&str, &str)] = &[("app-id", "name"), ("id", "name"), ("nickname", "nick")];
+
+/// Short forms of the options, which the command line may give them as
+const SHORTS: &[(&str, &str)] = &[
+    ("input", "-i"),
+    ("output", "-o"),
+    ("name", "-n"),
+    ("logfile", "-l"),
+    ("keyfile", "-k"),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Text(String),
+    Integer(i64),
+    Bool(bool),
+    List(Vec<Value>),
+    /// Keys in order, only in a [`document`]
+    Table(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Text(text) => text.clone().into(),
+            Value::Integer(n) => (*n).into(),
+            Value::Bool(b) => (*b).into(),
+            Value::List(values) => values.iter().map(Value::to_json).collect(),
+            Value::Table(entries) => table_to_json(entries),
+        }
+    }
+}
+
+fn table_to_json(entries: &[(String, Value)]) -> serde_json::Value {
+    let entries = entries
+        .iter()
+        .map(|(key, value)| (key.clone(), value.to_json()));
+    serde_json::Value::Object(entries.collect())
+}
+
+/// Options of the file, in order
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    entries: Vec<(String, Value)>,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// The command line `args`, program name first, with the options of the
+/// configuration file it uses inserted before its own
+pub fn merge(args: Vec<OsString>) -> Result<Vec<OsString>, Box<dyn Error>> {
+    let given = explicit_path(&args);
+    let path = given.clone().unwrap_or_else(dirs::config_file);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        // Only a file asked for has to be there
+        Err(e) if e.kind() == io::ErrorKind::NotFound && given.is_none() => return Ok(args),
+        Err(e) => return Err(format!("Could not read {:?}: {}", path, e).into()),
+    };
+    let config = Config::parse(&text).map_err(|e| format!("{:?}, {}", path, e))?;
+    let mut merged = args[..1.min(args.len())].to_vec();
+    merged.extend(config.args(&args));
+    merged.extend(args.into_iter().skip(1));
+    Ok(merged)
+}
+
+/// The path given with `--config`
+fn explicit_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next().map(|path| PathBuf::from(path.as_ref()));
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// `text`, a TOML document of the subset read, tables included, as json to
+/// deserialize
+pub fn document(text: &str) -> Result<serde_json::Value, ParseError> {
+    Ok(table_to_json(&Parser::new(text, true).entries()?))
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, ParseError> {
+        let entries = Parser::new(text, false).entries()?;
+        Ok(Config { entries })
+    }
+
+    /// The options of the file not already in `given`, as arguments
+    pub fn args(&self, given: &[OsString]) -> Vec<OsString> {
+        let given: Vec<_> = given.iter().map(|arg| arg.to_string_lossy()).collect();
+        let mut args = Vec::new();
+        for (key, value) in &self.entries {
+            let key = key.replace('_', "-");
+            let name = ALIASES
+                .iter()
+                .find(|(alias, _)| *alias == key)
+                .map_or(key.as_str(), |(_, name)| name);
+            let long = format!("--{}", name);
+            let short = SHORTS.iter().find(|(n, _)| *n == name).map(|(_, s)| *s);
+            if sets(&given, &long, short) {
+                continue;
+            }
+            let values = match value {
+                Value::List(values) => values.as_slice(),
+                value => std::slice::from_ref(value),
+            };
+            for value in values {
+                match value {
+                    Value::Bool(true) => args.push(long.clone().into()),
+                    Value::Bool(false) => {}
+                    Value::Text(text) => args.extend([long.clone().into(), text.into()]),
+                    Value::Integer(n) => args.extend([long.clone().into(), n.to_string().into()]),
+                    Value::List(_) | Value::Table(_) => {}
+                }
+            }
+        }
+        args
+    }
+}
+
+/// Whether the command line `given`, program name first, sets the option
+/// `long` or `short` as clap reads them: `--input in`, `--input=in`, `-i in` or
+/// `-iin`. Clap takes no value starting with a dash from the next argument, so
+/// an argument starting with a short option is that option.
+fn sets(given: &[Cow<str>], long: &str, short: Option<&str>) -> bool {
+    for arg in given.iter().skip(1) {
+        // Only values after it
+        if arg == "--" {
+            return false;
+        }
+        let long_given = arg
+            .strip_prefix(long)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('='));
+        // Every short option takes a value, what follows it is one
+        let short_given = short.is_some_and(|short| arg.starts_with(short));
+        if long_given || short_given {
+            return true;
+        }
+    }
+    false
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+    /// Whether tables are read, options only go at the top
+    tables: bool,
+}
+
+impl Parser {
+    fn new(text: &str, tables: bool) -> Parser {
+        Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+            line: 1,
+            tables,
+        }
+    }
+
+    /// The keys of the document, the tables under theirs
+    fn entries(&mut self) -> Result<Vec<(String, Value)>, ParseError> {
+        let mut entries: Vec<(String, Value)> = Vec::new();
+        // Where the next keys go, after a `[table]` or `[[table]]` header
+        let mut table: Option<String> = None;
+        loop {
+            self.skip_blank(true);
+            if self.peek().is_none() {
+                return Ok(entries);
+            }
+            if self.tables && self.peek() == Some('[') {
+                table = Some(self.header(&mut entries)?);
+                continue;
+            }
+            let key = self.key()?;
+            self.skip_blank(false);
+            self.expect('=')?;
+            self.skip_blank(false);
+            let value = self.value()?;
+            self.skip_blank(false);
+            match self.peek() {
+                None | Some('\n') => {}
+                Some(c) => return Err(self.error(format!("unexpected `{}` after the value", c))),
+            }
+            let keys = match &table {
+                None => &mut entries,
+                Some(name) => match entries.iter_mut().rev().find(|(k, _)| k == name) {
+                    Some((_, Value::Table(keys))) => keys,
+                    Some((_, Value::List(tables))) => match tables.last_mut() {
+                        Some(Value::Table(keys)) => keys,
+                        _ => unreachable!("an array of tables"),
+                    },
+                    _ => unreachable!("a table header"),
+                },
+            };
+            if keys.iter().any(|(k, _)| *k == key) {
+                return Err(self.error(format!("`{}` is set twice", key)));
+            }
+            keys.push((key, value));
+        }
+    }
+
+    /// Reads `[name]`, a table, or `[[name]]`, the next table of an array,
+    /// adds it to `entries` and returns its name
+    fn header(&mut self, entries: &mut Vec<(String, Value)>) -> Result<String, ParseError> {
+        self.expect('[')?;
+        let array = self.peek() == Some('[');
+        if array {
+            self.bump();
+        }
+        let name = self.key()?;
+        self.expect(']')?;
+        if array {
+            self.expect(']')?;
+        }
+        self.skip_blank(false);
+        if !matches!(self.peek(), None | Some('\n')) {
+            return Err(self.error(format!("unexpected text after the header of {}", name)));
+        }
+        match entries.iter_mut().find(|(k, _)| *k == name) {
+            Some((_, Value::List(tables))) if array && !tables.is_empty() => {
+                if !tables.iter().all(|t| matches!(t, Value::Table(_))) {
+                    return Err(self.error(format!("`{}` is not an array of tables", name)));
+                }
+                tables.push(Value::Table(Vec::new()));
+            }
+            Some(_) => return Err(self.error(format!("`{}` is set twice", name))),
+            None if array => {
+                let tables = vec![Value::Table(Vec::new())];
+                entries.push((name.clone(), Value::List(tables)));
+            }
+            None => entries.push((name.clone(), Value::Table(Vec::new()))),
+        }
+        Ok(name)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: String) -> ParseError {
+        ParseError {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ParseError> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("expected `{}`, found `{}`", expected, c))),
+            None => Err(self.error(format!("expected `{}`", expected))),
+        }
+    }
+
+    /// Skips spaces and comments, and line ends too if `newlines`
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                '\n' if newlines => {}
+                '#' => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                    continue;
+                }
+                _ => return,
+            }
+            self.bump();
+        }
+    }
+
+    fn key(&mut self) -> Result<String, ParseError> {
+        let mut key = String::new();
+        while let Some(c) = self
+            .peek()
+            .filter(|c| c.is_ascii_alphanumeric() || "-_".contains(*c))
+        {
+            key.push(c);
+            self.bump();
+        }
+        match self.peek() {
+            _ if !key.is_empty() => Ok(key),
+            Some('[') => Err(self.error("tables are not supported, keys go at the top".to_owned())),
+            Some(c) => Err(self.error(format!("expected a key, found `{}`", c))),
+            None => Err(self.error("expected a key".to_owned())),
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::Text),
+            Some('\'') => {
+                self.bump();
+                let mut text = String::new();
+                loop {
+                    match self.bump() {
+                        Some('\'') => return Ok(Value::Text(text)),
+                        Some('\n') | None => {
+                            return Err(self.error("unterminated string".to_owned()))
+                        }
+                        Some(c) => text.push(c),
+                    }
+                }
+            }
+            Some('[') => {
+                self.bump();
+                let mut values = Vec::new();
+                loop {
+                    self.skip_blank(true);
+                    if self.peek() == Some(']') {
+                        self.bump();
+                        return Ok(Value::List(values));
+                    }
+                    values.push(self.value()?);
+                    self.skip_blank(true);
+                    match self.peek() {
+                        Some(',') => {
+                            self.bump();
+                        }
+                        Some(']') => {}
+                        _ => return Err(self.error("expected `,` or `]` in the array".to_owned())),
+                    }
+                }
+            }
+            Some('{') if self.tables => {
+                self.bump();
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_blank(false);
+                    if entries.is_empty() && self.peek() == Some('}') {
+                        self.bump();
+                        return Ok(Value::Table(entries));
+                    }
+                    let key = self.key()?;
+                    self.skip_blank(false);
+                    self.expect('=')?;
+                    self.skip_blank(false);
+                    entries.push((key, self.value()?));
+                    self.skip_blank(false);
+                    match self.bump() {
+                        Some(',') => {}
+                        Some('}') => return Ok(Value::Table(entries)),
+                        _ => return Err(self.error("expected `,` or `}` in the table".to_owned())),
+                    }
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) = self
+                    .peek()
+                    .filter(|c| c.is_ascii_alphanumeric() || "+-_".contains(*c))
+                {
+                    word.push(c);
+                    self.bump();
+                }
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ => word
+                        .replace('_', "")
+                        .parse()
+                        .map(Value::Integer)
+                        .map_err(|_| self.error(format!("expected a value, found `{}`", word))),
+                }
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, ParseError> {
+        self.expect('"')?;
+        let mut text = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(text),
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let hex: String = (0..4).filter_map(|_| self.bump()).collect();
+                            u32::from_str_radix(&hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error(format!("invalid escape \\u{}", hex)))?
+                        }
+                        c => return Err(self.error(format!("invalid escape {:?}", c))),
+                    };
+                    text.push(escaped);
+                }
+                Some('\n') | None => return Err(self.error("unterminated string".to_owned())),
+                Some(c) => text.push(c),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_command_line_wins() {
+        let config = Config::parse(
+            r#"
+            # Lab setup
+            app_id = "alice"
+            input = '/tmp/lab in'
+            output = "/tmp/lab-out"  # next to it
+            connect = [
+                "lab:7878",
+                "home:7878",
+            ]
+            heartbeat = 1_0
+            bell = true
+            lurk = false
+            "#,
+        )
+        .unwrap();
+        let given: Vec<OsString> = ["netchat", "-o", "out", "--heartbeat=5"]
+            .iter()
+            .map(OsString::from)
+            .collect();
+        let args: Vec<_> = config.args(&given);
+        assert_eq!(
+            args,
+            [
+                "--name",
+                "alice",
+                "--input",
+                "/tmp/lab in",
+                "--connect",
+                "lab:7878",
+                "--connect",
+                "home:7878",
+                "--bell"
+            ]
+        );
+
+        let error = Config::parse("a = 1\n[transports]\n").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(Config::parse("a = \"open").is_err());
+        assert!(Config::parse("a = 1 2").is_err());
+    }
+
+    #[test]
+    fn documents_have_tables() {
+        let document = document(
+            r#"
+            nodes = ["alice", "bob"]
+            links = [["alice", "bob"]]
+
+            [[messages]]
+            at = 0
+            from = "alice"
+
+            [[messages]]
+            at = 2
+            from = "bob"
+
+            [misbehave]
+            mallory = ["duplicate"]
+            faults = [{ at = 1, cut = ["alice", "bob"] }, {}]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            document,
+            serde_json::json!({
+                "nodes": ["alice", "bob"],
+                "links": [["alice", "bob"]],
+                "messages": [{"at": 0, "from": "alice"}, {"at": 2, "from": "bob"}],
+                "misbehave": {
+                    "mallory": ["duplicate"],
+                    "faults": [{"at": 1, "cut": ["alice", "bob"]}, {}],
+                },
+            })
+        );
+
+        let error = |text| super::document(text).unwrap_err().line;
+        assert_eq!(error("[a]\nb = 1\n[a]\n"), 3, "twice");
+        assert_eq!(error("a = 1\n[[a]]\n"), 2);
+        assert_eq!(error("[a]\nb = 1\nb = 2\n"), 3);
+        assert!(super::document("a = { b = 1").is_err());
+        assert!(Config::parse("a = { b = 1 }").is_err(), "not an option");
+    }
+
+    #[test]
+    fn options_are_found_as_clap_reads_them() {
+        let sets_input = |given: &[&str]| {
+            let given: Vec<_> = given.iter().map(|arg| Cow::from(*arg)).collect();
+            sets(&given, "--input", Some("-i"))
+        };
+        assert!(sets_input(&["netchat", "-i", "in"]));
+        assert!(sets_input(&["netchat", "-iin"]));
+        assert!(sets_input(&["netchat", "--input=in"]));
+        assert!(sets_input(&["netchat", "--bell", "--input", "in"]));
+        assert!(!sets_input(&["netchat", "--input-dir", "x"]));
+        assert!(!sets_input(&["netchat", "-o-in"]), "the output is -in");
+        assert!(!sets_input(&["netchat", "--nick", "ivan"]));
+        assert!(!sets_input(&["netchat", "--", "-i"]));
+        assert!(!sets_input(&["-i"]), "the program name");
+    }
+}
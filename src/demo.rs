@@ -0,0 +1,299 @@
+//! `--demo`: the app chats with two simulated apps running in the same
+//! process, which walk the user through netchat
+
+use std::cmp::Ordering;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use netchat_core::messages::Channel;
+use netchat_core::messages::Header::{self, Private, Public};
+use netchat_core::node::Node;
+use netchat_core::{AppId, Clock, Msg};
+
+/// Time left to read a line of the tutorial before the next one
+const PAUSE: Duration = Duration::from_millis(2500);
+
+/// Pipes between the app and the simulated ones, removed once the demo is over
+pub struct Demo {
+    pub dir: PathBuf,
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+impl Demo {
+    /// Creates the pipes and starts the simulated apps, `user` is the id of the app
+    pub fn start(user: AppId) -> io::Result<Demo> {
+        let dir = std::env::temp_dir().join(format!("netchat-demo-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let demo = Demo {
+            input: dir.join("to-you"),
+            output: dir.join("from-you"),
+            dir,
+        };
+        mkfifo(&demo.input)?;
+        mkfifo(&demo.output)?;
+        let (input, output) = (demo.input.clone(), demo.output.clone());
+        thread::spawn(move || {
+            if let Err(e) = run(user, &input, &output) {
+                log::error!("The demo stopped: {}", e);
+            }
+        });
+        Ok(demo)
+    }
+}
+
+impl Drop for Demo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn mkfifo(path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mkfifo(path.as_ptr(), 0o600) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy)]
+enum Peer {
+    Carol,
+    Dave,
+}
+
+/// A ring: the app writes to carol, carol to dave and dave to the app
+struct Mesh {
+    carol: Node,
+    dave: Node,
+    to_user: File,
+    partitioned: bool,   // The link from carol to dave is cut
+    held: Vec<Vec<u8>>,  // What carol wrote meanwhile
+    from_user: Vec<Msg>, // Messages of the app dave received
+}
+
+impl Mesh {
+    fn node(&mut self, peer: Peer) -> &mut Node {
+        match peer {
+            Peer::Carol => &mut self.carol,
+            Peer::Dave => &mut self.dave,
+        }
+    }
+
+    /// Moves the frames around the ring until no one has anything to write
+    fn settle(&mut self) -> io::Result<()> {
+        let mut moved = true;
+        while moved {
+            moved = false;
+            while let Some(frame) = self.carol.poll_frame() {
+                moved = true;
+                if self.partitioned {
+                    self.held.push(frame);
+                } else {
+                    let _ = self.dave.feed(&frame);
+                }
+            }
+            while let Some(frame) = self.dave.poll_frame() {
+                moved = true;
+                self.to_user.write_all(&frame)?;
+            }
+        }
+        while self.carol.poll_event().is_some() {}
+        while let Some(msg) = self.dave.poll_event() {
+            self.from_user.push(msg);
+        }
+        Ok(())
+    }
+
+    fn say(&mut self, peer: Peer, header: Header) -> io::Result<Msg> {
+        let msg = self.node(peer).send(header);
+        self.settle()?;
+        thread::sleep(PAUSE);
+        Ok(msg)
+    }
+
+    fn heal(&mut self) -> io::Result<()> {
+        self.partitioned = false;
+        for frame in std::mem::take(&mut self.held) {
+            let _ = self.dave.feed(&frame);
+        }
+        self.settle()
+    }
+}
+
+/// Dates of a clock, sorted by app
+fn show(clock: &Clock) -> String {
+    let mut dates: Vec<String> = clock
+        .iter()
+        .map(|(id, d)| format!("{} {}", id, d))
+        .collect();
+    dates.sort();
+    dates.join(", ")
+}
+
+fn run(user: AppId, input: &Path, output: &Path) -> io::Result<()> {
+    let (tx, lines) = mpsc::channel();
+    let output = output.to_owned();
+    thread::spawn(move || -> io::Result<()> {
+        for line in BufReader::new(File::open(output)?).split(b'\n') {
+            if tx.send(line?).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    });
+
+    let mut mesh = Mesh {
+        carol: Node::new("carol".to_owned()),
+        dave: Node::new("dave".to_owned()),
+        to_user: OpenOptions::new().write(true).open(input)?,
+        partitioned: false,
+        held: Vec::new(),
+        from_user: Vec::new(),
+    };
+    mesh.settle()?;
+    let mut tutorial = Tutorial { mesh, lines, user };
+    if tutorial.run()? {
+        // The tutorial is over, keep relaying
+        while let Ok(line) = tutorial.lines.recv() {
+            tutorial.forward(&line)?;
+        }
+    }
+    Ok(())
+}
+
+struct Tutorial {
+    mesh: Mesh,
+    lines: mpsc::Receiver<Vec<u8>>,
+    user: AppId,
+}
+
+impl Tutorial {
+    fn forward(&mut self, line: &[u8]) -> io::Result<()> {
+        let _ = self.mesh.carol.feed(line);
+        self.mesh.settle()
+    }
+
+    /// Relays until the app sends a message matching `expected`,
+    /// false if the app left first
+    fn wait_for(&mut self, expected: impl Fn(&Header) -> bool) -> io::Result<bool> {
+        loop {
+            let user = &self.user;
+            let found = self
+                .mesh
+                .from_user
+                .drain(..)
+                .any(|msg| msg.sender_id == *user && expected(&msg.header));
+            if found {
+                return Ok(true);
+            }
+            match self.lines.recv() {
+                Ok(line) => self.forward(&line)?,
+                Err(_) => return Ok(false),
+            }
+        }
+    }
+
+    fn say(&mut self, peer: Peer, text: &str) -> io::Result<Msg> {
+        self.mesh
+            .say(peer, Public(Channel::default(), text.to_owned()))
+    }
+
+    /// Returns false if the app left before the end
+    fn run(&mut self) -> io::Result<bool> {
+        use Peer::*;
+
+        self.say(
+            Dave,
+            "Welcome! carol and I are simulated apps, we run inside this netchat.",
+        )?;
+        self.say(
+            Dave,
+            "Type a message and press Enter to send it to everyone.",
+        )?;
+        if !self.wait_for(|h| matches!(h, Public(..)))? {
+            return Ok(false);
+        }
+
+        self.say(
+            Carol,
+            "Got it! Your app wrote it to me, I relayed it to dave, dave to you.",
+        )?;
+        self.say(
+            Dave,
+            "Every app relays each message once, so yours came back and was dropped.",
+        )?;
+        let clock = show(self.mesh.dave.clock());
+        self.say(
+            Dave,
+            &format!(
+                "Messages carry a vector clock, the date of every app. Mine: {}.",
+                clock
+            ),
+        )?;
+        self.say(
+            Dave,
+            "Press Ctrl+h to see yours, it grows with every message.",
+        )?;
+        self.say(
+            Dave,
+            "Now a private message: type dave, press Ctrl+r, then type it and press Ctrl+p.",
+        )?;
+        if !self.wait_for(|h| matches!(h, Private(to, _) if to == "dave"))? {
+            return Ok(false);
+        }
+
+        let thanks = Private(
+            self.user.clone(),
+            "Only I display it, carol relayed it without showing it.".to_owned(),
+        );
+        self.mesh.say(Dave, thanks)?;
+        self.say(
+            Dave,
+            "Now the link from carol to me is cut: what she says waits.",
+        )?;
+        self.mesh.partitioned = true;
+        let lost = self.say(Carol, "Has anyone seen my cat?")?;
+        let weather = self.say(Dave, "Meanwhile, I say something else: nice weather today.")?;
+        self.mesh.heal()?;
+        thread::sleep(PAUSE);
+        self.say(
+            Dave,
+            "The link is back, carol's question just arrived, after my message.",
+        )?;
+        if lost.clock.compare(&weather.clock).is_none() {
+            self.say(
+                Dave,
+                &format!(
+                    "Her clock ({}) and mine ({}) are concurrent,",
+                    show(&lost.clock),
+                    show(&weather.clock)
+                ),
+            )?;
+            self.say(Dave, "neither of us had seen the other's message.")?;
+        }
+        self.say(
+            Dave,
+            "Any order is right for concurrent messages, apps may show them differently.",
+        )?;
+        let found = self.say(Carol, "Found it, it was under the bed!")?;
+        let answer = self.say(Dave, "Glad to hear it, carol.")?;
+        if answer.clock.compare(&found.clock) == Some(Ordering::Greater) {
+            self.say(
+                Dave,
+                "My answer's clock is after hers: I saw her message, the answer comes after it.",
+            )?;
+        }
+        self.say(
+            Dave,
+            "That's all! Keep chatting, carol and I still relay. Ctrl+c to leave.",
+        )?;
+        Ok(true)
+    }
+}
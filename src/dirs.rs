@@ -0,0 +1,78 @@
+//! Where netchat keeps its files: under the XDG base directories, or all of
+//! them in `--data-dir`
+
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct Dirs {
+    /// Keys, contacts, history, scrollback, drafts and snapshots
+    pub data: PathBuf,
+    /// Logs
+    pub state: PathBuf,
+}
+
+impl Dirs {
+    /// `data_dir` if given, `$XDG_DATA_HOME/netchat` and `$XDG_STATE_HOME/netchat` otherwise
+    pub fn new(data_dir: Option<PathBuf>) -> Dirs {
+        match data_dir {
+            Some(dir) => Dirs {
+                state: dir.clone(),
+                data: dir,
+            },
+            None => Dirs::xdg(|name| env::var_os(name)),
+        }
+    }
+
+    fn xdg(var: impl Fn(&str) -> Option<OsString>) -> Dirs {
+        Dirs {
+            data: xdg_base(&var, "XDG_DATA_HOME", ".local/share"),
+            state: xdg_base(&var, "XDG_STATE_HOME", ".local/state"),
+        }
+    }
+
+    pub fn create(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.data)?;
+        fs::create_dir_all(&self.state)
+    }
+
+    pub fn logfile(&self) -> PathBuf {
+        self.state.join("netchat.log")
+    }
+}
+
+/// `$XDG_CONFIG_HOME/netchat/config.toml`
+pub fn config_file() -> PathBuf {
+    xdg_base(&|name| env::var_os(name), "XDG_CONFIG_HOME", ".config").join("config.toml")
+}
+
+/// `netchat` in the directory of the XDG variable `name`, or in `fallback` in
+/// the home directory
+fn xdg_base(var: &dyn Fn(&str) -> Option<OsString>, name: &str, fallback: &str) -> PathBuf {
+    // Relative paths are invalid in XDG variables and ignored
+    var(name)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(fallback)))
+        .unwrap_or_else(env::temp_dir)
+        .join("netchat")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xdg_variables_win_over_home_when_absolute() {
+        let dirs = Dirs::xdg(|name| match name {
+            "HOME" => Some("/home/alice".into()),
+            "XDG_DATA_HOME" => Some("/data".into()),
+            "XDG_STATE_HOME" => Some("state".into()),
+            _ => None,
+        });
+        assert_eq!(dirs.data, Path::new("/data/netchat"));
+        assert_eq!(dirs.state, Path::new("/home/alice/.local/state/netchat"));
+    }
+}
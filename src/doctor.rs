@@ -0,0 +1,413 @@
+//! `netchat doctor`: checks the directories, pipes, sockets and keys netchat
+//! would use with the same options, without chatting
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::dirs::Dirs;
+use crate::server::identity::Identity;
+use netchat_core::crypto::FrameKey;
+
+pub enum Finding {
+    Ok(String),
+    Warning(String),
+    Problem(String),
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Finding::Ok(text) => write!(f, "ok       {}", text),
+            Finding::Warning(text) => write!(f, "warning  {}", text),
+            Finding::Problem(text) => write!(f, "problem  {}", text),
+        }
+    }
+}
+
+/// What netchat was told to use
+pub struct Setup {
+    pub dirs: Dirs,
+    pub pipes: Option<(PathBuf, PathBuf)>,
+    /// Keys to check, every key of the data directory when empty
+    pub keyfiles: Vec<PathBuf>,
+    pub psk: Option<PathBuf>,
+    pub listen: Option<String>,
+    pub connect: Vec<String>,
+}
+
+/// How long a peer has to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether any user can write to `path`, and so chat in our name
+pub fn is_world_writable(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o002 != 0)
+}
+
+/// Whether users other than the owner can read `path`
+fn is_shared(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o077 != 0)
+}
+
+pub fn check(setup: &Setup) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    check_dir(&setup.dirs.data, "data directory", &mut findings);
+    if setup.dirs.state != setup.dirs.data {
+        check_dir(&setup.dirs.state, "log directory", &mut findings);
+    }
+
+    if let Some((input, output)) = &setup.pipes {
+        if input == output {
+            findings.push(Finding::Problem(format!(
+                "{:?} is both the input and the output, the app would only hear itself",
+                input
+            )));
+        }
+        check_pipe(input, &mut findings);
+        check_pipe(output, &mut findings);
+        if input.exists() && output.exists() {
+            check_pipe_ends(input, output, &mut findings);
+        }
+    }
+
+    if let Some(addr) = &setup.listen {
+        match TcpListener::bind(addr) {
+            Ok(_) => findings.push(Finding::Ok(format!("can listen on {}", addr))),
+            Err(e) => findings.push(Finding::Problem(format!(
+                "cannot listen on {}: {}, is another app using it?",
+                addr, e
+            ))),
+        }
+    }
+    for addr in &setup.connect {
+        check_peer(addr, &mut findings);
+    }
+
+    let keyfiles = if setup.keyfiles.is_empty() {
+        keys_in(&setup.dirs.data)
+    } else {
+        setup.keyfiles.clone()
+    };
+    for keyfile in &keyfiles {
+        check_key(keyfile, &mut findings);
+    }
+
+    match &setup.psk {
+        Some(path) => check_psk(path, &mut findings),
+        None if setup.listen.is_some() || !setup.connect.is_empty() => {
+            findings.push(Finding::Warning(
+                "TCP frames are not sealed, anyone reaching the socket can chat, see --psk"
+                    .to_owned(),
+            ))
+        }
+        None => {}
+    }
+    findings
+}
+
+fn check_dir(dir: &Path, what: &str, findings: &mut Vec<Finding>) {
+    if !dir.exists() {
+        findings.push(Finding::Ok(format!(
+            "{} {:?} will be created on start",
+            what, dir
+        )));
+        return;
+    }
+    if !dir.is_dir() {
+        findings.push(Finding::Problem(format!(
+            "{} {:?} is not a directory",
+            what, dir
+        )));
+        return;
+    }
+    let probe = dir.join(".netchat-doctor");
+    if let Err(e) = fs::write(&probe, "").and_then(|_| fs::remove_file(&probe)) {
+        findings.push(Finding::Problem(format!(
+            "{} {:?} is not writable: {}",
+            what, dir, e
+        )));
+    } else if is_shared(dir) {
+        findings.push(Finding::Warning(format!(
+            "{} {:?} can be read by other users, chmod go-rwx it",
+            what, dir
+        )));
+    } else {
+        findings.push(Finding::Ok(format!("{} {:?}", what, dir)));
+    }
+}
+
+fn check_pipe(pipe: &Path, findings: &mut Vec<Finding>) {
+    match fs::metadata(pipe) {
+        Err(e) => findings.push(Finding::Problem(format!("{:?}: {}", pipe, e))),
+        Ok(metadata) if !metadata.file_type().is_fifo() => findings.push(Finding::Warning(
+            format!("{:?} is not a named pipe, see mkfifo", pipe),
+        )),
+        Ok(_) if is_world_writable(pipe) => findings.push(Finding::Warning(format!(
+            "every user can write to {:?}, chmod o-w it",
+            pipe
+        ))),
+        Ok(_) => findings.push(Finding::Ok(format!("pipe {:?}", pipe))),
+    }
+}
+
+/// Whether the other app is at the ends of the pipes, and only it
+fn check_pipe_ends(input: &Path, output: &Path, findings: &mut Vec<Finding>) {
+    let (input_ends, output_ends) = match (pipe_ends(input), pipe_ends(output)) {
+        (Ok(input_ends), Ok(output_ends)) => (input_ends, output_ends),
+        (Err(e), _) | (_, Err(e)) => {
+            findings.push(Finding::Warning(format!(
+                "cannot tell who uses the pipes: {}",
+                e
+            )));
+            return;
+        }
+    };
+    let me = std::process::id();
+    let list = |ends: &[End], writes: bool| -> Vec<String> {
+        ends.iter()
+            .filter(|end| end.pid != me && (if writes { end.writes } else { end.reads }))
+            .map(|end| format!("{} ({})", end.name, end.pid))
+            .collect()
+    };
+    let (writers, readers) = (list(&input_ends, true), list(&output_ends, false));
+    let (stealers, intruders) = (list(&input_ends, false), list(&output_ends, true));
+
+    if writers.is_empty() {
+        findings.push(Finding::Warning(format!(
+            "nobody writes to {:?} yet, start the app at the other end with it as its output",
+            input
+        )));
+    } else {
+        findings.push(Finding::Ok(format!(
+            "{:?} is written by {}",
+            input,
+            writers.join(", ")
+        )));
+    }
+    if readers.is_empty() {
+        findings.push(Finding::Warning(format!(
+            "nobody reads {:?} yet, start the app at the other end with it as its input",
+            output
+        )));
+    } else {
+        findings.push(Finding::Ok(format!(
+            "{:?} is read by {}",
+            output,
+            readers.join(", ")
+        )));
+    }
+    // Pipes used the same way at both ends
+    let mut others = [stealers, intruders].concat();
+    others.sort();
+    others.dedup();
+    if !others.is_empty() {
+        findings.push(Finding::Problem(format!(
+            "{} already reads {:?} or writes {:?}: this app runs already, or -i and -o are swapped on one side",
+            others.join(", "),
+            input,
+            output
+        )));
+    }
+}
+
+/// A process with a pipe open
+struct End {
+    pid: u32,
+    name: String,
+    reads: bool,
+    writes: bool,
+}
+
+/// Processes of the user with `pipe` open, from /proc: an app still waiting
+/// for the other end in `open` is not among them
+fn pipe_ends(pipe: &Path) -> io::Result<Vec<End>> {
+    let metadata = fs::metadata(pipe)?;
+    let mut ends = Vec::new();
+    for process in fs::read_dir("/proc")?.flatten() {
+        let pid = match process
+            .file_name()
+            .to_str()
+            .and_then(|pid| pid.parse().ok())
+        {
+            Some(pid) => pid,
+            None => continue,
+        };
+        // Those of other users cannot be read
+        let fds = match fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(_) => continue,
+        };
+        for fd in fds.flatten() {
+            let same = fs::metadata(fd.path())
+                .is_ok_and(|m| m.dev() == metadata.dev() && m.ino() == metadata.ino());
+            if !same {
+                continue;
+            }
+            let info = process.path().join("fdinfo").join(fd.file_name());
+            let flags = fs::read_to_string(info).ok().and_then(|info| {
+                info.lines()
+                    .find_map(|line| line.strip_prefix("flags:"))
+                    .and_then(|flags| u32::from_str_radix(flags.trim(), 8).ok())
+            });
+            let mode = flags.unwrap_or(0) & 0o3;
+            let name = fs::read_to_string(process.path().join("comm")).unwrap_or_default();
+            ends.push(End {
+                pid,
+                name: name.trim_end().to_owned(),
+                reads: mode != 1,
+                writes: mode != 0,
+            });
+        }
+    }
+    Ok(ends)
+}
+
+/// Whether a peer listens on `addr`, and how long connecting to it takes
+fn check_peer(addr: &str, findings: &mut Vec<Finding>) {
+    let resolved = match addr.to_socket_addrs() {
+        Ok(mut addrs) => addrs.next(),
+        Err(e) => {
+            findings.push(Finding::Problem(format!("cannot resolve {}: {}", addr, e)));
+            return;
+        }
+    };
+    let resolved = match resolved {
+        Some(resolved) => resolved,
+        None => {
+            findings.push(Finding::Problem(format!("{} has no address", addr)));
+            return;
+        }
+    };
+    let start = Instant::now();
+    match TcpStream::connect_timeout(&resolved, CONNECT_TIMEOUT) {
+        // The peer sees us join and leave right away
+        Ok(_) => findings.push(Finding::Ok(format!(
+            "{} answers, {:.1} ms round trip",
+            addr,
+            start.elapsed().as_secs_f64() * 1000.0
+        ))),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => findings.push(Finding::Warning(
+            format!("nobody listens on {} yet, netchat will keep trying", addr),
+        )),
+        Err(e) => findings.push(Finding::Problem(format!(
+            "cannot reach {}: {}, check the address and the firewall",
+            addr, e
+        ))),
+    }
+}
+
+/// Identity keys kept in `dir`, `<id>.key`
+fn keys_in(dir: &Path) -> Vec<PathBuf> {
+    let mut keys: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "key"))
+        .collect();
+    keys.sort();
+    keys
+}
+
+fn check_key(keyfile: &Path, findings: &mut Vec<Finding>) {
+    if !keyfile.exists() {
+        findings.push(Finding::Ok(format!(
+            "key {:?} will be generated on start",
+            keyfile
+        )));
+        return;
+    }
+    let loaded = fs::read_to_string(keyfile)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str::<Identity>(&json).map_err(|e| e.to_string()));
+    if let Err(e) = loaded {
+        findings.push(Finding::Problem(format!(
+            "key {:?} does not load: {}",
+            keyfile, e
+        )));
+    } else if is_shared(keyfile) {
+        // Anyone who read it can chat in our name, it is not left so
+        let finding = match fs::set_permissions(keyfile, fs::Permissions::from_mode(0o600)) {
+            Ok(()) => Finding::Warning(format!(
+                "key {:?} could be read by other users, it is kept to you now",
+                keyfile
+            )),
+            Err(e) => Finding::Problem(format!(
+                "key {:?} can be read by other users and cannot be chmod: {}",
+                keyfile, e
+            )),
+        };
+        findings.push(finding);
+    } else if !keyfile.with_extension("revocation").exists() {
+        findings.push(Finding::Warning(format!(
+            "key {:?} has no revocation certificate next to it",
+            keyfile
+        )));
+    } else {
+        findings.push(Finding::Ok(format!("key {:?}", keyfile)));
+    }
+}
+
+fn check_psk(path: &Path, findings: &mut Vec<Finding>) {
+    match fs::read_to_string(path) {
+        Err(e) => findings.push(Finding::Problem(format!(
+            "pre-shared key {:?}: {}",
+            path, e
+        ))),
+        Ok(hex) if FrameKey::from_hex(&hex).is_none() => findings.push(Finding::Problem(format!(
+            "pre-shared key {:?} is not 64 hexadecimal digits",
+            path
+        ))),
+        Ok(_) if is_shared(path) => findings.push(Finding::Warning(format!(
+            "pre-shared key {:?} can be read by other users, chmod go-rwx it",
+            path
+        ))),
+        Ok(_) => findings.push(Finding::Ok(format!("pre-shared key {:?}", path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_setups_have_problems() {
+        let dir = std::env::temp_dir().join(format!("netchat-test-{}-doctor", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700)).unwrap();
+        let (pipe, psk, key) = (dir.join("pipe"), dir.join("psk"), dir.join("me.key"));
+        fs::write(&pipe, "").unwrap();
+        fs::write(&psk, "not hex").unwrap();
+        fs::write(&key, "{}").unwrap();
+
+        let setup = Setup {
+            dirs: Dirs::new(Some(dir.clone())),
+            pipes: Some((pipe.clone(), pipe)),
+            keyfiles: Vec::new(),
+            psk: Some(psk),
+            listen: None,
+            connect: Vec::new(),
+        };
+        let findings: Vec<_> = check(&setup).iter().map(|f| f.to_string()).collect();
+        let problems = findings.iter().filter(|f| f.starts_with("problem")).count();
+
+        // A key left shared is kept to the user
+        Identity::generate().save(&key, &"me".to_owned()).unwrap();
+        fs::set_permissions(&key, fs::Permissions::from_mode(0o644)).unwrap();
+        let mut shared = Vec::new();
+        check_key(&key, &mut shared);
+        let mode = fs::metadata(&key).unwrap().permissions().mode() & 0o777;
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(findings[0].starts_with("ok"), "{:?}", findings);
+        assert!(findings.iter().any(|f| f.contains("nobody reads")));
+        // Same pipe twice, undecodable key and bad pre-shared key
+        assert_eq!(problems, 3, "{:?}", findings);
+        assert!(shared[0].to_string().contains("kept to you now"));
+        assert_eq!(mode, 0o600);
+    }
+}
@@ -1,25 +1,64 @@
-use std::fs::OpenOptions;
+use std::fs::{self, OpenOptions};
+use std::net::{SocketAddr, SocketAddrV4, ToSocketAddrs};
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
 use gag::Redirect;
-use log;
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
 
 use structopt::StructOpt;
 
+mod config;
+
+mod demo;
+use demo::Demo;
+
+mod dirs;
+use dirs::Dirs;
+
+mod doctor;
+use doctor::is_world_writable;
+
+mod relay;
+
+mod replay;
+
+mod simulate;
+
 mod server;
+use server::backend::Endpoint;
+use server::discovery::{self, Advert};
+use server::framing::{Limits, DEFAULT_MAX_TEXT_LEN, MAX_FRAME_LEN};
+use server::history::History;
+use server::identity::{Contacts, Identity, RevocationCertificate};
+use server::messages::{Channel, Codec};
+use server::recorder::{self, Recorder};
+use server::tcp::Socket;
+use server::unix;
 use server::Server;
 
 mod app;
+use app::drafts::Drafts;
+use app::mutes::Mutes;
+use app::scrollback::DEFAULT_CAPACITY;
+use app::unread::ReadMarker;
 use app::App;
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "netchat")]
+#[structopt(
+    name = "netchat",
+    raw(setting = "structopt::clap::AppSettings::SubcommandsNegateReqs")
+)]
 /// A fully decentralized (thus inefficient) chat written in rust
 ///
 /// Enter  -> sends the content of the input field to everyone
 ///
+/// Alt+Enter -> starts a new line of the same message
+///
 /// Ctrl+c -> exit
 ///
 /// Ctrl+s -> get a snapshot containing every messages sent by every site
@@ -29,17 +68,87 @@ use app::App;
 ///
 /// Ctrl+p -> sends the content of the input field to the current private recipient
 ///
+/// Ctrl+k -> rotate the identity key, the old key endorses the new one
+///
+/// Ctrl+l -> accept the last request to link a device to your identity
+///
+/// /help [<command>] -> list the commands or tell how one is used, Tab
+/// completes their names
+///
+/// /outbox -> show or hide the messages waiting to be sent,
+/// `/outbox retry [<number>]` and `/outbox cancel <number>` act on them
+///
+/// /reconnect [<transport>] -> retry a broken transport now
+///
+/// /partition [<transport>] -> stop writing to and reading from a transport,
+/// `/heal [<transport>]` lets what was held back through
+///
+/// /join #<channel> -> show a channel in a new tab, Alt+<n> switches tabs and
+/// `/leave` leaves the channel shown
+///
+/// /msg <app> <text> -> send a private message to app
+/// /announce <text> -> send an announcement, see --operator
+/// /slow [<seconds>] -> hold back public messages sent closer than that, see
+/// --operator
+/// /services -> list what the apps offer, see --service
+///
+/// /alias <name> <line> -> make /<name> stand for line, see --alias
+///
+/// F3 -> record a macro until F4, which then replays it
+///
+/// /away -> count the messages received until `/back`, which sums them up
+///
+/// /mentions -> list the mentions missed, `/mentions <number>` jumps to one
+///
+/// Ctrl+u -> scroll to the first unread message, sending one or `/read` marks
+/// them all as read
+///
 /// Up     -> scroll messages up
 ///
 /// Down   -> scroll messages down
 pub struct Opt {
     /// Input file
-    #[structopt(short = "i", long = "input", parse(from_os_str))]
-    input: PathBuf,
+    #[structopt(
+        short = "i",
+        long = "input",
+        parse(from_os_str),
+        requires = "output",
+        raw(required_unless_one = r#"&["demo", "listen", "connect", "discover", "socket"]"#)
+    )]
+    input: Option<PathBuf>,
 
     /// Output file
-    #[structopt(short = "o", long = "output", parse(from_os_str))]
-    output: PathBuf,
+    #[structopt(
+        short = "o",
+        long = "output",
+        parse(from_os_str),
+        requires = "input",
+        raw(required_unless_one = r#"&["demo", "listen", "connect", "discover", "socket"]"#)
+    )]
+    output: Option<PathBuf>,
+
+    /// Chat over TCP, with every peer connecting to this address
+    #[structopt(long = "listen")]
+    listen: Option<String>,
+
+    /// Chat over TCP with the peer listening on this address, give it several
+    /// times to reach several peers
+    #[structopt(long = "connect")]
+    connect: Vec<String>,
+
+    /// Chat with the apps of this host over a Unix domain socket: listen on
+    /// this path, or connect to the app already listening there
+    #[structopt(long = "socket", parse(from_os_str))]
+    socket: Option<PathBuf>,
+
+    /// Look for the apps on the local network over mDNS, for /connect, and
+    /// tell them where we are if we listen
+    #[structopt(long = "discover")]
+    discover: bool,
+
+    /// Chat with simulated apps which show how netchat works, no pipe needed
+    #[structopt(long = "demo")]
+    demo: bool,
 
     //Application Identifier
     #[structopt(short = "n", long = "name")]
@@ -48,13 +157,483 @@ pub struct Opt {
     //Application Identifier
     #[structopt(short = "l", long = "logfile")]
     logfile: Option<PathBuf>,
+
+    /// What is logged, as in RUST_LOG which wins over it, e.g. info or
+    /// netchat::server=debug [default: error]
+    #[structopt(long = "log-level")]
+    log_level: Option<String>,
+
+    /// Options read before these ones, keys of long options and their values
+    /// [default: $XDG_CONFIG_HOME/netchat/config.toml]
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Name shown to the others instead of the id, as set by /nick
+    #[structopt(long = "nick")]
+    nick: Option<String>,
+
+    /// Identity key file, created if missing [default: <id>.key]
+    #[structopt(short = "k", long = "keyfile", parse(from_os_str))]
+    keyfile: Option<PathBuf>,
+
+    /// Revocation certificate to broadcast, e.g. after losing a device
+    #[structopt(long = "revoke", parse(from_os_str))]
+    revoke: Vec<PathBuf>,
+
+    /// Link this app as a device of the given identity, which has to accept
+    #[structopt(long = "owner")]
+    owner: Option<String>,
+
+    /// Longest message sent or accepted, in bytes, once serialized
+    #[structopt(long = "max-frame-size")]
+    max_frame_size: Option<usize>,
+
+    /// Longest chat message that can be typed, in characters
+    #[structopt(long = "max-text-len")]
+    max_text_len: Option<usize>,
+
+    /// Split chat messages over the limit instead of refusing them
+    #[structopt(long = "split-long")]
+    split_long: bool,
+
+    /// Relay and record without ever sending anything, the others do not see this app
+    #[structopt(long = "observe")]
+    observe: bool,
+
+    /// Run without the terminal UI, only forwarding messages between the
+    /// transports as with --observe; what happens goes to the log
+    #[structopt(long = "relay")]
+    relay: bool,
+
+    /// Seconds between two summaries of the relay in the log
+    #[structopt(long = "relay-status", default_value = "60")]
+    relay_status: u64,
+
+    /// Join only on sending a first message, to read the others before they know you are there
+    #[structopt(long = "lurk")]
+    lurk: bool,
+
+    /// Serve the counters of /stats as Prometheus text on this port of
+    /// 127.0.0.1, at /metrics
+    #[structopt(long = "metrics-port")]
+    metrics_port: Option<u16>,
+
+    /// Skip already relayed messages after decoding their id only, for busy relays
+    #[structopt(long = "fast-relay")]
+    fast_relay: bool,
+
+    /// Encoding of the messages sent: json, or cbor, smaller, once every app
+    /// heard of said it reads it
+    #[structopt(
+        long = "wire-format",
+        default_value = "json",
+        raw(possible_values = "netchat_core::messages::CODECS")
+    )]
+    wire_format: String,
+
+    /// Compresses the messages longer than this many bytes once every app
+    /// reads compressed frames, 0 to never compress
+    #[structopt(long = "compress-above", default_value = "8192")]
+    compress_above: usize,
+
+    /// Local dates the ids of the messages seen are remembered for, to drop their copies
+    #[structopt(long = "seen-horizon", default_value = "100000")]
+    seen_horizon: u64,
+
+    /// Seconds between two heartbeats, 0 to never send any
+    #[structopt(long = "heartbeat", default_value = "30")]
+    heartbeat: u64,
+
+    /// Seconds between two summaries of the chat messages we have, for the
+    /// neighbours to send back those we missed, 0 to never send any
+    #[structopt(long = "sync-interval", default_value = "60")]
+    sync_interval: u64,
+
+    /// Seconds after which an app not heard from is offline, 0 for never
+    #[structopt(long = "presence-timeout", default_value = "90")]
+    presence_timeout: u64,
+
+    /// Order chat messages are shown in: on arrival, per sender (fifo), after
+    /// what their sender had seen (causal), or the same on every app (total)
+    #[structopt(
+        long = "delivery",
+        default_value = "arrival",
+        raw(possible_values = "netchat_core::delivery::POLICIES")
+    )]
+    delivery: String,
+
+    /// Seconds after which messages held back by the delivery order are shown
+    /// anyway, 0 to only show them once 64 others wait behind them
+    #[structopt(long = "hold-timeout", default_value = "10")]
+    hold_timeout: u64,
+
+    /// Times a chat message nobody acknowledged is sent again, 0 disables it
+    #[structopt(long = "retransmit", default_value = "3")]
+    retransmit: u32,
+
+    /// Seconds to wait for an acknowledgement before sending a message again
+    #[structopt(long = "ack-timeout", default_value = "5")]
+    ack_timeout: u64,
+
+    /// Files received from the apps without a policy of their own, see
+    /// `/files policy`: accepted when signed by the key first seen for the
+    /// sender, under --auto-accept-below, and kept in quarantine otherwise
+    /// (auto), always kept in quarantine (prompt) or refused (reject). The key
+    /// first seen is trusted as it came, not checked against a fingerprint:
+    /// use prompt for senders you have not met. Executables are always refused.
+    #[structopt(
+        long = "accept-files",
+        default_value = "auto",
+        raw(possible_values = "server::acceptance::POLICIES")
+    )]
+    accept_files: String,
+
+    /// MiB of the largest file accepted without asking
+    #[structopt(long = "auto-accept-below", default_value = "4")]
+    auto_accept_below: usize,
+
+    /// Seconds without a chunk of a file being received after which it is
+    /// given up on, 0 waits for ever
+    #[structopt(long = "file-timeout", default_value = "60")]
+    file_timeout: u64,
+
+    /// Seconds to wait on quitting for the acknowledgements of the last
+    /// messages sent, before saying goodbye
+    #[structopt(long = "goodbye-timeout", default_value = "3")]
+    goodbye_timeout: u64,
+
+    /// Messages a second shown or relayed for each other app, the rest are
+    /// dropped: chat, typing, live lines and edits. 0 for no limit
+    #[structopt(long = "rate-limit", default_value = "10")]
+    rate_limit: u32,
+
+    /// Messages an app may send at once before the rate limit applies
+    #[structopt(long = "rate-burst", default_value = "50")]
+    rate_burst: u32,
+
+    /// Seed of the random source, for reproducible runs (keys stay random)
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// Service offered to the others, e.g. "mailbox" or "bridge to IRC #foo"
+    #[structopt(long = "service")]
+    service: Vec<String>,
+
+    /// Encrypts every line on the pipes with the key in this file, 64
+    /// hexadecimal digits shared by the apps, with XChaCha20-Poly1305
+    #[structopt(long = "psk", parse(from_os_str))]
+    psk: Option<PathBuf>,
+
+    /// Private messages and files to an app they cannot be encrypted for: not
+    /// sent (require), sent in clear with a warning (prefer) or without one
+    /// (allow-plaintext)
+    #[structopt(
+        long = "encryption",
+        default_value = "prefer",
+        raw(possible_values = "server::encryption::POLICIES")
+    )]
+    encryption: String,
+
+    /// Message of the day, sent privately to each app joining, e.g. the rules
+    /// of a shared relay
+    #[structopt(long = "motd", parse(from_os_str))]
+    motd: Option<PathBuf>,
+
+    /// Records the session there, to scrub through it with `netchat replay`
+    #[structopt(long = "record", parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// Rewrites the typed text before sending it, in the order given:
+    /// strip-ansi, abbreviations:<file> or wrap-code
+    #[structopt(long = "filter")]
+    filter: Vec<String>,
+
+    /// Segment shown at the left of the status bar: peers, clock, time or
+    /// cmd:<shell command>, followed by @<seconds> to change how often it is
+    /// updated, e.g. `cmd:git branch --show-current@30`
+    #[structopt(long = "status-left")]
+    status_left: Vec<String>,
+
+    /// Segment shown at the right of the status bar, as for --status-left
+    #[structopt(long = "status-right")]
+    status_right: Vec<String>,
+
+    /// Colors of the interface: dark, light for a light background, or mono
+    /// for none
+    #[structopt(
+        long = "theme",
+        default_value = "dark",
+        raw(possible_values = "app::theme::THEMES")
+    )]
+    theme: String,
+
+    /// Shows the peer panel on start, `/peers` toggles it
+    #[structopt(long = "peers")]
+    peers: bool,
+
+    /// What the peer panel groups the apps by: nothing, the channels they
+    /// spoke in or their tags
+    #[structopt(
+        long = "peer-group",
+        default_value = "none",
+        raw(possible_values = "app::peers::GROUPINGS")
+    )]
+    peer_group: String,
+
+    /// Order of the apps in the peer panel: most messages, name or last heard from first
+    #[structopt(
+        long = "peer-sort",
+        default_value = "activity",
+        raw(possible_values = "app::peers::SORTS")
+    )]
+    peer_sort: String,
+
+    /// Defines a command standing for a longer line: <name>=<line>, e.g.
+    /// `std=/msg bob standup in 5` makes `/std` send it
+    #[structopt(long = "alias")]
+    alias: Vec<String>,
+
+    /// Transports the public messages of a channel go through, the others
+    /// are not used for them: #<channel>=<transport>[,<transport>...]
+    #[structopt(long = "via")]
+    via: Vec<String>,
+
+    /// Rings the terminal bell on private messages and mentions
+    #[structopt(long = "bell")]
+    bell: bool,
+
+    /// Hours the bell is silent and the app away, e.g. 22:00-08:00
+    #[structopt(long = "quiet-hours")]
+    quiet_hours: Option<app::quiet::QuietHours>,
+
+    /// Contact whose private messages and mentions ring during quiet hours
+    #[structopt(long = "quiet-override")]
+    quiet_override: Vec<String>,
+
+    /// Shell command run on what would ring the bell, with the message as
+    /// json on its standard input, e.g. `curl -d @- ntfy.sh/my-topic`
+    #[structopt(long = "notify-command")]
+    notify_command: Option<String>,
+
+    /// Text-to-speech shell command reading out loud the text given on its
+    /// standard input, e.g. `espeak` or `say`
+    #[structopt(long = "speak-command")]
+    speak_command: Option<String>,
+
+    /// Messages read out loud: all, private, mentions, announcements,
+    /// from:<app> or in:#<channel> [default: private and mentions]
+    #[structopt(long = "speak")]
+    speak: Vec<app::speech::Rule>,
+
+    /// Shell command translating the text given on its standard input, the
+    /// translation is shown under the message, e.g. `trans -b :en`
+    #[structopt(long = "translate-command")]
+    translate_command: Option<String>,
+
+    /// App whose messages are translated as they come
+    #[structopt(long = "translate-from")]
+    translate_from: Vec<String>,
+
+    /// Identity whose signed announcements are shown as such, those of the
+    /// others are shown as public messages
+    #[structopt(long = "operator")]
+    operator: Vec<String>,
+
+    /// Directory of the keys, history, scrollback and snapshots, and of the log
+    /// [default: $XDG_DATA_HOME/netchat, logs in $XDG_STATE_HOME/netchat]
+    #[structopt(long = "data-dir", parse(from_os_str))]
+    data_dir: Option<PathBuf>,
+
+    /// Directory of the chat history, one file per day [default: <id>.history]
+    #[structopt(long = "history-dir", parse(from_os_str))]
+    history_dir: Option<PathBuf>,
+
+    /// Chat messages of the previous sessions shown again on start, all of
+    /// them are kept in <id>.messages.jsonl
+    #[structopt(long = "history-replay", default_value = "50")]
+    history_replay: usize,
+
+    /// Messages kept in memory, older ones are moved to <id>.scrollback [default: 1000]
+    #[structopt(long = "scrollback")]
+    scrollback: Option<usize>,
+
+    /// Permissions removed from the files and directories created, in octal,
+    /// the default keeps them to the user
+    #[structopt(
+        long = "umask",
+        default_value = "077",
+        parse(try_from_str = "parse_umask")
+    )]
+    umask: libc::mode_t,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Runs a json or TOML scenario of simulated apps, prints what happened
+    /// and the invariants it broke, exits with 2 if it broke any
+    #[structopt(name = "simulate")]
+    Simulate {
+        #[structopt(parse(from_os_str))]
+        scenario: PathBuf,
+
+        /// Writes the causal graph of the messages there, in the dot format
+        #[structopt(long = "dag", parse(from_os_str))]
+        dag: Option<PathBuf>,
+
+        /// Makes an app misbehave: <app>=lie-clock, duplicate, drop-relays or forge:<other app>
+        #[structopt(long = "misbehave")]
+        misbehave: Vec<String>,
+    },
+    /// Plays a session recorded with --record back, step by step
+    #[structopt(name = "replay")]
+    Replay {
+        #[structopt(parse(from_os_str))]
+        recording: PathBuf,
+    },
+    /// Checks the directories, pipes and keys the other options point to,
+    /// exits with 1 if netchat could not start with them
+    #[structopt(name = "doctor")]
+    Doctor,
+}
+
+fn parse_umask(octal: &str) -> Result<libc::mode_t, String> {
+    libc::mode_t::from_str_radix(octal, 8)
+        .ok()
+        .filter(|mask| *mask <= 0o777)
+        .ok_or_else(|| format!("expected an octal mask such as 077, got {}", octal))
+}
+
+/// What `--discover` tells the local network: where `--listen` waits, on the
+/// address of the host on the link when it listens on all of them
+fn advert(app_id: &str, listen: Option<&str>) -> Option<Advert> {
+    let addr = listen?
+        .to_socket_addrs()
+        .ok()?
+        .find_map(|addr| match addr {
+            SocketAddr::V4(addr) => Some(addr),
+            SocketAddr::V6(_) => None,
+        })?;
+    let ip = match addr.ip().is_unspecified() {
+        true => discovery::local_ip()
+            .map_err(|e| log::warn!("Not advertised on the local network: {}", e))
+            .ok()?,
+        false => *addr.ip(),
+    };
+    Some(Advert {
+        app_id: app_id.to_owned(),
+        addr: SocketAddrV4::new(ip, addr.port()),
+    })
 }
 
 fn main() {
-    let opt = Opt::from_args();
+    let args = config::merge(std::env::args_os().collect()).unwrap_or_else(|e| {
+        eprintln!("Invalid configuration: {}", e);
+        std::process::exit(1)
+    });
+    let opt = Opt::from_iter(args);
+
+    if let Some(Command::Simulate {
+        scenario,
+        dag,
+        misbehave,
+    }) = &opt.command
+    {
+        let mut scenario = simulate::Scenario::load(scenario).unwrap_or_else(|e| {
+            eprintln!("Could not load {:?}: {}", scenario, e);
+            std::process::exit(1)
+        });
+        for spec in misbehave {
+            if let Err(e) = scenario.misbehave(spec) {
+                eprintln!("Invalid --misbehave {}: {}", spec, e);
+                std::process::exit(1)
+            }
+        }
+        let outcome = simulate::run(&scenario);
+        print!("{}", outcome.transcript);
+        if let Some(dag) = dag {
+            if let Err(e) = std::fs::write(dag, &outcome.dag) {
+                eprintln!("Could not write {:?}: {}", dag, e);
+                std::process::exit(1)
+            }
+        }
+        let violations: Vec<_> = scenario
+            .invariants()
+            .iter()
+            .flat_map(|invariant| invariant.check(&outcome))
+            .collect();
+        for violation in &violations {
+            print!("\n{}", violation);
+        }
+        if !violations.is_empty() {
+            std::process::exit(2)
+        }
+        return;
+    }
+
+    if let Some(Command::Replay { recording }) = &opt.command {
+        let entries = recorder::load(recording).unwrap_or_else(|e| {
+            eprintln!("Could not load {:?}: {}", recording, e);
+            std::process::exit(1)
+        });
+        if let Err(e) = replay::run(entries) {
+            eprintln!("Could not replay {:?}: {}", recording, e);
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    let dirs = Dirs::new(opt.data_dir.clone());
+
+    if let Some(Command::Doctor) = &opt.command {
+        let keyfiles = match (&opt.keyfile, &opt.id) {
+            (Some(keyfile), _) => vec![keyfile.clone()],
+            (None, Some(id)) => vec![dirs.data.join(format!("{}.key", id))],
+            (None, None) => Vec::new(),
+        };
+        let setup = doctor::Setup {
+            pipes: opt.input.clone().zip(opt.output.clone()),
+            keyfiles,
+            psk: opt.psk.clone(),
+            listen: opt.listen.clone(),
+            connect: opt.connect.clone(),
+            dirs,
+        };
+        let findings = doctor::check(&setup);
+        for finding in &findings {
+            println!("{}", finding);
+        }
+        if findings
+            .iter()
+            .any(|finding| matches!(finding, doctor::Finding::Problem(_)))
+        {
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    if opt.translate_command.is_none() && !opt.translate_from.is_empty() {
+        eprintln!("--translate-from needs a --translate-command");
+        std::process::exit(1)
+    }
+    if opt.speak_command.is_none() && !opt.speak.is_empty() {
+        eprintln!("--speak needs a --speak-command");
+        std::process::exit(1)
+    }
+
+    // Logs, history and keys are kept to the user on shared machines
+    unsafe {
+        libc::umask(opt.umask);
+    }
+    if let Err(e) = dirs.create() {
+        eprintln!("Could not create {:?}: {}", dirs.data, e);
+        std::process::exit(1)
+    }
 
     // Open a log file
-    let logfile = opt.logfile.clone().unwrap_or("/tmp/netchat.log".into());
+    let logfile = opt.logfile.clone().unwrap_or_else(|| dirs.logfile());
     let log = OpenOptions::new()
         .truncate(true)
         .read(true)
@@ -65,9 +644,19 @@ fn main() {
 
     let _stderr_redirect_handle = Redirect::stderr(log).unwrap();
     color_backtrace::install();
-    env_logger::init();
+    // A relay has nothing but the log to say what it does
+    let level = match &opt.log_level {
+        Some(level) => level.as_str(),
+        None if opt.relay => "error,netchat::relay=info",
+        None => "error",
+    };
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(level)).init();
+    // Already merged in, only said
+    if let Some(path) = &opt.config {
+        log::info!("Options read from {:?}", path);
+    }
 
-    let (app_tx, server_rx) = mpsc::channel(); // server -> app
+    let (app_tx, server_rx) = app::channel::channel(app::channel::CAPACITY); // server -> app
     let (server_tx, app_rx) = mpsc::channel(); // app    -> server
 
     // Create default app state
@@ -75,22 +664,290 @@ fn main() {
 
     if let Some(id) = opt.id.to_owned() {
         app.id = id;
+    } else if opt.demo {
+        app.id = "you".to_owned();
     }
 
-    app.messages.push(app::Message::System(format!(
-        "input : {:?}, output : {:?}, id : {}",
-        opt.input, opt.output, app.id
-    )));
+    // The demo keeps its files out of the current directory
+    let demo = if opt.demo {
+        Some(Demo::start(app.id.clone()).expect("Could not start the demo"))
+    } else {
+        None
+    };
+    let dir = demo.as_ref().map_or(dirs.data, |d| d.dir.clone());
+    let mut endpoints = Vec::new();
+    match &demo {
+        Some(demo) => endpoints.push(Endpoint::Pipes {
+            input: demo.input.clone(),
+            output: demo.output.clone(),
+        }),
+        None => {
+            if let (Some(input), Some(output)) = (&opt.input, &opt.output) {
+                endpoints.push(Endpoint::Pipes {
+                    input: input.clone(),
+                    output: output.clone(),
+                });
+            }
+            if let Some(addr) = &opt.listen {
+                let socket = Socket::listen(addr).unwrap_or_else(|e| {
+                    eprintln!("Could not listen on {}: {}", addr, e);
+                    std::process::exit(1)
+                });
+                endpoints.push(Endpoint::Tcp(socket));
+            }
+            for addr in &opt.connect {
+                endpoints.push(Endpoint::Tcp(Socket::connect(addr)));
+            }
+            if let Some(path) = &opt.socket {
+                let socket = unix::Socket::open(path).unwrap_or_else(|e| {
+                    eprintln!("Could not open the socket {:?}: {}", path, e);
+                    std::process::exit(1)
+                });
+                endpoints.push(Endpoint::Unix(socket));
+            }
+        }
+    }
+
+    let scrollback = opt.scrollback.unwrap_or(DEFAULT_CAPACITY);
+    if let Err(e) = app
+        .messages
+        .spill_to(dir.join(format!("{}.scrollback", app.id)), scrollback)
+    {
+        log::error!("Could not open the scrollback file: {}", e);
+    }
+    for spec in &opt.filter {
+        match app::filters::by_name(spec) {
+            Ok(filter) => app.filters.push(filter),
+            Err(e) => {
+                eprintln!("Invalid --filter {}: {}", spec, e);
+                std::process::exit(1)
+            }
+        }
+    }
+    for spec in &opt.alias {
+        match spec.split_once('=') {
+            Some((name, line)) => {
+                let name = name.trim_start_matches('/').to_owned();
+                app.aliases.insert(name, line.to_owned());
+            }
+            None => {
+                eprintln!("Invalid --alias {}: expected <name>=<line>", spec);
+                std::process::exit(1)
+            }
+        }
+    }
+    for spec in &opt.via {
+        let parsed = spec
+            .split_once('=')
+            .and_then(|(channel, names)| Some((Channel::parse(channel)?, names)))
+            .filter(|(_, names)| !names.is_empty());
+        match parsed {
+            Some((channel, names)) => {
+                let names = names.split(',').map(str::to_owned).collect();
+                app.via.insert(channel, names);
+            }
+            None => {
+                eprintln!(
+                    "Invalid --via {}: expected #<channel>=<transport>[,<transport>...]",
+                    spec
+                );
+                std::process::exit(1)
+            }
+        }
+    }
+    for (specs, left) in [(&opt.status_left, true), (&opt.status_right, false)] {
+        for spec in specs {
+            match app::status::by_name(spec) {
+                Ok(segment) if left => app.status.push_left(segment),
+                Ok(segment) => app.status.push_right(segment),
+                Err(e) => {
+                    eprintln!("Invalid status bar segment {}: {}", spec, e);
+                    std::process::exit(1)
+                }
+            }
+        }
+    }
+    app.peers.shown = opt.peers;
+    app.peers.grouping = app::peers::Grouping::by_name(&opt.peer_group).expect("checked by clap");
+    app.peers.sort = app::peers::Sort::by_name(&opt.peer_sort).expect("checked by clap");
+    app.bell = opt.bell;
+    app.theme = app::theme::Theme::by_name(&opt.theme).expect("checked by clap");
+    app.observe = opt.observe;
+    app.quiet_hours = opt.quiet_hours;
+    app.quiet_override = opt.quiet_override.iter().cloned().collect();
+    app.notify_command = opt.notify_command.map(app::hook::NotifyCommand::new);
+    if let Some(command) = opt.speak_command {
+        app.speaker = Some(app::speech::Speaker::new(command, opt.speak));
+    }
+    if let Some(command) = opt.translate_command {
+        let mut translator = app::translate::Translator::new(command, app_tx.clone());
+        translator.auto = opt.translate_from.into_iter().collect();
+        app.translator = Some(translator);
+    }
+    app.unread = ReadMarker::load(dir.join(format!("{}.read.json", app.id)));
+    app.mutes = Mutes::load(dir.join(format!("{}.mutes.json", app.id)));
+    app.keep_drafts(Drafts::load(dir.join(format!("{}.drafts.json", app.id))));
+    let history_dir = opt
+        .history_dir
+        .clone()
+        .unwrap_or_else(|| dir.join(format!("{}.history", app.id)));
+    if let Err(e) = app.messages.log_to(history_dir) {
+        log::error!("Could not open the history directory: {}", e);
+    }
+
+    for endpoint in &endpoints {
+        match endpoint {
+            Endpoint::Pipes { input, output } => {
+                app.messages.push(app::Message::System(format!(
+                    "input : {:?}, output : {:?}, id : {}",
+                    input, output, app.id
+                )));
+                for pipe in [input, output] {
+                    if is_world_writable(pipe) {
+                        app.messages.push(app::Message::System(format!(
+                            "Warning: every user can write to {:?}, chmod o-w it",
+                            pipe
+                        )));
+                    }
+                }
+            }
+            Endpoint::Tcp(socket) => app
+                .messages
+                .push(app::Message::System(format!("{}, id : {}", socket, app.id))),
+            Endpoint::Unix(socket) => app
+                .messages
+                .push(app::Message::System(format!("{}, id : {}", socket, app.id))),
+        }
+    }
+
+    let limits = Limits {
+        max_frame_len: opt.max_frame_size.unwrap_or(MAX_FRAME_LEN),
+        max_text_len: opt.max_text_len.unwrap_or(DEFAULT_MAX_TEXT_LEN),
+        split_long_text: opt.split_long,
+    };
+    app.limits = limits;
 
-    let server = Server::new(app.id.to_owned());
+    let keyfile = opt
+        .keyfile
+        .clone()
+        .unwrap_or_else(|| dir.join(format!("{}.key", app.id)));
+    let identity =
+        Identity::load_or_generate(&keyfile, &app.id).expect("Could not load the identity key");
+    let contacts = Contacts::load(keyfile.with_extension("contacts.json"));
+    for (app_id, tags) in contacts.tags() {
+        for tag in tags {
+            app.peers.tag(app_id, tag);
+        }
+    }
+
+    let mut server = Server::new(app.id.to_owned(), identity, &keyfile, contacts);
+    server.set_limits(limits);
+    match History::open(
+        &dir.join(format!("{}.messages.jsonl", app.id)),
+        opt.history_replay,
+    ) {
+        Ok(history) => server.set_history(history),
+        Err(e) => log::error!("Could not open the message history: {}", e),
+    }
+    server.set_snapshot_dir(dir.clone());
+    server.set_download_dirs(
+        dir.join("downloads"),
+        dir.join("quarantine"),
+        dir.join("blobs"),
+    );
+    let mut acceptance =
+        server::acceptance::Acceptance::load(dir.join(format!("{}.file-policies.json", app.id)));
+    acceptance.policy =
+        server::acceptance::Policy::by_name(&opt.accept_files).expect("checked by clap");
+    acceptance.max_auto_len = opt.auto_accept_below.saturating_mul(1024 * 1024);
+    server.set_acceptance(acceptance);
+    if opt.file_timeout > 0 {
+        server.set_file_timeout(Duration::from_secs(opt.file_timeout));
+    }
+    server.set_fast_relay(opt.fast_relay);
+    if let Some(port) = opt.metrics_port {
+        server.set_metrics_port(port);
+    }
+    server.set_wire_format(Codec::by_name(&opt.wire_format).expect("checked by clap"));
+    if opt.compress_above > 0 {
+        server.set_compress_above(opt.compress_above);
+    }
+    server.set_observe(opt.observe || opt.relay);
+    server.set_lurk(opt.lurk);
+    server.set_seen_horizon(opt.seen_horizon);
+    if opt.hold_timeout > 0 {
+        server.set_hold_timeout(Duration::from_secs(opt.hold_timeout));
+    }
+    server.set_retransmission(opt.retransmit, Duration::from_secs(opt.ack_timeout.max(1)));
+    server.set_rate_limit(opt.rate_limit, opt.rate_burst);
+    server.set_goodbye_timeout(Duration::from_secs(opt.goodbye_timeout));
+    if opt.presence_timeout > 0 {
+        server.set_presence_timeout(Duration::from_secs(opt.presence_timeout));
+    }
+    if opt.heartbeat > 0 {
+        server.set_heartbeat(Duration::from_secs(opt.heartbeat));
+    }
+    if opt.sync_interval > 0 {
+        server.set_sync_interval(Duration::from_secs(opt.sync_interval));
+    }
+    if opt.discover {
+        server.set_discovery(advert(&app.id, opt.listen.as_deref()));
+    }
+    server.set_delivery(netchat_core::delivery::by_name(&opt.delivery).expect("checked by clap"));
+    if let Some(seed) = opt.seed {
+        server.set_rng(SmallRng::seed_from_u64(seed));
+    }
+    server.set_operators(opt.operator.iter().cloned().collect());
+    server.set_encryption(
+        server::encryption::Policy::by_name(&opt.encryption).expect("checked by clap"),
+    );
+    server.set_services(opt.service.clone());
+    if let Some(nick) = &opt.nick {
+        if !server::messages::is_valid_nick(nick) {
+            eprintln!(
+                "Invalid --nick {}: one word of {} characters at most",
+                nick,
+                server::messages::MAX_NICK_LEN
+            );
+            std::process::exit(1)
+        }
+        server.set_nick(nick.clone());
+    }
+    if let Some(path) = &opt.psk {
+        let hex = fs::read_to_string(path).expect("Could not read the pre-shared key");
+        let key = netchat_core::crypto::FrameKey::from_hex(&hex)
+            .expect("The pre-shared key must be 64 hexadecimal digits");
+        server.set_frame_key(key);
+    }
+    if let Some(path) = &opt.motd {
+        let motd = fs::read_to_string(path).expect("Could not read the message of the day");
+        server.set_motd(motd.trim_end().to_owned());
+    }
+    if let Some(path) = &opt.record {
+        server.set_recorder(Recorder::create(path).expect("Could not create the recording"));
+    }
+    if let Some(owner) = opt.owner.to_owned() {
+        server.link_to(owner);
+    }
+    for path in &opt.revoke {
+        server.revoke(
+            RevocationCertificate::load(path).expect("Could not load the revocation certificate"),
+        );
+    }
 
     let server_handle = thread::spawn(move || {
-        if let Err(e) = server::run(server, app_rx, app_tx, opt.input, opt.output) {
+        if let Err(e) = server::run(server, app_rx, app_tx, endpoints) {
             log::error!("{}", e);
         }
     });
 
-    if let Err(e) = app::run(app, server_rx, server_tx) {
+    if opt.relay {
+        relay::run(
+            server_rx,
+            server_tx,
+            Duration::from_secs(opt.relay_status.max(1)),
+        );
+    } else if let Err(e) = app::run(app, server_rx, server_tx) {
         log::error!("{}", e);
     };
 
@@ -0,0 +1,91 @@
+//! `--relay`: the server alone, without the terminal UI, forwarding what
+//! comes in on a transport to the others until SIGINT or SIGTERM. Connections
+//! and notices are logged, with a summary every `--relay-status` seconds.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::app::channel::Receiver;
+use crate::app::events::Event;
+use crate::server::events::Event as ServerEvent;
+use crate::server::notice::Severity;
+use crate::server::reconnect::State;
+
+/// How often the stop signal is checked for
+const TICK: Duration = Duration::from_millis(250);
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn stop(_: libc::c_int) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Handles the events of the server until a signal stops it, then shuts it down
+pub fn run(events: Receiver, server_tx: mpsc::Sender<ServerEvent>, status_every: Duration) {
+    let handler = stop as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+    let ticks = events.sender();
+    thread::spawn(move || {
+        while ticks.send(Event::Tick).is_ok() {
+            thread::sleep(TICK);
+        }
+    });
+
+    log::info!("Relaying, without the terminal UI");
+    let mut connections = BTreeMap::new();
+    let (mut messages, mut last_status) = (0, Instant::now());
+    loop {
+        match events.recv() {
+            Event::Tick if STOP.load(Ordering::SeqCst) => break,
+            Event::Tick if last_status.elapsed() >= status_every => {
+                let up = connections
+                    .values()
+                    .filter(|state| **state == State::Connected)
+                    .count();
+                log::info!(
+                    "{}/{} transports up, {} chat messages passed through",
+                    up,
+                    connections.len(),
+                    messages
+                );
+                last_status = Instant::now();
+            }
+            Event::DistantMessage(_) => messages += 1,
+            Event::PeerOnline(app_id) => log::info!("{} online", app_id),
+            Event::PeerOffline(app_id) => log::info!("{} offline", app_id),
+            Event::Connection(change) => {
+                log::info!("{}: {}", change.name, describe(&change.state));
+                match change.state {
+                    State::Left => connections.remove(&change.name),
+                    state => connections.insert(change.name, state),
+                };
+            }
+            Event::Notice(notice) => match notice.severity {
+                Severity::Info => log::info!("{}", notice.text),
+                Severity::Warning => log::warn!("{}", notice.text),
+                Severity::Error => log::error!("{}", notice.text),
+            },
+            _ => {}
+        }
+    }
+    log::info!("Stopping the relay");
+    if server_tx.send(ServerEvent::Shutdown).is_err() {
+        log::error!("The server was already gone");
+    }
+}
+
+fn describe(state: &State) -> String {
+    match state {
+        State::Connected => "up".to_owned(),
+        State::Disconnected => "down".to_owned(),
+        State::Reconnecting { attempt, .. } => format!("reconnecting, attempt {}", attempt),
+        State::Partitioned => "cut".to_owned(),
+        State::Left => "left".to_owned(),
+    }
+}
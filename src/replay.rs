@@ -0,0 +1,293 @@
+//! `netchat replay`: scrubs through a session recorded with `--record`,
+//! showing the clock, the peers and the transports at each step
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io;
+
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use tui::backend::TermionBackend;
+use tui::layout::{Alignment, Constraint, Direction, Layout};
+use tui::style::{Color, Modifier, Style};
+use tui::widgets::{Block, Borders, List, Paragraph, Text, Widget};
+use tui::Terminal;
+
+use crate::app::AppId;
+use crate::server::messages::Header::{Announcement, Private, Public};
+use crate::server::messages::{Channel, Msg};
+use crate::server::reconnect::{Change, State};
+use crate::server::recorder::{Entry, Step};
+use crate::server::Clock;
+
+/// Steps skipped by PageUp and PageDown
+const PAGE: usize = 10;
+
+/// What the app knew once a step was played
+#[derive(Debug, PartialEq)]
+pub struct View {
+    pub clock: Clock,
+    /// Whether each peer heard from is still there
+    pub peers: BTreeMap<AppId, bool>,
+    pub transports: Vec<Change>,
+}
+
+/// State after `entries[..=position]`
+pub fn view_at(entries: &[Entry], position: usize) -> View {
+    let mut view = View {
+        clock: Clock(Default::default()),
+        peers: BTreeMap::new(),
+        transports: Vec::new(),
+    };
+    for entry in entries.iter().take(position + 1) {
+        if let Some(clock) = &entry.clock {
+            view.clock = clock.clone();
+        }
+        match &entry.step {
+            Step::Sent(_) | Step::Notice(_) => {}
+            Step::Delivered(msg) => {
+                view.peers.entry(msg.sender_id.clone()).or_insert(true);
+            }
+            Step::Joined(app_id) => {
+                view.peers.insert(app_id.clone(), true);
+            }
+            Step::Left(app_id) => {
+                view.peers.insert(app_id.clone(), false);
+            }
+            Step::Transport(change) => {
+                match view.transports.iter_mut().find(|c| c.name == change.name) {
+                    _ if change.state == State::Left => {
+                        view.transports.retain(|c| c.name != change.name)
+                    }
+                    Some(previous) => *previous = change.clone(),
+                    None => view.transports.push(change.clone()),
+                }
+            }
+        }
+    }
+    view
+}
+
+/// ` in #rust`, nothing for `#general`
+fn in_channel(channel: &Channel) -> String {
+    match channel.is_general() {
+        true => String::new(),
+        false => format!(" in {}", channel),
+    }
+}
+
+fn chat(msg: &Msg, sent: bool) -> String {
+    match (&msg.header, sent) {
+        (Public(channel, text), true) => format!("You{}: {}", in_channel(channel), text),
+        (Private(to, text), true) => format!("You to {}: {}", to, text),
+        (Public(channel, text), false) => {
+            format!("{}{}: {}", msg.sender_id, in_channel(channel), text)
+        }
+        (Private(_, text), false) => format!("{} to You: {}", msg.sender_id, text),
+        (Announcement(text, _), true) => format!("You announce: {}", text),
+        (Announcement(text, _), false) => format!("{} announces: {}", msg.sender_id, text),
+        (header, _) => format!("{}: {:?}", msg.sender_id, header),
+    }
+}
+
+fn state(state: &State) -> (String, Color) {
+    match state {
+        State::Connected => ("up".to_owned(), Color::Green),
+        State::Disconnected => ("down".to_owned(), Color::Red),
+        State::Reconnecting { attempt, .. } => (format!("retry {}", attempt), Color::Yellow),
+        State::Partitioned => ("cut".to_owned(), Color::Magenta),
+        State::Left => ("left".to_owned(), Color::DarkGray),
+    }
+}
+
+/// Timeline line of a step
+fn line(entry: &Entry) -> String {
+    let what = match &entry.step {
+        Step::Sent(msg) => chat(msg, true),
+        Step::Delivered(msg) => chat(msg, false),
+        Step::Joined(app_id) => format!("{} joined", app_id),
+        Step::Left(app_id) => format!("{} left", app_id),
+        Step::Transport(change) => format!("{} {}", change.name, state(&change.state).0),
+        Step::Notice(notice) => notice.to_string(),
+    };
+    format!("{:>7.1}s  {}", entry.at_ms as f64 / 1000.0, what)
+}
+
+pub fn run(entries: Vec<Entry>) -> Result<(), Box<dyn Error>> {
+    if entries.is_empty() {
+        return Err("nothing was recorded".into());
+    }
+    let lines: Vec<String> = entries.iter().map(line).collect();
+
+    let stdout = io::stdout().into_raw_mode()?;
+    let stdout = AlternateScreen::from(stdout);
+    let backend = TermionBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+    terminal.hide_cursor()?;
+
+    let mut keys = io::stdin().keys();
+    let last = entries.len() - 1;
+    let mut position = 0;
+    loop {
+        let view = view_at(&entries, position);
+        terminal.draw(|mut f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(
+                    [
+                        Constraint::Length(1),
+                        Constraint::Min(1),
+                        Constraint::Length(1),
+                    ]
+                    .as_ref(),
+                )
+                .split(f.size());
+            let body = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(65), Constraint::Percentage(35)].as_ref())
+                .split(chunks[1]);
+            let side = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                .split(body[1]);
+
+            let mut title = vec![Text::raw(format!(
+                "NetChat replay  step {}/{}",
+                position + 1,
+                entries.len()
+            ))];
+            for change in &view.transports {
+                let (state, color) = state(&change.state);
+                title.push(Text::raw(format!("  {} ", change.name)));
+                title.push(Text::styled(state, Style::default().fg(color)));
+            }
+            Paragraph::new(title.iter())
+                .alignment(Alignment::Center)
+                .render(&mut f, chunks[0]);
+
+            // The current step stays in the middle, the steps to come are dimmed
+            let height = usize::from(body[0].inner(1).height).max(1);
+            let first = position.saturating_sub(height / 2);
+            let timeline = lines.iter().enumerate().skip(first).map(|(i, line)| {
+                let style = match i {
+                    _ if i == position => Style::default().modifier(Modifier::REVERSED),
+                    _ if i > position => Style::default().fg(Color::DarkGray),
+                    _ => Style::default(),
+                };
+                Text::styled(line.as_str(), style)
+            });
+            List::new(timeline)
+                .block(Block::default().borders(Borders::ALL).title(" Timeline "))
+                .render(&mut f, body[0]);
+
+            let mut dates: Vec<_> = view.clock.iter().collect();
+            dates.sort();
+            List::new(
+                dates
+                    .into_iter()
+                    .map(|(app_id, date)| Text::raw(format!("{} {}", app_id, date))),
+            )
+            .block(Block::default().borders(Borders::ALL).title(" Clock "))
+            .render(&mut f, side[0]);
+
+            List::new(view.peers.iter().map(|(app_id, here)| {
+                let (state, color) = if *here {
+                    ("here", Color::Green)
+                } else {
+                    ("left", Color::Red)
+                };
+                Text::styled(format!("{} {}", app_id, state), Style::default().fg(color))
+            }))
+            .block(Block::default().borders(Borders::ALL).title(" Peers "))
+            .render(&mut f, side[1]);
+
+            Paragraph::new(
+                [
+                    Text::styled("Left Right", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(" Step "),
+                    Text::styled("PgUp PgDn", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(format!(" {} steps ", PAGE)),
+                    Text::styled("Home End", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(" First/last "),
+                    Text::styled("q", Style::default().modifier(Modifier::REVERSED)),
+                    Text::raw(" Quit"),
+                ]
+                .iter(),
+            )
+            .render(&mut f, chunks[2]);
+        })?;
+
+        position = match keys.next() {
+            Some(Ok(Key::Right)) | Some(Ok(Key::Down)) => (position + 1).min(last),
+            Some(Ok(Key::Left)) | Some(Ok(Key::Up)) => position.saturating_sub(1),
+            Some(Ok(Key::PageDown)) => (position + PAGE).min(last),
+            Some(Ok(Key::PageUp)) => position.saturating_sub(PAGE),
+            Some(Ok(Key::Home)) => 0,
+            Some(Ok(Key::End)) => last,
+            Some(Ok(Key::Char('q'))) | Some(Ok(Key::Ctrl('c'))) | None => break,
+            Some(Ok(_)) => position,
+            Some(Err(e)) => return Err(e.into()),
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::Header;
+
+    fn entry(at_ms: u64, clock: &[(&str, u64)], step: Step) -> Entry {
+        let clock = clock.iter().map(|(id, d)| ((*id).to_owned(), *d)).collect();
+        Entry {
+            at_ms,
+            clock: Some(Clock(clock)),
+            step,
+        }
+    }
+
+    #[test]
+    fn scrubbing_shows_the_state_at_each_step() {
+        let hi = Msg::new(
+            1,
+            "bob".to_owned(),
+            Header::Public(Channel::default(), "hi".to_owned()),
+            Clock::new("bob".to_owned()),
+        );
+        let cut = Change {
+            name: "a2b".to_owned(),
+            state: State::Partitioned,
+        };
+        let entries = vec![
+            entry(0, &[("alice", 1)], Step::Joined("bob".to_owned())),
+            entry(1500, &[("alice", 2), ("bob", 1)], Step::Delivered(hi)),
+            Entry {
+                at_ms: 2000,
+                clock: None,
+                step: Step::Transport(cut.clone()),
+            },
+            entry(
+                3000,
+                &[("alice", 3), ("bob", 2)],
+                Step::Left("bob".to_owned()),
+            ),
+        ];
+        assert_eq!(line(&entries[1]), "    1.5s  bob: hi");
+
+        let before = view_at(&entries, 1);
+        assert_eq!(before.clock.get("bob"), Some(&1));
+        assert!(before.transports.is_empty());
+        let cut_off = view_at(&entries, 2);
+        assert_eq!(
+            cut_off.clock, before.clock,
+            "transports do not know the clock"
+        );
+        assert_eq!(cut_off.transports, vec![cut]);
+        assert_eq!(view_at(&entries, 3).peers.get("bob"), Some(&false));
+        // Scrubbing back gives the same state again
+        assert_eq!(view_at(&entries, 1), before);
+    }
+}
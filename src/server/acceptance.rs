@@ -0,0 +1,276 @@
+//! `--accept-files` and `/files policy`: what becomes of a file once received.
+//! Executables are always refused. Otherwise a file signed by the key first
+//! seen for its sender, trusted on first use rather than confirmed, and under
+//! `--auto-accept-below` goes to the downloads, and the others wait in the
+//! quarantine directory until the user accepts or rejects them. A policy set
+//! for a contact wins over the one of `--accept-files`, and is saved.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::app::AppId;
+
+/// Names accepted by [`Policy::by_name`]
+pub const POLICIES: &[&str] = &["auto", "prompt", "reject"];
+
+/// Extensions of the files run when opened, refused whoever sends them
+const EXECUTABLES: &[&str] = &[
+    "app", "apk", "bash", "bat", "cmd", "com", "command", "cpl", "dll", "dylib", "exe", "hta",
+    "jar", "js", "jse", "msi", "pif", "ps1", "run", "scr", "sh", "so", "vbe", "vbs", "wsf",
+];
+
+/// First bytes of ELF, PE, Mach-O and fat Mach-O binaries, and of scripts
+const MAGICS: &[&[u8]] = &[
+    b"\x7fELF",
+    b"MZ",
+    b"\xfe\xed\xfa\xce",
+    b"\xfe\xed\xfa\xcf",
+    b"\xce\xfa\xed\xfe",
+    b"\xcf\xfa\xed\xfe",
+    b"\xca\xfe\xba\xbe",
+    b"#!",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Accepted when signed by the key first seen for the sender and under the
+    /// size limit, quarantined otherwise
+    Auto,
+    /// Always quarantined, for the user to accept or reject
+    Prompt,
+    /// Always refused
+    Reject,
+}
+
+impl Policy {
+    /// The policy called `name`, one of [`POLICIES`]
+    pub fn by_name(name: &str) -> Option<Policy> {
+        match name {
+            "auto" => Some(Policy::Auto),
+            "prompt" => Some(Policy::Prompt),
+            "reject" => Some(Policy::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Policy::Auto => "auto",
+            Policy::Prompt => "prompt",
+            Policy::Reject => "reject",
+        }
+    }
+}
+
+/// What becomes of a file received
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Written in the downloads
+    Accept,
+    /// Written in the quarantine directory, with why
+    Quarantine(String),
+    /// Dropped, with why
+    Reject(String),
+}
+
+/// Whether a file called `name` which starts with `data` runs once opened
+pub fn is_executable(name: &str, data: &[u8]) -> bool {
+    let extension = Path::new(name)
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase());
+    extension.is_some_and(|e| EXECUTABLES.contains(&e.as_str()))
+        || MAGICS.iter().any(|magic| data.starts_with(magic))
+}
+
+pub struct Acceptance {
+    /// For the senders without a policy of their own
+    pub policy: Policy,
+    /// Bytes of the largest file accepted without asking
+    pub max_auto_len: usize,
+    per_contact: BTreeMap<AppId, Policy>,
+    path: Option<PathBuf>,
+}
+
+impl Default for Acceptance {
+    fn default() -> Acceptance {
+        Acceptance {
+            policy: Policy::Auto,
+            max_auto_len: 4 * 1024 * 1024,
+            per_contact: BTreeMap::new(),
+            path: None,
+        }
+    }
+}
+
+impl Acceptance {
+    /// Reads the policies of the contacts saved in `path`, they are saved
+    /// there when changed
+    pub fn load(path: PathBuf) -> Self {
+        let per_contact = fs::read_to_string(&path)
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Acceptance {
+            per_contact,
+            path: Some(path),
+            ..Acceptance::default()
+        }
+    }
+
+    /// Applies `policy` to the files of `app_id`, an app or an identity, the
+    /// one of `--accept-files` again for None
+    pub fn set(&mut self, app_id: &str, policy: Option<Policy>) {
+        match policy {
+            Some(policy) => self.per_contact.insert(app_id.to_owned(), policy),
+            None => self.per_contact.remove(app_id),
+        };
+        self.save();
+    }
+
+    /// Policy of the files of `app_id`, a device of `identity`
+    pub fn policy_of(&self, app_id: &str, identity: &str) -> Policy {
+        self.per_contact
+            .get(app_id)
+            .or_else(|| self.per_contact.get(identity))
+            .copied()
+            .unwrap_or(self.policy)
+    }
+
+    /// Why the file `name` of `app_id` is refused from its first chunk on, out
+    /// of its name only
+    pub fn screen(&self, app_id: &str, identity: &str, name: &str) -> Result<(), String> {
+        if is_executable(name, &[]) {
+            return Err("executables are refused".to_owned());
+        }
+        match self.policy_of(app_id, identity) {
+            Policy::Reject => Err(format!("the files of {} are refused", app_id)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the file `name` of `size` bytes `app_id` offers is fetched
+    /// without asking, as it would be accepted
+    pub fn fetches(
+        &self,
+        app_id: &str,
+        identity: &str,
+        verified: bool,
+        name: &str,
+        size: u64,
+    ) -> bool {
+        self.screen(app_id, identity, name).is_ok()
+            && self.policy_of(app_id, identity) == Policy::Auto
+            && verified
+            && size <= self.max_auto_len as u64
+    }
+
+    /// What becomes of the file `name` of `app_id`, whose whole content is
+    /// `data`, `verified` if it was signed by the key first seen for `app_id`
+    pub fn decide(
+        &self,
+        app_id: &str,
+        identity: &str,
+        verified: bool,
+        name: &str,
+        data: &[u8],
+    ) -> Decision {
+        if is_executable(name, data) {
+            return Decision::Reject("executables are refused".to_owned());
+        }
+        match self.policy_of(app_id, identity) {
+            Policy::Reject => Decision::Reject(format!("the files of {} are refused", app_id)),
+            Policy::Prompt => Decision::Quarantine(format!("the files of {} wait for you", app_id)),
+            Policy::Auto if !verified => {
+                Decision::Quarantine(format!("{} signed it with no key we know", app_id))
+            }
+            Policy::Auto if data.len() > self.max_auto_len => Decision::Quarantine(format!(
+                "it is over the {} bytes accepted without asking",
+                self.max_auto_len
+            )),
+            Policy::Auto => Decision::Accept,
+        }
+    }
+
+    /// Each contact with a policy of its own
+    pub fn iter(&self) -> impl Iterator<Item = (&AppId, Policy)> {
+        self.per_contact
+            .iter()
+            .map(|(app_id, policy)| (app_id, *policy))
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            let saved = serde_json::to_string(&self.per_contact)
+                .map_err(|e| e.to_string())
+                .and_then(|json| fs::write(path, json).map_err(|e| e.to_string()));
+            if let Err(e) = saved {
+                log::error!("Could not save the file policies: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_accepted_quarantined_or_refused() {
+        let path = std::env::temp_dir().join(format!("netchat-test-{}.accept", std::process::id()));
+        let mut acceptance = Acceptance::load(path.clone());
+        acceptance.max_auto_len = 10;
+        let decide = |acceptance: &Acceptance, verified, name, data: &[u8]| {
+            acceptance.decide("alice", "alice", verified, name, data)
+        };
+        assert_eq!(
+            decide(&acceptance, true, "notes.txt", b"hi"),
+            Decision::Accept
+        );
+        assert!(matches!(
+            decide(&acceptance, false, "notes.txt", b"hi"),
+            Decision::Quarantine(why) if why.contains("no key we know")
+        ));
+        assert!(matches!(
+            decide(&acceptance, true, "notes.txt", b"more than ten bytes"),
+            Decision::Quarantine(_)
+        ));
+        for (name, data) in [
+            ("setup.EXE", b"hi".as_ref()),
+            ("notes.txt", b"\x7fELF\x02"),
+            ("notes.txt", b"#!/bin/sh\nrm -rf ~"),
+        ] {
+            assert!(
+                matches!(decide(&acceptance, true, name, data), Decision::Reject(_)),
+                "{} is refused",
+                name
+            );
+        }
+        assert!(acceptance.screen("alice", "alice", "run.sh").is_err());
+        // As written, once sanitized
+        for name in ["run.bat.", "run.ps1 ", "x.j\u{202e}s"] {
+            let name = crate::server::files::sanitize(name);
+            assert!(
+                acceptance.screen("alice", "alice", &name).is_err(),
+                "{}",
+                name
+            );
+        }
+        assert!(acceptance.fetches("alice", "alice", true, "notes.txt", 10));
+        assert!(!acceptance.fetches("alice", "alice", true, "notes.txt", 11));
+        assert!(!acceptance.fetches("alice", "alice", false, "notes.txt", 10));
+
+        // Saved, and set for an identity of several devices
+        acceptance.set("alice", Some(Policy::Reject));
+        let mut acceptance = Acceptance::load(path.clone());
+        assert!(acceptance.screen("alice", "alice", "notes.txt").is_err());
+        acceptance.set("alice", None);
+        acceptance.set("ops", Some(Policy::Prompt));
+        assert_eq!(acceptance.policy_of("phone", "ops"), Policy::Prompt);
+        assert_eq!(acceptance.policy_of("bob", "bob"), Policy::Auto);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,181 @@
+//! Acknowledgements of chat messages, and retransmission of those nobody
+//! acknowledged: a pipe drops what is written while no one reads it
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+use super::messages::{Header, Msg, MsgId};
+use crate::app::AppId;
+
+/// A duplicate coming this long after the first copy is a retransmission, our
+/// acknowledgement was lost: relayed copies come right after each other
+const REACK_AFTER: Duration = Duration::from_secs(1);
+
+/// Acknowledged messages remembered above this, the oldest are forgotten
+const MAX_ACKED: usize = 4096;
+
+struct Pending {
+    msg: Msg,
+    what: String,             // Shown if it is never acknowledged
+    recipient: Option<AppId>, // The only one who may acknowledge a private message
+    attempts: u32,
+    due: Instant,
+}
+
+/// Chat messages sent and not acknowledged yet, and those received and acknowledged
+pub struct Retransmissions {
+    attempts: u32,
+    timeout: Duration,
+    pending: BTreeMap<MsgId, Pending>,
+    acked: HashMap<MsgId, Instant>, // When each was last acknowledged
+}
+
+impl Retransmissions {
+    /// Sends messages again after `timeout`, `attempts` times at most, 0 never
+    pub fn new(attempts: u32, timeout: Duration) -> Self {
+        Retransmissions {
+            attempts,
+            timeout,
+            pending: BTreeMap::new(),
+            acked: HashMap::new(),
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        (self.attempts > 0).then_some(self.timeout)
+    }
+
+    /// How many messages wait for an acknowledgement
+    pub fn waiting(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Waits for an acknowledgement of `msg`, described by `what`
+    pub fn track(&mut self, msg: &Msg, what: String, now: Instant) {
+        if self.attempts == 0 {
+            return;
+        }
+        let recipient = match &msg.header {
+            Header::Private(app_id, _)
+            | Header::Encrypted(app_id, _)
+            | Header::File { to: app_id, .. }
+            | Header::EncryptedFile { to: app_id, .. } => Some(app_id.clone()),
+            _ => None,
+        };
+        self.pending.insert(
+            msg.id,
+            Pending {
+                msg: msg.clone(),
+                what,
+                recipient,
+                attempts: 0,
+                due: now + self.timeout,
+            },
+        );
+    }
+
+    /// Takes an acknowledgement of `id` by `from`, a device of `identity`
+    pub fn ack(&mut self, id: MsgId, from: &str, identity: &str) {
+        let by_recipient = |p: &Pending| {
+            p.recipient
+                .as_deref()
+                .is_none_or(|to| to == from || to == identity)
+        };
+        if self.pending.get(&id).is_some_and(by_recipient) {
+            self.pending.remove(&id);
+        }
+    }
+
+    /// Messages to send again, and the description of those given up on
+    pub fn due(&mut self, now: Instant) -> (Vec<Msg>, Vec<String>) {
+        let (mut again, mut failed) = (Vec::new(), Vec::new());
+        let (attempts, timeout) = (self.attempts, self.timeout);
+        self.pending.retain(|_, pending| {
+            if pending.due > now {
+                true
+            } else if pending.attempts < attempts {
+                pending.attempts += 1;
+                pending.due = now + timeout;
+                again.push(pending.msg.clone());
+                true
+            } else {
+                failed.push(std::mem::take(&mut pending.what));
+                false
+            }
+        });
+        (again, failed)
+    }
+
+    /// Whether to acknowledge a copy of message `id`, already acknowledged,
+    /// received at `now`, false for those never acknowledged
+    pub fn should_ack_again(&mut self, id: MsgId, now: Instant) -> bool {
+        self.acked.contains_key(&id) && self.should_ack(id, now)
+    }
+
+    /// Whether to acknowledge message `id` received at `now`: the first time,
+    /// and again for a retransmission
+    pub fn should_ack(&mut self, id: MsgId, now: Instant) -> bool {
+        if self.acked.len() > MAX_ACKED {
+            let oldest = self.acked.values().min().copied();
+            self.acked.retain(|_, at| Some(*at) != oldest);
+        }
+        match self.acked.get(&id) {
+            Some(at) if now.duration_since(*at) < REACK_AFTER => false,
+            _ => {
+                self.acked.insert(id, now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::Channel;
+    use crate::server::Clock;
+
+    fn msg(id: MsgId, header: Header) -> Msg {
+        Msg::new(
+            id,
+            "alice".to_owned(),
+            header,
+            Clock::new("alice".to_owned()),
+        )
+    }
+
+    #[test]
+    fn unacknowledged_messages_are_sent_again_then_given_up() {
+        let (start, second) = (Instant::now(), Duration::from_secs(1));
+        let mut sent = Retransmissions::new(2, second);
+        sent.track(
+            &msg(1, Header::Public(Channel::default(), "hi".to_owned())),
+            "hi".to_owned(),
+            start,
+        );
+        let private = Header::Private("bob".to_owned(), "psst".to_owned());
+        sent.track(&msg(2, private), "to bob: psst".to_owned(), start);
+
+        // Only the recipient acknowledges a private message
+        sent.ack(2, "carol", "carol");
+        assert_eq!(sent.due(start).0.len(), 0);
+        let (again, failed) = sent.due(start + second);
+        assert_eq!(again.len(), 2);
+        assert!(failed.is_empty());
+
+        sent.ack(1, "carol", "carol");
+        assert_eq!(sent.due(start + 2 * second).0.len(), 1);
+        let (again, failed) = sent.due(start + 3 * second);
+        assert!(again.is_empty());
+        assert_eq!(failed, vec!["to bob: psst".to_owned()]);
+    }
+
+    #[test]
+    fn retransmissions_are_acknowledged_again() {
+        let now = Instant::now();
+        let mut received = Retransmissions::new(0, Duration::from_secs(5));
+        assert!(received.should_ack(1, now));
+        assert!(!received.should_ack(1, now + Duration::from_millis(10)));
+        assert!(received.should_ack(1, now + REACK_AFTER));
+    }
+}
@@ -0,0 +1,121 @@
+//! What the server reads from and writes to: a pair of named pipes, or TCP
+//! connections, see [`tcp`](super::tcp)
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use super::crypto::FrameKey;
+use super::output::Output;
+use super::tcp::Socket;
+use super::unix;
+
+/// Someone at the other end of an input
+pub struct Peer {
+    /// Used to refer to it in the UI, messages read from it are not written
+    /// back to its `writer`
+    pub name: String,
+    pub reader: Box<dyn Read + Send>,
+    /// Way back to this peer, for connections which go both ways. Dropped
+    /// once broken, the next connection brings a new one.
+    pub writer: Option<Box<dyn Transport>>,
+}
+
+/// Where the messages of the other apps are read from
+pub trait Input: Send {
+    /// Name used to refer to it in the UI
+    fn name(&self) -> String;
+    /// Blocks until someone is at the other end
+    fn open(&mut self) -> io::Result<Peer>;
+    /// Whether more peers can come while one is read, each is then read on
+    /// its own thread
+    fn concurrent(&self) -> bool {
+        false
+    }
+}
+
+/// Where the messages are written, reconnected by the
+/// [`ReconnectManager`](super::reconnect::ReconnectManager) once broken
+pub trait Transport: Send {
+    /// Name used to refer to it in the UI
+    fn name(&self) -> String;
+    /// Writes a whole line at once, `frame` ends with its newline
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()>;
+    /// Tries to write again without blocking, fails while no one is there
+    fn reconnect(&mut self) -> io::Result<()>;
+}
+
+/// Name of the file at `path`, used to refer to a pipe in the UI
+pub fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Input pipe, opened again each time its writer goes away
+pub struct PipeInput(pub PathBuf);
+
+impl Input for PipeInput {
+    fn name(&self) -> String {
+        file_name(&self.0)
+    }
+
+    /// The output pipe may lead elsewhere, as in a ring, so nothing is
+    /// known of the way back
+    fn open(&mut self) -> io::Result<Peer> {
+        Ok(Peer {
+            name: self.name(),
+            reader: Box::new(File::open(&self.0)?),
+            writer: None,
+        })
+    }
+}
+
+/// What the server is started with, several of them make it a node of a mesh
+pub enum Endpoint {
+    Pipes { input: PathBuf, output: PathBuf },
+    Tcp(Socket),
+    Unix(unix::Socket),
+}
+
+impl Endpoint {
+    pub fn input(&self) -> io::Result<Box<dyn Input>> {
+        Ok(match self {
+            Endpoint::Pipes { input, .. } => Box::new(PipeInput(input.clone())),
+            Endpoint::Tcp(socket) => Box::new(socket.input()?),
+            Endpoint::Unix(socket) => Box::new(socket.input()),
+        })
+    }
+
+    /// Output opened on start, opening a pipe blocks until someone reads it.
+    /// TCP and Unix socket connections bring their output with them.
+    pub fn output(&self) -> io::Result<Option<Box<dyn Transport>>> {
+        Ok(match self {
+            Endpoint::Pipes { output, .. } => Some(Box::new(Output::open(output.clone())?)),
+            Endpoint::Tcp(_) | Endpoint::Unix(_) => None,
+        })
+    }
+}
+
+/// Seals every frame written with a pre-shared key, only apps holding it can
+/// read them
+pub struct Sealed {
+    pub inner: Box<dyn Transport>,
+    pub key: FrameKey,
+}
+
+impl Transport for Sealed {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let line = self.key.seal(frame.strip_suffix(b"\n").unwrap_or(frame));
+        self.inner.write_frame(format!("{}\n", line).as_bytes())
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        self.inner.reconnect()
+    }
+}
@@ -0,0 +1,232 @@
+//! Content of the files received, each kept once under its hash whatever the
+//! channel or sender it came from. Downloads and quarantine hold copies of
+//! the blobs, so editing one changes neither the blob nor the other copies;
+//! a blob is read-only, and checked against its name when read back, to be
+//! copied again or served to another app. Next to each blob, `<hash>.copies`
+//! lists where it was copied, it goes away with the last copy deleted.
+
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use super::crypto::{sha512, to_hex};
+
+/// Name of the blob of `data`: the first 32 bytes of its SHA-512, hex encoded
+pub fn hash_of(data: &[u8]) -> String {
+    to_hex(&sha512(data)[..32])
+}
+
+/// The first characters of `hash`, enough to tell files apart when shown
+pub fn short(hash: &str) -> &str {
+    &hash[..hash.len().min(12)]
+}
+
+/// Whether `hash` is one [`hash_of`] returns, and so a blob name
+fn is_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[derive(Clone)]
+pub struct Blobs {
+    dir: PathBuf,
+}
+
+impl Blobs {
+    /// Keeps the blobs in `dir`, created on the first one
+    pub fn new(dir: PathBuf) -> Blobs {
+        Blobs { dir }
+    }
+
+    /// Where the blob `hash` is kept, in a directory named after its first
+    /// two characters
+    pub fn path(&self, hash: &str) -> Option<PathBuf> {
+        match is_hash(hash) {
+            true => Some(self.dir.join(&hash[..2]).join(hash)),
+            false => None,
+        }
+    }
+
+    pub fn has(&self, hash: &str) -> bool {
+        self.path(hash).is_some_and(|path| path.is_file())
+    }
+
+    /// Keeps `data`, unless a blob of the same content was kept already, and
+    /// returns its hash
+    pub fn put(&self, data: &[u8]) -> io::Result<String> {
+        let hash = hash_of(data);
+        let path = self.path(&hash).expect("a hash");
+        // One changed since is replaced
+        if self.get(&hash).is_ok() {
+            return Ok(hash);
+        }
+        let dir = path.parent().expect("in a directory");
+        fs::create_dir_all(dir)?;
+        // Renamed once whole, a blob is never seen half written
+        let written = dir.join(format!(".{}.{}", hash, std::process::id()));
+        let _ = fs::remove_file(&written);
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o400)
+            .open(&written)?
+            .write_all(data)?;
+        fs::rename(&written, &path)?;
+        Ok(hash)
+    }
+
+    /// Content of the blob `hash`, an error if it is not what was kept
+    pub fn get(&self, hash: &str) -> io::Result<Vec<u8>> {
+        let path = self
+            .path(hash)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a blob hash"))?;
+        let data = fs::read(path)?;
+        if hash_of(&data) != hash {
+            let e = format!("the blob {} was changed", hash);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        Ok(data)
+    }
+
+    /// Copies the blob `hash` in `dir` under `name`, next to an existing file
+    /// of the same name rather than over it, and returns where
+    pub fn copy(&self, hash: &str, dir: &Path, name: &str) -> io::Result<PathBuf> {
+        let data = self.get(hash)?;
+        fs::create_dir_all(dir)?;
+        let mut path = dir.join(name);
+        for n in 1.. {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut copy) => {
+                    copy.write_all(&data)?;
+                    break;
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    path = dir.join(format!("{}.{}", name, n));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let mut copies = self.copies(hash);
+        copies.push(path.clone());
+        self.set_copies(hash, &copies)?;
+        Ok(path)
+    }
+
+    /// Deletes the file at `path`, and its blob once no other copy of it is
+    /// left
+    pub fn remove(&self, path: &Path) -> io::Result<()> {
+        let hash = hash_of(&fs::read(path)?);
+        fs::remove_file(path)?;
+        let copies: Vec<PathBuf> = self
+            .copies(&hash)
+            .into_iter()
+            .filter(|copy| copy != path)
+            .collect();
+        match (copies.is_empty(), self.path(&hash)) {
+            (true, Some(blob)) if blob.is_file() => {
+                fs::remove_file(blob)?;
+                self.set_copies(&hash, &copies)
+            }
+            // Edited, or not copied from the store
+            _ if copies.is_empty() => Ok(()),
+            _ => self.set_copies(&hash, &copies),
+        }
+    }
+
+    /// Where the blob `hash` was copied, those deleted since left out
+    fn copies(&self, hash: &str) -> Vec<PathBuf> {
+        let listed = self
+            .path(hash)
+            .and_then(|blob| fs::read(blob.with_extension("copies")).ok())
+            .unwrap_or_default();
+        listed
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| PathBuf::from(OsStr::from_bytes(line)))
+            .filter(|copy| copy.is_file())
+            .collect()
+    }
+
+    fn set_copies(&self, hash: &str, copies: &[PathBuf]) -> io::Result<()> {
+        let listed = match self.path(hash) {
+            Some(blob) => blob.with_extension("copies"),
+            None => return Ok(()),
+        };
+        if copies.is_empty() {
+            return match fs::remove_file(listed) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+        let mut lines = Vec::new();
+        for copy in copies {
+            lines.extend_from_slice(copy.as_os_str().as_bytes());
+            lines.push(b'\n');
+        }
+        fs::write(listed, lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_same_content_is_kept_once() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("netchat-test-{}-blobs", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let blobs = Blobs::new(dir.join("blobs"));
+        let hash = blobs.put(b"report").unwrap();
+        assert_eq!(hash, hash_of(b"report"));
+        assert_eq!(blobs.put(b"report").unwrap(), hash);
+        assert!(blobs.has(&hash));
+        assert!(blobs.path("../../etc/passwd").is_none());
+        let blob = blobs.path(&hash).unwrap();
+        let mode = fs::metadata(&blob).unwrap().permissions().mode() & 0o777;
+
+        // Received from two channels, then one is edited
+        let first = blobs.copy(&hash, &dir.join("downloads"), "r.txt").unwrap();
+        let again = blobs.copy(&hash, &dir.join("downloads"), "r.txt").unwrap();
+        fs::write(&again, b"changed").unwrap();
+        let untouched = (blobs.get(&hash).unwrap(), fs::read(&first).unwrap());
+
+        blobs.remove(&first).unwrap();
+        let edited_kept = blob.is_file();
+
+        // Kept while a copy is left
+        let notes = blobs.put(b"notes").unwrap();
+        let notes_blob = blobs.path(&notes).unwrap();
+        let one = blobs.copy(&notes, &dir.join("downloads"), "n.txt").unwrap();
+        let other = blobs
+            .copy(&notes, &dir.join("quarantine"), "n.txt")
+            .unwrap();
+        blobs.remove(&other).unwrap();
+        let kept = notes_blob.is_file();
+        blobs.remove(&one).unwrap();
+        let gone = !notes_blob.is_file() && !notes_blob.with_extension("copies").exists();
+
+        // Changed in the store anyway
+        let notes = blobs.put(b"notes").unwrap();
+        let notes_blob = blobs.path(&notes).unwrap();
+        fs::set_permissions(&notes_blob, fs::Permissions::from_mode(0o600)).unwrap();
+        fs::write(&notes_blob, b"forged").unwrap();
+        let read = blobs.get(&notes);
+        let copied = blobs.copy(&notes, &dir.join("downloads"), "f.txt");
+        blobs.put(b"notes").unwrap();
+        let restored = blobs.get(&notes);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(mode, 0o400, "read-only");
+        assert_eq!(again, dir.join("downloads").join("r.txt.1"));
+        assert_eq!(untouched, (b"report".to_vec(), b"report".to_vec()));
+        assert!(edited_kept, "an edited copy is still one");
+        assert!(kept, "still copied in the downloads");
+        assert!(gone, "copied nowhere");
+        assert_eq!(read.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(copied.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(restored.unwrap(), b"notes");
+    }
+}
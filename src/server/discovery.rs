@@ -0,0 +1,367 @@
+//! LAN discovery over mDNS (RFC 6762 and 6763): apps started with
+//! `--discover` ask the link for the `_netchat._tcp` service every
+//! [`BROWSE_INTERVAL`], and those listening over TCP answer with their id,
+//! address and port. Only the few records that takes are read and written.
+
+use std::convert::TryInto;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::FromRawFd;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::events::Event;
+use crate::app::AppId;
+
+const GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const PORT: u16 = 5353;
+
+/// What is browsed and advertised
+const SERVICE: &str = "_netchat._tcp.local";
+
+/// Apps are asked for again this often, to find those started since
+pub const BROWSE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Seconds the others may remember our records
+const TTL: u32 = 120;
+
+const A: u16 = 1;
+const PTR: u16 = 12;
+const TXT: u16 = 16;
+const SRV: u16 = 33;
+const IN: u16 = 1;
+/// Set on the class of the records only we have
+const CACHE_FLUSH: u16 = 0x8000;
+const RESPONSE: u16 = 0x8400;
+
+/// Compression pointers followed in a name at most, loops are cut there
+const MAX_JUMPS: usize = 16;
+
+/// What we advertise: our id, where we listen
+pub struct Advert {
+    pub app_id: AppId,
+    pub addr: SocketAddrV4,
+}
+
+impl Advert {
+    fn instance(&self) -> String {
+        format!("{}.{}", self.app_id, SERVICE)
+    }
+
+    fn host(&self) -> String {
+        format!("{}-netchat.local", self.app_id)
+    }
+}
+
+/// A query for the apps offering the service
+fn query() -> Vec<u8> {
+    let mut packet = header(0, 1, 0);
+    name(SERVICE, &mut packet);
+    packet.extend_from_slice(&PTR.to_be_bytes());
+    packet.extend_from_slice(&IN.to_be_bytes());
+    packet
+}
+
+/// The answer telling `advert`
+fn response(advert: &Advert) -> Vec<u8> {
+    let mut packet = header(RESPONSE, 0, 4);
+    let (instance, host) = (advert.instance(), advert.host());
+
+    record(SERVICE, PTR, IN, &mut packet, |data| name(&instance, data));
+    record(&instance, SRV, IN | CACHE_FLUSH, &mut packet, |data| {
+        data.extend_from_slice(&[0, 0, 0, 0]); // Priority and weight
+        data.extend_from_slice(&advert.addr.port().to_be_bytes());
+        name(&host, data);
+    });
+    record(&instance, TXT, IN | CACHE_FLUSH, &mut packet, |data| {
+        let id = format!("id={}", advert.app_id);
+        data.push(id.len().min(255) as u8);
+        data.extend_from_slice(&id.as_bytes()[..id.len().min(255)]);
+    });
+    record(&host, A, IN | CACHE_FLUSH, &mut packet, |data| {
+        data.extend_from_slice(&advert.addr.ip().octets())
+    });
+    packet
+}
+
+fn header(flags: u16, questions: u16, answers: u16) -> Vec<u8> {
+    let mut packet = vec![0, 0];
+    for field in [flags, questions, answers, 0, 0].iter() {
+        packet.extend_from_slice(&field.to_be_bytes());
+    }
+    packet
+}
+
+fn name(name: &str, out: &mut Vec<u8>) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend_from_slice(label);
+    }
+    out.push(0);
+}
+
+fn record(owner: &str, kind: u16, class: u16, out: &mut Vec<u8>, data: impl FnOnce(&mut Vec<u8>)) {
+    name(owner, out);
+    out.extend_from_slice(&kind.to_be_bytes());
+    out.extend_from_slice(&class.to_be_bytes());
+    out.extend_from_slice(&TTL.to_be_bytes());
+    let len_at = out.len();
+    out.extend_from_slice(&[0, 0]);
+    data(out);
+    let len = (out.len() - len_at - 2) as u16;
+    out[len_at..len_at + 2].copy_from_slice(&len.to_be_bytes());
+}
+
+/// Reads the names and records of a packet, None once it is malformed
+struct Reader<'a> {
+    packet: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.packet.get(self.at..self.at + len)?;
+        self.at += len;
+        Some(bytes)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// A name from here, lowercase, following the compression pointers
+    fn name(&mut self) -> Option<String> {
+        let mut labels = Vec::new();
+        let (mut at, mut end) = (self.at, None);
+        for _ in 0..MAX_JUMPS {
+            loop {
+                let len = *self.packet.get(at)? as usize;
+                match len {
+                    0 => {
+                        self.at = end.unwrap_or(at + 1);
+                        return Some(labels.join(".").to_lowercase());
+                    }
+                    len if len & 0xc0 == 0xc0 => {
+                        let low = *self.packet.get(at + 1)? as usize;
+                        end.get_or_insert(at + 2);
+                        at = (len & 0x3f) << 8 | low;
+                        break;
+                    }
+                    len => {
+                        let label = self.packet.get(at + 1..at + 1 + len)?;
+                        labels.push(String::from_utf8_lossy(label).into_owned());
+                        at += 1 + len;
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+/// What a packet tells: whether it asks for the service, and the apps it
+/// answers with, by id
+#[derive(Debug, Default, PartialEq)]
+struct Heard {
+    asks: bool,
+    apps: Vec<(AppId, SocketAddr)>,
+}
+
+fn read(packet: &[u8]) -> Option<Heard> {
+    let mut reader = Reader { packet, at: 0 };
+    let _id = reader.u16()?;
+    let flags = reader.u16()?;
+    let questions = reader.u16()?;
+    let records: u16 = [reader.u16()?, reader.u16()?, reader.u16()?].iter().sum();
+    let mut heard = Heard::default();
+    for _ in 0..questions {
+        let asked = reader.name()?;
+        let kind = reader.u16()?;
+        reader.u16()?;
+        heard.asks |= flags & 0x8000 == 0 && asked == SERVICE.to_lowercase() && kind == PTR;
+    }
+
+    let (mut instances, mut services, mut hosts) = (Vec::new(), Vec::new(), Vec::new());
+    let mut ids = Vec::new();
+    for _ in 0..records {
+        let owner = reader.name()?;
+        let kind = reader.u16()?;
+        reader.bytes(6)?; // Class and ttl
+        let len = reader.u16()? as usize;
+        let end = reader.at + len;
+        match kind {
+            PTR if owner == SERVICE.to_lowercase() => instances.push(reader.name()?),
+            SRV => {
+                reader.bytes(4)?;
+                let port = reader.u16()?;
+                services.push((owner, port, reader.name()?));
+            }
+            TXT => {
+                let mut at = reader.at;
+                while at < end {
+                    let text_len = *packet.get(at)? as usize;
+                    let text = packet.get(at + 1..(at + 1 + text_len).min(end))?;
+                    if let Some(id) = text.strip_prefix(b"id=") {
+                        ids.push((owner.clone(), String::from_utf8_lossy(id).into_owned()));
+                    }
+                    at += 1 + text_len;
+                }
+            }
+            A if len == 4 => {
+                let ip: [u8; 4] = reader.bytes(4)?.try_into().ok()?;
+                hosts.push((owner, Ipv4Addr::from(ip)));
+            }
+            _ => {}
+        }
+        reader.at = end;
+    }
+    let suffix = format!(".{}", SERVICE.to_lowercase());
+    for instance in instances {
+        // Names are read lowercase, the id as it was given
+        let app_id = match ids.iter().find(|(owner, _)| *owner == instance) {
+            Some((_, id)) => id.clone(),
+            None => match instance.strip_suffix(&suffix) {
+                Some(app_id) => app_id.to_owned(),
+                None => continue,
+            },
+        };
+        let service = services.iter().find(|(owner, ..)| *owner == instance);
+        if let Some((_, port, target)) = service {
+            if let Some((_, ip)) = hosts.iter().find(|(host, _)| host == target) {
+                heard
+                    .apps
+                    .push((app_id, SocketAddrV4::new(*ip, *port).into()));
+            }
+        }
+    }
+    Some(heard)
+}
+
+/// The mDNS port, shared with the other responders of the host
+fn bind() -> io::Result<UdpSocket> {
+    // SO_REUSEADDR must be set before binding, which std cannot do
+    let socket = unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let on: libc::c_int = 1;
+        for option in [libc::SO_REUSEADDR, libc::SO_REUSEPORT].iter() {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                *option,
+                &on as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            );
+        }
+        let addr = libc::sockaddr_in {
+            sin_family: libc::AF_INET as libc::sa_family_t,
+            sin_port: PORT.to_be(),
+            sin_addr: libc::in_addr { s_addr: 0 },
+            sin_zero: [0; 8],
+        };
+        let bound = libc::bind(
+            fd,
+            &addr as *const _ as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        );
+        if bound < 0 {
+            let e = io::Error::last_os_error();
+            libc::close(fd);
+            return Err(e);
+        }
+        UdpSocket::from_raw_fd(fd)
+    };
+    socket.join_multicast_v4(&GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    Ok(socket)
+}
+
+/// The address of this host on the link, the one packets to the group leave from
+pub fn local_ip() -> io::Result<Ipv4Addr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((GROUP, PORT))?;
+    match socket.local_addr()? {
+        SocketAddr::V4(addr) => Ok(*addr.ip()),
+        SocketAddr::V6(_) => Err(io::ErrorKind::AddrNotAvailable.into()),
+    }
+}
+
+/// Browses for the others, and answers for `advert`, on a thread of its own.
+/// Every app found, us left out, is sent to the server as `Discovered`.
+pub fn spawn(advert: Option<Advert>, own_id: AppId, tx: mpsc::Sender<Event>) -> io::Result<()> {
+    let socket = bind()?;
+    socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let group = SocketAddr::from((GROUP, PORT));
+    thread::spawn(move || {
+        let mut browsed: Option<Instant> = None;
+        let mut buf = [0; 9000];
+        loop {
+            if browsed.is_none_or(|at| at.elapsed() >= BROWSE_INTERVAL) {
+                browsed = Some(Instant::now());
+                if let Err(e) = socket.send_to(&query(), group) {
+                    log::warn!("Could not ask the link for other apps: {}", e);
+                }
+                if let Some(advert) = &advert {
+                    let _ = socket.send_to(&response(advert), group);
+                }
+            }
+            let len = match socket.recv_from(&mut buf) {
+                Ok((len, _)) => len,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => {
+                    log::error!("Discovery stopped: {}", e);
+                    break;
+                }
+            };
+            let heard = match read(&buf[..len]) {
+                Some(heard) => heard,
+                None => continue,
+            };
+            if let (true, Some(advert)) = (heard.asks, &advert) {
+                let _ = socket.send_to(&response(advert), group);
+            }
+            for (app_id, addr) in heard.apps {
+                if app_id != own_id && tx.send(Event::Discovered(app_id, addr)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adverts_are_read_back() {
+        let asked = read(&query()).unwrap();
+        assert!(asked.asks);
+        assert!(asked.apps.is_empty());
+
+        let advert = Advert {
+            app_id: "Bob".to_owned(),
+            addr: SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 20), 4000),
+        };
+        let heard = read(&response(&advert)).unwrap();
+        assert!(!heard.asks, "a response asks nothing");
+        let addr: SocketAddr = "192.168.1.20:4000".parse().unwrap();
+        assert_eq!(heard.apps, [("Bob".to_owned(), addr)]);
+
+        // Names compressed by another responder
+        let mut packet = header(RESPONSE, 0, 1);
+        name(SERVICE, &mut packet);
+        packet.extend_from_slice(&[0, 12, 0, 1, 0, 0, 0, 120, 0, 8]);
+        packet.extend_from_slice(&[5, b'c', b'a', b'r', b'o', b'l', 0xc0]);
+        packet.push(12);
+        assert!(read(&packet).unwrap().apps.is_empty(), "no address given");
+        let looping = [&header(RESPONSE, 0, 1)[..], &[0xc0, 12]].concat();
+        assert_eq!(read(&looping), None);
+        assert_eq!(read(&response(&advert)[..40]), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Who wrote the public messages seen, ours included: an `Edit` or a `Delete`
+//! is only taken from the sender of the message it changes
+
+use std::collections::{HashMap, VecDeque};
+
+use super::messages::{Header, Msg, MsgId};
+use crate::app::AppId;
+
+/// Messages kept above this, the oldest are forgotten and cannot be changed
+const MAX_KEPT: usize = 4096;
+
+#[derive(Default)]
+pub struct Authors {
+    of: HashMap<MsgId, AppId>,
+    order: VecDeque<MsgId>, // Oldest first
+}
+
+impl Authors {
+    /// Remembers the sender of `msg`, if it is a public message
+    pub fn keep(&mut self, msg: &Msg) {
+        if let Header::Public(..) = msg.header {
+            if self.order.len() >= MAX_KEPT {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.of.remove(&oldest);
+                }
+            }
+            if self.of.insert(msg.id, msg.sender_id.clone()).is_none() {
+                self.order.push_back(msg.id);
+            }
+        }
+    }
+
+    /// Who wrote message `id`, None if it is unknown or was deleted
+    pub fn of(&self, id: MsgId) -> Option<&str> {
+        self.of.get(&id).map(String::as_str)
+    }
+
+    /// Message `id` was deleted, it cannot be changed anymore
+    pub fn forget(&mut self, id: MsgId) {
+        if self.of.remove(&id).is_some() {
+            self.order.retain(|kept| *kept != id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::Channel;
+    use crate::server::Clock;
+
+    fn msg(id: MsgId, sender: &str, header: Header) -> Msg {
+        Msg::new(id, sender.to_owned(), header, Clock::new(sender.to_owned()))
+    }
+
+    #[test]
+    fn only_public_messages_have_authors() {
+        let mut authors = Authors::default();
+        let public = Header::Public(Channel::default(), "hi".to_owned());
+        authors.keep(&msg(1, "alice", public));
+        authors.keep(&msg(
+            2,
+            "alice",
+            Header::Private("bob".to_owned(), "hi".to_owned()),
+        ));
+        assert_eq!(authors.of(1), Some("alice"));
+        assert_eq!(authors.of(2), None);
+        authors.forget(1);
+        assert_eq!(authors.of(1), None);
+
+        for id in 0..MAX_KEPT as MsgId + 1 {
+            let header = Header::Public(Channel::default(), "hi".to_owned());
+            authors.keep(&msg(id, "bob", header));
+        }
+        assert_eq!(authors.of(0), None, "forgotten");
+        assert_eq!(authors.of(MAX_KEPT as MsgId), Some("bob"));
+    }
+}
@@ -0,0 +1,92 @@
+//! `--encryption`: whether private messages and files may go in clear to the
+//! apps they cannot be encrypted for, those which run without encryption, did
+//! not announce a key yet, or are an identity of several devices not all of
+//! which did. Files are not encrypted for an identity of several devices.
+
+use super::messages::VersionInfo;
+
+/// Names accepted by [`Policy::by_name`]
+pub const POLICIES: &[&str] = &["require", "prefer", "allow-plaintext"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Policy {
+    /// Private messages and files which cannot be encrypted are not sent
+    Require,
+    /// They are sent in clear, the user is warned once per recipient, and
+    /// once per file
+    Prefer,
+    /// They are sent in clear without a word
+    AllowPlaintext,
+}
+
+impl Policy {
+    /// The policy called `name`, one of [`POLICIES`]
+    pub fn by_name(name: &str) -> Option<Policy> {
+        match name {
+            "require" => Some(Policy::Require),
+            "prefer" => Some(Policy::Prefer),
+            "allow-plaintext" => Some(Policy::AllowPlaintext),
+            _ => None,
+        }
+    }
+}
+
+/// Why private messages to `app_id` cannot be encrypted, out of what it said
+/// it runs and whether it is an identity of several devices
+pub fn why_not(app_id: &str, runs: Option<&VersionInfo>, devices: bool) -> String {
+    let supports = |info: &VersionInfo| info.features.iter().any(|f| f == "encryption");
+    match runs {
+        _ if devices => format!(
+            "{} is an identity of several devices, not all of which announced a key",
+            app_id
+        ),
+        Some(info) if !supports(info) => {
+            format!("{} runs {}, without encryption", app_id, info.version)
+        }
+        _ => format!("{} has no encryption key", app_id),
+    }
+}
+
+/// Same as [`why_not`], for files: they are only encrypted for the apps which
+/// read encrypted chunks
+pub fn why_not_files(app_id: &str, runs: Option<&VersionInfo>, devices: bool) -> String {
+    let supports = |info: &VersionInfo| info.features.iter().any(|f| f == "encrypted-files");
+    match runs {
+        _ if devices => format!(
+            "{} is an identity of several devices, each with its own key",
+            app_id
+        ),
+        Some(info) if !supports(info) => {
+            format!("{} runs {}, without encrypted files", app_id, info.version)
+        }
+        _ => why_not(app_id, runs, devices),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reasons_tell_what_the_peer_lacks() {
+        let old = VersionInfo::local().without("encryption");
+        let runs = format!("bob runs {}, without encryption", old.version);
+        assert_eq!(why_not("bob", Some(&old), false), runs);
+        let local = VersionInfo::local();
+        assert_eq!(
+            why_not("bob", Some(&local), false),
+            "bob has no encryption key"
+        );
+        assert_eq!(why_not("bob", None, false), "bob has no encryption key");
+        assert!(why_not("bob", None, true).contains("not all of which"));
+
+        let old = VersionInfo::local().without("encrypted-files");
+        let runs = format!("bob runs {}, without encrypted files", old.version);
+        assert_eq!(why_not_files("bob", Some(&old), false), runs);
+        assert_eq!(
+            why_not_files("bob", Some(&local), false),
+            "bob has no encryption key"
+        );
+        assert!(why_not_files("bob", Some(&local), true).contains("its own key"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Anti-entropy, for the chat messages an app missed while it was away: every
+//! `--sync-interval`, each app tells its neighbours which chat messages of each
+//! app it has, as a version vector of their ranks, and they send back the
+//! ones it lacks out of those they kept. Summaries and answers are not
+//! relayed, the messages sent back are, as if they were new.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::messages::{Date, Msg};
+use super::Clock;
+use crate::app::AppId;
+
+/// Chat messages kept per app to be sent again, the oldest are forgotten
+const KEPT_PER_APP: usize = 256;
+
+#[derive(Default)]
+pub struct Entropy {
+    kept: HashMap<AppId, BTreeMap<Date, Msg>>, // Last chat messages of each app, by rank
+    had: HashMap<AppId, Date>,                 // Ranks told in the last summary
+    stuck: HashMap<AppId, Date>,               // Gaps after those, asked for once
+}
+
+impl Entropy {
+    /// Keeps `msg` as sent, if it carries its rank among the chat messages of its sender
+    pub fn keep(&mut self, msg: &Msg) {
+        let rank = match msg.delivered.as_ref().and_then(|d| d.get(&msg.sender_id)) {
+            Some(rank) => *rank,
+            None => return,
+        };
+        let kept = self.kept.entry(msg.sender_id.clone()).or_default();
+        kept.insert(rank, msg.clone());
+        if kept.len() > KEPT_PER_APP {
+            kept.pop_first();
+        }
+    }
+
+    /// `app_id` arrived anew, it counts its messages from the start again
+    pub fn forget(&mut self, app_id: &str) {
+        self.kept.remove(app_id);
+        self.had.remove(app_id);
+        self.stuck.remove(app_id);
+    }
+
+    /// Rank of the last message of each app kept without a gap before it,
+    /// from the oldest kept. A gap still there a summary later is lost for
+    /// everyone, it is skipped.
+    pub fn summary(&mut self) -> Clock {
+        let mut summary = Clock(HashMap::new());
+        for (app_id, kept) in &self.kept {
+            let first = match kept.keys().next() {
+                Some(first) => *first,
+                None => continue,
+            };
+            let before = first.saturating_sub(1);
+            let mut last = self
+                .had
+                .get(app_id)
+                .map_or(before, |had| (*had).max(before));
+            loop {
+                last = kept
+                    .range(last + 1..)
+                    .zip(last + 1..)
+                    .take_while(|((rank, _), expected)| **rank == *expected)
+                    .last()
+                    .map_or(last, |((rank, _), _)| *rank);
+                let next = match kept.range(last + 1..).next() {
+                    Some((next, _)) => *next,
+                    None => {
+                        self.stuck.remove(app_id);
+                        break;
+                    }
+                };
+                if self.stuck.get(app_id) != Some(&last) {
+                    self.stuck.insert(app_id.clone(), last);
+                    break;
+                }
+                last = next;
+            }
+            self.had.insert(app_id.clone(), last);
+            summary.insert(app_id.clone(), last);
+        }
+        summary
+    }
+
+    /// The messages kept that the app which sent `summary` lacks, oldest
+    /// first. Only those of the apps it heard from, it may have joined after
+    /// the others spoke.
+    pub fn missing(&self, summary: &Clock) -> Vec<Msg> {
+        let mut missing = Vec::new();
+        for (app_id, last) in summary.iter() {
+            if let Some(kept) = self.kept.get(app_id) {
+                missing.extend(kept.range(last + 1..).map(|(_, msg)| msg.clone()));
+            }
+        }
+        missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::{Channel, Header};
+
+    fn public(sender: &str, rank: Date) -> Msg {
+        let mut msg = Msg::new(
+            rank,
+            sender.to_owned(),
+            Header::Public(Channel::default(), format!("{} {}", sender, rank)),
+            Clock::new(sender.to_owned()),
+        );
+        let delivered = Clock([(sender.to_owned(), rank)].iter().cloned().collect());
+        msg.delivered = Some(Box::new(delivered));
+        msg
+    }
+
+    #[test]
+    fn what_the_others_lack_is_sent_back() {
+        let mut away = Entropy::default();
+        let mut stayed = Entropy::default();
+        for rank in 1..=6 {
+            stayed.keep(&public("bob", rank));
+        }
+        stayed.keep(&public("carol", 1));
+        for rank in [2, 3, 6].iter() {
+            away.keep(&public("bob", *rank));
+        }
+        let summary = away.summary();
+        assert_eq!(summary.get("bob"), Some(&3), "4 and 5 are missing");
+
+        let ranks: Vec<Date> = stayed.missing(&summary).iter().map(|m| m.id).collect();
+        assert_eq!(ranks, [4, 5, 6], "nothing of carol, never heard of");
+        assert!(away.missing(&stayed.summary()).is_empty());
+        away.keep(&public("bob", 4));
+        assert_eq!(away.summary().get("bob"), Some(&4), "5 is still asked for");
+        assert_eq!(away.summary().get("bob"), Some(&6), "5 was lost, skipped");
+
+        for rank in 7..KEPT_PER_APP as Date + 10 {
+            stayed.keep(&public("bob", rank));
+        }
+        let first = stayed.missing(&summary)[0].id;
+        assert_eq!(first, 10, "the oldest were forgotten");
+        away.forget("bob");
+        assert_eq!(away.summary().get("bob"), None);
+    }
+}
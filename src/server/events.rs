@@ -1,23 +1,127 @@
 use crate::app::AppId;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
+use std::io::BufReader;
+use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread;
 
+use super::acceptance;
+use super::backend::{Input, Peer, Transport};
+use super::crypto::FrameKey;
+use super::framing::{self, Frame, FrameReader, Pool};
+use super::messages::{self, Channel, Msg, MsgId, ParseError};
+use super::metrics::{Metric, Metrics};
+use netchat_core::dedup::Seen;
+
 pub enum Event {
-    /// User public message
-    UserPublicMessage(String),
+    /// User public message, in a channel, through the transports named if any
+    UserPublicMessage(Channel, String, Option<Vec<String>>),
     /// User private message
     UserPrivateMessage(AppId, String),
-    /// Message from another app (write in a file)
-    DistantInput(String),
+    /// New text of a public message of the user
+    UserEdit(MsgId, String),
+    /// The user takes back one of its public messages
+    UserDelete(MsgId),
+    /// User announcement, for everyone
+    UserAnnouncement(String),
+    /// Name the user wants to be shown as, for everyone
+    UserNick(String),
+    /// The user is composing a message
+    UserTyping,
+    /// The line being typed in live line mode, for the app given
+    UserLiveLine(AppId, String),
+    /// Seconds everyone waits between two public messages in a channel, 0 for none
+    UserSlowMode(Channel, u64),
+    /// Show the public messages of a channel from now on
+    JoinChannel(Channel),
+    /// Stop showing the public messages of a channel
+    LeaveChannel(Channel),
+    /// File the user sends to an app, or all the devices of an identity
+    UserFile(PathBuf, AppId),
+    /// File the user offers in a channel, fetched by those who want it
+    UserOffer(PathBuf, Channel),
+    /// Time to send the next chunk of the file being sent
+    FileChunk,
+    /// Fetch the file offered whose hash starts with the text given
+    GetFile(String),
+    /// List the files in quarantine and the file policies of the contacts
+    ListFiles,
+    /// Move a file out of quarantine into the downloads
+    AcceptFile(String),
+    /// Delete a file in quarantine
+    RejectFile(String),
+    /// What becomes of the files of an app or identity, as `--accept-files`
+    /// says for None
+    FilePolicy(AppId, Option<acceptance::Policy>),
+    /// Message from another app, with the peer it was read from
+    DistantInput(Box<Msg>, Arc<str>),
+    /// Copy of a message already read, dropped undecoded by `--fast-relay`: it
+    /// is only acknowledged again, its sender may have missed the first ack
+    DuplicateInput(MsgId),
+    /// Same, held back by a partition or missed while away: it comes with
+    /// others of its sender, past their rate limit
+    ReplayedInput(Box<Msg>, Arc<str>),
+    /// Message from another app this version cannot decode, with its sender and header name
+    Undecodable(AppId, String),
+    /// Line of the input file dropped for being longer than the limit, with its length
+    OversizedFrame(usize),
+    /// Line of the input file dropped for not opening with the pre-shared key
+    UnsealedFrame,
     /// Shutdown the server
     Shutdown,
     /// Clock request from the user
     GetClock,
+    /// Services request from the user
+    GetServices,
+    /// Counters of the server, for `/stats`
+    GetStats,
+    /// Write the metrics and the queues as JSON to a file, for `/metrics dump`
+    DumpMetrics(PathBuf),
+    /// Statistics of a channel, out of the history
+    GetChannelStats(Channel),
     /// Snapshot request from the user
     GetSnapshot,
+    /// Replace the identity key and let the other apps know
+    RotateKey,
+    /// Accept the last request to link a device to our identity
+    AcceptLink,
+    /// Add a tag to a contact, or remove it
+    Tag(AppId, String, bool),
+    /// Time to send a heartbeat
+    Heartbeat,
+    /// Time to send again the chat messages nobody acknowledged
+    Retransmit,
+    /// Time to tell the neighbours which chat messages we have
+    Sync,
+    /// Time to hand over the messages held back for too long
+    ExpireHeld,
+    /// Time to tell the apps silent for too long are offline
+    ExpirePresence,
+    /// Time to give up on the files no chunk of came for too long
+    ExpireDownloads,
+    /// Time to leave after a shutdown, acknowledged or not
+    Goodbye,
+    /// Someone opened the other end of an input, with the way back to them if
+    /// the connection goes both ways
+    PeerConnected(String, Option<Box<dyn Transport>>),
+    /// The other end of an input was closed
+    PeerDisconnected(String),
+    /// An app listening over TCP on the local network, with its address
+    Discovered(AppId, SocketAddr),
+    /// Dial the peer listening at this address, like `--connect`
+    Connect(String),
+    /// Attempt to reconnect a transport now, or all of them
+    Reconnect(Option<String>),
+    /// Stop writing to or reading from a transport, or all of them
+    Partition(Option<String>),
+    /// Undo a partition, what was held back goes through
+    Heal(Option<String>),
+    /// Retry a message of the outbox now, or all of them
+    RetryOutbox(Option<u32>),
+    /// Drop a message from the outbox
+    CancelOutbox(u32),
+    /// Stop a long-running operation
+    CancelJob(String),
     /// Timer used for snapshot building. When finished,
     /// the server stops waiting for Snapshots from other apps,
     /// and writes the snapshot to file
@@ -29,12 +133,55 @@ pub enum Event {
 pub struct Events {
     rx: mpsc::Receiver<Event>,
     _app_handle: thread::JoinHandle<()>,
-    _input_file_handle: thread::JoinHandle<()>,
+    _input_handles: Vec<thread::JoinHandle<()>>,
+    dialer: Dialer,
+}
+
+/// Reads the peers of an input on a thread of its own, those of a
+/// concurrent input each on their own
+#[derive(Clone)]
+pub struct Dialer {
+    max_frame_len: usize,
+    tx: mpsc::Sender<Event>,
+    frames: mpsc::Sender<(Arc<str>, Frame)>,
+    pool: Pool,
+}
+
+impl Dialer {
+    /// Reads `input` from now on, until the server stops
+    pub fn open(&self, mut input: Box<dyn Input>) -> thread::JoinHandle<()> {
+        let Dialer {
+            max_frame_len,
+            tx,
+            frames,
+            pool,
+        } = self.clone();
+        thread::spawn(move || loop {
+            // Blocks until someone writes at the other end
+            let peer = input.open().expect("Could not open input");
+            if input.concurrent() {
+                let (tx, frames, pool) = (tx.clone(), frames.clone(), pool.clone());
+                thread::spawn(move || read(peer, max_frame_len, tx, frames, pool));
+            } else {
+                read(
+                    peer,
+                    max_frame_len,
+                    tx.clone(),
+                    frames.clone(),
+                    pool.clone(),
+                );
+            }
+        })
+    }
 }
 
 impl Events {
     pub fn new(
-        input_file_path: PathBuf,
+        inputs: Vec<Box<dyn Input>>,
+        max_frame_len: usize,
+        fast_relay: bool,
+        key: Option<FrameKey>,
+        metrics: Arc<Metrics>,
         app_rx: mpsc::Receiver<Event>,
         server_rx: mpsc::Receiver<Event>,
     ) -> Events {
@@ -54,20 +201,29 @@ impl Events {
             })
         };
 
-        // listen to the server for distant events
-        let _input_file_handle = {
+        // decode distant messages apart from the rest of the server
+        let (frame_tx, frame_rx) = mpsc::channel();
+        let pool = Pool::default();
+        {
             let tx = tx.clone();
-            thread::spawn(move || loop {
-                let input_file = File::open(&input_file_path).expect("Could not open input file");
-                let reader = BufReader::new(input_file);
-                reader.lines().for_each(|line| {
-                    tx.send(Event::DistantInput(
-                        line.expect("Could not read from input file"),
-                    ))
-                    .unwrap();
-                })
-            })
+            let pool = pool.clone();
+            let key = key.clone();
+            thread::spawn(move || {
+                decode(frame_rx, tx, max_frame_len, fast_relay, key, metrics, pool)
+            });
+        }
+
+        // listen to the other apps for distant events, sealed frames are longer
+        let dialer = Dialer {
+            max_frame_len: match key {
+                Some(_) => FrameKey::sealed_len(max_frame_len),
+                None => max_frame_len,
+            },
+            tx: tx.clone(),
+            frames: frame_tx,
+            pool,
         };
+        let _input_handles = inputs.into_iter().map(|input| dialer.open(input)).collect();
 
         // listen to server events to allow to speak to itself asynchronously
         let _server_handle = {
@@ -83,11 +239,235 @@ impl Events {
         Events {
             rx,
             _app_handle,
-            _input_file_handle,
+            _input_handles,
+            dialer,
         }
     }
 
+    /// Opens more inputs while the server runs
+    pub fn dialer(&self) -> Dialer {
+        self.dialer.clone()
+    }
+
     pub fn next(&self) -> Result<Event, mpsc::RecvError> {
         self.rx.recv()
     }
 }
+
+/// Reads the frames of `peer` until it goes away
+fn read(
+    peer: Peer,
+    max_frame_len: usize,
+    tx: mpsc::Sender<Event>,
+    frames: mpsc::Sender<(Arc<str>, Frame)>,
+    pool: Pool,
+) {
+    let name: Arc<str> = peer.name.as_str().into();
+    tx.send(Event::PeerConnected(peer.name, peer.writer))
+        .unwrap();
+    let reader = FrameReader::new(BufReader::new(peer.reader), max_frame_len, pool);
+    for frame in reader {
+        match frame {
+            Ok(Frame::TooLong(len)) => tx.send(Event::OversizedFrame(len)).unwrap(),
+            Ok(frame) => frames.send((name.clone(), frame)).unwrap(),
+            Err(e) => {
+                log::error!("Could not read from {}: {}", name, e);
+                break;
+            }
+        }
+    }
+    tx.send(Event::PeerDisconnected(name.to_string())).unwrap();
+}
+
+/// What a sealed line held, None if it is neither a line nor a binary frame
+fn unsealed(frame: Vec<u8>) -> Option<Frame> {
+    match framing::binary_payload(&frame) {
+        Some(payload) => Some(Frame::Binary(payload.to_vec())),
+        None => String::from_utf8(frame).ok().map(Frame::Line),
+    }
+}
+
+/// Decoding stage, turns input frames into messages
+///
+/// With `fast_relay`, lines carrying a message already decoded once are
+/// dropped after reading their id only. Binary frames are decoded whole.
+/// A frame read by `decode`
+enum Decoded {
+    New(Box<Msg>),
+    /// Copy of a message already read with `--fast-relay`, only its id decoded
+    Duplicate(MsgId),
+}
+
+fn decode(
+    frames: mpsc::Receiver<(Arc<str>, Frame)>,
+    tx: mpsc::Sender<Event>,
+    max_frame_len: usize,
+    fast_relay: bool,
+    key: Option<FrameKey>,
+    metrics: Arc<Metrics>,
+    pool: Pool,
+) {
+    let mut seen = Seen::default();
+    for (origin, frame) in frames {
+        // Sealed lines are opened first, those which do not open are dropped
+        let frame = match &key {
+            None => frame,
+            Some(key) => {
+                let opened = match frame {
+                    Frame::Line(line) => {
+                        let opened = key.open(&line).and_then(unsealed);
+                        pool.give(line);
+                        opened
+                    }
+                    _ => None,
+                };
+                match opened {
+                    Some(opened) => opened,
+                    None => {
+                        if tx.send(Event::UnsealedFrame).is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                }
+            }
+        };
+        let decoded = match &frame {
+            Frame::Line(line) if fast_relay => {
+                messages::parse_envelope(line).and_then(|envelope| {
+                    if seen.insert(envelope.id) {
+                        envelope.open().map(|msg| Decoded::New(Box::new(msg)))
+                    } else {
+                        metrics.add(Metric::Duplicated);
+                        Ok(Decoded::Duplicate(envelope.id))
+                    }
+                })
+            }
+            Frame::Line(line) => line.parse::<Msg>().map(|msg| Decoded::New(Box::new(msg))),
+            Frame::Binary(payload) => messages::parse_binary(payload, max_frame_len).map(|msg| {
+                if !fast_relay || seen.insert(msg.id) {
+                    return Decoded::New(Box::new(msg));
+                }
+                metrics.add(Metric::Duplicated);
+                Decoded::Duplicate(msg.id)
+            }),
+            Frame::TooLong(len) => Err(ParseError::TooLong(*len)),
+        };
+        let event = match (decoded, &frame) {
+            (Ok(Decoded::New(msg)), _) => Some(Event::DistantInput(msg, origin)),
+            (Ok(Decoded::Duplicate(id)), _) => Some(Event::DuplicateInput(id)),
+            (Err(ParseError::Json(e)), Frame::Line(line)) => {
+                metrics.add(Metric::Dropped);
+                log::error!("Could not decode `{}` as a Msg: {}", line, e);
+                messages::undecodable(line)
+                    .map(|(sender_id, header)| Event::Undecodable(sender_id, header))
+            }
+            (Err(e), _) => {
+                metrics.add(Metric::Dropped);
+                log::error!("Dropped an input frame: {}", e);
+                None
+            }
+        };
+        if let Frame::Line(line) = frame {
+            pool.give(line);
+        }
+        if let Some(event) = event {
+            if tx.send(event).is_err() {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::{Codec, Header};
+    use crate::server::Clock;
+
+    /// What the decode stage hands the server for `frames`, and its counters
+    fn decoded(frames: Vec<Frame>, fast_relay: bool) -> (Vec<Event>, Arc<Metrics>) {
+        let (frame_tx, frame_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        for frame in frames {
+            frame_tx.send((Arc::from("in"), frame)).unwrap();
+        }
+        drop(frame_tx);
+        let metrics = Arc::new(Metrics::default());
+        let max_len = framing::MAX_FRAME_LEN;
+        decode(
+            frame_rx,
+            tx,
+            max_len,
+            fast_relay,
+            None,
+            metrics.clone(),
+            Pool::default(),
+        );
+        (rx.try_iter().collect(), metrics)
+    }
+
+    fn hi(id: MsgId) -> Msg {
+        let header = Header::Public(Channel::default(), "hi".to_owned());
+        Msg::new(id, "bob".to_owned(), header, Clock::new("bob".to_owned()))
+    }
+
+    #[test]
+    fn fast_relays_drop_duplicate_lines_before_decoding_them() {
+        let line = hi(7).serialize().unwrap();
+        // Same id, a header no version knows: only a full decode would see it
+        let unknown = line.replace(r##"{"Public":["#general","hi"]}"##, r#"{"Nope":1}"#);
+        assert_ne!(unknown, line);
+        let frames = || vec![Frame::Line(line.clone()), Frame::Line(unknown.clone())];
+
+        let (events, metrics) = decoded(frames(), true);
+        assert!(matches!(&events[..], [
+            Event::DistantInput(msg, _),
+            Event::DuplicateInput(7),
+        ] if msg.id == 7));
+        assert_eq!(metrics.get(Metric::Duplicated), 1);
+        assert_eq!(metrics.get(Metric::Dropped), 0);
+
+        let (events, metrics) = decoded(frames(), false);
+        assert!(
+            matches!(
+                &events[..],
+                [Event::DistantInput(..), Event::Undecodable(..)]
+            ),
+            "decoded in full"
+        );
+        assert_eq!(metrics.get(Metric::Dropped), 1);
+    }
+
+    #[test]
+    fn fast_relays_count_binary_duplicates() {
+        let mut frame = Vec::new();
+        Codec::Cbor.encode_into(&hi(7), &mut frame).unwrap();
+        let payload = framing::binary_payload(&frame[..frame.len() - 1])
+            .unwrap()
+            .to_vec();
+        let frames = || {
+            vec![
+                Frame::Binary(payload.clone()),
+                Frame::Binary(payload.clone()),
+            ]
+        };
+
+        let (events, metrics) = decoded(frames(), true);
+        assert!(matches!(
+            &events[..],
+            [Event::DistantInput(..), Event::DuplicateInput(7)]
+        ));
+        assert_eq!(metrics.get(Metric::Duplicated), 1);
+
+        let (events, metrics) = decoded(frames(), false);
+        assert!(
+            matches!(
+                &events[..],
+                [Event::DistantInput(..), Event::DistantInput(..)]
+            ),
+            "the server tells duplicates apart"
+        );
+        assert_eq!(metrics.get(Metric::Duplicated), 0);
+    }
+}
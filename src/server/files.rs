@@ -0,0 +1,457 @@
+//! Files sent with `/send`: cut into chunks small enough for one line each,
+//! and put back together on the other side, in the downloads directory or in
+//! quarantine after [`acceptance`](super::acceptance), as links to their
+//! [`blobs`](super::blobs). A file nothing came of for a while is given up on,
+//! the chunks received dropped.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use super::blobs::{self, Blobs};
+use super::crypto::{from_hex, to_hex};
+use super::messages::Header;
+use crate::app::AppId;
+
+/// Bytes of the file in each chunk, twice as many once hex encoded
+pub const CHUNK_LEN: usize = 64 * 1024;
+
+/// Largest file sent or received, the chunks of a file are held in memory
+pub const MAX_FILE_LEN: usize = 16 * 1024 * 1024;
+
+/// Files received at once at most, the chunks of the others are refused
+const MAX_DOWNLOADS: usize = 8;
+
+/// A file being sent, a chunk at a time
+pub struct Upload {
+    pub to: AppId,
+    pub name: String,
+    /// What the user is told is sent
+    pub shown: String,
+    data: Vec<u8>,
+    next: u32,
+}
+
+impl Upload {
+    /// Reads the file at `path`, for `to`
+    pub fn new(path: &Path, to: AppId) -> io::Result<Upload> {
+        let (name, data) = read(path)?;
+        Ok(Upload {
+            to,
+            shown: name.clone(),
+            name,
+            data,
+            next: 0,
+        })
+    }
+
+    /// Sends `data`, the blob `hash`, to `to` which fetches it
+    pub fn of_blob(hash: &str, data: Vec<u8>, to: AppId) -> Upload {
+        Upload {
+            to,
+            name: hash.to_owned(),
+            shown: format!("file {}", blobs::short(hash)),
+            data,
+            next: 0,
+        }
+    }
+
+    pub fn total_chunks(&self) -> u32 {
+        // An empty file is sent as one empty chunk
+        self.data.len().div_ceil(CHUNK_LEN).max(1) as u32
+    }
+
+    pub fn sent_chunks(&self) -> u32 {
+        self.next
+    }
+
+    /// Header carrying the next chunk, None once all were taken
+    pub fn next_chunk(&mut self) -> Option<Header> {
+        if self.next == self.total_chunks() {
+            return None;
+        }
+        let start = self.next as usize * CHUNK_LEN;
+        let end = (start + CHUNK_LEN).min(self.data.len());
+        let header = Header::File {
+            to: self.to.clone(),
+            name: self.name.clone(),
+            chunk_index: self.next,
+            total_chunks: self.total_chunks(),
+            data: to_hex(&self.data[start..end]),
+        };
+        self.next += 1;
+        Some(header)
+    }
+}
+
+/// Name and content of the file at `path`, unless it is too large to send
+pub fn read(path: &Path) -> io::Result<(String, Vec<u8>)> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file name"))?
+        .to_string_lossy()
+        .into_owned();
+    if fs::metadata(path)?.len() > MAX_FILE_LEN as u64 {
+        let e = format!("over the {} bytes limit", MAX_FILE_LEN);
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, e));
+    }
+    Ok((name, fs::read(path)?))
+}
+
+struct Partial {
+    chunks: Vec<Option<Vec<u8>>>,
+    received: u32,
+    /// When the last chunk came
+    last: Instant,
+}
+
+/// A file given up on, out of its sender and name
+#[derive(Debug, PartialEq)]
+pub struct Stalled {
+    pub sender: AppId,
+    pub name: String,
+    /// Chunks received before it stalled, and how many it has
+    pub received: u32,
+    pub total_chunks: u32,
+}
+
+/// What a chunk received changed
+#[derive(Debug, PartialEq)]
+pub enum Received {
+    /// Chunks of the file received so far, and how many it has
+    Progress(u32, u32),
+    /// The last chunk came, with the whole file
+    Complete(Vec<u8>),
+    /// A chunk of a file refused earlier, until it is sent again from its first chunk
+    Skipped,
+}
+
+/// Files being received, by sender and name
+pub struct Downloads {
+    dir: PathBuf,
+    quarantine: PathBuf,
+    blobs: Blobs,
+    partial: HashMap<(AppId, String), Partial>,
+    refused: HashSet<(AppId, String)>,
+}
+
+impl Downloads {
+    /// Links the files accepted in `dir` and the others in `quarantine`,
+    /// created on the first one, to their content kept in `blobs`
+    pub fn new(dir: PathBuf, quarantine: PathBuf, blobs: Blobs) -> Downloads {
+        Downloads {
+            dir,
+            quarantine,
+            blobs,
+            partial: HashMap::new(),
+            refused: HashSet::new(),
+        }
+    }
+
+    /// Takes a chunk of file `name` sent by `sender` at `now`, the error tells
+    /// why it was refused
+    pub fn receive(
+        &mut self,
+        sender: &str,
+        name: &str,
+        chunk_index: u32,
+        total_chunks: u32,
+        data: &str,
+        now: Instant,
+    ) -> Result<Received, String> {
+        let key = (sender.to_owned(), name.to_owned());
+        if chunk_index > 0 && self.refused.contains(&key) {
+            return Ok(Received::Skipped);
+        }
+        self.refused.remove(&key);
+        let received = self.take(key.clone(), chunk_index, total_chunks, data, now);
+        if received.is_err() {
+            self.partial.remove(&key);
+            self.refused.insert(key);
+        }
+        received
+    }
+
+    fn take(
+        &mut self,
+        key: (AppId, String),
+        chunk_index: u32,
+        total_chunks: u32,
+        data: &str,
+        now: Instant,
+    ) -> Result<Received, String> {
+        let chunk = from_hex(data).ok_or("the chunk is not hex encoded")?;
+        if chunk.len() > CHUNK_LEN || total_chunks as usize > MAX_FILE_LEN.div_ceil(CHUNK_LEN) {
+            return Err(format!("over the {} bytes limit", MAX_FILE_LEN));
+        }
+        if chunk_index >= total_chunks {
+            return Err(format!("chunk {} of {}", chunk_index, total_chunks));
+        }
+        if !self.partial.contains_key(&key) && self.partial.len() >= MAX_DOWNLOADS {
+            return Err(format!(
+                "{} files are being received already",
+                MAX_DOWNLOADS
+            ));
+        }
+        let partial = self.partial.entry(key.clone()).or_insert_with(|| Partial {
+            chunks: vec![None; total_chunks as usize],
+            received: 0,
+            last: now,
+        });
+        if partial.chunks.len() != total_chunks as usize {
+            return Err("the number of chunks changed".to_owned());
+        }
+        partial.last = now;
+        let slot = &mut partial.chunks[chunk_index as usize];
+        if slot.is_none() {
+            *slot = Some(chunk);
+            partial.received += 1;
+        }
+        if partial.received < total_chunks {
+            return Ok(Received::Progress(partial.received, total_chunks));
+        }
+        let partial = self.partial.remove(&key).expect("just looked up");
+        let data = partial.chunks.into_iter().flatten().flatten().collect();
+        Ok(Received::Complete(data))
+    }
+
+    /// Refuses the file `name` of `sender`, the rest of it is skipped until it
+    /// is sent again from its first chunk
+    pub fn refuse(&mut self, sender: &str, name: &str) {
+        let key = (sender.to_owned(), name.to_owned());
+        self.partial.remove(&key);
+        self.refused.insert(key);
+    }
+
+    /// Gives up on the files no chunk came of for `timeout` at `now`. Like a
+    /// refused file, the rest of one is skipped until it is sent again.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<Stalled> {
+        let stalled: Vec<(AppId, String)> = self
+            .partial
+            .iter()
+            .filter(|(_, partial)| now.duration_since(partial.last) >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut given_up = Vec::new();
+        for key in stalled {
+            let partial = self.partial.remove(&key).expect("just looked up");
+            given_up.push(Stalled {
+                sender: key.0.clone(),
+                name: key.1.clone(),
+                received: partial.received,
+                total_chunks: partial.chunks.len() as u32,
+            });
+            self.refused.insert(key);
+        }
+        given_up
+    }
+
+    /// Writes the file `name` in the downloads, returns where
+    pub fn save(&self, name: &str, data: &[u8]) -> io::Result<PathBuf> {
+        let hash = self.blobs.put(data)?;
+        self.blobs.copy(&hash, &self.dir, &sanitize(name))
+    }
+
+    /// Writes the file `name` in quarantine, returns where
+    pub fn quarantine(&self, name: &str, data: &[u8]) -> io::Result<PathBuf> {
+        let hash = self.blobs.put(data)?;
+        self.blobs.copy(&hash, &self.quarantine, &sanitize(name))
+    }
+
+    /// Names of the files in quarantine, in order
+    pub fn quarantined(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.quarantine) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            names.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Moves the file `name` out of quarantine into the downloads, returns
+    /// where it is now
+    pub fn release(&self, name: &str) -> io::Result<PathBuf> {
+        let held = self.quarantine.join(sanitize(name));
+        let path = self.save(name, &fs::read(&held)?)?;
+        self.blobs.remove(&held)?;
+        Ok(path)
+    }
+
+    /// Deletes the file `name` in quarantine, and its content unless it was
+    /// received elsewhere too
+    pub fn discard(&self, name: &str) -> io::Result<()> {
+        self.blobs.remove(&self.quarantine.join(sanitize(name)))
+    }
+}
+
+/// `name` made a file name fit to write: its last component, without control
+/// characters, those Windows forbids nor those turning the text around, which
+/// hide an extension. Neither hidden nor empty, 255 bytes at most.
+pub fn sanitize(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let bidi = |c| matches!(c, '\u{202a}'..='\u{202e}' | '\u{2066}'..='\u{2069}');
+    let name: String = name
+        .chars()
+        .filter(|c| !bidi(*c))
+        .map(|c| match c {
+            c if c.is_control() => '_',
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c => c,
+        })
+        .collect();
+    let mut name = name
+        .trim()
+        .trim_start_matches('.')
+        .trim_end_matches(['.', ' '])
+        .to_owned();
+    while name.len() > 255 {
+        name.pop();
+    }
+    match name.is_empty() {
+        true => "file".to_owned(),
+        false => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_put_back_together_whatever_the_order() {
+        let dir = std::env::temp_dir().join(format!("netchat-test-{}-files", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let sent = dir.join("notes.bin");
+        let content: Vec<u8> = (0..CHUNK_LEN * 2 + 10).map(|i| i as u8).collect();
+        fs::write(&sent, &content).unwrap();
+
+        let mut upload = Upload::new(&sent, "bob".to_owned()).unwrap();
+        let mut chunks = Vec::new();
+        while let Some(chunk) = upload.next_chunk() {
+            chunks.push(chunk);
+        }
+        assert_eq!(chunks.len(), 3);
+        chunks.swap(0, 2);
+
+        let mut downloads = Downloads::new(
+            dir.join("downloads"),
+            dir.join("quarantine"),
+            Blobs::new(dir.join("blobs")),
+        );
+        let now = Instant::now();
+        let mut received = Vec::new();
+        for chunk in &chunks {
+            if let Header::File {
+                name,
+                chunk_index,
+                total_chunks,
+                data,
+                ..
+            } = chunk
+            {
+                received.push(
+                    downloads
+                        .receive("alice", name, *chunk_index, *total_chunks, data, now)
+                        .unwrap(),
+                );
+            }
+        }
+        assert_eq!(
+            received,
+            [
+                Received::Progress(1, 3),
+                Received::Progress(2, 3),
+                Received::Complete(content.clone())
+            ]
+        );
+        let saved = downloads.save("notes.bin", &content).unwrap();
+        assert_eq!(saved, dir.join("downloads").join("notes.bin"));
+        assert_eq!(fs::read(&saved).unwrap(), content);
+
+        // A name cannot lead out of the downloads directory
+        let evil = downloads
+            .receive("alice", "../../evil", 0, 1, "00", now)
+            .unwrap();
+        assert_eq!(evil, Received::Complete(vec![0]));
+        let path = downloads.save("../../evil", &[0]).unwrap();
+        // Once refused, the rest of a file is skipped
+        let refused = downloads.receive("alice", "x", 0, 2, "not hex", now);
+        let skipped = downloads.receive("alice", "x", 1, 2, "00", now);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(path, dir.join("downloads").join("evil"));
+        assert!(refused.is_err());
+        assert_eq!(skipped, Ok(Received::Skipped));
+    }
+
+    #[test]
+    fn quarantined_files_wait_for_the_user() {
+        assert_eq!(sanitize("..\\..\\boot.ini"), "boot.ini");
+        assert_eq!(sanitize(".bashrc"), "bashrc", "not hidden");
+        assert_eq!(sanitize("a<b>\n.txt. "), "a_b__.txt");
+        assert_eq!(sanitize("photo\u{202e}gpj.exe"), "photogpj.exe");
+        assert_eq!(sanitize(".."), "file");
+        assert_eq!(sanitize(&"x".repeat(300)).len(), 255);
+
+        let dir =
+            std::env::temp_dir().join(format!("netchat-test-{}-quarantine", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let downloads = Downloads::new(
+            dir.join("downloads"),
+            dir.join("quarantine"),
+            Blobs::new(dir.join("blobs")),
+        );
+        assert!(downloads.quarantined().unwrap().is_empty());
+        let held = downloads.quarantine("report.pdf", b"%PDF").unwrap();
+        downloads.quarantine("spam.txt", b"buy").unwrap();
+        assert_eq!(held, dir.join("quarantine").join("report.pdf"));
+        assert_eq!(downloads.quarantined().unwrap(), ["report.pdf", "spam.txt"]);
+
+        let released = downloads.release("report.pdf").unwrap();
+        downloads.discard("spam.txt").unwrap();
+        let left = downloads.quarantined().unwrap();
+        let content = fs::read(&released);
+        let missing = downloads.release("../downloads/report.pdf");
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(released, dir.join("downloads").join("report.pdf"));
+        assert_eq!(content.unwrap(), b"%PDF");
+        assert!(left.is_empty());
+        assert!(missing.is_err(), "only files in quarantine are released");
+    }
+
+    #[test]
+    fn stalled_files_are_given_up_on() {
+        let dir = std::env::temp_dir().join("netchat-never-written");
+        let mut downloads = Downloads::new(dir.clone(), dir.clone(), Blobs::new(dir));
+        let start = Instant::now();
+        let timeout = Duration::from_secs(60);
+        let received = downloads.receive("alice", "big", 0, 3, "00", start);
+        assert_eq!(received, Ok(Received::Progress(1, 3)));
+        let later = start + Duration::from_secs(50);
+        downloads
+            .receive("alice", "big", 1, 3, "01", later)
+            .unwrap();
+        assert!(downloads.expire(start + timeout, timeout).is_empty());
+
+        let stalled = downloads.expire(later + timeout, timeout);
+        assert_eq!(
+            stalled,
+            [Stalled {
+                sender: "alice".to_owned(),
+                name: "big".to_owned(),
+                received: 2,
+                total_chunks: 3,
+            }]
+        );
+        // The last chunk, retransmitted too late, does not start the file over
+        let late = downloads.receive("alice", "big", 2, 3, "02", later + timeout);
+        assert_eq!(late, Ok(Received::Skipped));
+    }
+}
@@ -0,0 +1,89 @@
+//! Chat messages of the previous sessions: every message sent or handed over
+//! to the app is appended to a [`Store`], and the last ones are shown again on
+//! start
+
+use std::io;
+use std::path::Path;
+
+use super::messages::Msg;
+use super::store::{FileStore, Store};
+
+/// The default one keeps nothing
+#[derive(Default)]
+pub struct History {
+    store: Option<Box<dyn Store>>,
+    replay: Vec<Msg>,
+}
+
+impl History {
+    /// Keeps messages in `store`, once its last `replay_len` messages are read back
+    pub fn new(store: Box<dyn Store>, replay_len: usize) -> io::Result<Self> {
+        Ok(History {
+            replay: store.last(replay_len)?,
+            store: Some(store),
+        })
+    }
+
+    /// Appends to the file at `path`, one json line per message
+    pub fn open(path: &Path, replay_len: usize) -> io::Result<Self> {
+        History::new(Box::new(FileStore::open(path)?), replay_len)
+    }
+
+    /// The messages read back on opening, to show again
+    pub fn take_replay(&mut self) -> Vec<Msg> {
+        std::mem::take(&mut self.replay)
+    }
+
+    /// Every message kept, oldest first
+    pub fn messages(&self) -> io::Result<Vec<Msg>> {
+        match &self.store {
+            Some(store) => store.range(0..store.len()?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn append(&mut self, msg: &Msg) {
+        if let Some(store) = &mut self.store {
+            if let Err(e) = store.append(msg) {
+                log::error!("Could not append to the history: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::Channel;
+    use crate::server::messages::Header::Public;
+    use crate::server::Clock;
+    use std::fs;
+
+    #[test]
+    fn last_messages_are_read_back() {
+        let path =
+            std::env::temp_dir().join(format!("netchat-test-{}-history.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut history = History::open(&path, 2).unwrap();
+        assert!(history.take_replay().is_empty());
+        for (id, text) in ["one", "two", "three"].iter().enumerate() {
+            let clock = Clock::new("alice".to_owned());
+            let msg = Msg::new(
+                id as u64,
+                "alice".to_owned(),
+                Public(Channel::default(), text.to_string()),
+                clock,
+            );
+            history.append(&msg);
+        }
+
+        let mut history = History::open(&path, 2).unwrap();
+        let texts: Vec<_> = history
+            .take_replay()
+            .into_iter()
+            .map(|msg| msg.header.summary())
+            .collect();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(texts, ["two", "three"]);
+    }
+}
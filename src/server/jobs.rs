@@ -0,0 +1,69 @@
+//! Long-running operations of the server, which `/cancel` stops between two
+//! of their steps
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Checked by an operation between its steps, set to make it stop
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Operations running, by id, shared by the threads of the server
+#[derive(Clone, Default)]
+pub struct Jobs(Arc<Mutex<HashMap<String, CancelToken>>>);
+
+impl Jobs {
+    /// Registers an operation, it checks the token it is given
+    pub fn start(&self, op_id: &str) -> CancelToken {
+        let token = CancelToken::default();
+        self.lock().insert(op_id.to_owned(), token.clone());
+        token
+    }
+
+    pub fn finish(&self, op_id: &str) {
+        self.lock().remove(op_id);
+    }
+
+    /// Stops an operation, returns false if there is no such operation
+    pub fn cancel(&self, op_id: &str) -> bool {
+        match self.lock().get(op_id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CancelToken>> {
+        self.0.lock().expect("poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_running_jobs_are_cancelled() {
+        let jobs = Jobs::default();
+        assert!(!jobs.cancel("outbox"));
+        let token = jobs.start("outbox");
+        assert!(!token.is_cancelled());
+        assert!(jobs.cancel("outbox"));
+        assert!(token.is_cancelled());
+        jobs.finish("outbox");
+        assert!(!jobs.cancel("outbox"));
+    }
+}
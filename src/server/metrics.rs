@@ -0,0 +1,214 @@
+//! Counters of what the server did since it started, and a few gauges of its
+//! state and queues: shown by `/stats`, served as Prometheus text on
+//! `--metrics-port` for the relays left running, and written as JSON by
+//! `/metrics dump <file>` to attach to a report
+
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Metric {
+    /// Messages of our own written
+    Sent,
+    /// Messages of the others passed on
+    Relayed,
+    /// First copies of the messages of the others
+    Received,
+    /// Copies of messages already had, dropped
+    Duplicated,
+    /// Frames which did not fit, open or decode, and forgeries
+    Dropped,
+    /// Apps in our clock
+    ClockSize,
+    /// Apps online
+    Peers,
+    /// Messages waiting in the outbox for a transport to take them
+    Outbox,
+    /// Private messages waiting for their recipient to be back
+    Queued,
+    /// Messages sent waiting for an acknowledgement
+    Unacked,
+}
+
+pub const METRICS: [Metric; 10] = [
+    Metric::Sent,
+    Metric::Relayed,
+    Metric::Received,
+    Metric::Duplicated,
+    Metric::Dropped,
+    Metric::ClockSize,
+    Metric::Peers,
+    Metric::Outbox,
+    Metric::Queued,
+    Metric::Unacked,
+];
+
+impl Metric {
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Sent => "sent",
+            Metric::Relayed => "relayed",
+            Metric::Received => "received",
+            Metric::Duplicated => "duplicated",
+            Metric::Dropped => "dropped",
+            Metric::ClockSize => "clock_size",
+            Metric::Peers => "peers",
+            Metric::Outbox => "outbox",
+            Metric::Queued => "queued",
+            Metric::Unacked => "unacked",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Metric::Sent => "Messages of our own written",
+            Metric::Relayed => "Messages of the others passed on",
+            Metric::Received => "First copies of the messages of the others",
+            Metric::Duplicated => "Copies of messages already had",
+            Metric::Dropped => "Frames which did not fit, open or decode, and forgeries",
+            Metric::ClockSize => "Apps in the vector clock",
+            Metric::Peers => "Apps online",
+            Metric::Outbox => "Messages waiting in the outbox",
+            Metric::Queued => "Private messages queued for the apps offline",
+            Metric::Unacked => "Messages waiting for an acknowledgement",
+        }
+    }
+
+    fn is_gauge(self) -> bool {
+        !matches!(
+            self,
+            Metric::Sent
+                | Metric::Relayed
+                | Metric::Received
+                | Metric::Duplicated
+                | Metric::Dropped
+        )
+    }
+}
+
+/// Shared by the threads of the server, each counting what it sees
+#[derive(Default)]
+pub struct Metrics {
+    values: [AtomicU64; METRICS.len()],
+}
+
+impl Metrics {
+    pub fn add(&self, metric: Metric) {
+        self.values[metric as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set(&self, metric: Metric, value: u64) {
+        self.values[metric as usize].store(value, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, metric: Metric) -> u64 {
+        self.values[metric as usize].load(Ordering::Relaxed)
+    }
+
+    /// Name and value of each metric, in the order of [`METRICS`]
+    pub fn snapshot(&self) -> Vec<(&'static str, u64)> {
+        METRICS
+            .iter()
+            .map(|metric| (metric.name(), self.get(*metric)))
+            .collect()
+    }
+
+    /// The counters and the gauges by name, as JSON
+    pub fn json(&self) -> serde_json::Value {
+        let (mut counters, mut gauges) = (serde_json::Map::new(), serde_json::Map::new());
+        for metric in METRICS.iter() {
+            let kind = if metric.is_gauge() {
+                &mut gauges
+            } else {
+                &mut counters
+            };
+            kind.insert(metric.name().to_owned(), self.get(*metric).into());
+        }
+        serde_json::json!({ "counters": counters, "gauges": gauges })
+    }
+
+    /// The Prometheus text exposition of the metrics, prefixed with `netchat_`
+    pub fn prometheus(&self) -> String {
+        let mut text = String::new();
+        for metric in METRICS.iter() {
+            let (name, kind) = match metric.is_gauge() {
+                true => (format!("netchat_{}", metric.name()), "gauge"),
+                false => (
+                    format!("netchat_messages_{}_total", metric.name()),
+                    "counter",
+                ),
+            };
+            text.push_str(&format!("# HELP {} {}\n", name, metric.help()));
+            text.push_str(&format!("# TYPE {} {}\n", name, kind));
+            text.push_str(&format!("{} {}\n", name, self.get(*metric)));
+        }
+        text
+    }
+}
+
+/// Answers `GET /metrics` on `port` of the loopback interface, on a thread of
+/// its own. Binds now so a taken port is reported on start.
+pub fn serve(metrics: Arc<Metrics>, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let answered = stream.and_then(|stream| answer(stream, &metrics));
+            if let Err(e) = answered {
+                log::warn!("Could not answer a metrics request: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn answer(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    // The request line is all that matters, the rest of the head is skipped
+    let mut head = Vec::new();
+    let mut buf = [0; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") && head.len() < 8192 {
+        match stream.read(&mut buf)? {
+            0 => break,
+            len => head.extend_from_slice(&buf[..len]),
+        }
+    }
+    let request = String::from_utf8_lossy(&head);
+    let (status, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => ("200 OK", metrics.prometheus()),
+        _ => ("404 Not Found", "GET /metrics\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_are_exposed_as_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.add(Metric::Received);
+        metrics.add(Metric::Received);
+        metrics.set(Metric::Peers, 3);
+        assert_eq!(metrics.snapshot()[2], ("received", 2));
+        let text = metrics.prometheus();
+        assert!(text.contains(
+            "# TYPE netchat_messages_received_total counter\nnetchat_messages_received_total 2\n"
+        ));
+        assert!(text.contains("# TYPE netchat_peers gauge\nnetchat_peers 3\n"));
+        let json = metrics.json();
+        assert_eq!(json["counters"]["received"], 2);
+        assert_eq!(json["gauges"]["peers"], 3);
+        assert!(json["counters"].get("peers").is_none());
+    }
+}
@@ -1,30 +1,127 @@
 use crate::app::AppId;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
-use std::fs::{File, OpenOptions};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::fs::OpenOptions;
 use std::io::Write;
-use std::path::PathBuf;
-use std::sync::mpsc;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
-use rand::{thread_rng, Rng};
+use rand::rngs::SmallRng;
+use rand::{FromEntropy, Rng, RngCore};
 
-use shrinkwraprs::Shrinkwrap;
+pub use netchat_core::{crypto, framing, identity, messages, store, Clock};
 
-pub mod messages;
-use messages::{Date, Header::*, Msg, MsgId};
+use crypto::{from_hex, to_hex, EncryptionKey, FrameKey, PublicKey};
+use messages::{Channel, Codec, Date, Header, Header::*, Msg, MsgId, VersionInfo};
+use netchat_core::dedup::Seen;
+use netchat_core::delivery::{self, Delivered, DeliveryPolicy};
+
+pub mod acceptance;
+use acceptance::{Acceptance, Decision};
+
+pub mod acks;
+use acks::Retransmissions;
+
+pub mod backend;
+use backend::{Endpoint, Sealed};
+
+pub mod blobs;
+use blobs::Blobs;
+
+pub mod discovery;
+use discovery::Advert;
+
+pub mod edits;
+use edits::Authors;
+
+pub mod encryption;
+
+pub mod entropy;
+use entropy::Entropy;
 
 pub mod events;
 use events::{Event, Events};
 
+use framing::Limits;
+
+use identity::{Contacts, DeviceLink, Identity, KeyStatus, RevocationCertificate};
+
+pub mod files;
+use files::{Downloads, Received, Stalled, Upload, MAX_FILE_LEN};
+
+pub mod history;
+use history::History;
+
+pub mod jobs;
+use jobs::{CancelToken, Jobs};
+
+pub mod metrics;
+use metrics::{Metric, Metrics};
+
+pub mod outbox;
+
+pub mod notice;
+use notice::Notice;
+
+pub mod offers;
+use offers::{Offer, Offers};
+
+pub mod output;
+
+pub mod peers;
+use peers::PeerManager;
+
+pub mod presence;
+use presence::Presence;
+
+pub mod queued;
+use queued::Queued;
+
+pub mod ratelimit;
+use ratelimit::{RateLimiter, Verdict};
+
+pub mod reconnect;
+use reconnect::ReconnectManager;
+
+pub mod receipts;
+use receipts::Receipts;
+
+pub mod recorder;
+use recorder::{Recorder, Step};
+
+pub mod tcp;
+use tcp::Socket;
+
+pub mod unix;
+
+pub mod timer;
+use timer::{RealTime, Timer};
+
+pub mod timesync;
+use timesync::TimeSync;
+
+pub mod transport;
+use transport::{Command, Transport};
+
+use crate::app::channel::Sender as AppSender;
+use crate::app::chanstats::ChannelStats;
 use crate::app::events::Event as AppEvent;
 
-#[derive(Shrinkwrap, Clone, Serialize, Deserialize, Debug, PartialEq)]
-#[shrinkwrap(mutable)]
-pub struct Clock(pub HashMap<AppId, Date>);
+/// How long the other apps have to answer a snapshot request
+const SNAPSHOT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Typing messages whose ids are remembered, they come by the burst and
+/// their copies soon after
+const TYPING_SEEN: Date = 1_000;
+
+/// Serialized messages sent to a linked device at most in one history, a
+/// part of the frame length once sealed and hex encoded
+const HISTORY_CHUNK_LEN: usize = 256 * 1024;
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Snapshot {
@@ -39,30 +136,71 @@ pub struct Snapshot {
 pub struct Server {
     app_id: AppId,
     clock: Clock,
-    sent_messages_ids: HashSet<MsgId>,
+    departed: HashSet<AppId>, // Left, their dates in the clocks of the others are stale until they connect again
+    sent_messages_ids: Seen,
+    typing_ids: Seen, // Apart, typing messages come often and do not matter for long
     snapshot: Snapshot,
+    snapshot_dir: PathBuf,
     saved_messages: Vec<Msg>, //Saved messages - will be used to build snapshot
-}
-
-// Vector Clock implementation
-impl Clock {
-    fn new(app_id: AppId) -> Self {
-        let mut map = HashMap::new();
-        map.insert(app_id.to_owned(), 0);
-        Clock(map)
-    }
-
-    fn merge(&mut self, clock: &Self) {
-        for (id, date) in &clock.0 {
-            match self.get(id) {
-                // Clock is updated only if it contains an older date
-                Some(local_date) if local_date >= date => {}
-                _ => {
-                    self.insert(id.to_owned(), date.to_owned());
-                }
-            }
-        }
-    }
+    history_synced: HashMap<AppId, usize>, // Saved messages sent to each linked device already
+    identity: Identity,
+    identity_path: PathBuf,
+    contacts: Contacts,
+    encryption_keys: HashMap<AppId, EncryptionKey>, // Announced by the others, checked against their identity key
+    unencrypted: HashSet<AppId>, // Recipients already told about getting private messages in clear
+    encryption: encryption::Policy, // Whether private messages may go in clear
+    revocations: Vec<RevocationCertificate>, // Broadcast once connected
+    owner: Option<AppId>,        // Identity this app is a device of
+    link_requests: Vec<(AppId, PublicKey)>, // Devices asking to be linked to us
+    peers: HashMap<AppId, VersionInfo>, // What each peer said it runs
+    undecodable_senders: HashSet<AppId>, // Peers already reported as sending unknown messages
+    forged_senders: HashSet<AppId>, // Apps already reported as impersonated
+    limits: Limits,
+    dropped_frames: u64, // Inbound lines dropped for being too long or not sealed
+    fast_relay: bool,    // Skip duplicates before decoding them entirely
+    metrics: Arc<Metrics>,
+    metrics_port: Option<u16>,     // Serving them as Prometheus text
+    wire_format: Codec,            // Preferred, used once every peer reads it
+    codec: Codec,                  // What the transport sends
+    compress_above: Option<usize>, // Frames longer are compressed, once every peer reads them
+    compressing: bool,
+    observe: bool, // Never sends anything of ours, relays messages untouched
+    lurking: bool, // Not joined yet, until the first message typed
+    heartbeat: Option<Duration>,
+    presence: Presence,           // Apps heard from lately
+    queued: Queued,               // Private messages for the apps offline
+    time_sync: TimeSync,          // How far the clocks of the others are off
+    rng: Box<dyn RngCore + Send>, // Source of message ids
+    timer: Arc<dyn Timer>,
+    delivery: Box<dyn DeliveryPolicy>,
+    hold_timeout: Option<Duration>, // Held messages are handed over after it
+    retransmissions: Retransmissions,
+    goodbye_timeout: Duration, // Waiting for the last acknowledgements on shutdown
+    receipts: Receipts,        // Of the private messages sent, and of those shown
+    authors: Authors,          // Of the public messages, for their edits
+    rate_limiter: Option<RateLimiter>, // Of the messages of each sender, None for no limit
+    entropy: Entropy,          // Chat messages kept for the apps that missed them
+    sync_interval: Option<Duration>,
+    discovery: Option<Option<Advert>>, // Browsing the local network, advertising us if listening
+    discovered: BTreeMap<AppId, SocketAddr>,
+    delivered: Delivered, // Chat messages handed over to the app, from each app
+    recorder: Recorder,
+    history: History,
+    operators: HashSet<AppId>, // Identities whose announcements are shown as such
+    motd: Option<String>,      // Sent privately to each app joining
+    frame_key: Option<FrameKey>, // Seals the frames on the pipes
+    jobs: Jobs,                // Long-running operations, to cancel them
+    uploads: VecDeque<(String, Upload, CancelToken)>, // Files sent, the first one a chunk at a time
+    downloads: Downloads,
+    blobs: Blobs, // Content of the files received and offered, served to who fetches them
+    offers: Offers, // Files offered in the channels, and those being fetched
+    acceptance: Acceptance, // Where the files received go
+    file_timeout: Option<Duration>, // Files received are given up on when no chunk came for it
+    services: Vec<String>, // What we offer
+    nick: Option<String>, // What we are shown as, told to each app joining
+    channels: BTreeSet<Channel>, // Joined, their public messages are handed over to the app
+    slow_mode: BTreeMap<Channel, u64>, // Set by our `/slow`, told to each app joining
+    directory: HashMap<AppId, Vec<String>>, // What the others offer
 }
 
 impl Snapshot {
@@ -77,13 +215,15 @@ impl Snapshot {
     pub fn add(&mut self, msg: Msg) {
         // We store the snapshot sending date from each app
         // Will be used to ensure snpashot consistency
+        let date = match msg.clock.get(&msg.sender_id) {
+            Some(date) => *date,
+            None => {
+                log::error!("Inconsistent snapshot : missing sender date in vector clock");
+                return;
+            }
+        };
         if let Entry::Vacant(v) = self.dates.entry(msg.sender_id.to_owned()) {
-            v.insert(
-                msg.clock
-                    .get(&msg.sender_id)
-                    .expect("Inconsistent message : missing sender date in vector clock")
-                    .to_owned(),
-            );
+            v.insert(date);
             if let messages::Header::SnapshotResponse(_, messages) = msg.header {
                 self.messages.insert(msg.sender_id, messages);
             }
@@ -91,9 +231,10 @@ impl Snapshot {
             log::error!("received snapshot twice from the same App");
         }
     }
-    pub fn dump(&mut self, saving_date: Date) {
+    /// Writes the snapshot and the message history in `dir`
+    pub fn dump(&mut self, saving_date: Date, dir: &Path) {
         let mut unique_messages = HashSet::new();
-        for (id, messages) in self.messages.to_owned() {
+        for (id, messages) in self.messages.clone() {
             let mut consistent_msgs = Vec::new();
             let local_sender_date = self
                 .dates
@@ -101,17 +242,14 @@ impl Snapshot {
                 .expect("Found messages without snapshot sending date");
             for m in messages {
                 // We ensure snapshot consistency by removing messages created
-                // after snapshot sending date
-                let sender_date = m
-                    .clock
-                    .get(&id)
-                    .expect("Inconsistent message : missing sender date in vector clock");
-                if sender_date <= local_sender_date {
+                // after snapshot sending date, a missing date means it predates them
+                let sender_date = m.clock.get(&id).copied().unwrap_or(0);
+                if sender_date <= *local_sender_date {
                     consistent_msgs.push(m.to_owned());
                 }
             }
             self.messages.insert(id, consistent_msgs.clone());
-            for m in consistent_msgs.to_owned() {
+            for m in consistent_msgs {
                 if !(unique_messages.contains(&m.id)) {
                     unique_messages.insert(m.id);
                     self.msg_history.push(m);
@@ -121,35 +259,25 @@ impl Snapshot {
         let self_id = self.local_id.clone();
 
         // Sorting messages using vector clocks to build a consistent message history
+        // Dates missing from a clock count as 0, snapshots come from other apps
+        let date = |m: &Msg, id: &AppId| m.clock.get(id).copied().unwrap_or(0);
         self.msg_history.sort_by(|a, b| {
             // First, we sort by local date (date of the snapshot requester)
-            if a.clock.get(&self_id).unwrap() == b.clock.get(&self_id).unwrap() {
+            if date(a, &self_id) == date(b, &self_id) {
                 if b.clock.contains_key(&a.sender_id)
-                    && a.clock.get(&a.sender_id).unwrap() != b.clock.get(&a.sender_id).unwrap()
+                    && date(a, &a.sender_id) != date(b, &a.sender_id)
                 {
                     // Then if possible we sort by date of app a
-                    a.clock
-                        .get(&a.sender_id)
-                        .unwrap()
-                        .cmp(b.clock.get(&a.sender_id).unwrap())
+                    date(a, &a.sender_id).cmp(&date(b, &a.sender_id))
                 } else if a.clock.contains_key(&b.sender_id) {
                     // Else if possible by date of app b
-                    a.clock
-                        .get(&b.sender_id)
-                        .unwrap()
-                        .cmp(&b.clock.get(&b.sender_id).unwrap())
+                    date(a, &b.sender_id).cmp(&date(b, &b.sender_id))
                 } else {
                     // Otherwise the two messages have the same date
-                    a.clock
-                        .get(&self_id)
-                        .unwrap()
-                        .cmp(&b.clock.get(&self_id).unwrap())
+                    date(a, &self_id).cmp(&date(b, &self_id))
                 }
             } else {
-                a.clock
-                    .get(&self_id)
-                    .unwrap()
-                    .cmp(&b.clock.get(&self_id).unwrap())
+                date(a, &self_id).cmp(&date(b, &self_id))
             }
         });
 
@@ -158,7 +286,7 @@ impl Snapshot {
             .write(true)
             .create(true)
             .truncate(true)
-            .open("snapshot.json")
+            .open(dir.join("snapshot.json"))
             .expect("Failed to create snapshot file");
 
         if let Ok(snapshot_str) = serde_json::to_string_pretty(self) {
@@ -175,7 +303,7 @@ impl Snapshot {
             .write(true)
             .create(true)
             .truncate(true)
-            .open("history.json")
+            .open(dir.join("history.json"))
             .expect("Failed to create history file");
 
         if let Ok(history_str) = serde_json::to_string_pretty(&self.msg_history) {
@@ -194,263 +322,3502 @@ impl Snapshot {
 }
 
 impl Server {
-    pub fn new(app_id: AppId) -> Self {
+    pub fn new(
+        app_id: AppId,
+        identity: Identity,
+        identity_path: &Path,
+        contacts: Contacts,
+    ) -> Self {
         Server {
             app_id: app_id.clone(),
             clock: Clock::new(app_id.clone()),
-            sent_messages_ids: HashSet::new(),
+            departed: HashSet::new(),
+            sent_messages_ids: Seen::default(),
+            typing_ids: Seen::with_horizon(TYPING_SEEN),
             snapshot: Snapshot::new(app_id),
+            snapshot_dir: PathBuf::new(),
             saved_messages: Vec::new(),
+            history_synced: HashMap::new(),
+            identity,
+            identity_path: identity_path.to_owned(),
+            contacts,
+            encryption_keys: HashMap::new(),
+            unencrypted: HashSet::new(),
+            encryption: encryption::Policy::Prefer,
+            revocations: Vec::new(),
+            owner: None,
+            link_requests: Vec::new(),
+            peers: HashMap::new(),
+            undecodable_senders: HashSet::new(),
+            forged_senders: HashSet::new(),
+            limits: Limits::default(),
+            dropped_frames: 0,
+            fast_relay: false,
+            metrics: Arc::default(),
+            metrics_port: None,
+            wire_format: Codec::Json,
+            codec: Codec::Json,
+            compress_above: None,
+            compressing: false,
+            observe: false,
+            lurking: false,
+            heartbeat: None,
+            presence: Presence::default(),
+            queued: Queued::default(),
+            time_sync: TimeSync::default(),
+            rng: Box::new(SmallRng::from_entropy()),
+            timer: Arc::new(RealTime),
+            delivery: Box::new(delivery::Arrival),
+            hold_timeout: None,
+            retransmissions: Retransmissions::new(0, Duration::from_secs(5)),
+            goodbye_timeout: Duration::from_secs(3),
+            receipts: Receipts::default(),
+            authors: Authors::default(),
+            rate_limiter: None,
+            entropy: Entropy::default(),
+            sync_interval: None,
+            discovery: None,
+            discovered: BTreeMap::new(),
+            delivered: Delivered::default(),
+            recorder: Recorder::default(),
+            history: History::default(),
+            operators: HashSet::new(),
+            motd: None,
+            frame_key: None,
+            jobs: Jobs::default(),
+            uploads: VecDeque::new(),
+            downloads: Downloads::new(
+                PathBuf::from("downloads"),
+                PathBuf::from("quarantine"),
+                Blobs::new(PathBuf::from("blobs")),
+            ),
+            blobs: Blobs::new(PathBuf::from("blobs")),
+            offers: Offers::default(),
+            acceptance: Acceptance::default(),
+            file_timeout: None,
+            services: Vec::new(),
+            nick: None,
+            channels: std::iter::once(Channel::default()).collect(),
+            slow_mode: BTreeMap::new(),
+            directory: HashMap::new(),
         }
     }
 
-    fn get_date(&self) -> Date {
-        *self.clock.get(&self.app_id).expect("missing local app_id")
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
     }
 
-    fn increment_clock(&mut self) {
-        let date = self.clock.entry(self.app_id.to_owned()).or_insert(0);
-        *date += 1;
+    /// Joins without ever sending: the others do not know we are there, what
+    /// we receive is relayed without our clock
+    pub fn set_observe(&mut self, enabled: bool) {
+        self.observe = enabled;
     }
 
-    fn send_message(&mut self, msg: &Msg, output_file: &mut File, app_tx: &mpsc::Sender<AppEvent>) {
-        if let Ok(msg_str) = msg.serialize() {
-            if let Ok(_) = output_file.write_all(format!("{}\n", msg_str).as_bytes()) {
-                log::info!(
-                    "sent, local date: {}, messsage: {:?}",
-                    self.get_date(),
-                    msg.header
-                );
-            } else {
-                send_to_app(
-                    AppEvent::ServerMessage("No one can hear you".to_owned()),
-                    app_tx,
-                );
-                log::error!("Failed to write to output file");
+    /// Joins only when the user sends a first message: until then nothing
+    /// tells the others we are there, no heartbeat nor acknowledgement
+    pub fn set_lurk(&mut self, enabled: bool) {
+        self.lurking = enabled;
+    }
+
+    /// Remembers the ids of the messages seen during the last `horizon` local dates
+    pub fn set_seen_horizon(&mut self, horizon: Date) {
+        self.sent_messages_ids = Seen::with_horizon(horizon);
+    }
+
+    /// Decode only the id of incoming messages until they are known to be new
+    pub fn set_fast_relay(&mut self, enabled: bool) {
+        self.fast_relay = enabled;
+    }
+
+    /// Serve the metrics as Prometheus text on `port` of the loopback interface
+    pub fn set_metrics_port(&mut self, port: u16) {
+        self.metrics_port = Some(port);
+    }
+
+    /// Sends messages with `codec` once every app heard of said it reads it
+    pub fn set_wire_format(&mut self, codec: Codec) {
+        self.wire_format = codec;
+    }
+
+    /// Compresses the frames longer than `bytes` once every app heard of
+    /// said it reads them
+    pub fn set_compress_above(&mut self, bytes: usize) {
+        self.compress_above = Some(bytes);
+    }
+
+    /// Tell the others we are alive every `interval`, even when silent
+    pub fn set_heartbeat(&mut self, interval: Duration) {
+        self.heartbeat = Some(interval);
+    }
+
+    /// Tell the neighbours which chat messages we have every `interval`, for
+    /// them to send back those we missed
+    pub fn set_sync_interval(&mut self, interval: Duration) {
+        self.sync_interval = Some(interval);
+    }
+
+    /// Look for the apps listening on the local network over mDNS, and answer
+    /// for us with `advert` if we listen too
+    pub fn set_discovery(&mut self, advert: Option<Advert>) {
+        self.discovery = Some(advert);
+    }
+
+    /// Apps not heard from for `timeout` are told to the app as offline
+    pub fn set_presence_timeout(&mut self, timeout: Duration) {
+        self.presence = Presence::new(Some(timeout));
+    }
+
+    /// Hands over the messages the delivery policy held for longer than
+    /// `timeout`, at most twice as long
+    pub fn set_hold_timeout(&mut self, timeout: Duration) {
+        self.hold_timeout = Some(timeout);
+    }
+
+    /// Writes the snapshots in `dir` instead of the current directory
+    pub fn set_snapshot_dir(&mut self, dir: PathBuf) {
+        self.snapshot_dir = dir;
+    }
+
+    /// Writes the files accepted in `dir` instead of `./downloads`, those
+    /// waiting for the user in `quarantine` instead of `./quarantine`, and
+    /// their content once in `blobs` instead of `./blobs`
+    pub fn set_download_dirs(&mut self, dir: PathBuf, quarantine: PathBuf, blobs: PathBuf) {
+        self.blobs = Blobs::new(blobs);
+        self.downloads = Downloads::new(dir, quarantine, self.blobs.clone());
+    }
+
+    /// Decides with `acceptance` which files received are accepted
+    pub fn set_acceptance(&mut self, acceptance: Acceptance) {
+        self.acceptance = acceptance;
+    }
+
+    /// Gives up on the files being received when no chunk of them came for
+    /// `timeout`
+    pub fn set_file_timeout(&mut self, timeout: Duration) {
+        self.file_timeout = Some(timeout);
+    }
+
+    /// Sends the chat messages nobody acknowledged again after `timeout`,
+    /// `attempts` times, then tells the app they were not delivered
+    pub fn set_retransmission(&mut self, attempts: u32, timeout: Duration) {
+        self.retransmissions = Retransmissions::new(attempts, timeout);
+    }
+
+    /// Waits `timeout` at most on shutdown for the acknowledgements of the
+    /// messages sent, before saying goodbye
+    pub fn set_goodbye_timeout(&mut self, timeout: Duration) {
+        self.goodbye_timeout = timeout;
+    }
+
+    /// Draws message ids from `rng`, a seeded one makes runs reproducible
+    pub fn set_rng(&mut self, rng: impl RngCore + Send + 'static) {
+        self.rng = Box::new(rng);
+    }
+
+    /// Takes the time from `timer` instead of the system clock
+    #[cfg(test)]
+    pub fn set_timer(&mut self, timer: Arc<dyn Timer>) {
+        self.timer = timer;
+    }
+
+    /// Hands chat messages over to the app in the order of `policy`
+    pub fn set_delivery(&mut self, policy: Box<dyn DeliveryPolicy>) {
+        self.delivery = policy;
+    }
+
+    /// Keeps the chat messages in `history`, and shows those it has from the
+    /// previous sessions on start
+    pub fn set_history(&mut self, history: History) {
+        self.history = history;
+    }
+
+    /// Records the session with `recorder`, for `netchat replay`
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = recorder;
+    }
+
+    /// Only announcements signed by these identities, or their devices, are
+    /// shown as announcements, the others as public messages
+    pub fn set_operators(&mut self, operators: HashSet<AppId>) {
+        self.operators = operators;
+    }
+
+    /// Drops the messages of a sender above `rate` a second, `burst` at once
+    pub fn set_rate_limit(&mut self, rate: u32, burst: u32) {
+        self.rate_limiter = (rate > 0).then(|| RateLimiter::new(rate, burst));
+    }
+
+    /// Whether private messages which cannot be encrypted are sent in clear
+    pub fn set_encryption(&mut self, policy: encryption::Policy) {
+        self.encryption = policy;
+    }
+
+    /// Seals every frame written with `key`, and drops the input lines which
+    /// do not open with it
+    pub fn set_frame_key(&mut self, key: FrameKey) {
+        self.frame_key = Some(key);
+    }
+
+    /// Message of the day, sent privately to each app joining
+    pub fn set_motd(&mut self, motd: String) {
+        self.motd = Some(motd);
+    }
+
+    /// Name we are shown as, sent on joining as if set with `/nick`
+    pub fn set_nick(&mut self, nick: String) {
+        self.nick = Some(nick);
+    }
+
+    /// Services offered to the others, listed by their `/services`
+    pub fn set_services(&mut self, services: Vec<String>) {
+        self.services = services;
+    }
+
+    /// Whether `msg` is an announcement or a slow mode its sender may make
+    fn may_announce(&self, msg: &Msg) -> bool {
+        let sender = &msg.sender_id;
+        match &msg.header {
+            Announcement(text, signature) => {
+                self.operators.contains(self.contacts.identity_of(sender))
+                    && self.contacts.is_signed_by(
+                        sender,
+                        &identity::announcement_payload(sender, text),
+                        signature,
+                    )
             }
-        } else {
-            log::error!("Could not serialize `{:?}`", msg);
+            SlowMode(channel, seconds, signature) => {
+                self.operators.contains(self.contacts.identity_of(sender))
+                    && self.contacts.is_signed_by(
+                        sender,
+                        &identity::slow_mode_payload(sender, channel, *seconds),
+                        signature,
+                    )
+            }
+            _ => false,
         }
     }
 
-    fn receive_message(
-        &mut self,
-        msg: &mut Msg,
-        output_file: &mut File,
-        app_tx: &mpsc::Sender<AppEvent>,
-    ) {
-        self.clock.merge(&msg.clock);
-        log::info!(
-            "received, local date: {}, messsage: {:?}",
-            self.get_date(),
-            msg.header
-        );
-        msg.clock = self.clock.clone();
-        self.send_message(msg, output_file, app_tx);
+    /// Makes this app a device of the `owner` identity, once the owner accepts
+    pub fn link_to(&mut self, owner: AppId) {
+        self.owner = Some(owner);
     }
-}
 
-pub fn send_to_app(msg: AppEvent, app_tx: &mpsc::Sender<AppEvent>) {
-    app_tx.send(msg).expect("Could not send message to the app");
-}
+    /// Logical identity of this app, shared with its linked devices
+    fn identity(&self) -> &AppId {
+        self.owner.as_ref().unwrap_or(&self.app_id)
+    }
 
-pub fn run(
-    mut server: Server,
-    app_rx: mpsc::Receiver<Event>,
-    app_tx: mpsc::Sender<AppEvent>,
-    input_file_path: PathBuf,
-    output_file_path: PathBuf,
-) -> Result<(), Box<dyn std::error::Error>> {
-    // Channel to asynchronously speak to itself
-    let (self_tx, server_rx) = mpsc::channel();
+    /// Whether messages addressed to `app_id` must be delivered here
+    fn is_for_me(&self, app_id: &str) -> bool {
+        app_id == self.app_id || app_id == self.identity()
+    }
 
-    // 1 Setup event handlers
-    let events = Events::new(input_file_path.to_owned(), app_rx, server_rx);
+    /// Tells the app about the apps `msg` shows are online, or the one it shows
+    /// left. Its sender gets the private messages queued for it.
+    fn update_presence(&mut self, msg: &Msg, transport: &transport::Handle, app_tx: &AppSender) {
+        let now = self.timer.now();
+        let heard = match &msg.header {
+            Disconnection => {
+                if self.presence.left(&msg.sender_id) {
+                    send_to_app(AppEvent::PeerOffline(msg.sender_id.clone()), app_tx);
+                }
+                return;
+            }
+            // Merged heartbeats stand for every app they list
+            Heartbeat(app_ids) => app_ids.iter().chain(Some(&msg.sender_id)).collect(),
+            _ => vec![&msg.sender_id],
+        };
+        for app_id in heard {
+            if *app_id != self.app_id && self.presence.seen(app_id, now) {
+                send_to_app(AppEvent::PeerOnline(app_id.clone()), app_tx);
+            }
+        }
+        // Those arriving get their messages with their encryption key
+        if !matches!(msg.header, Connection | Hello(_) | KeyAnnouncement(_)) {
+            self.send_queued(&msg.sender_id, transport, app_tx);
+        }
+    }
 
-    // 2 Open the output pipe,
-    // the program will freeze until there is someone at the other end
-    let mut output_file = OpenOptions::new()
-        .write(true)
-        .append(true)
-        .open(output_file_path.to_owned())
-        .expect("failed to open output file");
-
-    let mut rng = thread_rng();
-
-    let msg_id: MsgId = rng.gen();
-    server.sent_messages_ids.insert(msg_id.clone());
-    server.increment_clock();
-    let msg = Msg::new(
-        msg_id,
-        server.app_id.clone(),
-        Connection,
-        server.clock.clone(),
-    );
-    server.send_message(&msg, &mut output_file, &app_tx);
+    /// Sends the private messages kept while `app_id` was offline, sealed
+    /// with the key it announced since
+    fn send_queued(&mut self, app_id: &str, transport: &transport::Handle, app_tx: &AppSender) {
+        let queued = self.queued.release(app_id);
+        if queued.is_empty() {
+            return;
+        }
+        for text in &queued {
+            self.send_private(app_id.to_owned(), text.clone(), transport, app_tx);
+        }
+        let notice = Notice::info(
+            "queue-sent",
+            format!("{} is back, queued messages sent: {}", app_id, queued.len()),
+        );
+        self.notify(notice.with("recipient", app_id), app_tx);
+    }
 
-    let mut is_waiting_for_snapshot = false;
+    /// Whether `msg` gets through the rate limit of its sender. The user is
+    /// told when the sender starts being throttled, and once it calmed down.
+    fn within_rate(&mut self, msg: &Msg, app_tx: &AppSender) -> bool {
+        let limited = delivery::is_chat(&msg.header)
+            || matches!(msg.header, Typing | LiveLine(_) | Edit(..) | Delete(_));
+        let now = self.timer.now();
+        let verdict = match &mut self.rate_limiter {
+            Some(limiter) if limited && msg.sender_id != self.app_id => {
+                limiter.take(&msg.sender_id, now)
+            }
+            _ => return true,
+        };
+        let notice = match verdict {
+            Verdict::Pass => return true,
+            Verdict::Dropped => return false,
+            Verdict::Calmed(dropped) => Notice::info(
+                "throttle-ended",
+                format!(
+                    "{} slowed down, {} of its messages were dropped",
+                    msg.sender_id, dropped
+                ),
+            )
+            .with("dropped", dropped),
+            Verdict::Throttled => Notice::warning(
+                "throttled",
+                format!("{} sends too fast, its messages are dropped", msg.sender_id),
+            ),
+        };
+        self.notify(notice.with("app", &msg.sender_id), app_tx);
+        matches!(verdict, Verdict::Calmed(_))
+    }
 
-    loop {
-        // Handle events
-        match events.next()? {
-            // User / Server commands
-            //-----------------------
-            Event::UserPublicMessage(message) => {
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    Public(message),
-                    server.clock.clone(),
+    /// Edits our public message `id` with `text`, or deletes it for None
+    fn change_own(
+        &mut self,
+        id: MsgId,
+        text: Option<String>,
+        transport: &transport::Handle,
+        app_tx: &AppSender,
+    ) {
+        if self.authors.of(id) != Some(self.app_id.as_str()) {
+            let notice = Notice::warning(
+                "change-refused",
+                "Not changed: the message is not ours, or too old".to_owned(),
+            );
+            self.notify(notice, app_tx);
+            return;
+        }
+        let header = match &text {
+            Some(text) => Edit(id, text.clone()),
+            None => Delete(id),
+        };
+        let msg = self.new_message(header);
+        transport.send(&msg);
+        self.sent(msg);
+        self.show_change(id, text, app_tx);
+    }
+
+    /// Hands over the edit or the deletion of message `id` brought by `msg`,
+    /// only if the sender of `msg` wrote it
+    fn take_change(&mut self, msg: &Msg, id: MsgId, app_tx: &AppSender) {
+        match self.authors.of(id) {
+            Some(author) if author == msg.sender_id => {}
+            Some(author) => {
+                let notice = Notice::warning(
+                    "change-refused",
+                    format!("Ignored {} changing a message of {}", msg.sender_id, author),
                 );
-                server.send_message(&msg, &mut output_file, &app_tx);
-                server.saved_messages.push(msg);
+                self.notify(notice.with("app", &msg.sender_id), app_tx);
+                self.metrics.add(Metric::Dropped);
+                return;
             }
-            Event::UserPrivateMessage(app_id, message) => {
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    Private(app_id, message),
-                    server.clock.clone(),
+            None => {
+                log::debug!(
+                    "{} changed message {}, we do not have it",
+                    msg.sender_id,
+                    id
                 );
-                server.send_message(&msg, &mut output_file, &app_tx);
-                server.saved_messages.push(msg);
-            }
-            Event::GetClock => {
-                send_to_app(AppEvent::DisplayClock(server.clock.clone()), &app_tx);
+                return;
             }
-            Event::Shutdown => {
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    Disconnection,
-                    server.clock.clone(),
-                );
-                server.send_message(&msg, &mut output_file, &app_tx);
-                break;
+        }
+        self.history.append(msg);
+        let text = match &msg.header {
+            Edit(_, text) => Some(text.clone()),
+            _ => None,
+        };
+        self.show_change(id, text, app_tx);
+    }
+
+    fn show_change(&mut self, id: MsgId, text: Option<String>, app_tx: &AppSender) {
+        match text {
+            Some(text) => send_to_app(AppEvent::Edited(id, text), app_tx),
+            None => {
+                self.authors.forget(id);
+                send_to_app(AppEvent::Deleted(id), app_tx);
             }
-            Event::GetSnapshot => {
-                is_waiting_for_snapshot = true;
+        }
+    }
 
-                let msg_id: MsgId = rng.gen();
-                server.sent_messages_ids.insert(msg_id.clone());
-                server.increment_clock();
-                let msg = Msg::new(
-                    msg_id,
-                    server.app_id.clone(),
-                    SnapshotRequest(server.app_id.to_owned()),
-                    server.clock.clone(),
-                );
-                server.send_message(&msg, &mut output_file, &app_tx);
-                server.saved_messages.push(msg.clone());
+    /// Writes the metrics to `path` as JSON, with the private messages queued
+    /// for each app offline
+    fn dump_metrics(&self, path: &Path) -> std::io::Result<()> {
+        let mut dump = self.metrics.json();
+        dump["app_id"] = self.app_id.clone().into();
+        dump["written_at"] = wall_clock().into();
+        dump["queued"] = serde_json::json!(self.queued.counts());
+        let json = serde_json::to_string_pretty(&dump)?;
+        std::fs::write(path, json + "\n")
+    }
 
-                // Set up timeout
-                let self_tx = self_tx.clone();
-                thread::spawn(move || {
-                    thread::sleep(Duration::from_secs(5));
-                    self_tx.send(Event::SnapshotTimeout).unwrap();
-                });
+    /// Whether `msg` is a chat message we must tell its sender we received
+    fn is_to_ack(&self, msg: &Msg) -> bool {
+        if self.lurking {
+            return false;
+        }
+        match &msg.header {
+            Private(app_id, _)
+            | Encrypted(app_id, _)
+            | File { to: app_id, .. }
+            | EncryptedFile { to: app_id, .. } => self.is_for_me(app_id),
+            header => delivery::is_chat(header),
+        }
+    }
 
-                // Adding local messages and clock to snapshot
-                server.snapshot.dates.insert(
-                    server.app_id.clone(),
-                    *server
-                        .clock
-                        .get(&server.app_id)
-                        .expect("Missing server date !"),
-                );
+    /// Whether `app_id` is another device of our identity
+    fn is_sibling(&self, app_id: &str) -> bool {
+        app_id != self.app_id && self.contacts.identity_of(app_id) == self.identity()
+    }
 
-                server
-                    .snapshot
-                    .messages
-                    .insert(server.app_id.clone(), server.saved_messages.clone());
+    /// Sends `device`, another device of ours, the saved messages it lacks
+    fn sync_history(
+        &mut self,
+        device: &AppId,
+        clock: Option<&Clock>,
+        transport: &transport::Handle,
+    ) {
+        for header in self.history_for(device, clock) {
+            let msg = self.new_message(header);
+            transport.send(&msg);
+        }
+    }
+
+    /// The saved messages `device` lacks: those not sent to it yet, which it
+    /// did not see by its `clock` when it just connected, or saw sealed for
+    /// another key. They go sealed with its key, their private messages
+    /// decrypted, in chunks a frame holds.
+    fn history_for(&mut self, device: &AppId, clock: Option<&Clock>) -> Vec<Header> {
+        let synced = self.history_synced.get(device).copied().unwrap_or(0);
+        let date = |clock: &Clock, app_id: &str| clock.get(app_id).copied().unwrap_or(0);
+        let lacks = |m: &Msg| match (&m.header, clock) {
+            (Encrypted(to, _), _) if to != device => true,
+            (_, Some(clock)) => date(clock, &m.sender_id) < date(&m.clock, &m.sender_id),
+            (_, None) => true,
+        };
+        let missing: Vec<Msg> = self.saved_messages[synced.min(self.saved_messages.len())..]
+            .iter()
+            .filter(|m| lacks(m))
+            .cloned()
+            .collect();
+        let reads = |info: &VersionInfo| info.features.iter().any(|f| f == "encrypted-history");
+        let key = match self.encryption_keys.get(device) {
+            Some(key) if self.peers.get(device).is_some_and(reads) => Some(*key),
+            _ => None,
+        };
+        if key.is_none() && self.encryption == encryption::Policy::Require {
+            log::warn!("{} reads no encrypted history, none sent", device);
+            return Vec::new();
+        }
+        self.history_synced
+            .insert(device.clone(), self.saved_messages.len());
+        if missing.is_empty() {
+            return Vec::new();
+        }
+        let missing: Vec<Msg> = match key {
+            Some(_) => missing
+                .into_iter()
+                .map(|m| self.decrypted(m.clone()).unwrap_or(m))
+                .collect(),
+            None => missing,
+        };
+        let budget = HISTORY_CHUNK_LEN.min(self.limits.max_frame_len / 4);
+        let mut chunks: Vec<Vec<Msg>> = vec![Vec::new()];
+        let mut len = 0;
+        for m in missing {
+            let size = m.serialize().map_or(0, |line| line.len());
+            if len + size > budget && !chunks[chunks.len() - 1].is_empty() {
+                chunks.push(Vec::new());
+                len = 0;
             }
-            Event::SnapshotTimeout => {
-                if is_waiting_for_snapshot {
-                    is_waiting_for_snapshot = false;
-                    // Writing snapshot to file
-                    server.snapshot.dump(server.get_date());
+            len += size;
+            chunks.last_mut().expect("one at least").push(m);
+        }
+        chunks
+            .into_iter()
+            .filter_map(|chunk| match &key {
+                Some(key) => self
+                    .identity
+                    .encrypt_history(&self.app_id, device, key, &chunk)
+                    .map(|sealed| EncryptedHistory(device.clone(), sealed)),
+                None => Some(HistorySync(device.clone(), chunk)),
+            })
+            .collect()
+    }
 
-                    send_to_app(
-                        AppEvent::ServerMessage("Snapshot saved".to_owned()),
-                        &app_tx,
-                    );
+    /// Takes the past `messages` another device of ours sent
+    fn take_history(&mut self, messages: &[Msg], app_tx: &AppSender) {
+        // Skip what we already received live
+        let now = self.get_date();
+        let missing: Vec<Msg> = messages
+            .iter()
+            .filter(|m| self.sent_messages_ids.insert_at(m.id, now))
+            .cloned()
+            .collect();
+        self.saved_messages.extend(missing.iter().cloned());
+        // Those encrypted by or for the other device in a clear history do not decrypt here
+        let missing: Vec<Msg> = missing
+            .into_iter()
+            .filter_map(|m| self.decrypted(m))
+            .collect();
+        for msg in &missing {
+            self.history.append(msg);
+        }
+        send_to_app(AppEvent::History(missing), app_tx);
+    }
 
-                    // emptying local snapshot save
-                    server.snapshot = Snapshot::new(server.app_id.clone());
-                }
-            }
-            // Input from a distant app
-            //-------------------------
-            Event::DistantInput(msg) => {
-                if let Ok(mut msg) = Msg::from_str(&msg) {
-                    // If we receive this message for the first time
-                    if server.sent_messages_ids.insert(msg.id.clone()) {
-                        server.increment_clock();
-                        server.receive_message(&mut msg, &mut output_file, &app_tx);
-
-                        match &msg.header {
-                            Public(_) => {
-                                send_to_app(AppEvent::DistantMessage(msg), &app_tx);
-                            }
-                            Private(app_id, _) if *app_id == server.app_id => {
-                                send_to_app(AppEvent::DistantMessage(msg.clone()), &app_tx);
-                                server.saved_messages.push(msg);
-                            }
-                            Connection => {
-                                send_to_app(
-                                    AppEvent::ServerMessage(format!("{} joined", msg.sender_id)),
-                                    &app_tx,
-                                );
-                            }
-                            Disconnection => {
-                                send_to_app(
-                                    AppEvent::ServerMessage(format!("{} left", msg.sender_id)),
-                                    &app_tx,
-                                );
-                            }
-                            SnapshotRequest(app_id) => {
-                                let msg_id: MsgId = rng.gen();
-                                server.sent_messages_ids.insert(msg_id.clone());
-                                server.increment_clock();
-                                let msg = Msg::new(
-                                    msg_id,
-                                    server.app_id.clone(),
-                                    SnapshotResponse(app_id.clone(), server.saved_messages.clone()),
-                                    server.clock.clone(),
-                                );
-                                server.send_message(&msg, &mut output_file, &app_tx);
-                            }
-                            SnapshotResponse(app_id, _) if *app_id == server.app_id => {
-                                server.snapshot.add(msg);
+    /// Queues a revocation certificate to be broadcast on connection
+    pub fn revoke(&mut self, certificate: RevocationCertificate) {
+        self.revocations.push(certificate);
+    }
+
+    fn get_date(&self) -> Date {
+        *self.clock.get(&self.app_id).expect("missing local app_id")
+    }
 
-                                if server.snapshot.dates.len() == server.clock.len() {
-                                    // We have received a snapshot from every site we know of
-                                    // works because the server's clock has already been updated
+    fn increment_clock(&mut self) {
+        let date = self.clock.entry(self.app_id.to_owned()).or_insert(0);
+        *date += 1;
+    }
 
-                                    // Doesn't work if there are disconnected sites
-                                    // in which case the snapshot request will timeout
+    /// Tells the others we joined, and what they need to know about us
+    fn arrive(&mut self, transport: &transport::Handle) {
+        let msg = self.new_message(Connection);
+        transport.send(&msg);
+        self.introduce(transport);
+    }
 
-                                    self_tx.send(Event::SnapshotTimeout).unwrap();
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                } else {
-                    log::error!("Could not decode `{}` as a Msg", msg);
-                }
-            }
+    /// Our version, keys, services and nick, for apps which just joined
+    fn introduce(&mut self, transport: &transport::Handle) {
+        let hello = self.new_message(Hello(VersionInfo::local()));
+        transport.send(&hello);
+        let announcement = self.new_message(KeyAnnouncement(self.identity.public));
+        transport.send(&announcement);
+        let announcement = self.encryption_announcement();
+        transport.send(&announcement);
+        if !self.services.is_empty() {
+            let services = self.new_message(Services(self.services.clone()));
+            transport.send(&services);
+        }
+        if let Some(nick) = self.nick.clone() {
+            let nick = self.new_message(Nick(nick));
+            transport.send(&nick);
+        }
+        for (channel, seconds) in self.slow_mode.clone() {
+            let slow_mode = self.slow_mode_message(channel, seconds);
+            transport.send(&slow_mode);
         }
     }
 
+    /// Slow mode of `channel`, signed by our identity key
+    fn slow_mode_message(&mut self, channel: Channel, seconds: u64) -> Msg {
+        let payload = identity::slow_mode_payload(&self.app_id, &channel, seconds);
+        let signature = self.identity.sign(&payload);
+        self.new_message(SlowMode(channel, seconds, signature))
+    }
+
+    /// Stamps a new local message with a fresh id and date
+    fn new_message(&mut self, header: Header) -> Msg {
+        let msg_id: MsgId = self.rng.gen();
+        self.sent_messages_ids.insert_at(msg_id, self.get_date());
+        self.increment_clock();
+        let mut msg = Msg::new(msg_id, self.app_id.clone(), header, self.clock.clone());
+        if delivery::is_chat(&msg.header) {
+            self.delivered.stamp(&mut msg);
+            msg.stamp_time();
+        } else if let Hello(_) = msg.header {
+            // For the others to tell how far our clock is off
+            msg.stamp_time();
+        }
+        msg.signature = Some(self.identity.sign(&identity::message_payload(&msg)));
+        if delivery::is_chat(&msg.header) {
+            self.entropy.keep(&msg);
+        }
+        self.metrics.add(Metric::Sent);
+        msg
+    }
+
+    /// Checks the signature of `msg` against the key trusted for its sender,
+    /// returns false for a forgery. Those of apps whose key is not known yet go
+    /// through unverified, signed or not; once it is, unsigned ones are forgeries.
+    fn authenticate(&mut self, msg: &mut Msg, app_tx: &AppSender) -> bool {
+        // Key messages are signed by the key they bring
+        let key = match &msg.header {
+            KeyAnnouncement(key) | KeyRotation(key, _) => Some(key),
+            _ => self.contacts.trusted_key(&msg.sender_id),
+        };
+        let key = match key {
+            Some(key) => key,
+            None => return true,
+        };
+        msg.verified = match &msg.signature {
+            Some(signature) => crypto::verify(key, &identity::message_payload(msg), signature),
+            // Stripping the signature does not get a forgery through
+            None => false,
+        };
+        if !msg.verified && self.forged_senders.insert(msg.sender_id.clone()) {
+            let notice = Notice::warning(
+                "forged-message",
+                format!(
+                    "Dropped a message claiming to come from {}: not signed by its key",
+                    msg.sender_id
+                ),
+            );
+            self.notify(notice.with("app", &msg.sender_id), app_tx);
+        }
+        msg.verified
+    }
+
+    /// Our encryption key, signed by our identity key
+    fn encryption_announcement(&mut self) -> Msg {
+        let key = self.identity.encryption_key();
+        let signature = self
+            .identity
+            .sign(&identity::encryption_payload(&self.app_id, &key));
+        self.new_message(EncryptionAnnouncement(key, signature))
+    }
+
+    /// Private message to `app_id`, encrypted when it announced a key. None
+    /// when it cannot be and `--encryption require` keeps it from going in
+    /// clear.
+    ///
+    /// The identity of several linked devices gets one message a device, each
+    /// sealed with the key of that device, or a single one in clear as soon as
+    /// one of them announced no key.
+    fn private(&mut self, app_id: AppId, text: String, app_tx: &AppSender) -> Option<Vec<Header>> {
+        let devices = self.contacts.has_devices(&app_id);
+        let recipients: Vec<AppId> = std::iter::once(&app_id)
+            .chain(self.contacts.devices(&app_id))
+            .filter(|to| **to != self.app_id)
+            .cloned()
+            .collect();
+        let sealed: Option<Vec<Header>> = recipients
+            .into_iter()
+            .map(|to| {
+                let key = self.encryption_keys.get(&to)?;
+                let sealed = self.identity.encrypt(&self.app_id, &to, key, &text)?;
+                Some(Encrypted(to, sealed))
+            })
+            .collect();
+        if let Some(sealed) = sealed.filter(|sealed| !sealed.is_empty()) {
+            return Some(sealed);
+        }
+        match self.encryption {
+            encryption::Policy::Require => return None,
+            encryption::Policy::Prefer if self.unencrypted.insert(app_id.clone()) => {
+                let reason = encryption::why_not(&app_id, self.peers.get(&app_id), devices);
+                let notice = Notice::warning(
+                    "sent-in-clear",
+                    format!("{}, private messages to it are sent in clear", reason),
+                );
+                self.notify(notice.with("recipient", &app_id), app_tx);
+            }
+            _ => {}
+        }
+        Some(vec![Private(app_id, text)])
+    }
+
+    /// `msg` with its text or file chunk decrypted if it was encrypted, None if it
+    /// does not decrypt with the key of the other end
+    fn decrypted(&self, mut msg: Msg) -> Option<Msg> {
+        let other = |recipient| match msg.sender_id == self.app_id {
+            true => self.encryption_keys.get(recipient),
+            false => self.encryption_keys.get(&msg.sender_id),
+        };
+        let header = match &msg.header {
+            Encrypted(recipient, sealed) => {
+                let text =
+                    self.identity
+                        .decrypt(&msg.sender_id, recipient, other(recipient)?, sealed)?;
+                Private(recipient.clone(), text)
+            }
+            EncryptedFile {
+                to,
+                name,
+                chunk_index,
+                total_chunks,
+                sealed,
+            } => {
+                let key = other(to)?;
+                let data = self.identity.decrypt_chunk(
+                    &msg.sender_id,
+                    to,
+                    key,
+                    name,
+                    *chunk_index,
+                    sealed,
+                )?;
+                File {
+                    to: to.clone(),
+                    name: name.clone(),
+                    chunk_index: *chunk_index,
+                    total_chunks: *total_chunks,
+                    data: to_hex(&data),
+                }
+            }
+            _ => return Some(msg),
+        };
+        msg.header = header;
+        Some(msg)
+    }
+
+    /// Whether the chunks of a file for `app_id` are encrypted: it announced
+    /// a key, reads encrypted chunks and is a single device
+    fn seals_files_for(&self, app_id: &str) -> bool {
+        let reads = |info: &VersionInfo| info.features.iter().any(|f| f == "encrypted-files");
+        self.encryption_keys.contains_key(app_id)
+            && !self.contacts.has_devices(app_id)
+            && self.peers.get(app_id).is_some_and(reads)
+    }
+
+    /// `chunk` encrypted for its recipient when it can be, as it is otherwise
+    fn seal_chunk(&self, chunk: Header) -> Header {
+        let (to, name, chunk_index, total_chunks, data) = match chunk {
+            File {
+                to,
+                name,
+                chunk_index,
+                total_chunks,
+                data,
+            } => (to, name, chunk_index, total_chunks, data),
+            header => return header,
+        };
+        let sealed = match self.encryption_keys.get(&to) {
+            Some(key) if self.seals_files_for(&to) => from_hex(&data).and_then(|bytes| {
+                self.identity
+                    .encrypt_chunk(&self.app_id, &to, key, &name, chunk_index, &bytes)
+            }),
+            _ => None,
+        };
+        match sealed {
+            Some(sealed) => EncryptedFile {
+                to,
+                name,
+                chunk_index,
+                total_chunks,
+                sealed,
+            },
+            None => File {
+                to,
+                name,
+                chunk_index,
+                total_chunks,
+                data,
+            },
+        }
+    }
+
+    /// Takes a chunk of a file for us, decrypted if it was encrypted
+    fn receive_chunk(&mut self, msg: Msg, app_tx: &AppSender) {
+        let (sender, verified) = (msg.sender_id.clone(), msg.verified);
+        let identity = self.contacts.identity_of(&sender).to_owned();
+        let (name, chunk_index, total_chunks, data) = match self.decrypted(msg) {
+            Some(Msg {
+                header:
+                    File {
+                        name,
+                        chunk_index,
+                        total_chunks,
+                        data,
+                        ..
+                    },
+                ..
+            }) => (name, chunk_index, total_chunks, data),
+            _ => {
+                log::warn!("dropped a file chunk of {} which does not decrypt", sender);
+                return;
+            }
+        };
+        // Screened, decided on and saved under the name it is written as
+        let name = files::sanitize(&name);
+        // A file offered, fetched from `sender` under its hash
+        let offer = self.offers.fetched_from(&name, &sender).cloned();
+        let shown = offer
+            .as_ref()
+            .map_or(name.clone(), |offer| offer.name.clone());
+        let screened = match chunk_index {
+            0 if offer.is_none() => self.acceptance.screen(&sender, &identity, &name),
+            _ => Ok(()),
+        };
+        let now = self.timer.now();
+        let received = screened.and_then(|()| {
+            self.downloads
+                .receive(&sender, &name, chunk_index, total_chunks, &data, now)
+        });
+        let data = match received {
+            Ok(Received::Progress(done, _)) => {
+                send_to_app(receiving(&sender, &shown, done, total_chunks), app_tx);
+                return;
+            }
+            Ok(Received::Skipped) => {
+                log::debug!("skipped chunk {} of {} from {}", chunk_index, name, sender);
+                return;
+            }
+            Ok(Received::Complete(data)) => data,
+            Err(why) => {
+                self.downloads.refuse(&sender, &name);
+                if offer.is_some() {
+                    self.offers.done(&name);
+                }
+                return self.refused_file(&sender, &shown, total_chunks, &why, app_tx);
+            }
+        };
+        let (sender, decision) = match offer {
+            None => {
+                let decision = self
+                    .acceptance
+                    .decide(&sender, &identity, verified, &name, &data);
+                (sender, decision)
+            }
+            Some(_) if blobs::hash_of(&data) != name => {
+                self.offers.done(&name);
+                let why = "it is not the file offered";
+                return self.refused_file(&sender, &shown, total_chunks, why, app_tx);
+            }
+            Some(_) => {
+                let (offer, requested) = self.offers.done(&name).expect("being fetched");
+                let decision = self.fetched_decision(&offer, requested, &data);
+                (offer.sender, decision)
+            }
+        };
+        self.keep_file(&sender, &shown, total_chunks, decision, &data, app_tx);
+    }
+
+    /// What becomes of the file of `offer`, whose whole content is `data`. One
+    /// the user asked for does not wait in quarantine.
+    fn fetched_decision(&self, offer: &Offer, requested: bool, data: &[u8]) -> Decision {
+        let identity = self.contacts.identity_of(&offer.sender);
+        let decision =
+            self.acceptance
+                .decide(&offer.sender, identity, offer.verified, &offer.name, data);
+        match decision {
+            Decision::Quarantine(_) if requested => Decision::Accept,
+            decision => decision,
+        }
+    }
+
+    /// Writes the file `name` of `sender`, whole, in the downloads or in
+    /// quarantine after `decision`
+    fn keep_file(
+        &self,
+        sender: &str,
+        name: &str,
+        total_chunks: u32,
+        decision: Decision,
+        data: &[u8],
+        app_tx: &AppSender,
+    ) {
+        let kept = match decision {
+            Decision::Accept => self.downloads.save(name, data).map(|path| (path, None)),
+            Decision::Quarantine(why) => self
+                .downloads
+                .quarantine(name, data)
+                .map(|path| (path, Some(why))),
+            Decision::Reject(why) => {
+                return self.refused_file(sender, name, total_chunks, &why, app_tx)
+            }
+        };
+        let (path, why) = match kept {
+            Ok(kept) => kept,
+            Err(e) => {
+                let why = format!("could not be saved: {}", e);
+                return self.refused_file(sender, name, total_chunks, &why, app_tx);
+            }
+        };
+        send_to_app(receiving(sender, name, total_chunks, total_chunks), app_tx);
+        let notice = match why {
+            None => Notice::info(
+                "file-received",
+                format!("{} sent {}, saved as {}", sender, name, path.display()),
+            ),
+            Some(why) => {
+                let file = path.file_name().unwrap_or_default().to_string_lossy();
+                Notice::warning(
+                    "file-quarantined",
+                    format!(
+                        "{} sent {}, in quarantine as {}: {}. /files accept {} moves it to the downloads, /files reject {} deletes it",
+                        sender,
+                        name,
+                        path.display(),
+                        why,
+                        file,
+                        file
+                    ),
+                )
+            }
+        };
+        let notice = notice.with("sender", sender).with("path", path.display());
+        self.notify(notice, app_tx);
+    }
+
+    /// Fetches the file `hash` of `offer`, `requested` by the user, from the
+    /// first to say it holds it. Kept at once if we hold it already.
+    fn fetch(
+        &mut self,
+        hash: String,
+        offer: Offer,
+        requested: bool,
+        transport: &transport::Handle,
+        app_tx: &AppSender,
+    ) {
+        if let Ok(data) = self.blobs.get(&hash) {
+            self.offers.done(&hash);
+            let decision = self.fetched_decision(&offer, requested, &data);
+            return self.keep_file(&offer.sender, &offer.name, 1, decision, &data, app_tx);
+        }
+        if self.offers.want(&hash, offer, requested, self.timer.now()) {
+            let msg = self.new_message(BlobWanted(hash));
+            transport.send(&msg);
+        }
+    }
+
+    /// Takes the offer `msg` of a file in a channel we joined, fetched at once
+    /// when `--accept-files` would accept it
+    fn take_offer(&mut self, msg: &Msg, transport: &transport::Handle, app_tx: &AppSender) {
+        let (channel, name, size, hash) = match &msg.header {
+            FileOffer {
+                channel,
+                name,
+                size,
+                hash,
+            } => (channel.clone(), files::sanitize(name), *size, hash.clone()),
+            _ => return,
+        };
+        let sender = msg.sender_id.clone();
+        if self.blobs.path(&hash).is_none() || size > MAX_FILE_LEN as u64 {
+            log::warn!("ignored the offer of {} by {}", name, sender);
+            return;
+        }
+        let identity = self.contacts.identity_of(&sender).to_owned();
+        if let Err(why) = self.acceptance.screen(&sender, &identity, &name) {
+            log::info!("ignored {} offered by {}: {}", name, sender, why);
+            return;
+        }
+        let offer = Offer {
+            sender: sender.clone(),
+            channel: channel.clone(),
+            name: name.clone(),
+            size,
+            verified: msg.verified,
+        };
+        self.offers.offer(hash.clone(), offer.clone());
+        let fetched = self
+            .acceptance
+            .fetches(&sender, &identity, msg.verified, &name, size);
+        let text = match fetched {
+            true => format!(
+                "{} offers {} in {}, {} bytes, fetching it",
+                sender, name, channel, size
+            ),
+            false => format!(
+                "{} offers {} in {}, {} bytes: /files get {} fetches it",
+                sender,
+                name,
+                channel,
+                size,
+                blobs::short(&hash)
+            ),
+        };
+        let notice = Notice::info("file-offered", text)
+            .with("sender", &sender)
+            .with("hash", &hash);
+        self.notify(notice, app_tx);
+        if fetched {
+            self.fetch(hash, offer, false, transport, app_tx);
+        }
+    }
+
+    /// Tells the app the file `name` of `sender` was refused, and why
+    fn refused_file(
+        &self,
+        sender: &str,
+        name: &str,
+        total_chunks: u32,
+        why: &str,
+        app_tx: &AppSender,
+    ) {
+        // Ends the progress shown so far
+        send_to_app(receiving(sender, name, total_chunks, total_chunks), app_tx);
+        let notice = Notice::warning(
+            "file-refused",
+            format!("Refused {} from {}: {}", name, sender, why),
+        );
+        self.notify(notice.with("sender", sender).with("name", name), app_tx);
+    }
+
+    /// Shows the live line `msg` brings, if it is for us
+    fn show_live_line(&self, msg: Msg, app_tx: &AppSender) {
+        let line = match msg.header {
+            LiveLine(line) => *line,
+            _ => return,
+        };
+        match &line {
+            Private(to, _) | Encrypted(to, _) if self.is_for_me(to) => {}
+            _ => return,
+        }
+        let msg = Msg {
+            header: line,
+            ..msg
+        };
+        if let Some(Msg {
+            header: Private(_, text),
+            sender_id,
+            ..
+        }) = self.decrypted(msg)
+        {
+            send_to_app(AppEvent::LiveLine(sender_id, text), app_tx);
+        }
+    }
+
+    fn notify(&self, notice: Notice, app_tx: &AppSender) {
+        report_notice(notice, Some(&self.clock), &self.recorder, app_tx);
+    }
+
+    /// Switches to the wire format preferred once every app heard of reads
+    /// it, back to json when one does not
+    fn negotiate_codec(&mut self, transport: &transport::Handle, app_tx: &AppSender) {
+        if self.wire_format == Codec::Json {
+            return;
+        }
+        let reads = |info: &VersionInfo| info.features.iter().any(|f| f == "cbor");
+        let (codec, name, text) = match self.peers.iter().find(|(_, info)| !reads(info)) {
+            Some((app_id, _)) => (
+                Codec::Json,
+                "json",
+                format!("{} does not read cbor, messages are sent as json", app_id),
+            ),
+            None if self.peers.is_empty() => return,
+            None => (
+                Codec::Cbor,
+                "cbor",
+                "Every app reads cbor, messages are sent as cbor".to_owned(),
+            ),
+        };
+        if codec != self.codec {
+            self.codec = codec;
+            transport.command(Command::Codec(codec));
+            self.notify(
+                Notice::info("wire-format", text).with("codec", name),
+                app_tx,
+            );
+        }
+    }
+
+    /// Compresses long frames once every app heard of reads them, stops as
+    /// soon as one does not
+    fn negotiate_compression(&mut self, transport: &transport::Handle) {
+        if self.compress_above.is_none() || self.peers.is_empty() {
+            return;
+        }
+        let reads = |info: &VersionInfo| info.features.iter().any(|f| f == "compression");
+        let compressing = self.peers.values().all(reads);
+        if compressing != self.compressing {
+            self.compressing = compressing;
+            log::info!("Long frames compressed: {}", compressing);
+            let above = self.compress_above.filter(|_| compressing);
+            transport.command(Command::Compression(above));
+        }
+    }
+
+    /// Keeps track of a chat message typed here, as shown
+    fn sent(&mut self, msg: Msg) {
+        self.history.append(&msg);
+        self.recorder.record(Some(&self.clock), Step::Sent(msg));
+    }
+
+    fn send_private(
+        &mut self,
+        app_id: AppId,
+        message: String,
+        transport: &transport::Handle,
+        app_tx: &AppSender,
+    ) {
+        let headers = match self.private(app_id.clone(), message.clone(), app_tx) {
+            Some(headers) => headers,
+            None => {
+                let devices = self.contacts.has_devices(&app_id);
+                let reason = encryption::why_not(&app_id, self.peers.get(&app_id), devices);
+                let notice = Notice::error(
+                    "encryption-required",
+                    format!("Not sent: {}, and --encryption require", reason),
+                );
+                self.notify(notice.with("recipient", &app_id), app_tx);
+                return;
+            }
+        };
+        let now = self.timer.now();
+        for (i, header) in headers.into_iter().enumerate() {
+            let msg = self.new_message(header);
+            transport.send(&msg);
+            self.receipts.sent(msg.id, app_id.clone());
+            // The recording is kept locally, with the text
+            let mut recorded = msg.clone();
+            recorded.header = Private(app_id.clone(), message.clone());
+            self.retransmissions
+                .track(&msg, recorded.header.summary(), now);
+            // Shown and kept once, whatever the number of devices
+            if i == 0 {
+                let sent = AppEvent::PrivateSent(msg.id, app_id.clone(), message.clone());
+                send_to_app(sent, app_tx);
+                self.sent(recorded);
+            }
+            self.saved_messages.push(msg);
+        }
+    }
+
+    /// Sends the chat messages released by the delivery policy to the app
+    fn hand_over(&mut self, messages: Vec<Msg>, app_tx: &AppSender) {
+        for msg in messages {
+            self.delivered.count(&msg);
+            match &msg.header {
+                Public(channel, _) if !self.channels.contains(channel) => continue,
+                Private(app_id, _) | Encrypted(app_id, _) if !self.is_for_me(app_id) => continue,
+                Private(..) | Encrypted(..) => self.saved_messages.push(msg.clone()),
+                _ => {}
+            }
+            let sender = msg.sender_id.clone();
+            let msg = match self.decrypted(msg) {
+                Some(msg) => msg,
+                None => {
+                    let notice = Notice::error(
+                        "decrypt-failed",
+                        format!("Could not decrypt a private message from {}", sender),
+                    );
+                    self.notify(notice.with("sender", sender), app_tx);
+                    continue;
+                }
+            };
+            if let Private(..) = msg.header {
+                if msg.sender_id != self.app_id && !self.lurking {
+                    self.receipts.shown(msg.id);
+                }
+            }
+            self.history.append(&msg);
+            self.recorder
+                .record(Some(&self.clock), Step::Delivered(msg.clone()));
+            let mut msg = msg;
+            msg.sent_at = self.time_sync.sent_at(&msg);
+            send_to_app(AppEvent::DistantMessage(Box::new(msg)), app_tx);
+        }
+    }
+
+    /// Tells the senders of the private messages handed over they were shown
+    fn send_receipts(&mut self, transport: &transport::Handle) {
+        for id in self.receipts.take_due() {
+            let receipt = self.new_message(Receipt(id));
+            transport.send(&receipt);
+        }
+    }
+
+    /// Merges the clock of `msg`, but for the dates of the apps which left:
+    /// relayed in the clocks of those which did not hear of it yet, they
+    /// would come back until the app connects again
+    fn merge_clock(&mut self, msg: &Msg) {
+        if let Connection = msg.header {
+            self.departed.remove(&msg.sender_id);
+        }
+        self.clock.merge_without(&msg.clock, &self.departed);
+    }
+
+    /// `app_id` left, its date is not needed anymore: it starts again from 0
+    /// if it comes back
+    fn forget_date(&mut self, app_id: &AppId) {
+        self.clock.remove(app_id);
+        self.departed.insert(app_id.clone());
+    }
+
+    fn receive_message(&mut self, msg: &mut Msg, origin: Arc<str>, transport: &transport::Handle) {
+        self.merge_clock(msg);
+        log::info!(
+            "received, local date: {}, messsage: {:?}",
+            self.get_date(),
+            msg.header
+        );
+        // Reuses the allocation of the received clock
+        if !self.observe && !self.lurking {
+            msg.clock.clone_from(&self.clock);
+        }
+        self.metrics.add(Metric::Relayed);
+        transport.relay(msg, origin);
+    }
+}
+
+/// Seconds since the Unix epoch on the system clock
+fn wall_clock() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Sends `msg` to the app, dropped once it quit: the server outlives it while
+/// saying goodbye
+pub fn send_to_app(msg: AppEvent, app_tx: &AppSender) {
+    if app_tx.send(msg).is_err() {
+        log::debug!("the app is gone, an event was dropped");
+    }
+}
+
+/// Progress of file `name` received from `sender`, `done` of its `total` chunks
+fn receiving(sender: &str, name: &str, done: u32, total: u32) -> AppEvent {
+    AppEvent::Progress {
+        op_id: format!("receive-{}", name.replace(char::is_whitespace, "_")),
+        label: format!("Receiving {} from {}", name, sender),
+        done: done.into(),
+        total: total.into(),
+    }
+}
+
+/// Tells the app about something worth a notice, and records it
+fn report_notice(notice: Notice, clock: Option<&Clock>, recorder: &Recorder, app_tx: &AppSender) {
+    recorder.record(clock, Step::Notice(notice.clone()));
+    send_to_app(AppEvent::Notice(notice), app_tx);
+}
+
+/// Tells the app about a transport change, and records it
+fn report_connection(
+    change: reconnect::Change,
+    clock: Option<&Clock>,
+    recorder: &Recorder,
+    app_tx: &AppSender,
+) {
+    recorder.record(clock, Step::Transport(change.clone()));
+    send_to_app(AppEvent::Connection(change), app_tx);
+}
+
+/// Seals what is written to `output` when there is a pre-shared key
+fn seal(
+    output: Box<dyn backend::Transport>,
+    key: &Option<FrameKey>,
+) -> Box<dyn backend::Transport> {
+    match key.clone() {
+        Some(key) => Box::new(Sealed { inner: output, key }),
+        None => output,
+    }
+}
+
+pub fn run(
+    mut server: Server,
+    app_rx: mpsc::Receiver<Event>,
+    app_tx: AppSender,
+    endpoints: Vec<Endpoint>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // Channel to asynchronously speak to itself
+    let (self_tx, server_rx) = mpsc::channel();
+
+    // 1 Setup event handlers
+    let mut peers = PeerManager::default();
+    let mut inputs = Vec::with_capacity(endpoints.len());
+    for endpoint in &endpoints {
+        let input = endpoint.input()?;
+        if let Endpoint::Pipes { .. } = endpoint {
+            peers.add_input(input.name());
+        }
+        inputs.push(input);
+    }
+    let events = Events::new(
+        inputs,
+        server.limits.max_frame_len,
+        server.fast_relay,
+        server.frame_key.clone(),
+        server.metrics.clone(),
+        app_rx,
+        server_rx,
+    );
+
+    // 2 Open the output pipes,
+    // the program will freeze until there is someone at the other end
+    let mut outputs = ReconnectManager::new(server.timer.clone());
+    for endpoint in &endpoints {
+        if let Some(output) = endpoint.output()? {
+            let name = output.name();
+            outputs.add(seal(output, &server.frame_key));
+            peers.add_output(name.clone());
+            report_connection(
+                reconnect::Change {
+                    name,
+                    state: reconnect::State::Connected,
+                },
+                Some(&server.clock),
+                &server.recorder,
+                &app_tx,
+            );
+        }
+    }
+
+    // 3 Encoding and writing happen on their own thread
+    let (mut transport, transport_handle) = Transport::new(
+        outputs,
+        server.limits.max_frame_len,
+        app_tx.clone(),
+        server.timer.clone(),
+        server.recorder.clone(),
+        server.jobs.clone(),
+        server.metrics.clone(),
+    )
+    .spawn();
+    if server.observe {
+        transport.silence();
+    }
+
+    if let Some(interval) = server.heartbeat {
+        let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+        thread::spawn(move || loop {
+            timer.sleep(interval);
+            if self_tx.send(Event::Heartbeat).is_err() {
+                break;
+            }
+        });
+    }
+
+    if let Some(timeout) = server.retransmissions.timeout() {
+        let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+        thread::spawn(move || loop {
+            timer.sleep(timeout);
+            if self_tx.send(Event::Retransmit).is_err() {
+                break;
+            }
+        });
+    }
+    if let Some(interval) = server.sync_interval {
+        let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+        thread::spawn(move || loop {
+            timer.sleep(interval);
+            if self_tx.send(Event::Sync).is_err() {
+                break;
+            }
+        });
+    }
+    if let Some(timeout) = server.hold_timeout {
+        let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+        thread::spawn(move || loop {
+            timer.sleep(timeout);
+            if self_tx.send(Event::ExpireHeld).is_err() {
+                break;
+            }
+        });
+    }
+
+    if let Some(timeout) = server.file_timeout {
+        let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+        thread::spawn(move || loop {
+            timer.sleep(timeout / 2);
+            if self_tx.send(Event::ExpireDownloads).is_err() {
+                break;
+            }
+        });
+    }
+
+    if let Some(timeout) = server.presence.timeout() {
+        let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+        thread::spawn(move || loop {
+            timer.sleep(timeout / presence::CHECKS_PER_TIMEOUT);
+            if self_tx.send(Event::ExpirePresence).is_err() {
+                break;
+            }
+        });
+    }
+
+    if let Some(advert) = server.discovery.take() {
+        if let Err(e) = discovery::spawn(advert, server.app_id.clone(), self_tx.clone()) {
+            let notice = Notice::error(
+                "discovery-failed",
+                format!("Could not look for the apps on the local network: {}", e),
+            );
+            server.notify(notice, &app_tx);
+        }
+    }
+    let dialer = events.dialer();
+    if let Some(port) = server.metrics_port {
+        if let Err(e) = metrics::serve(server.metrics.clone(), port) {
+            let notice = Notice::error(
+                "metrics-failed",
+                format!("Could not serve the metrics on port {}: {}", port, e),
+            );
+            server.notify(notice, &app_tx);
+        }
+    }
+
+    if !server.lurking {
+        server.arrive(&transport);
+    }
+
+    let fingerprint = server.identity.public.fingerprint();
+    let notice = Notice::info(
+        "fingerprint",
+        format!("identity key fingerprint: {}", fingerprint),
+    );
+    server.notify(notice.with("fingerprint", fingerprint), &app_tx);
+    let replay = server.history.take_replay();
+    if !replay.is_empty() {
+        send_to_app(AppEvent::History(replay), &app_tx);
+    }
+    for app_id in server.contacts.revoked() {
+        send_to_app(AppEvent::IdentityRevoked(app_id.to_owned()), &app_tx);
+    }
+    for certificate in std::mem::take(&mut server.revocations) {
+        if server.contacts.revoke(&certificate) == KeyStatus::Rejected {
+            let notice = Notice::error(
+                "invalid-certificate",
+                format!(
+                    "not broadcasting the revocation of {}: invalid certificate",
+                    certificate.app_id
+                ),
+            );
+            server.notify(notice.with("app", &certificate.app_id), &app_tx);
+            continue;
+        }
+        send_to_app(
+            AppEvent::IdentityRevoked(certificate.app_id.clone()),
+            &app_tx,
+        );
+        let msg = server.new_message(Revocation(certificate));
+        transport.send(&msg);
+    }
+
+    if let Some(owner) = server.owner.clone() {
+        if server.contacts.identity_of(&server.app_id) != owner {
+            let msg = server.new_message(LinkRequest(owner, server.identity.public));
+            transport.send(&msg);
+        }
+    }
+
+    let mut is_waiting_for_snapshot = false;
+    let mut leaving = false;
+
+    loop {
+        if leaving && server.retransmissions.waiting() == 0 {
+            break;
+        }
+        let event = events.next()?;
+        server
+            .metrics
+            .set(Metric::ClockSize, server.clock.len() as u64);
+        server
+            .metrics
+            .set(Metric::Peers, server.presence.online() as u64);
+        server
+            .metrics
+            .set(Metric::Queued, server.queued.total() as u64);
+        server
+            .metrics
+            .set(Metric::Unacked, server.retransmissions.waiting() as u64);
+        if let Event::UserPublicMessage(..)
+        | Event::UserPrivateMessage(..)
+        | Event::UserAnnouncement(_)
+        | Event::UserFile(..)
+        | Event::UserOffer(..) = &event
+        {
+            if server.lurking {
+                server.lurking = false;
+                server.arrive(&transport);
+                let notice = Notice::info(
+                    "joined",
+                    "Joined, the others now know you are here".to_owned(),
+                );
+                server.notify(notice, &app_tx);
+            }
+        }
+        // Messages replayed come in bursts, only the live ones are rate limited
+        let live = matches!(event, Event::DistantInput(..));
+        // Handle events
+        match event {
+            // User / Server commands
+            //-----------------------
+            Event::UserPublicMessage(channel, message, via) => {
+                let mut msg = server.new_message(Public(channel.clone(), message.clone()));
+                msg.via = via;
+                transport.send(&msg);
+                let now = server.timer.now();
+                server
+                    .retransmissions
+                    .track(&msg, msg.header.summary(), now);
+                server.authors.keep(&msg);
+                send_to_app(AppEvent::PublicSent(msg.id, channel, message), &app_tx);
+                server.sent(msg.clone());
+                server.saved_messages.push(msg);
+            }
+            Event::UserEdit(id, text) => server.change_own(id, Some(text), &transport, &app_tx),
+            Event::UserDelete(id) => server.change_own(id, None, &transport, &app_tx),
+            Event::UserAnnouncement(message) => {
+                let payload = identity::announcement_payload(&server.app_id, &message);
+                let signature = server.identity.sign(&payload);
+                let msg = server.new_message(Announcement(message, signature));
+                transport.send(&msg);
+                let now = server.timer.now();
+                server
+                    .retransmissions
+                    .track(&msg, msg.header.summary(), now);
+                server.sent(msg.clone());
+                server.saved_messages.push(msg);
+            }
+            Event::UserNick(nick) => {
+                // Sent on joining when lurking
+                if !server.lurking {
+                    let msg = server.new_message(Nick(nick.clone()));
+                    transport.send(&msg);
+                }
+                server.nick = Some(nick);
+            }
+            // Not joining for it when lurking
+            Event::UserTyping if !server.lurking => {
+                // Neither a date nor an id of the others, it is not saved
+                let mut msg = Msg::new(
+                    server.rng.gen(),
+                    server.app_id.clone(),
+                    Typing,
+                    server.clock.clone(),
+                );
+                msg.signature = Some(server.identity.sign(&identity::message_payload(&msg)));
+                server.typing_ids.insert(msg.id);
+                transport.send(&msg);
+            }
+            Event::UserTyping => {}
+            Event::UserLiveLine(..) if server.lurking => {}
+            Event::UserLiveLine(app_id, line) => {
+                // Sealed like a private message, but forgotten like typing
+                let lines = match server.private(app_id, line, &app_tx) {
+                    Some(lines) => lines,
+                    None => continue,
+                };
+                for line in lines {
+                    let mut msg = Msg::new(
+                        server.rng.gen(),
+                        server.app_id.clone(),
+                        LiveLine(Box::new(line)),
+                        server.clock.clone(),
+                    );
+                    msg.signature = Some(server.identity.sign(&identity::message_payload(&msg)));
+                    server.typing_ids.insert(msg.id);
+                    transport.send(&msg);
+                }
+            }
+            Event::UserSlowMode(channel, seconds) => {
+                match seconds {
+                    0 => server.slow_mode.remove(&channel),
+                    _ => server.slow_mode.insert(channel.clone(), seconds),
+                };
+                // Sent on joining when lurking
+                if !server.lurking {
+                    let msg = server.slow_mode_message(channel, seconds);
+                    transport.send(&msg);
+                }
+            }
+            Event::JoinChannel(channel) => {
+                if server.channels.insert(channel.clone()) && !server.lurking {
+                    let msg = server.new_message(ChannelJoin(channel));
+                    transport.send(&msg);
+                }
+            }
+            Event::LeaveChannel(channel) => {
+                if server.channels.remove(&channel) && !server.lurking {
+                    let msg = server.new_message(ChannelLeave(channel));
+                    transport.send(&msg);
+                }
+            }
+            Event::UserFile(path, to)
+                if server.encryption == encryption::Policy::Require
+                    && !server.seals_files_for(&to) =>
+            {
+                let devices = server.contacts.has_devices(&to);
+                let reason = encryption::why_not_files(&to, server.peers.get(&to), devices);
+                let notice = Notice::error(
+                    "encryption-required",
+                    format!(
+                        "Not sent: {}, {} would go in clear, and --encryption require",
+                        reason,
+                        path.display()
+                    ),
+                );
+                server.notify(notice.with("recipient", &to), &app_tx);
+            }
+            Event::UserFile(path, to) => match Upload::new(&path, to) {
+                Ok(upload) => {
+                    if server.encryption == encryption::Policy::Prefer
+                        && !server.seals_files_for(&upload.to)
+                    {
+                        let devices = server.contacts.has_devices(&upload.to);
+                        let runs = server.peers.get(&upload.to);
+                        let reason = encryption::why_not_files(&upload.to, runs, devices);
+                        let notice = Notice::warning(
+                            "sent-in-clear",
+                            format!("{}, {} is sent in clear", reason, upload.name),
+                        );
+                        server.notify(notice.with("recipient", &upload.to), &app_tx);
+                    }
+                    let op_id = format!("send-{}", upload.name.replace(char::is_whitespace, "_"));
+                    let token = server.jobs.start(&op_id);
+                    if server.uploads.is_empty() {
+                        self_tx.send(Event::FileChunk)?;
+                    }
+                    server.uploads.push_back((op_id, upload, token));
+                }
+                Err(e) => {
+                    let notice = Notice::error(
+                        "file-unreadable",
+                        format!("Could not send {}: {}", path.display(), e),
+                    );
+                    server.notify(notice.with("path", path.display()), &app_tx);
+                }
+            },
+            Event::UserOffer(path, channel) => {
+                let offered = files::read(&path).and_then(|(name, data)| {
+                    let hash = server.blobs.put(&data)?;
+                    Ok((name, data.len() as u64, hash))
+                });
+                match offered {
+                    Ok((name, size, hash)) => {
+                        let offer = FileOffer {
+                            channel: channel.clone(),
+                            name: name.clone(),
+                            size,
+                            hash: hash.clone(),
+                        };
+                        let msg = server.new_message(offer);
+                        transport.send(&msg);
+                        let fetches =
+                            |info: &VersionInfo| info.features.iter().any(|f| f == "file-offers");
+                        let behind = server.peers.values().filter(|info| !fetches(info)).count();
+                        let mut text = format!(
+                            "Offered {} in {}, fetched by those who want it",
+                            name, channel
+                        );
+                        if behind > 0 {
+                            text.push_str(&format!(
+                                ", {} peers run a version which cannot: /send <path> @<app> sends it to one",
+                                behind
+                            ));
+                        }
+                        let notice = Notice::info("file-offered", text)
+                            .with("channel", &channel)
+                            .with("hash", &hash);
+                        server.notify(notice, &app_tx);
+                    }
+                    Err(e) => {
+                        let notice = Notice::error(
+                            "file-unreadable",
+                            format!("Could not offer {}: {}", path.display(), e),
+                        );
+                        server.notify(notice.with("path", path.display()), &app_tx);
+                    }
+                }
+            }
+            Event::GetFile(prefix) => match server.offers.find(&prefix) {
+                Ok((hash, offer)) => server.fetch(hash, offer, true, &transport, &app_tx),
+                Err(why) => {
+                    let notice = Notice::error(
+                        "file-not-offered",
+                        format!("Could not fetch {}: {}", prefix, why),
+                    );
+                    server.notify(notice, &app_tx);
+                }
+            },
+            Event::FileChunk => {
+                if let Some((op_id, mut upload, token)) = server.uploads.pop_front() {
+                    let chunk = if token.is_cancelled() {
+                        None
+                    } else {
+                        upload.next_chunk().map(|chunk| server.seal_chunk(chunk))
+                    };
+                    // The recipient may have stopped reading encrypted chunks since
+                    let refused = server.encryption == encryption::Policy::Require
+                        && matches!(chunk, Some(File { .. }));
+                    match chunk {
+                        Some(chunk) if !refused => {
+                            let msg = server.new_message(chunk);
+                            transport.send(&msg);
+                            let now = server.timer.now();
+                            server
+                                .retransmissions
+                                .track(&msg, msg.header.summary(), now);
+                            let progress = AppEvent::Progress {
+                                op_id: op_id.clone(),
+                                label: format!("Sending {} to {}", upload.shown, upload.to),
+                                done: upload.sent_chunks().into(),
+                                total: upload.total_chunks().into(),
+                            };
+                            send_to_app(progress, &app_tx);
+                            server.uploads.push_front((op_id, upload, token));
+                        }
+                        _ => {
+                            server.jobs.finish(&op_id);
+                            let notice = if refused {
+                                Notice::error(
+                                    "encryption-required",
+                                    format!(
+                                        "Stopped sending {} to {}: the rest would go in clear, and --encryption require",
+                                        upload.shown, upload.to
+                                    ),
+                                )
+                            } else if token.is_cancelled() {
+                                Notice::warning(
+                                    "file-cancelled",
+                                    format!("Stopped sending {} to {}", upload.shown, upload.to),
+                                )
+                            } else {
+                                Notice::info(
+                                    "file-sent",
+                                    format!("Sent {} to {}", upload.shown, upload.to),
+                                )
+                            };
+                            let notice = notice.with("name", &upload.name).with("to", &upload.to);
+                            server.notify(notice, &app_tx);
+                        }
+                    }
+                    if !server.uploads.is_empty() {
+                        self_tx.send(Event::FileChunk)?;
+                    }
+                }
+            }
+            Event::ListFiles => {
+                let notice = match server.downloads.quarantined() {
+                    Ok(names) => {
+                        let held = match names.is_empty() {
+                            true => "Nothing in quarantine".to_owned(),
+                            false => format!("In quarantine: {}", names.join(", ")),
+                        };
+                        let policies: Vec<String> = server
+                            .acceptance
+                            .iter()
+                            .map(|(app_id, policy)| format!("{} {}", app_id, policy.name()))
+                            .chain(Some(format!(
+                                "the others {}",
+                                server.acceptance.policy.name()
+                            )))
+                            .collect();
+                        let offered: Vec<String> = server
+                            .offers
+                            .iter()
+                            .map(|(hash, offer)| {
+                                format!(
+                                    "{} by {} ({})",
+                                    offer.name,
+                                    offer.sender,
+                                    blobs::short(hash)
+                                )
+                            })
+                            .collect();
+                        let offered = match offered.is_empty() {
+                            true => String::new(),
+                            false => format!(". Offered: {}", offered.join(", ")),
+                        };
+                        let text = format!("{}{}. Files: {}", held, offered, policies.join(", "));
+                        Notice::info("files", text).with("quarantined", names.len())
+                    }
+                    Err(e) => Notice::error(
+                        "quarantine-unreadable",
+                        format!("Could not read the quarantine: {}", e),
+                    ),
+                };
+                server.notify(notice, &app_tx);
+            }
+            Event::AcceptFile(name) => {
+                let notice = match server.downloads.release(&name) {
+                    Ok(path) => Notice::info(
+                        "file-accepted",
+                        format!("Moved {} to {}", name, path.display()),
+                    )
+                    .with("path", path.display()),
+                    Err(e) => Notice::error(
+                        "file-not-accepted",
+                        format!("Could not move {} out of quarantine: {}", name, e),
+                    ),
+                };
+                server.notify(notice.with("name", &name), &app_tx);
+            }
+            Event::RejectFile(name) => {
+                let notice = match server.downloads.discard(&name) {
+                    Ok(()) => Notice::info("file-rejected", format!("Deleted {}", name)),
+                    Err(e) => Notice::error(
+                        "file-not-rejected",
+                        format!("Could not delete {} from quarantine: {}", name, e),
+                    ),
+                };
+                server.notify(notice.with("name", &name), &app_tx);
+            }
+            Event::FilePolicy(app_id, policy) => server.acceptance.set(&app_id, policy),
+            Event::ExpireDownloads => {
+                let timeout = server.file_timeout.unwrap_or_default();
+                let now = server.timer.now();
+                for offer in server.offers.expire(now, timeout) {
+                    let notice = Notice::warning(
+                        "file-unavailable",
+                        format!(
+                            "Gave up on {} offered by {}: nobody said to hold it for {} seconds",
+                            offer.name,
+                            offer.sender,
+                            timeout.as_secs()
+                        ),
+                    );
+                    let notice = notice
+                        .with("sender", &offer.sender)
+                        .with("name", &offer.name);
+                    server.notify(notice, &app_tx);
+                }
+                let stalled = server.downloads.expire(now, timeout);
+                for Stalled {
+                    sender,
+                    name,
+                    received,
+                    total_chunks,
+                } in stalled
+                {
+                    // Fetched under its hash
+                    let name = match server.offers.fetched_from(&name, &sender) {
+                        Some(_) => server.offers.done(&name).expect("being fetched").0.name,
+                        None => name,
+                    };
+                    // Ends the progress shown so far
+                    send_to_app(
+                        receiving(&sender, &name, total_chunks, total_chunks),
+                        &app_tx,
+                    );
+                    let notice = Notice::warning(
+                        "file-stalled",
+                        format!(
+                            "Gave up on {} from {}: nothing came of it for {} seconds, {} of {} chunks received",
+                            name,
+                            sender,
+                            timeout.as_secs(),
+                            received,
+                            total_chunks
+                        ),
+                    );
+                    server.notify(notice.with("sender", &sender).with("name", &name), &app_tx);
+                }
+            }
+            Event::UserPrivateMessage(app_id, _) if server.contacts.is_revoked(&app_id) => {
+                log::warn!("refused to send a private message to revoked {}", app_id);
+            }
+            Event::UserPrivateMessage(app_id, message) if server.presence.is_offline(&app_id) => {
+                let waiting = server.queued.push(app_id.clone(), message);
+                let notice = Notice::warning(
+                    "recipient-offline",
+                    format!(
+                        "{} appears offline, message queued until it is back ({} waiting)",
+                        app_id, waiting
+                    ),
+                );
+                server.notify(notice.with("recipient", &app_id), &app_tx);
+            }
+            Event::UserPrivateMessage(app_id, message) => {
+                server.send_private(app_id, message, &transport, &app_tx)
+            }
+            Event::GetServices => {
+                let mut directory: BTreeMap<_, _> = server.directory.clone().into_iter().collect();
+                if !server.services.is_empty() {
+                    directory.insert(server.app_id.clone(), server.services.clone());
+                }
+                send_to_app(AppEvent::DisplayServices(directory), &app_tx);
+            }
+            Event::GetStats => {
+                let stats = AppEvent::DisplayStats(server.metrics.snapshot());
+                send_to_app(stats, &app_tx);
+            }
+            Event::DumpMetrics(path) => {
+                let notice = match server.dump_metrics(&path) {
+                    Ok(()) => Notice::info(
+                        "metrics-dumped",
+                        format!("Metrics written to {}", path.display()),
+                    ),
+                    Err(e) => Notice::error(
+                        "metrics-dump-failed",
+                        format!("Could not write the metrics to {}: {}", path.display(), e),
+                    ),
+                };
+                server.notify(notice.with("path", path.display()), &app_tx);
+            }
+            Event::GetChannelStats(channel) => match server.history.messages() {
+                Ok(mut messages) => {
+                    for msg in &mut messages {
+                        msg.sent_at = server.time_sync.sent_at(msg);
+                    }
+                    let stats = ChannelStats::collect(&messages, &channel);
+                    send_to_app(AppEvent::DisplayChannelStats(channel, stats), &app_tx);
+                }
+                Err(e) => {
+                    let notice = Notice::error(
+                        "history-unreadable",
+                        format!("Could not read the history: {}", e),
+                    );
+                    server.notify(notice, &app_tx);
+                }
+            },
+            Event::GetClock => {
+                send_to_app(AppEvent::DisplayClock(server.clock.clone()), &app_tx);
+            }
+            Event::Shutdown if leaving => {}
+            Event::Shutdown => {
+                // What the others have not heard yet goes first, then the
+                // messages sent are given a little time to be acknowledged
+                leaving = true;
+                transport.command(Command::RetryOutbox(None));
+                let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+                let timeout = server.goodbye_timeout;
+                thread::spawn(move || {
+                    timer.sleep(timeout);
+                    let _ = self_tx.send(Event::Goodbye);
+                });
+            }
+            Event::Goodbye => {
+                log::warn!(
+                    "Leaving with {} messages not acknowledged",
+                    server.retransmissions.waiting()
+                );
+                break;
+            }
+            Event::GetSnapshot => {
+                is_waiting_for_snapshot = true;
+
+                let msg = server.new_message(SnapshotRequest(server.app_id.to_owned()));
+                transport.send(&msg);
+                server.saved_messages.push(msg.clone());
+
+                // Set up timeout
+                let (self_tx, timer) = (self_tx.clone(), server.timer.clone());
+                thread::spawn(move || {
+                    timer.sleep(SNAPSHOT_TIMEOUT);
+                    self_tx.send(Event::SnapshotTimeout).unwrap();
+                });
+
+                // Adding local messages and clock to snapshot
+                server.snapshot.dates.insert(
+                    server.app_id.clone(),
+                    *server
+                        .clock
+                        .get(&server.app_id)
+                        .expect("Missing server date !"),
+                );
+
+                server
+                    .snapshot
+                    .messages
+                    .insert(server.app_id.clone(), server.saved_messages.clone());
+            }
+            Event::RotateKey => {
+                let (new_key, signature) = server.identity.rotate(&server.app_id);
+                if let Err(e) = server.identity.save(&server.identity_path, &server.app_id) {
+                    log::error!("Could not save rotated identity: {}", e);
+                }
+
+                let msg = server.new_message(KeyRotation(new_key, signature));
+                transport.send(&msg);
+                // The encryption key is derived from the identity key
+                let msg = server.encryption_announcement();
+                transport.send(&msg);
+
+                let notice = Notice::info(
+                    "key-rotated",
+                    format!(
+                        "identity key rotated, new fingerprint: {}",
+                        new_key.fingerprint()
+                    ),
+                );
+                server.notify(notice.with("fingerprint", new_key.fingerprint()), &app_tx);
+            }
+            Event::AcceptLink => {
+                if let Some((device, device_key)) = server.link_requests.pop() {
+                    let link =
+                        DeviceLink::new(&server.identity, &server.app_id, &device, device_key);
+                    server.contacts.observe(&device, device_key);
+                    server.contacts.link(&link);
+                    let msg = server.new_message(DeviceLink(link));
+                    transport.send(&msg);
+
+                    server.sync_history(&device, None, &transport);
+
+                    let notice = Notice::info(
+                        "device-linked",
+                        format!("{} is now linked to your identity", device),
+                    );
+                    server.notify(notice.with("device", device), &app_tx);
+                } else {
+                    let notice = Notice::warning(
+                        "no-link-request",
+                        "No device asked to be linked".to_owned(),
+                    );
+                    server.notify(notice, &app_tx);
+                }
+            }
+            Event::ExpireHeld => {
+                let released = server.delivery.expire();
+                server.hand_over(released, &app_tx);
+                server.send_receipts(&transport);
+            }
+            Event::ExpirePresence => {
+                let timeout = server.presence.timeout().unwrap_or_default();
+                for app_id in server.presence.expire(server.timer.now()) {
+                    let notice = Notice::info(
+                        "peer-timed-out",
+                        format!(
+                            "{} was not heard from for {} seconds, offline",
+                            app_id,
+                            timeout.as_secs()
+                        ),
+                    );
+                    server.notify(notice.with("app", &app_id), &app_tx);
+                    send_to_app(AppEvent::PeerOffline(app_id), &app_tx);
+                }
+            }
+            Event::Retransmit => {
+                let (again, failed) = server.retransmissions.due(server.timer.now());
+                for msg in again {
+                    transport.send(&msg);
+                }
+                for what in failed {
+                    send_to_app(AppEvent::DeliveryFailed(what), &app_tx);
+                }
+            }
+            Event::Sync if server.lurking => {}
+            Event::Sync => {
+                let summary = server.entropy.summary();
+                let msg = server.new_message(SyncSummary(summary));
+                transport.send(&msg);
+            }
+            Event::Heartbeat if server.lurking => {}
+            Event::Heartbeat => {
+                let msg = server.new_message(Heartbeat(vec![server.app_id.clone()]));
+                transport.send(&msg);
+            }
+            Event::PeerConnected(name, way_back) => {
+                let change = peers.connected(&name, way_back.is_some());
+                match way_back {
+                    Some(output) => {
+                        transport.command(Command::AddPeer(seal(output, &server.frame_key)))
+                    }
+                    // The output pipe can likely be written to again
+                    None => transport.command(Command::Reconnect(None)),
+                }
+                if let Some(change) = change {
+                    report_connection(change, Some(&server.clock), &server.recorder, &app_tx);
+                }
+            }
+            Event::PeerDisconnected(name) => {
+                transport.command(Command::RemovePeer(name.clone()));
+                if let Some(change) = peers.disconnected(&name) {
+                    report_connection(change, Some(&server.clock), &server.recorder, &app_tx);
+                }
+            }
+            Event::Tag(app_id, tag, on) => {
+                if !server.contacts.set_tag(&app_id, &tag, on) {
+                    let notice = Notice::warning(
+                        "tag-not-saved",
+                        format!(
+                            "The key of {} is not known yet, its tags are not saved",
+                            app_id
+                        ),
+                    );
+                    server.notify(notice.with("app", &app_id), &app_tx);
+                }
+            }
+            Event::Discovered(app_id, addr) => {
+                if server.discovered.insert(app_id.clone(), addr) != Some(addr) {
+                    send_to_app(AppEvent::Discovered(app_id, addr), &app_tx);
+                }
+            }
+            Event::Connect(addr) => match Socket::connect(&addr).input() {
+                Ok(input) => {
+                    dialer.open(Box::new(input));
+                }
+                Err(e) => log::error!("Could not connect to {}: {}", addr, e),
+            },
+            Event::Reconnect(name) => transport.command(Command::Reconnect(name)),
+            Event::Partition(name) | Event::Heal(name) if !peers.is_known(name.as_deref()) => {
+                let name = name.unwrap_or_default();
+                let notice =
+                    Notice::warning("unknown-transport", format!("No transport named {}", name));
+                server.notify(notice.with("transport", name), &app_tx);
+            }
+            Event::Partition(name) => {
+                for change in peers.partition(name.as_deref()) {
+                    report_connection(change, Some(&server.clock), &server.recorder, &app_tx);
+                }
+                if peers.has_output(name.as_deref()) {
+                    transport.command(Command::Partition(name));
+                }
+            }
+            Event::Heal(name) => {
+                let (changes, held) = peers.heal(name.as_deref());
+                for change in changes {
+                    report_connection(change, Some(&server.clock), &server.recorder, &app_tx);
+                }
+                for (msg, origin) in held {
+                    self_tx.send(Event::ReplayedInput(Box::new(msg), origin))?;
+                }
+                if peers.has_output(name.as_deref()) {
+                    transport.command(Command::Heal(name));
+                }
+            }
+            Event::RetryOutbox(seq) => transport.command(Command::RetryOutbox(seq)),
+            Event::CancelOutbox(seq) => transport.command(Command::CancelOutbox(seq)),
+            Event::CancelJob(op_id) => {
+                if !server.jobs.cancel(&op_id) {
+                    let notice = Notice::warning("unknown-job", format!("No job named {}", op_id));
+                    server.notify(notice.with("job", op_id), &app_tx);
+                }
+            }
+            Event::SnapshotTimeout => {
+                if is_waiting_for_snapshot {
+                    is_waiting_for_snapshot = false;
+                    // Writing snapshot to file
+                    server
+                        .snapshot
+                        .dump(server.get_date(), &server.snapshot_dir);
+
+                    server.notify(
+                        Notice::info("snapshot-saved", "Snapshot saved".to_owned()),
+                        &app_tx,
+                    );
+
+                    // emptying local snapshot save
+                    server.snapshot = Snapshot::new(server.app_id.clone());
+                }
+            }
+            // Input from a distant app
+            //-------------------------
+            Event::OversizedFrame(len) => {
+                server.dropped_frames += 1;
+                server.metrics.add(Metric::Dropped);
+                log::warn!("dropped an input line of {} bytes", len);
+                let notice = Notice::error(
+                    "oversized-frame",
+                    format!(
+                        "Dropped an incoming message of {} bytes, {} dropped so far",
+                        len, server.dropped_frames
+                    ),
+                );
+                let notice = notice
+                    .with("bytes", len)
+                    .with("dropped", server.dropped_frames);
+                server.notify(notice, &app_tx);
+            }
+            Event::UnsealedFrame => {
+                server.dropped_frames += 1;
+                server.metrics.add(Metric::Dropped);
+                log::warn!("dropped an input line which does not open with the key");
+                let notice = Notice::error(
+                    "unsealed-frame",
+                    format!(
+                        "Dropped an incoming line not sealed with our key, {} dropped so far",
+                        server.dropped_frames
+                    ),
+                );
+                server.notify(notice.with("dropped", server.dropped_frames), &app_tx);
+            }
+            Event::DuplicateInput(id) => {
+                let now = server.timer.now();
+                if server.retransmissions.should_ack_again(id, now) {
+                    let ack = server.new_message(Ack(id));
+                    transport.send(&ack);
+                }
+            }
+            Event::DistantInput(msg, origin) | Event::ReplayedInput(msg, origin) => {
+                let mut msg = match peers.hold(*msg, &origin) {
+                    Some(msg) => msg,
+                    None => continue,
+                };
+                // Forgeries are neither acknowledged, relayed nor shown, copies
+                // of a message are checked once
+                let seen = match msg.header {
+                    Typing | LiveLine(_) => server.typing_ids.contains(msg.id),
+                    _ => server.sent_messages_ids.contains(msg.id),
+                };
+                if !seen && !server.authenticate(&mut msg, &app_tx) {
+                    server.metrics.add(Metric::Dropped);
+                    continue;
+                }
+                if !seen && live && !server.within_rate(&msg, &app_tx) {
+                    server.metrics.add(Metric::Dropped);
+                    continue;
+                }
+                if let Typing = msg.header {
+                    if server.typing_ids.insert(msg.id) {
+                        transport.relay(&msg, origin);
+                        send_to_app(AppEvent::Typing(msg.sender_id), &app_tx);
+                    }
+                    continue;
+                }
+                if let LiveLine(_) = msg.header {
+                    if server.typing_ids.insert(msg.id) {
+                        transport.relay(&msg, origin);
+                        server.show_live_line(msg, &app_tx);
+                    }
+                    continue;
+                }
+                // If we receive this message for the first time
+                let first = server
+                    .sent_messages_ids
+                    .insert_at(msg.id, server.get_date());
+                server.metrics.add(match first {
+                    true => Metric::Received,
+                    false => Metric::Duplicated,
+                });
+                if server.is_to_ack(&msg)
+                    && server
+                        .retransmissions
+                        .should_ack(msg.id, server.timer.now())
+                {
+                    let ack = server.new_message(Ack(msg.id));
+                    transport.send(&ack);
+                }
+                // Only for the neighbours, never relayed
+                match &msg.header {
+                    SyncSummary(summary) if first && msg.sender_id != server.app_id => {
+                        let missing = server.entropy.missing(summary);
+                        if !missing.is_empty() && !server.lurking {
+                            log::info!("{} missed {} messages", msg.sender_id, missing.len());
+                            let answer = SyncMissing(msg.sender_id.clone(), missing);
+                            let answer = server.new_message(answer);
+                            transport.send(&answer);
+                        }
+                        continue;
+                    }
+                    SyncMissing(app_id, messages) if first && *app_id == server.app_id => {
+                        // As if they came from the neighbour, the copies we have are skipped
+                        for missed in messages {
+                            let missed = Box::new(missed.clone());
+                            self_tx.send(Event::ReplayedInput(missed, origin.clone()))?;
+                        }
+                        continue;
+                    }
+                    SyncSummary(_) | SyncMissing(..) => continue,
+                    _ => {}
+                }
+                if first {
+                    if delivery::is_chat(&msg.header) {
+                        server.entropy.keep(&msg);
+                    }
+                    server.increment_clock();
+                    server.receive_message(&mut msg, origin, &transport);
+                    server.update_presence(&msg, &transport, &app_tx);
+                    if let Announcement(text, _) = &msg.header {
+                        if !server.may_announce(&msg) {
+                            log::warn!("{} may not announce, shown as public", msg.sender_id);
+                            msg.header = Public(Channel::default(), text.clone());
+                        }
+                    }
+
+                    match &msg.header {
+                        Public(..) | Private(..) | Encrypted(..) | Announcement(..) => {
+                            server.authors.keep(&msg);
+                            let released = server.delivery.receive(msg);
+                            server.hand_over(released, &app_tx);
+                            server.send_receipts(&transport);
+                        }
+                        Connection => {
+                            server.entropy.forget(&msg.sender_id);
+                            // Recorded as a join, not as a notice
+                            let notice =
+                                Notice::info("joined", format!("{} joined", msg.sender_id));
+                            send_to_app(
+                                AppEvent::Notice(notice.with("app", &msg.sender_id)),
+                                &app_tx,
+                            );
+                            server
+                                .recorder
+                                .record(Some(&server.clock), Step::Joined(msg.sender_id.clone()));
+
+                            // A lurker answers no one, it did not join yet
+                            if server.lurking {
+                                continue;
+                            }
+                            // The newcomer missed our version and key announcement
+                            server.introduce(&transport);
+
+                            // Another device of ours is back, it missed what we said meanwhile
+                            if server.is_sibling(&msg.sender_id) {
+                                server.sync_history(&msg.sender_id, Some(&msg.clock), &transport);
+                            }
+
+                            if let Some(motd) = server.motd.clone() {
+                                if !server.contacts.is_revoked(&msg.sender_id) {
+                                    let motd =
+                                        server.new_message(Private(msg.sender_id.clone(), motd));
+                                    transport.send(&motd);
+                                    server.sent(motd.clone());
+                                    server.saved_messages.push(motd);
+                                }
+                            }
+                        }
+                        LinkRequest(owner, device_key)
+                            if *owner == server.app_id && server.owner.is_none() =>
+                        {
+                            let notice = Notice::info(
+                                "link-request",
+                                format!(
+                                    "{} asks to be linked to your identity with key {}, Ctrl+l to accept",
+                                    msg.sender_id,
+                                    device_key.fingerprint()
+                                ),
+                            );
+                            let notice = notice
+                                .with("device", &msg.sender_id)
+                                .with("fingerprint", device_key.fingerprint());
+                            server.notify(notice, &app_tx);
+                            server
+                                .link_requests
+                                .push((msg.sender_id.clone(), *device_key));
+                        }
+                        DeviceLink(link) => {
+                            let notice = match server.contacts.link(link) {
+                                KeyStatus::New if link.device == server.app_id => {
+                                    Some(Notice::info(
+                                        "device-linked",
+                                        format!("This app is now a device of {}", link.owner),
+                                    ))
+                                }
+                                KeyStatus::New => Some(Notice::info(
+                                    "device-linked",
+                                    format!("{} is now a device of {}", link.device, link.owner),
+                                )),
+                                KeyStatus::Known => None,
+                                _ => Some(Notice::warning(
+                                    "invalid-link",
+                                    format!(
+                                        "ignored an invalid link of {} to {}",
+                                        link.device, link.owner
+                                    ),
+                                )),
+                            };
+                            if let Some(notice) = notice {
+                                let notice = notice
+                                    .with("device", &link.device)
+                                    .with("owner", &link.owner);
+                                server.notify(notice, &app_tx);
+                            }
+                        }
+                        HistorySync(app_id, messages)
+                            if *app_id == server.app_id && server.is_sibling(&msg.sender_id) =>
+                        {
+                            server.take_history(messages, &app_tx);
+                        }
+                        EncryptedHistory(app_id, sealed)
+                            if *app_id == server.app_id && server.is_sibling(&msg.sender_id) =>
+                        {
+                            let messages =
+                                server.encryption_keys.get(&msg.sender_id).and_then(|key| {
+                                    server.identity.decrypt_history(
+                                        &msg.sender_id,
+                                        app_id,
+                                        key,
+                                        sealed,
+                                    )
+                                });
+                            match messages {
+                                Some(messages) => server.take_history(&messages, &app_tx),
+                                None => {
+                                    let notice = Notice::error(
+                                        "decrypt-failed",
+                                        format!(
+                                            "Could not decrypt the history {} sent",
+                                            msg.sender_id
+                                        ),
+                                    );
+                                    server.notify(notice.with("sender", &msg.sender_id), &app_tx);
+                                }
+                            }
+                        }
+                        Ack(id) => {
+                            let identity = server.contacts.identity_of(&msg.sender_id);
+                            server.retransmissions.ack(*id, &msg.sender_id, identity);
+                        }
+                        Edit(id, _) | Delete(id) => server.take_change(&msg, *id, &app_tx),
+                        Receipt(id) => {
+                            let identity = server.contacts.identity_of(&msg.sender_id);
+                            if server.receipts.received(*id, &msg.sender_id, identity) {
+                                send_to_app(AppEvent::Receipt(*id), &app_tx);
+                            }
+                        }
+                        Services(services) => {
+                            server
+                                .directory
+                                .insert(msg.sender_id.clone(), services.clone());
+                        }
+                        File { to, .. } | EncryptedFile { to, .. } if server.is_for_me(to) => {
+                            server.receive_chunk(msg.clone(), &app_tx);
+                        }
+                        FileOffer { channel, .. }
+                            if msg.sender_id != server.app_id
+                                && server.channels.contains(channel) =>
+                        {
+                            server.take_offer(&msg, &transport, &app_tx);
+                        }
+                        BlobWanted(hash)
+                            if msg.sender_id != server.app_id
+                                && !server.lurking
+                                && server.blobs.has(hash) =>
+                        {
+                            let held = BlobHeld(msg.sender_id.clone(), hash.clone());
+                            let held = server.new_message(held);
+                            transport.send(&held);
+                        }
+                        // The first to answer is the nearest
+                        BlobHeld(to, hash)
+                            if *to == server.app_id && server.offers.held(hash, &msg.sender_id) =>
+                        {
+                            let request = BlobRequest(msg.sender_id.clone(), hash.clone());
+                            let request = server.new_message(request);
+                            transport.send(&request);
+                        }
+                        BlobRequest(to, hash) if *to == server.app_id => {
+                            let requester = msg.sender_id.clone();
+                            // Sealed for the requester like any file, see the FileChunk loop
+                            let in_clear = server.encryption == encryption::Policy::Require
+                                && !server.seals_files_for(&requester);
+                            match server.blobs.get(hash) {
+                                _ if in_clear => log::warn!(
+                                    "did not send {} to {}: it would go in clear, and --encryption require",
+                                    hash,
+                                    requester
+                                ),
+                                Ok(data) => {
+                                    let op_id = format!("serve-{}", blobs::short(hash));
+                                    let token = server.jobs.start(&op_id);
+                                    if server.uploads.is_empty() {
+                                        self_tx.send(Event::FileChunk)?;
+                                    }
+                                    let upload = Upload::of_blob(hash, data, requester);
+                                    server.uploads.push_back((op_id, upload, token));
+                                }
+                                Err(e) => log::warn!("could not send {} to {}: {}", hash, requester, e),
+                            }
+                        }
+                        Nick(nick) if messages::is_valid_nick(nick) => {
+                            send_to_app(
+                                AppEvent::Nick(msg.sender_id.clone(), nick.clone()),
+                                &app_tx,
+                            );
+                        }
+                        Nick(nick) => {
+                            log::warn!("ignored the nick {:?} of {}", nick, msg.sender_id);
+                        }
+                        SlowMode(channel, seconds, _) if server.may_announce(&msg) => {
+                            let event = AppEvent::SlowMode(
+                                msg.sender_id.clone(),
+                                channel.clone(),
+                                *seconds,
+                            );
+                            send_to_app(event, &app_tx);
+                        }
+                        ChannelJoin(channel) if server.channels.contains(channel) => {
+                            let notice = Notice::info(
+                                "channel-joined",
+                                format!("{} joined {}", msg.sender_id, channel),
+                            );
+                            let notice =
+                                notice.with("app", &msg.sender_id).with("channel", channel);
+                            server.notify(notice, &app_tx);
+                        }
+                        ChannelLeave(channel) if server.channels.contains(channel) => {
+                            let notice = Notice::info(
+                                "channel-left",
+                                format!("{} left {}", msg.sender_id, channel),
+                            );
+                            let notice =
+                                notice.with("app", &msg.sender_id).with("channel", channel);
+                            server.notify(notice, &app_tx);
+                        }
+                        SlowMode(..) => {
+                            log::warn!("{} may not set the slow mode, ignored", msg.sender_id);
+                        }
+                        Hello(info) => {
+                            if let Some(sent_at) = msg.sent_at {
+                                server
+                                    .time_sync
+                                    .hello(&msg.sender_id, sent_at, wall_clock());
+                                let skew = server.time_sync.skew(&msg.sender_id);
+                                if skew != 0 {
+                                    let notice = Notice::warning(
+                                        "clock-skew",
+                                        format!(
+                                            "The clock of {} is {} of the others, the times of its messages are adjusted",
+                                            msg.sender_id,
+                                            timesync::describe(skew)
+                                        ),
+                                    );
+                                    let notice =
+                                        notice.with("app", &msg.sender_id).with("seconds", skew);
+                                    server.notify(notice, &app_tx);
+                                }
+                            }
+                            let changed = server.peers.get(&msg.sender_id) != Some(info);
+                            if changed {
+                                server.peers.insert(msg.sender_id.clone(), info.clone());
+                                if let Some(report) = info.compatibility(&msg.sender_id) {
+                                    let notice = Notice::warning("incompatible-version", report)
+                                        .with("app", &msg.sender_id);
+                                    server.notify(notice, &app_tx);
+                                }
+                                server.negotiate_codec(&transport, &app_tx);
+                                server.negotiate_compression(&transport);
+                            }
+                        }
+                        EncryptionAnnouncement(key, signature) => {
+                            let payload = identity::encryption_payload(&msg.sender_id, key);
+                            if server
+                                .contacts
+                                .is_signed_by(&msg.sender_id, &payload, signature)
+                            {
+                                server.encryption_keys.insert(msg.sender_id.clone(), *key);
+                                server.unencrypted.remove(&msg.sender_id);
+                                server.send_queued(&msg.sender_id, &transport, &app_tx);
+                            } else {
+                                let notice = Notice::warning(
+                                    "unsigned-encryption-key",
+                                    format!(
+                                        "ignored an encryption key from {}: not signed by its identity key",
+                                        msg.sender_id
+                                    ),
+                                );
+                                server.notify(notice.with("app", &msg.sender_id), &app_tx);
+                            }
+                        }
+                        KeyAnnouncement(key) => {
+                            let status = server.contacts.observe(&msg.sender_id, *key);
+                            if status == KeyStatus::Mismatch {
+                                let notice = Notice::warning(
+                                    "key-mismatch",
+                                    format!(
+                                        "{} announced an unknown key {}, keeping the trusted one",
+                                        msg.sender_id,
+                                        key.fingerprint()
+                                    ),
+                                );
+                                let notice = notice
+                                    .with("app", &msg.sender_id)
+                                    .with("fingerprint", key.fingerprint());
+                                server.notify(notice, &app_tx);
+                            }
+                        }
+                        KeyRotation(new_key, signature) => {
+                            let notice =
+                                match server.contacts.rotate(&msg.sender_id, *new_key, signature) {
+                                    KeyStatus::Rotated => Some(Notice::info(
+                                        "key-rotated",
+                                        format!(
+                                            "{} rotated their identity key, new fingerprint: {}",
+                                            msg.sender_id,
+                                            new_key.fingerprint()
+                                        ),
+                                    )),
+                                    KeyStatus::Rejected => Some(Notice::warning(
+                                        "rotation-rejected",
+                                        format!(
+                                            "rejected key rotation from {}: not signed by the trusted key",
+                                            msg.sender_id
+                                        ),
+                                    )),
+                                    KeyStatus::Revoked => Some(Notice::warning(
+                                        "rotation-rejected",
+                                        format!(
+                                            "rejected key rotation from {}: identity was revoked",
+                                            msg.sender_id
+                                        ),
+                                    )),
+                                    _ => None,
+                                };
+                            if let Some(notice) = notice {
+                                let notice = notice
+                                    .with("app", &msg.sender_id)
+                                    .with("fingerprint", new_key.fingerprint());
+                                server.notify(notice, &app_tx);
+                            }
+                        }
+                        Revocation(certificate) => match server.contacts.revoke(certificate) {
+                            KeyStatus::Revoked => {
+                                send_to_app(
+                                    AppEvent::IdentityRevoked(certificate.app_id.clone()),
+                                    &app_tx,
+                                );
+                            }
+                            KeyStatus::SetAside => log::info!(
+                                "kept aside the revocation of {}, not met yet",
+                                certificate.app_id
+                            ),
+                            _ => {
+                                let notice = Notice::warning(
+                                    "invalid-revocation",
+                                    format!(
+                                        "ignored an invalid revocation of {} sent by {}",
+                                        certificate.app_id, msg.sender_id
+                                    ),
+                                );
+                                let notice = notice
+                                    .with("app", &certificate.app_id)
+                                    .with("sender", &msg.sender_id);
+                                server.notify(notice, &app_tx);
+                            }
+                        },
+                        Disconnection => {
+                            // Recorded as a departure, not as a notice
+                            let notice = Notice::info("left", format!("{} left", msg.sender_id));
+                            send_to_app(
+                                AppEvent::Notice(notice.with("app", &msg.sender_id)),
+                                &app_tx,
+                            );
+                            server
+                                .recorder
+                                .record(Some(&server.clock), Step::Left(msg.sender_id.clone()));
+                            server.directory.remove(&msg.sender_id);
+                            if server.peers.remove(&msg.sender_id).is_some() {
+                                server.negotiate_codec(&transport, &app_tx);
+                                server.negotiate_compression(&transport);
+                            }
+                            let released = server.delivery.left(&msg.sender_id);
+                            server.hand_over(released, &app_tx);
+                            server.send_receipts(&transport);
+                            server.forget_date(&msg.sender_id);
+                        }
+                        SnapshotRequest(app_id) => {
+                            let msg = server.new_message(SnapshotResponse(
+                                app_id.clone(),
+                                server.saved_messages.clone(),
+                            ));
+                            transport.send(&msg);
+                        }
+                        SnapshotResponse(app_id, _) if *app_id == server.app_id => {
+                            server.snapshot.add(msg);
+
+                            if server.snapshot.dates.len() == server.clock.len() {
+                                // We have received a snapshot from every site we know of
+                                // works because the server's clock has already been updated
+
+                                // Doesn't work if there are disconnected sites
+                                // in which case the snapshot request will timeout
+
+                                self_tx.send(Event::SnapshotTimeout).unwrap();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Event::Undecodable(sender_id, header) => {
+                if server.undecodable_senders.insert(sender_id.clone()) {
+                    let notice = Notice::warning(
+                        "undecodable",
+                        format!(
+                            "{} sent a {} message this version does not understand",
+                            sender_id, header
+                        ),
+                    );
+                    let notice = notice.with("app", &sender_id).with("header", header);
+                    server.notify(notice, &app_tx);
+                }
+            }
+        }
+    }
+
+    if !server.lurking {
+        let msg = server.new_message(Disconnection);
+        transport.send(&msg);
+    }
+    // Let the transport write what is left, the Disconnection included
+    drop(transport);
+    transport_handle
+        .join()
+        .expect("something went wrong with the transport thread");
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use std::ffi::CString;
+    use std::fs;
+    use std::os::unix::ffi::OsStrExt;
+    use std::time::Instant;
+    use timer::VirtualTime;
+
+    fn seeded_server(seed: u64) -> Server {
+        let app_id = "alice".to_owned();
+        let path = std::env::temp_dir().join("netchat-test-seed.key");
+        let mut server = Server::new(app_id, Identity::generate(), &path, Contacts::default());
+        server.set_rng(SmallRng::seed_from_u64(seed));
+        server
+    }
+
+    /// A directory of the tests, removed once dropped, even by a test failing
+    struct TempDir(PathBuf);
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Runs `server` on the pipes of a directory of its own named after
+    /// `name`: the named pipe `in`, silent until the test opens it, and the
+    /// file `out` it writes to
+    fn pipes_server(
+        name: &str,
+        server: Server,
+        app_tx: AppSender,
+    ) -> (mpsc::Sender<Event>, thread::JoinHandle<()>, TempDir) {
+        let dir =
+            std::env::temp_dir().join(format!("netchat-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir = TempDir(dir);
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        let fifo = CString::new(input.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        fs::File::create(&output).unwrap();
+
+        let (server_tx, server_rx) = mpsc::channel();
+        let endpoint = Endpoint::Pipes { input, output };
+        let running =
+            thread::spawn(move || run(server, server_rx, app_tx, vec![endpoint]).unwrap());
+        (server_tx, running, dir)
+    }
+
+    /// Waits for `done` 5 seconds at most, returns whether it came
+    fn wait_until(mut done: impl FnMut() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+
+    #[test]
+    fn seeded_runs_are_reproducible() {
+        let ids = |seed| {
+            let mut server = seeded_server(seed);
+            (0..10)
+                .map(|i| {
+                    server
+                        .new_message(Public(Channel::default(), i.to_string()))
+                        .id
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(42), ids(42));
+        assert_ne!(ids(42), ids(43));
+    }
+
+    #[test]
+    fn only_operators_announce() {
+        let bob = "bob".to_owned();
+        let identity = Identity::generate();
+        let announce = |signer: &Identity, text: &str| {
+            let signature = signer.sign(&identity::announcement_payload(&bob, text));
+            Msg::new(
+                1,
+                bob.clone(),
+                Announcement(text.to_owned(), signature),
+                Clock::new(bob.clone()),
+            )
+        };
+        let mut server = seeded_server(1);
+        server.contacts.observe(&bob, identity.public);
+        assert!(!server.may_announce(&announce(&identity, "deploy at 5")));
+
+        server.set_operators(std::iter::once(bob.clone()).collect());
+        assert!(server.may_announce(&announce(&identity, "deploy at 5")));
+        let forged = announce(&Identity::generate(), "deploy at 5");
+        assert!(!server.may_announce(&forged));
+        let mut altered = announce(&identity, "deploy at 5");
+        altered.header = match altered.header {
+            Announcement(_, signature) => Announcement("deploy now".to_owned(), signature),
+            header => header,
+        };
+        assert!(!server.may_announce(&altered));
+    }
+
+    #[test]
+    fn unsigned_messages_of_a_known_key_are_dropped() {
+        let path = std::env::temp_dir().join("netchat-test-bob-unsigned.key");
+        let mut bob = Server::new(
+            "bob".to_owned(),
+            Identity::generate(),
+            &path,
+            Contacts::default(),
+        );
+        let mut alice = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(16);
+
+        let mut stripped = bob.new_message(Public(Channel::default(), "hi".to_owned()));
+        stripped.signature = None;
+        assert!(
+            alice.authenticate(&mut stripped.clone(), &app_tx),
+            "key unknown"
+        );
+        assert!(!stripped.verified);
+
+        alice.contacts.observe(&bob.app_id, bob.identity.public);
+        assert!(!alice.authenticate(&mut stripped, &app_tx));
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "forged-message"),
+            _ => panic!("expected a notice"),
+        }
+        let mut signed = bob.new_message(Public(Channel::default(), "hi".to_owned()));
+        assert!(alice.authenticate(&mut signed, &app_tx));
+    }
+
+    #[test]
+    fn forged_senders_are_dropped() {
+        let path = std::env::temp_dir().join("netchat-test-bob.key");
+        let mut bob = Server::new(
+            "bob".to_owned(),
+            Identity::generate(),
+            &path,
+            Contacts::default(),
+        );
+        let mut alice = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(16);
+
+        let mut msg = bob.new_message(Public(Channel::default(), "hi".to_owned()));
+        assert!(alice.authenticate(&mut msg.clone(), &app_tx), "key unknown");
+        assert!(!msg.verified);
+
+        alice.contacts.observe(&bob.app_id, bob.identity.public);
+        assert!(alice.authenticate(&mut msg, &app_tx));
+        assert!(msg.verified);
+
+        let mut forged = alice.new_message(Public(Channel::default(), "send me $".to_owned()));
+        forged.sender_id = bob.app_id.clone();
+        assert!(!alice.authenticate(&mut forged.clone(), &app_tx));
+        assert!(!alice.authenticate(&mut forged, &app_tx));
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "forged-message"),
+            _ => panic!("expected a notice"),
+        }
+        assert!(app_rx.try_recv().is_none(), "reported once");
+    }
+
+    #[test]
+    fn required_encryption_keeps_private_messages_from_going_in_clear() {
+        let mut server = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(16);
+        let private =
+            |server: &mut Server| server.private("bob".to_owned(), "hi".to_owned(), &app_tx);
+
+        server.set_encryption(encryption::Policy::AllowPlaintext);
+        assert!(matches!(
+            private(&mut server).as_deref(),
+            Some([Private(..)])
+        ));
+        assert!(app_rx.try_recv().is_none(), "without a word");
+        server.set_encryption(encryption::Policy::Prefer);
+        assert!(matches!(
+            private(&mut server).as_deref(),
+            Some([Private(..)])
+        ));
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "sent-in-clear"),
+            _ => panic!("expected a notice"),
+        }
+        server.set_encryption(encryption::Policy::Require);
+        assert_eq!(private(&mut server), None);
+
+        let bob = Identity::generate();
+        server
+            .encryption_keys
+            .insert("bob".to_owned(), bob.encryption_key());
+        assert!(matches!(
+            private(&mut server).as_deref(),
+            Some([Encrypted(..)])
+        ));
+    }
+
+    #[test]
+    fn each_linked_device_gets_its_own_sealed_message() {
+        let mut server = seeded_server(1);
+        let (app_tx, _app_rx) = crate::app::channel::channel(16);
+        server.set_encryption(encryption::Policy::Require);
+        let (bob, phone) = (Identity::generate(), Identity::generate());
+        let (owner, device) = ("bob".to_owned(), "bob-phone".to_owned());
+        server.contacts.observe(&owner, bob.public);
+        let link = DeviceLink::new(&bob, &owner, &device, phone.public);
+        assert_eq!(server.contacts.link(&link), KeyStatus::New);
+        server
+            .encryption_keys
+            .insert("bob".to_owned(), bob.encryption_key());
+        let private =
+            |server: &mut Server| server.private("bob".to_owned(), "hi".to_owned(), &app_tx);
+        assert_eq!(private(&mut server), None, "the phone announced no key");
+
+        server
+            .encryption_keys
+            .insert("bob-phone".to_owned(), phone.encryption_key());
+        let sealed = private(&mut server).unwrap();
+        let mut to: Vec<&str> = sealed
+            .iter()
+            .map(|header| match header {
+                Encrypted(to, sealed) => {
+                    assert!(!sealed.contains("hi"));
+                    to.as_str()
+                }
+                _ => panic!("expected it sealed"),
+            })
+            .collect();
+        to.sort();
+        assert_eq!(to, ["bob", "bob-phone"]);
+    }
+
+    #[test]
+    fn linked_devices_get_the_history_they_lack_sealed_in_chunks() {
+        let mut alice = seeded_server(1);
+        let (phone_id, phone) = ("alice-phone".to_owned(), Identity::generate());
+        let link = DeviceLink::new(&alice.identity, &alice.app_id, &phone_id, phone.public);
+        alice.contacts.observe(&phone_id, phone.public);
+        alice.contacts.link(&link);
+        alice.set_limits(Limits {
+            max_frame_len: 4096,
+            ..Limits::default()
+        });
+        let bob = Identity::generate();
+        alice
+            .encryption_keys
+            .insert("bob".to_owned(), bob.encryption_key());
+        let (app_tx, _app_rx) = crate::app::channel::channel(16);
+        let said = |alice: &mut Server, header: Header| {
+            let msg = alice.new_message(header);
+            alice.saved_messages.push(msg.clone());
+            msg
+        };
+        for i in 0..8 {
+            said(
+                &mut alice,
+                Public(Channel::default(), format!("{} {}", i, "x".repeat(400))),
+            );
+        }
+        let sealed = alice.private("bob".to_owned(), "secret".to_owned(), &app_tx);
+        said(&mut alice, sealed.unwrap().remove(0));
+
+        // In clear only without --encryption require
+        alice.set_encryption(encryption::Policy::Require);
+        assert!(alice.history_for(&phone_id, None).is_empty());
+        alice.set_encryption(encryption::Policy::Prefer);
+        let clear = alice.history_for(&phone_id, None);
+        assert!(clear.iter().all(|h| matches!(h, HistorySync(..))));
+        alice.history_synced.clear();
+
+        alice
+            .encryption_keys
+            .insert(phone_id.clone(), phone.encryption_key());
+        alice.peers.insert(phone_id.clone(), VersionInfo::local());
+        let key = alice.identity.encryption_key();
+        let open = |headers: Vec<Header>| -> Vec<Msg> {
+            headers
+                .iter()
+                .flat_map(|header| match header {
+                    EncryptedHistory(to, sealed) if *to == phone_id => {
+                        assert!(!sealed.contains("secret"));
+                        phone.decrypt_history("alice", to, &key, sealed).unwrap()
+                    }
+                    _ => panic!("expected a sealed history"),
+                })
+                .collect()
+        };
+        let history = alice.history_for(&phone_id, None);
+        assert!(history.len() > 1, "in chunks");
+        let history = open(history);
+        assert_eq!(history.len(), 9);
+        assert_eq!(
+            history[8].header,
+            Private("bob".to_owned(), "secret".to_owned())
+        );
+        assert!(
+            alice.history_for(&phone_id, None).is_empty(),
+            "sent already"
+        );
+
+        // Seen live by the phone, except what was sealed for bob
+        let seen = said(&mut alice, Public(Channel::default(), "seen".to_owned()));
+        let sealed = alice.private("bob".to_owned(), "again".to_owned(), &app_tx);
+        said(&mut alice, sealed.unwrap().remove(0));
+        let history = open(alice.history_for(&phone_id, Some(&alice.clock.clone())));
+        assert_eq!(history.len(), 1);
+        assert_ne!(history[0].id, seen.id);
+        assert_eq!(
+            history[0].header,
+            Private("bob".to_owned(), "again".to_owned())
+        );
+    }
+
+    #[test]
+    fn dates_of_apps_gone_are_not_merged_back() {
+        let mut alice = seeded_server(1);
+        let said = |sender: &str, header: Header, dates: &[(&str, Date)]| {
+            let clock = Clock(dates.iter().map(|(id, d)| ((*id).to_owned(), *d)).collect());
+            Msg::new(1, sender.to_owned(), header, clock)
+        };
+        let bob = "bob".to_owned();
+        alice.merge_clock(&said("bob", Disconnection, &[("bob", 3)]));
+        alice.forget_date(&bob);
+        // carol did not hear bob leave yet
+        let stale = said(
+            "carol",
+            Public(Channel::default(), "hi".to_owned()),
+            &[("bob", 3), ("carol", 1)],
+        );
+        alice.merge_clock(&stale);
+        assert_eq!(alice.clock.get("bob"), None);
+        assert_eq!(alice.clock.get("carol"), Some(&1));
+
+        alice.merge_clock(&said("bob", Connection, &[("bob", 1)]));
+        assert_eq!(alice.clock.get("bob"), Some(&1), "back");
+    }
+
+    #[test]
+    fn file_chunks_are_encrypted_and_acknowledged() {
+        let dir = std::env::temp_dir().join(format!("netchat-test-{}-sealed", std::process::id()));
+        let path = dir.join("bob.key");
+        let mut bob = Server::new(
+            "bob".to_owned(),
+            Identity::generate(),
+            &path,
+            Contacts::default(),
+        );
+        bob.set_download_dirs(
+            dir.join("downloads"),
+            dir.join("quarantine"),
+            dir.join("blobs"),
+        );
+        let mut alice = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(16);
+        let chunk = |chunk_index| File {
+            to: "bob".to_owned(),
+            name: "notes.txt".to_owned(),
+            chunk_index,
+            total_chunks: 2,
+            data: to_hex(b"secret"),
+        };
+        assert_eq!(alice.seal_chunk(chunk(0)), chunk(0), "no key yet");
+        alice
+            .encryption_keys
+            .insert("bob".to_owned(), bob.identity.encryption_key());
+        assert_eq!(
+            alice.seal_chunk(chunk(0)),
+            chunk(0),
+            "bob reads no encrypted chunk"
+        );
+        alice.peers.insert("bob".to_owned(), VersionInfo::local());
+        let sealed = alice.seal_chunk(chunk(0));
+        assert!(
+            matches!(&sealed, EncryptedFile { sealed, .. } if !sealed.contains(&to_hex(b"secret")))
+        );
+
+        // Sent again until bob acknowledges it
+        alice.set_retransmission(2, Duration::from_secs(5));
+        let msg = alice.new_message(sealed);
+        alice
+            .retransmissions
+            .track(&msg, msg.header.summary(), Instant::now());
+        alice.retransmissions.ack(msg.id, "carol", "carol");
+        assert_eq!(alice.retransmissions.waiting(), 1);
+        assert!(bob.is_to_ack(&msg));
+        alice.retransmissions.ack(msg.id, "bob", "bob");
+        assert_eq!(alice.retransmissions.waiting(), 0);
+
+        bob.encryption_keys
+            .insert("alice".to_owned(), alice.identity.encryption_key());
+        let mut moved = msg.clone();
+        if let EncryptedFile { chunk_index, .. } = &mut moved.header {
+            *chunk_index = 1;
+        }
+        assert_eq!(bob.decrypted(moved), None, "bound to its place in the file");
+        bob.receive_chunk(msg, &app_tx);
+        match app_rx.recv() {
+            AppEvent::Progress { done, total, .. } => assert_eq!((done, total), (1, 2)),
+            _ => panic!("expected the progress"),
+        }
+        let last = alice.new_message(alice.seal_chunk(chunk(1)));
+        bob.receive_chunk(last, &app_tx);
+        assert!(matches!(app_rx.recv(), AppEvent::Progress { .. }));
+        // Signed by a key bob does not know
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "file-quarantined"),
+            _ => panic!("expected a notice"),
+        }
+
+        for chunk_index in 0..2 {
+            let mut msg = alice.new_message(alice.seal_chunk(chunk(chunk_index)));
+            msg.verified = true;
+            bob.receive_chunk(msg, &app_tx);
+            assert!(matches!(app_rx.recv(), AppEvent::Progress { .. }));
+        }
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "file-received"),
+            _ => panic!("expected a notice"),
+        }
+        let quarantined = fs::read(dir.join("quarantine").join("notes.txt")).unwrap();
+        let received = fs::read(dir.join("downloads").join("notes.txt")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(quarantined, b"secretsecret");
+        assert_eq!(received, b"secretsecret");
+    }
+
+    #[test]
+    fn files_are_screened_under_the_name_they_are_saved_as() {
+        let mut bob = Server::new(
+            "bob".to_owned(),
+            Identity::generate(),
+            &std::env::temp_dir().join("netchat-test-screened.key"),
+            Contacts::default(),
+        );
+        let never = std::env::temp_dir().join("netchat-never-written");
+        bob.set_download_dirs(never.clone(), never.clone(), never);
+        let mut alice = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(16);
+        for name in ["run.bat.", "run.ps1 ", "x.j\u{202e}s"] {
+            let chunk = File {
+                to: "bob".to_owned(),
+                name: name.to_owned(),
+                chunk_index: 0,
+                total_chunks: 1,
+                data: to_hex(b"echo hi"),
+            };
+            let mut msg = alice.new_message(chunk);
+            msg.verified = true;
+            bob.receive_chunk(msg, &app_tx);
+            assert!(matches!(app_rx.recv(), AppEvent::Progress { .. }));
+            match app_rx.recv() {
+                AppEvent::Notice(notice) => assert_eq!(notice.code, "file-refused", "{:?}", name),
+                _ => panic!("expected a notice"),
+            }
+        }
+    }
+
+    #[test]
+    fn files_offered_are_fetched_from_their_holder() {
+        let dir = std::env::temp_dir().join(format!("netchat-test-{}-offers", std::process::id()));
+        let path = dir.join("bob.key");
+        let mut bob = Server::new(
+            "bob".to_owned(),
+            Identity::generate(),
+            &path,
+            Contacts::default(),
+        );
+        bob.set_download_dirs(
+            dir.join("downloads"),
+            dir.join("quarantine"),
+            dir.join("blobs"),
+        );
+        // Offered by carol, held by alice, the first to answer
+        let mut alice = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(16);
+        let offer = Offer {
+            sender: "carol".to_owned(),
+            channel: Channel::default(),
+            name: "report.txt".to_owned(),
+            size: 6,
+            verified: false,
+        };
+        let mut want = |hash: &str| {
+            bob.offers.offer(hash.to_owned(), offer.clone());
+            bob.offers.want(hash, offer.clone(), true, Instant::now());
+            bob.offers.held(hash, "alice");
+        };
+        let hash = blobs::hash_of(b"report");
+        let forged = blobs::hash_of(b"forged");
+        want(&hash);
+        want(&forged);
+        let chunk = |name: &str| File {
+            to: "bob".to_owned(),
+            name: name.to_owned(),
+            chunk_index: 0,
+            total_chunks: 1,
+            data: to_hex(b"report"),
+        };
+
+        bob.receive_chunk(alice.new_message(chunk(&hash)), &app_tx);
+        assert!(matches!(app_rx.recv(), AppEvent::Progress { .. }));
+        // Asked for, it does not wait in quarantine though carol is not verified
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "file-received"),
+            _ => panic!("expected a notice"),
+        }
+        bob.receive_chunk(alice.new_message(chunk(&forged)), &app_tx);
+        assert!(matches!(app_rx.recv(), AppEvent::Progress { .. }));
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "file-refused"),
+            _ => panic!("expected a notice"),
+        }
+        let received = fs::read(dir.join("downloads").join("report.txt"));
+        let held = bob.blobs.has(&hash);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(received.unwrap(), b"report");
+        assert!(held, "served to the others from now on");
+        assert!(bob.offers.iter().next().is_none());
+    }
+
+    #[test]
+    fn only_senders_change_their_messages() {
+        let mut server = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(16);
+        let from = |sender: &str, header| {
+            Msg::new(9, sender.to_owned(), header, Clock::new(sender.to_owned()))
+        };
+        let original = from("bob", Public(Channel::default(), "hi".to_owned()));
+        server.authors.keep(&original);
+
+        let edit = from("carol", Edit(original.id, "send me $".to_owned()));
+        server.take_change(&edit, original.id, &app_tx);
+        match app_rx.recv() {
+            AppEvent::Notice(notice) => assert_eq!(notice.code, "change-refused"),
+            _ => panic!("expected a notice"),
+        }
+        assert_eq!(server.metrics.get(Metric::Dropped), 1);
+
+        let edit = from("bob", Edit(original.id, "hello".to_owned()));
+        server.take_change(&edit, original.id, &app_tx);
+        assert!(matches!(app_rx.recv(), AppEvent::Edited(9, text) if text == "hello"));
+        server.take_change(&from("bob", Delete(original.id)), original.id, &app_tx);
+        assert!(matches!(app_rx.recv(), AppEvent::Deleted(9)));
+        server.take_change(&edit, original.id, &app_tx);
+        assert!(app_rx.try_recv().is_none(), "deleted, not edited anymore");
+    }
+
+    #[test]
+    fn only_the_channels_joined_are_shown() {
+        let mut server = seeded_server(1);
+        let (app_tx, app_rx) = crate::app::channel::channel(1024);
+        let rust = Channel::parse("#rust").unwrap();
+        let public = |id, channel: &Channel| {
+            let header = Public(channel.clone(), "hi".to_owned());
+            Msg::new(id, "bob".to_owned(), header, Clock::new("bob".to_owned()))
+        };
+        let shown = || {
+            let mut ids = Vec::new();
+            while let Some(event) = app_rx.try_recv() {
+                if let AppEvent::DistantMessage(msg) = event {
+                    ids.push(msg.id);
+                }
+            }
+            ids
+        };
+        server.hand_over(
+            vec![public(1, &Channel::default()), public(2, &rust)],
+            &app_tx,
+        );
+        assert_eq!(shown(), [1]);
+
+        server.channels.insert(rust.clone());
+        server.hand_over(vec![public(3, &rust)], &app_tx);
+        assert_eq!(shown(), [3]);
+    }
+
+    #[test]
+    fn heartbeats_follow_virtual_time() {
+        let time = VirtualTime::default();
+        let mut server = seeded_server(1);
+        server.set_heartbeat(Duration::from_secs(30));
+        server.set_timer(Arc::new(time.clone()));
+        let (app_tx, _app_rx) = crate::app::channel::channel(1024);
+        // No one writes to the input, it stays silent
+        let (server_tx, running, dir) = pipes_server("time", server, app_tx);
+        let output = dir.join("out");
+        let heartbeats = || {
+            fs::read_to_string(&output)
+                .unwrap()
+                .matches("\"Heartbeat\"")
+                .count()
+        };
+
+        time.wait_for_sleepers(1);
+        time.advance(Duration::from_secs(29));
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(heartbeats(), 0);
+
+        time.advance(Duration::from_secs(1));
+        wait_until(|| heartbeats() > 0);
+        assert_eq!(heartbeats(), 1);
+
+        server_tx.send(Event::Shutdown).unwrap();
+        running.join().unwrap();
+    }
+
+    #[test]
+    fn observers_send_nothing_of_their_own() {
+        let mut server = seeded_server(1);
+        server.set_observe(true);
+        let (app_tx, _app_rx) = crate::app::channel::channel(1024);
+        let (server_tx, running, dir) = pipes_server("observe", server, app_tx);
+        server_tx.send(Event::Heartbeat).unwrap();
+        server_tx
+            .send(Event::UserPublicMessage(
+                Channel::default(),
+                "hi".to_owned(),
+                None,
+            ))
+            .unwrap();
+        server_tx.send(Event::Shutdown).unwrap();
+        running.join().unwrap();
+
+        // Neither the connection, the hello, the keys, the message nor the disconnection
+        let written = fs::read_to_string(dir.join("out")).unwrap();
+        assert_eq!(written, "");
+    }
+
+    #[test]
+    fn cbor_is_sent_once_everyone_reads_it() {
+        let mut server = seeded_server(1);
+        server.set_wire_format(Codec::Cbor);
+        let (app_tx, app_rx) = crate::app::channel::channel(1024);
+        let (server_tx, running, dir) = pipes_server("cbor", server, app_tx);
+        let hello = Msg::new(
+            9,
+            "bob".to_owned(),
+            Hello(VersionInfo::local()),
+            Clock::new("bob".to_owned()),
+        );
+        let mut writer = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("in"))
+            .unwrap();
+        writeln!(writer, "{}", hello.serialize().unwrap()).unwrap();
+        loop {
+            if let AppEvent::Notice(notice) = app_rx.recv() {
+                if notice.code == "wire-format" {
+                    assert_eq!(notice.context["codec"], "cbor");
+                    break;
+                }
+            }
+        }
+        server_tx
+            .send(Event::UserPublicMessage(
+                Channel::default(),
+                "hi".to_owned(),
+                None,
+            ))
+            .unwrap();
+        server_tx.send(Event::Shutdown).unwrap();
+        running.join().unwrap();
+        drop(writer);
+
+        let written = fs::read(dir.join("out")).unwrap();
+        let mut frames = framing::FrameReader::new(&written[..], 1 << 20, Default::default());
+        assert!(
+            matches!(frames.next_frame(), Ok(Some(framing::Frame::Line(line))) if line.contains("Connection")),
+            "json until bob said it reads cbor"
+        );
+        let sent = frames
+            .filter_map(|frame| match frame.unwrap() {
+                framing::Frame::Binary(payload) => {
+                    messages::parse_binary(&payload, framing::MAX_FRAME_LEN).ok()
+                }
+                _ => None,
+            })
+            .find(|msg| msg.sender_id == "alice" && matches!(msg.header, Public(..)));
+        assert!(sent.is_some());
+    }
+
+    #[test]
+    fn goodbye_waits_for_the_acknowledgements() {
+        // Time stands still, the goodbye timeout never comes
+        let mut server = seeded_server(1);
+        server.set_retransmission(3, Duration::from_secs(5));
+        server.set_timer(Arc::new(VirtualTime::default()));
+        let (app_tx, _app_rx) = crate::app::channel::channel(1024);
+        let (server_tx, running, dir) = pipes_server("goodbye", server, app_tx);
+        let mut writer = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("in"))
+            .unwrap();
+        server_tx
+            .send(Event::UserPublicMessage(
+                Channel::default(),
+                "bye".to_owned(),
+                None,
+            ))
+            .unwrap();
+        server_tx.send(Event::Shutdown).unwrap();
+
+        let written = || fs::read_to_string(dir.join("out")).unwrap();
+        let mut sent = None;
+        let public = wait_until(|| {
+            sent = written()
+                .lines()
+                .filter_map(|line| messages::parse(line.as_bytes()).ok())
+                .find(|msg| matches!(msg.header, Public(..)));
+            sent.is_some()
+        });
+        assert!(public, "the message was not sent");
+        let sent = sent.unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert!(!written().contains("Disconnection"), "waiting for bob");
+
+        let ack = Msg::new(
+            9,
+            "bob".to_owned(),
+            Ack(sent.id),
+            Clock::new("bob".to_owned()),
+        );
+        writeln!(writer, "{}", ack.serialize().unwrap()).unwrap();
+        running.join().unwrap();
+        drop(writer);
+        assert!(written().lines().last().unwrap().contains("Disconnection"));
+    }
+
+    #[test]
+    fn fast_relays_acknowledge_retransmissions_again() {
+        let time = VirtualTime::default();
+        let mut server = seeded_server(1);
+        server.set_fast_relay(true);
+        server.set_timer(Arc::new(time.clone()));
+        let (app_tx, _app_rx) = crate::app::channel::channel(1024);
+        let (server_tx, running, dir) = pipes_server("reack", server, app_tx);
+        let mut writer = fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("in"))
+            .unwrap();
+
+        let hi = Msg::new(
+            7,
+            "bob".to_owned(),
+            Public(Channel::default(), "hi".to_owned()),
+            Clock::new("bob".to_owned()),
+        );
+        let line = hi.serialize().unwrap();
+        let acks = || {
+            fs::read_to_string(dir.join("out"))
+                .unwrap()
+                .lines()
+                .filter_map(|line| messages::parse(line.as_bytes()).ok())
+                .filter(|msg| msg.header == Ack(7))
+                .count()
+        };
+        writeln!(writer, "{}", line).unwrap();
+        wait_until(|| acks() >= 1);
+        assert_eq!(acks(), 1);
+
+        // The ack was lost, bob sends the message again
+        time.advance(Duration::from_secs(2));
+        writeln!(writer, "{}", line).unwrap();
+        wait_until(|| acks() >= 2);
+        assert_eq!(acks(), 2, "acknowledged again");
+
+        server_tx.send(Event::Shutdown).unwrap();
+        running.join().unwrap();
+        drop(writer);
+    }
+}
@@ -0,0 +1,86 @@
+//! Notices from the server to the app: what happened, how bad it is, and a
+//! stable code with the names and values involved for scripts reading
+//! `--record`
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    /// Something was ignored or downgraded, the chat goes on
+    Warning,
+    /// Something was lost or refused
+    Error,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Notice {
+    pub severity: Severity,
+    /// Kebab-case, for instance `decrypt-failed`, kept across versions
+    pub code: String,
+    /// What the user reads
+    pub text: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub context: BTreeMap<String, String>,
+}
+
+impl Notice {
+    pub fn new(severity: Severity, code: &str, text: String) -> Notice {
+        Notice {
+            severity,
+            code: code.to_owned(),
+            text,
+            context: BTreeMap::new(),
+        }
+    }
+
+    pub fn info(code: &str, text: String) -> Notice {
+        Notice::new(Severity::Info, code, text)
+    }
+
+    pub fn warning(code: &str, text: String) -> Notice {
+        Notice::new(Severity::Warning, code, text)
+    }
+
+    pub fn error(code: &str, text: String) -> Notice {
+        Notice::new(Severity::Error, code, text)
+    }
+
+    /// Adds `key` with `value` to the context
+    pub fn with(mut self, key: &str, value: impl ToString) -> Notice {
+        self.context.insert(key.to_owned(), value.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Notice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.severity {
+            Severity::Info => write!(f, "Server: {}", self.text),
+            Severity::Warning => write!(f, "Warning: {}", self.text),
+            Severity::Error => write!(f, "Error: {}", self.text),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notices_serialize_with_their_context_only_if_any() {
+        let notice = Notice::error("decrypt-failed", "Could not decrypt".to_owned());
+        assert_eq!(
+            serde_json::to_string(&notice).unwrap(),
+            r#"{"severity":"error","code":"decrypt-failed","text":"Could not decrypt"}"#
+        );
+        let notice = notice.with("sender", "bob");
+        let line = serde_json::to_string(&notice).unwrap();
+        assert!(line.ends_with(r#""context":{"sender":"bob"}}"#));
+        assert_eq!(serde_json::from_str::<Notice>(&line).unwrap(), notice);
+    }
+}
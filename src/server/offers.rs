@@ -0,0 +1,177 @@
+//! Files offered in a channel with `/send <path>`: only their name, size and
+//! hash are broadcast. Those who want one ask who holds it and fetch its
+//! chunks from the first to answer, the nearest, which may be another
+//! receiver rather than the sender.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use super::messages::Channel;
+use crate::app::AppId;
+
+/// Offers kept for `/files get`, the oldest are forgotten
+const MAX_OFFERS: usize = 64;
+
+/// A file offered, until it is fetched
+#[derive(Clone, Debug, PartialEq)]
+pub struct Offer {
+    pub sender: AppId,
+    pub channel: Channel,
+    pub name: String,
+    pub size: u64,
+    /// Signed by a key we know
+    pub verified: bool,
+}
+
+struct Fetch {
+    offer: Offer,
+    /// The first to hold the file, None until one answers
+    holder: Option<AppId>,
+    /// By the user rather than after `--accept-files`
+    requested: bool,
+    /// When the file was wanted
+    since: Instant,
+}
+
+/// Files offered and being fetched, by hash
+#[derive(Default)]
+pub struct Offers {
+    /// The latest last
+    offered: VecDeque<(String, Offer)>,
+    fetching: HashMap<String, Fetch>,
+}
+
+impl Offers {
+    /// Keeps `offer` of the file `hash`
+    pub fn offer(&mut self, hash: String, offer: Offer) {
+        self.offered.retain(|(offered, _)| *offered != hash);
+        if self.offered.len() == MAX_OFFERS {
+            self.offered.pop_front();
+        }
+        self.offered.push_back((hash, offer));
+    }
+
+    /// The file offered whose hash starts with `prefix`, the error tells why
+    /// there is not one
+    pub fn find(&self, prefix: &str) -> Result<(String, Offer), String> {
+        let mut found = self
+            .offered
+            .iter()
+            .filter(|(hash, _)| hash.starts_with(prefix));
+        match (found.next(), found.next()) {
+            (Some((hash, offer)), None) => Ok((hash.clone(), offer.clone())),
+            (None, _) => Err(format!("no file offered has the hash {}", prefix)),
+            (Some(_), Some(_)) => Err(format!("several files offered start with {}", prefix)),
+        }
+    }
+
+    /// Fetches the file `hash` of `offer` from now on, `requested` by the
+    /// user. False if it is being fetched already.
+    pub fn want(&mut self, hash: &str, offer: Offer, requested: bool, now: Instant) -> bool {
+        if let Some(fetch) = self.fetching.get_mut(hash) {
+            fetch.requested |= requested;
+            return false;
+        }
+        let fetch = Fetch {
+            offer,
+            holder: None,
+            requested,
+            since: now,
+        };
+        self.fetching.insert(hash.to_owned(), fetch);
+        true
+    }
+
+    /// Fetches the file `hash` from `holder` if it is the first to hold it,
+    /// returns whether it is
+    pub fn held(&mut self, hash: &str, holder: &str) -> bool {
+        match self.fetching.get_mut(hash) {
+            Some(fetch) if fetch.holder.is_none() => {
+                fetch.holder = Some(holder.to_owned());
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The offer of the file `hash`, if its chunks are fetched from `holder`
+    pub fn fetched_from(&self, hash: &str, holder: &str) -> Option<&Offer> {
+        self.fetching
+            .get(hash)
+            .filter(|fetch| fetch.holder.as_deref() == Some(holder))
+            .map(|fetch| &fetch.offer)
+    }
+
+    /// Ends the fetch of the file `hash`, returns its offer and whether the
+    /// user asked for it
+    pub fn done(&mut self, hash: &str) -> Option<(Offer, bool)> {
+        self.offered.retain(|(offered, _)| offered != hash);
+        self.fetching
+            .remove(hash)
+            .map(|fetch| (fetch.offer, fetch.requested))
+    }
+
+    /// Gives up on the files nobody said to hold for `timeout` at `now`,
+    /// returns their offers
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<Offer> {
+        let unheld: Vec<String> = self
+            .fetching
+            .iter()
+            .filter(|(_, fetch)| fetch.holder.is_none())
+            .filter(|(_, fetch)| now.duration_since(fetch.since) >= timeout)
+            .map(|(hash, _)| hash.clone())
+            .collect();
+        unheld
+            .iter()
+            .filter_map(|hash| self.fetching.remove(hash))
+            .map(|fetch| fetch.offer)
+            .collect()
+    }
+
+    /// Files offered, with their hash, the latest last
+    pub fn iter(&self) -> impl Iterator<Item = &(String, Offer)> {
+        self.offered.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_are_fetched_from_the_first_holder() {
+        let offer = Offer {
+            sender: "alice".to_owned(),
+            channel: Channel::default(),
+            name: "report.pdf".to_owned(),
+            size: 4,
+            verified: true,
+        };
+        let mut offers = Offers::default();
+        offers.offer("3fa2".to_owned(), offer.clone());
+        offers.offer("3fb7".to_owned(), offer.clone());
+        assert!(offers.find("3f").is_err(), "ambiguous");
+        assert!(offers.find("9").is_err());
+        let (hash, found) = offers.find("3fa").unwrap();
+        assert_eq!((hash.as_str(), &found), ("3fa2", &offer));
+
+        let start = Instant::now();
+        assert!(offers.want("3fa2", offer.clone(), false, start));
+        assert!(!offers.want("3fa2", offer.clone(), true, start), "once");
+        assert!(offers.fetched_from("3fa2", "carol").is_none());
+        // carol, nearer, answers before alice
+        assert!(offers.held("3fa2", "carol"));
+        assert!(!offers.held("3fa2", "alice"));
+        assert_eq!(offers.fetched_from("3fa2", "carol"), Some(&offer));
+        assert!(offers.fetched_from("3fa2", "alice").is_none());
+        assert_eq!(offers.done("3fa2"), Some((offer.clone(), true)));
+        assert_eq!(offers.iter().count(), 1);
+
+        // Nobody holds it
+        let timeout = Duration::from_secs(60);
+        offers.want("3fb7", offer.clone(), false, start);
+        assert!(offers.expire(start + timeout / 2, timeout).is_empty());
+        assert_eq!(offers.expire(start + timeout, timeout), [offer]);
+        assert!(!offers.held("3fb7", "alice"));
+    }
+}
@@ -0,0 +1,167 @@
+use serde::{Deserialize, Serialize};
+
+use super::messages::Msg;
+
+/// Attempts after which a message is given up on until the user retries it,
+/// one attempt is made per reconnection of the output so this spans about a minute
+pub const MAX_ATTEMPTS: u32 = 8;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Status {
+    /// Waiting for the messages queued before it
+    Queued,
+    /// Writing it failed at least once
+    Retrying,
+    /// Given up after `MAX_ATTEMPTS`
+    Failed,
+}
+
+/// What the app displays of a message waiting in the outbox
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Item {
+    pub seq: u32,
+    pub summary: String,
+    pub status: Status,
+    pub attempts: u32,
+}
+
+struct Entry {
+    item: Item,
+    line: String, // Serialized message
+}
+
+/// Messages which could not be written to the output yet, in sending order
+#[derive(Default)]
+pub struct Outbox {
+    entries: Vec<Entry>,
+    next_seq: u32,
+}
+
+impl Outbox {
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn push(&mut self, msg: &Msg, line: String) {
+        self.next_seq += 1;
+        self.entries.push(Entry {
+            item: Item {
+                seq: self.next_seq,
+                summary: msg.header.summary(),
+                status: Status::Queued,
+                attempts: 0,
+            },
+            line,
+        });
+    }
+
+    /// Messages the next flush attempts to write
+    pub fn pending(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|e| e.item.status != Status::Failed)
+            .count()
+    }
+
+    /// Removes the messages the next flush would write, gives how many
+    pub fn drop_pending(&mut self) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.item.status == Status::Failed);
+        len - self.entries.len()
+    }
+
+    pub fn items(&self) -> Vec<Item> {
+        self.entries.iter().map(|e| e.item.clone()).collect()
+    }
+
+    /// Removes a message, returns false if there is no such message
+    pub fn cancel(&mut self, seq: u32) -> bool {
+        let len = self.entries.len();
+        self.entries.retain(|e| e.item.seq != seq);
+        self.entries.len() != len
+    }
+
+    /// Gives a fresh set of attempts to one message, or all of them
+    pub fn reset(&mut self, seq: Option<u32>) -> bool {
+        let mut found = false;
+        for entry in &mut self.entries {
+            if seq.is_none_or(|seq| seq == entry.item.seq) {
+                entry.item.status = Status::Queued;
+                entry.item.attempts = 0;
+                found = true;
+            }
+        }
+        found
+    }
+
+    /// Writes messages in order until one fails, failed messages are skipped.
+    /// Returns whether the outbox changed.
+    pub fn flush<F>(&mut self, mut write: F) -> bool
+    where
+        F: FnMut(&str) -> bool,
+    {
+        let len = self.entries.len();
+        let mut changed = false;
+        let mut i = 0;
+        while i < self.entries.len() {
+            let entry = &mut self.entries[i];
+            if entry.item.status == Status::Failed {
+                i += 1;
+                continue;
+            }
+            if write(&entry.line) {
+                self.entries.remove(i);
+                continue;
+            }
+            entry.item.attempts += 1;
+            entry.item.status = if entry.item.attempts >= MAX_ATTEMPTS {
+                Status::Failed
+            } else {
+                Status::Retrying
+            };
+            changed = true;
+            break;
+        }
+        changed || self.entries.len() != len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::{Channel, Header};
+    use crate::server::Clock;
+
+    #[test]
+    fn flush_in_order_until_failure() {
+        let mut outbox = Outbox::default();
+        for text in &["a", "b"] {
+            let msg = Msg::new(
+                0,
+                "me".to_owned(),
+                Header::Public(Channel::default(), text.to_string()),
+                Clock::new("me".to_owned()),
+            );
+            outbox.push(&msg, text.to_string());
+        }
+
+        for _ in 0..MAX_ATTEMPTS {
+            assert!(outbox.flush(|_| false));
+        }
+        let items = outbox.items();
+        assert_eq!(items[0].status, Status::Failed);
+        assert_eq!(items[1].status, Status::Queued);
+
+        // The failed message no longer holds back the next one
+        let mut written = Vec::new();
+        assert!(outbox.flush(|line| {
+            written.push(line.to_owned());
+            true
+        }));
+        assert_eq!(written, vec!["b"]);
+
+        assert!(outbox.reset(None));
+        assert!(outbox.flush(|_| true));
+        assert!(outbox.is_empty());
+    }
+}
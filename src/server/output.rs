@@ -0,0 +1,103 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use super::backend::{file_name, Transport};
+
+/// Output pipe which can be reopened once its reader went away and came back
+pub struct Output {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl Output {
+    /// Opens the output pipe,
+    /// the program will freeze until there is someone at the other end
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).open(&path)?;
+        Ok(Output {
+            path,
+            file: Some(file),
+        })
+    }
+}
+
+impl Transport for Output {
+    fn name(&self) -> String {
+        file_name(&self.path)
+    }
+
+    fn reconnect(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .append(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&self.path)?;
+
+        // Once connected, writes must block again instead of failing on a full pipe
+        let fd = file.as_raw_fd();
+        unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let mut file = self
+            .file
+            .take()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        file.write_all(frame)?;
+        // On error, the reader is gone and the file descriptor is dropped for good
+        self.file = Some(file);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::fs;
+    use std::io::Read;
+    use std::os::unix::ffi::OsStrExt;
+
+    fn reader(path: &PathBuf) -> File {
+        OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+            .unwrap()
+    }
+
+    #[test]
+    fn outputs_reopen_once_a_reader_comes_back() {
+        let dir = std::env::temp_dir().join(format!("netchat-test-{}-output", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out");
+        let fifo = CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let first = reader(&path);
+        let mut output = Output::open(path.clone()).unwrap();
+        output.write_frame(b"one\n").unwrap();
+        drop(first);
+        assert!(output.write_frame(b"lost\n").is_err());
+        assert!(output.write_frame(b"lost\n").is_err(), "until reconnected");
+        assert!(output.reconnect().is_err(), "no reader yet");
+
+        let mut second = reader(&path);
+        output.reconnect().unwrap();
+        output.write_frame(b"two\n").unwrap();
+        drop(output);
+        let mut read = String::new();
+        second.read_to_string(&mut read).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read, "two\n");
+    }
+}
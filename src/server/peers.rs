@@ -0,0 +1,187 @@
+//! Peers the server reads from, as they come and go, and what is held back
+//! from those cut off with `/partition`
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+
+use super::messages::Msg;
+use super::reconnect::{Change, State};
+
+#[derive(Default)]
+struct InputState {
+    connected: bool,
+    way_back: bool, // Its output came with its connection
+    cut: bool,
+    held: Vec<Msg>, // Received while cut
+}
+
+/// Inputs and outputs by name, an input and an output of the same connection
+/// share it
+#[derive(Default)]
+pub struct PeerManager {
+    inputs: BTreeMap<Arc<str>, InputState>,
+    outputs: BTreeSet<String>, // Opened on start, peers add theirs as they connect
+}
+
+impl PeerManager {
+    /// Input opened on start, connected once someone writes to it
+    pub fn add_input(&mut self, name: String) {
+        self.inputs.entry(name.into()).or_default();
+    }
+
+    pub fn add_output(&mut self, name: String) {
+        self.outputs.insert(name);
+    }
+
+    /// Someone is at the other end of the input `name`, gives the change to
+    /// report unless it is cut
+    pub fn connected(&mut self, name: &str, way_back: bool) -> Option<Change> {
+        let peer = self.inputs.entry(name.into()).or_default();
+        peer.connected = true;
+        peer.way_back = way_back;
+        if way_back {
+            self.outputs.insert(name.to_owned());
+        }
+        change(name, peer)
+    }
+
+    /// The other end of the input `name` went away. Peers which came with
+    /// their output are forgotten, unless messages are held back from them.
+    pub fn disconnected(&mut self, name: &str) -> Option<Change> {
+        let peer = self.inputs.get_mut(name)?;
+        peer.connected = false;
+        if !peer.way_back {
+            return change(name, peer);
+        }
+        self.outputs.remove(name);
+        if peer.cut {
+            return None;
+        }
+        self.inputs.remove(name);
+        Some(Change {
+            name: name.to_owned(),
+            state: State::Left,
+        })
+    }
+
+    /// Whether an input or an output is called `name`, any is for `None`
+    pub fn is_known(&self, name: Option<&str>) -> bool {
+        match name {
+            None => !self.inputs.is_empty() || !self.outputs.is_empty(),
+            Some(name) => self.inputs.contains_key(name) || self.outputs.contains(name),
+        }
+    }
+
+    /// Whether an output is called `name`, any is for `None`
+    pub fn has_output(&self, name: Option<&str>) -> bool {
+        name.map_or(!self.outputs.is_empty(), |name| self.outputs.contains(name))
+    }
+
+    /// Holds back what is read from the inputs matching `name`
+    pub fn partition(&mut self, name: Option<&str>) -> Vec<Change> {
+        let mut changes = Vec::new();
+        for (peer_name, peer) in self.matching(name) {
+            if !peer.cut {
+                peer.cut = true;
+                changes.push(Change {
+                    name: peer_name.to_string(),
+                    state: State::Partitioned,
+                });
+            }
+        }
+        changes
+    }
+
+    /// Undoes `partition`, gives back what was held with where it came from
+    pub fn heal(&mut self, name: Option<&str>) -> (Vec<Change>, Vec<(Msg, Arc<str>)>) {
+        let (mut changes, mut held) = (Vec::new(), Vec::new());
+        for (peer_name, peer) in self.matching(name) {
+            if peer.cut {
+                peer.cut = false;
+                changes.extend(change(peer_name, peer));
+                held.extend(peer.held.drain(..).map(|msg| (msg, peer_name.clone())));
+            }
+        }
+        self.inputs
+            .retain(|_, peer| peer.connected || !peer.way_back);
+        (changes, held)
+    }
+
+    /// Keeps `msg` if it was read from an input which is cut, gives it back
+    /// otherwise
+    pub fn hold(&mut self, msg: Msg, origin: &str) -> Option<Msg> {
+        match self.inputs.get_mut(origin) {
+            Some(peer) if peer.cut => {
+                peer.held.push(msg);
+                None
+            }
+            _ => Some(msg),
+        }
+    }
+
+    fn matching<'a>(
+        &'a mut self,
+        name: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a Arc<str>, &'a mut InputState)> + 'a {
+        self.inputs
+            .iter_mut()
+            .filter(move |(peer_name, _)| name.is_none_or(|name| name == &***peer_name))
+    }
+}
+
+/// State of the input `name` to show, nothing while it is cut
+fn change(name: &str, peer: &InputState) -> Option<Change> {
+    if peer.cut {
+        return None;
+    }
+    let state = if peer.connected {
+        State::Connected
+    } else {
+        State::Disconnected
+    };
+    Some(Change {
+        name: name.to_owned(),
+        state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::Header;
+    use crate::server::Clock;
+
+    #[test]
+    fn peers_are_forgotten_once_gone_unless_cut() {
+        let mut peers = PeerManager::default();
+        peers.add_input("b2a".to_owned());
+        peers.add_output("a2b".to_owned());
+        assert!(!peers.has_output(Some("10.0.0.2:4000")));
+        peers.connected("10.0.0.2:4000", true);
+        assert!(peers.has_output(Some("10.0.0.2:4000")));
+
+        let changes = peers.partition(None);
+        assert_eq!(changes.len(), 2, "both inputs are cut");
+        let msg = Msg::new(
+            1,
+            "carol".to_owned(),
+            Header::Connection,
+            Clock::new("carol".to_owned()),
+        );
+        assert!(peers.hold(msg, "10.0.0.2:4000").is_none());
+        assert_eq!(peers.disconnected("10.0.0.2:4000"), None, "cut, not shown");
+        assert!(peers.is_known(Some("10.0.0.2:4000")), "messages are held");
+
+        let (_, held) = peers.heal(None);
+        assert_eq!(held.len(), 1);
+        assert!(!peers.is_known(Some("10.0.0.2:4000")));
+        assert_eq!(
+            peers.disconnected("b2a"),
+            Some(Change {
+                name: "b2a".to_owned(),
+                state: State::Disconnected
+            })
+        );
+        assert!(peers.is_known(Some("b2a")), "pipes stay");
+    }
+}
@@ -0,0 +1,104 @@
+//! Apps online: those heard from lately, any message of theirs counts and
+//! heartbeats keep silent ones online. An app goes offline when it leaves or
+//! once nothing came from it for the timeout.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use crate::app::AppId;
+
+/// Timeouts are checked for this many times per timeout
+pub const CHECKS_PER_TIMEOUT: u32 = 4;
+
+#[derive(Default)]
+pub struct Presence {
+    last_seen: HashMap<AppId, Instant>,
+    offline: HashSet<AppId>, // Went offline, the apps never heard from left out
+    timeout: Option<Duration>,
+}
+
+impl Presence {
+    /// Apps silent for `timeout` are offline, never if None
+    pub fn new(timeout: Option<Duration>) -> Presence {
+        Presence {
+            last_seen: HashMap::new(),
+            offline: HashSet::new(),
+            timeout,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// `app_id` was heard from at `now`. Returns whether it just came online.
+    pub fn seen(&mut self, app_id: &str, now: Instant) -> bool {
+        self.offline.remove(app_id);
+        self.last_seen.insert(app_id.to_owned(), now).is_none()
+    }
+
+    /// `app_id` left. Returns whether it was online.
+    pub fn left(&mut self, app_id: &str) -> bool {
+        self.offline.insert(app_id.to_owned());
+        self.last_seen.remove(app_id).is_some()
+    }
+
+    /// Apps online
+    pub fn online(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    /// Whether `app_id` was online and is not anymore
+    pub fn is_offline(&self, app_id: &str) -> bool {
+        self.offline.contains(app_id)
+    }
+
+    /// Apps which just went offline, silent for the timeout at `now`
+    pub fn expire(&mut self, now: Instant) -> Vec<AppId> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return Vec::new(),
+        };
+        let mut expired: Vec<AppId> = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| now.duration_since(**seen) >= timeout)
+            .map(|(app_id, _)| app_id.clone())
+            .collect();
+        expired.sort();
+        for app_id in &expired {
+            self.last_seen.remove(app_id);
+            self.offline.insert(app_id.clone());
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_apps_go_offline() {
+        let start = Instant::now();
+        let mut presence = Presence::new(Some(Duration::from_secs(90)));
+        assert!(!presence.is_offline("bob"), "never heard from");
+        assert!(presence.seen("bob", start));
+        assert!(presence.seen("carol", start));
+        assert!(!presence.seen("bob", start + Duration::from_secs(60)));
+        assert!(presence.expire(start + Duration::from_secs(89)).is_empty());
+
+        assert_eq!(presence.expire(start + Duration::from_secs(90)), ["carol"]);
+        assert!(!presence.left("carol"), "already offline");
+        assert!(presence.left("bob"));
+        assert!(presence.is_offline("bob") && presence.is_offline("carol"));
+        assert!(presence.seen("carol", start + Duration::from_secs(100)));
+        assert!(!presence.is_offline("carol"));
+
+        let mut forever = Presence::new(None);
+        forever.seen("bob", start);
+        assert!(forever
+            .expire(start + Duration::from_secs(1_000_000))
+            .is_empty());
+    }
+}
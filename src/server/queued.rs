@@ -0,0 +1,76 @@
+//! Private messages for the apps gone offline: kept instead of being written
+//! to no one, and sent once their recipient is heard from again. The text is
+//! kept, the keys of the recipient may change by the time it is back.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::app::AppId;
+
+/// Messages kept for an app above this, the oldest are dropped
+const MAX_QUEUED: usize = 256;
+
+#[derive(Default)]
+pub struct Queued {
+    texts: HashMap<AppId, Vec<String>>,
+}
+
+impl Queued {
+    /// Keeps `text` until `recipient` is back. Returns how many messages wait
+    /// for it.
+    pub fn push(&mut self, recipient: AppId, text: String) -> usize {
+        let queued = self.texts.entry(recipient).or_default();
+        if queued.len() >= MAX_QUEUED {
+            queued.remove(0);
+        }
+        queued.push(text);
+        queued.len()
+    }
+
+    /// How many messages wait, for each app
+    pub fn counts(&self) -> BTreeMap<&str, usize> {
+        self.texts
+            .iter()
+            .map(|(app_id, texts)| (app_id.as_str(), texts.len()))
+            .collect()
+    }
+
+    /// How many messages wait, for all apps
+    pub fn total(&self) -> usize {
+        self.texts.values().map(Vec::len).sum()
+    }
+
+    /// What waited for `app_id`, in sending order
+    pub fn release(&mut self, app_id: &str) -> Vec<String> {
+        self.texts.remove(app_id).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_recipient_gets_its_own_in_order() {
+        let mut queued = Queued::default();
+        queued.push("bob".to_owned(), "hi".to_owned());
+        assert_eq!(queued.push("bob".to_owned(), "there".to_owned()), 2);
+        queued.push("carol".to_owned(), "yo".to_owned());
+        assert_eq!(queued.total(), 3);
+        assert_eq!(queued.counts().get("carol"), Some(&1));
+
+        assert_eq!(queued.release("bob"), ["hi", "there"]);
+        assert!(queued.release("bob").is_empty(), "released once");
+        assert_eq!(queued.total(), 1);
+    }
+
+    #[test]
+    fn the_oldest_are_dropped_past_the_limit() {
+        let mut queued = Queued::default();
+        for i in 0..MAX_QUEUED + 2 {
+            queued.push("bob".to_owned(), i.to_string());
+        }
+        let released = queued.release("bob");
+        assert_eq!(released.len(), MAX_QUEUED);
+        assert_eq!(released[0], "2");
+    }
+}
@@ -0,0 +1,108 @@
+//! Flood protection: each sender has a bucket of tokens, refilled at a steady
+//! rate up to a burst, and each of its chat messages, typing notices, live
+//! lines and edits takes one. Without a token the message is dropped, neither
+//! relayed nor shown, so a peer gone mad cannot freeze the app.
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::app::AppId;
+
+/// Senders above this, those with a full bucket are forgotten
+const MAX_SENDERS: usize = 1024;
+
+/// What to do with a message of a sender
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Pass,
+    /// Passes, after this many messages of the sender were dropped
+    Calmed(u64),
+    /// Dropped, the first since the sender was last let through
+    Throttled,
+    Dropped,
+}
+
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+    dropped: u64, // Since the last message let through
+}
+
+pub struct RateLimiter {
+    rate: f64, // Tokens a second
+    burst: f64,
+    buckets: HashMap<AppId, Bucket>,
+}
+
+impl RateLimiter {
+    /// Lets `rate` messages a second through for each sender, `burst` at once
+    pub fn new(rate: u32, burst: u32) -> Self {
+        RateLimiter {
+            rate: f64::from(rate),
+            burst: f64::from(burst.max(1)),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token for a message of `sender` at `now`
+    pub fn take(&mut self, sender: &str, now: Instant) -> Verdict {
+        if self.buckets.len() >= MAX_SENDERS && !self.buckets.contains_key(sender) {
+            let (rate, burst) = (self.rate, self.burst);
+            self.buckets.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.at).as_secs_f64();
+                bucket.tokens + elapsed * rate < burst
+            });
+        }
+        let bucket = self.buckets.entry(sender.to_owned()).or_insert(Bucket {
+            tokens: self.burst,
+            at: now,
+            dropped: 0,
+        });
+        let elapsed = now.saturating_duration_since(bucket.at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.at = now;
+        if bucket.tokens < 1.0 {
+            bucket.dropped += 1;
+            return match bucket.dropped {
+                1 => Verdict::Throttled,
+                _ => Verdict::Dropped,
+            };
+        }
+        bucket.tokens -= 1.0;
+        match std::mem::take(&mut bucket.dropped) {
+            0 => Verdict::Pass,
+            dropped => Verdict::Calmed(dropped),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn floods_are_cut_to_the_rate() {
+        let mut limiter = RateLimiter::new(2, 3);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.take("bob", start), Verdict::Pass);
+        }
+        assert_eq!(limiter.take("bob", start), Verdict::Throttled);
+        assert_eq!(limiter.take("bob", start), Verdict::Dropped);
+        assert_eq!(limiter.take("alice", start), Verdict::Pass, "per sender");
+
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.take("bob", later), Verdict::Calmed(2));
+        assert_eq!(limiter.take("bob", later), Verdict::Throttled);
+        let much_later = start + Duration::from_secs(60);
+        assert_eq!(limiter.take("bob", much_later), Verdict::Calmed(1));
+        assert_eq!(limiter.take("bob", much_later), Verdict::Pass);
+        assert_eq!(limiter.take("bob", much_later), Verdict::Pass);
+        assert_eq!(
+            limiter.take("bob", much_later),
+            Verdict::Throttled,
+            "burst at most"
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! Receipts of private messages: the server of the recipient sends a
+//! `Receipt` once the message was shown, which the sender only takes from the
+//! recipient, or a device of the identity it was for
+
+use std::collections::VecDeque;
+
+use super::messages::MsgId;
+use crate::app::AppId;
+
+/// Private messages awaiting a receipt above this, the oldest are forgotten
+const MAX_AWAITED: usize = 1024;
+
+#[derive(Default)]
+pub struct Receipts {
+    awaited: VecDeque<(MsgId, AppId)>, // Sent, with their recipient
+    due: Vec<MsgId>,                   // Shown here, their receipt not sent yet
+}
+
+impl Receipts {
+    /// Waits for the receipt of private message `id`, for `recipient`
+    pub fn sent(&mut self, id: MsgId, recipient: AppId) {
+        if self.awaited.len() >= MAX_AWAITED {
+            self.awaited.pop_front();
+        }
+        self.awaited.push_back((id, recipient));
+    }
+
+    /// Takes a receipt of `id` by `from`, a device of `identity`. Returns
+    /// whether it was awaited from them.
+    pub fn received(&mut self, id: MsgId, from: &str, identity: &str) -> bool {
+        let awaited = self
+            .awaited
+            .iter()
+            .position(|(i, to)| *i == id && (to == from || to == identity));
+        awaited.and_then(|i| self.awaited.remove(i)).is_some()
+    }
+
+    /// Private message `id` was shown, its receipt is due
+    pub fn shown(&mut self, id: MsgId) {
+        self.due.push(id);
+    }
+
+    /// Receipts to send
+    pub fn take_due(&mut self) -> Vec<MsgId> {
+        std::mem::take(&mut self.due)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn receipts_come_from_the_recipient() {
+        let mut receipts = Receipts::default();
+        receipts.sent(1, "bob".to_owned());
+        receipts.sent(2, "carol-identity".to_owned());
+        assert!(!receipts.received(1, "mallory", "mallory"));
+        assert!(receipts.received(1, "bob", "bob"));
+        assert!(!receipts.received(1, "bob", "bob"), "taken once");
+        assert!(receipts.received(2, "carol-laptop", "carol-identity"));
+
+        for id in 0..MAX_AWAITED as u64 + 1 {
+            receipts.sent(id, "bob".to_owned());
+        }
+        assert!(!receipts.received(0, "bob", "bob"), "forgotten");
+
+        receipts.shown(7);
+        assert_eq!(receipts.take_due(), [7]);
+        assert!(receipts.take_due().is_empty());
+    }
+}
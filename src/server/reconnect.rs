@@ -0,0 +1,411 @@
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use rand::{thread_rng, Rng};
+
+use super::backend::Transport;
+use super::timer::{RealTime, Timer};
+
+/// Delay before the first reconnection attempt, doubled after each failure
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum State {
+    Connected,
+    /// Waiting for the other end, without anything to retry on our side
+    Disconnected,
+    /// Failed `attempt` times, the next attempt is in `retry_in_ms`
+    Reconnecting {
+        attempt: u32,
+        retry_in_ms: u64,
+    },
+    /// Cut on purpose with `/partition`, until `/heal`
+    Partitioned,
+    /// The peer went away with its connection, which is forgotten
+    Left,
+}
+
+/// Connection state change of a transport, reported to the app
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Change {
+    pub name: String,
+    pub state: State,
+}
+
+struct Link {
+    output: Box<dyn Transport>,
+    peer: bool, // Came with a connection, dropped once broken
+    connected: bool,
+    partitioned: bool, // Nothing is written, nor reconnected, until healed
+    attempt: u32,
+    retry_at: Instant,
+}
+
+/// Owns the outgoing transports and reconnects them with exponential backoff
+/// and jitter once they break
+pub struct ReconnectManager {
+    links: Vec<Link>,
+    changes: Vec<Change>,
+    timer: Arc<dyn Timer>,
+}
+
+impl Default for ReconnectManager {
+    fn default() -> Self {
+        ReconnectManager::new(Arc::new(RealTime))
+    }
+}
+
+/// Equal jitter: half of the exponential delay is random so apps which lost
+/// a peer at the same time do not retry in lockstep
+fn backoff(attempt: u32) -> Duration {
+    let exponential = BASE_DELAY
+        .checked_mul(1 << attempt.saturating_sub(1).min(16))
+        .unwrap_or(MAX_DELAY)
+        .min(MAX_DELAY);
+    let half = exponential / 2;
+    half + half.mul_f64(thread_rng().gen::<f64>())
+}
+
+impl ReconnectManager {
+    pub fn new(timer: Arc<dyn Timer>) -> Self {
+        ReconnectManager {
+            links: Vec::new(),
+            changes: Vec::new(),
+            timer,
+        }
+    }
+
+    pub fn add(&mut self, output: Box<dyn Transport>) {
+        self.links.push(Link {
+            output,
+            peer: false,
+            connected: true,
+            partitioned: false,
+            attempt: 0,
+            retry_at: self.timer.now(),
+        });
+    }
+
+    /// Adds the way back to a peer which connected, replacing the previous
+    /// connection of the same name
+    pub fn add_peer(&mut self, output: Box<dyn Transport>) {
+        let name = output.name();
+        self.links.retain(|l| !(l.peer && l.output.name() == name));
+        self.changes.push(Change {
+            name,
+            state: State::Connected,
+        });
+        self.links.push(Link {
+            output,
+            peer: true,
+            connected: true,
+            partitioned: false,
+            attempt: 0,
+            retry_at: self.timer.now(),
+        });
+    }
+
+    /// Drops the way back to a peer which went away
+    pub fn remove_peer(&mut self, name: &str) {
+        let len = self.links.len();
+        self.links.retain(|l| !(l.peer && l.output.name() == name));
+        if self.links.len() != len {
+            self.changes.push(Change {
+                name: name.to_owned(),
+                state: State::Left,
+            });
+        }
+    }
+
+    /// State changes since the last call
+    pub fn take_changes(&mut self) -> Vec<Change> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Writes to every transport but the one to `origin`, the peer the frame
+    /// came from. Fails if none of them took it, or if there are none left: a
+    /// peer which went away is dropped, not waited for. A transport down or
+    /// partitioned meanwhile misses the frame, and gets it back from the sync
+    /// summaries once up, rather than holding up the others.
+    pub fn write_frame(&mut self, frame: &[u8], origin: Option<&str>) -> io::Result<()> {
+        self.write_to(frame, |link| {
+            !(link.peer && origin.is_some_and(|origin| origin == link.output.name()))
+        })
+    }
+
+    /// Writes to the transports named in `via` only. Fails if none of them
+    /// took the frame, or if none of them exists.
+    pub fn write_frame_via(&mut self, frame: &[u8], via: &[String]) -> io::Result<()> {
+        let named = |link: &Link| via.contains(&link.output.name());
+        if !self.links.iter().any(named) {
+            return Err(io::ErrorKind::NotFound.into());
+        }
+        self.write_to(frame, named)
+    }
+
+    fn write_to(&mut self, frame: &[u8], wanted: impl Fn(&Link) -> bool) -> io::Result<()> {
+        // Ok while no transport is wanted, as for a frame relayed back to
+        // its only peer
+        let mut result = Ok(());
+        let mut taken = false;
+        let mut broken_peers = Vec::new();
+        for link in &mut self.links {
+            if !wanted(link) {
+                continue;
+            }
+            if !link.connected || link.partitioned {
+                result = Err(io::ErrorKind::NotConnected.into());
+                continue;
+            }
+            match link.output.write_frame(frame) {
+                Ok(()) => taken = true,
+                Err(e) => {
+                    log::warn!("{} disconnected: {}", link.output.name(), e);
+                    if link.peer {
+                        broken_peers.push(link.output.name());
+                        continue;
+                    }
+                    link.connected = false;
+                    link.attempt = 0;
+                    link.retry_at = self.timer.now() + backoff(1);
+                    self.changes.push(Change {
+                        name: link.output.name(),
+                        state: State::Disconnected,
+                    });
+                    result = Err(e);
+                }
+            }
+        }
+        for name in broken_peers {
+            self.remove_peer(&name);
+        }
+        if self.links.is_empty() {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        match taken {
+            true => Ok(()),
+            false => result,
+        }
+    }
+
+    /// Attempts the reconnections which are due, returns whether any was made
+    pub fn tick(&mut self, now: Instant) -> bool {
+        let mut attempted = false;
+        for link in self
+            .links
+            .iter_mut()
+            .filter(|l| !l.connected && !l.partitioned)
+        {
+            if link.retry_at > now {
+                continue;
+            }
+            attempted = true;
+            let state = match link.output.reconnect() {
+                Ok(()) => {
+                    link.connected = true;
+                    link.attempt = 0;
+                    State::Connected
+                }
+                Err(e) => {
+                    link.attempt += 1;
+                    let delay = backoff(link.attempt + 1);
+                    link.retry_at = now + delay;
+                    log::debug!("reconnecting {} failed: {}", link.output.name(), e);
+                    State::Reconnecting {
+                        attempt: link.attempt,
+                        retry_in_ms: delay.as_millis() as u64,
+                    }
+                }
+            };
+            self.changes.push(Change {
+                name: link.output.name(),
+                state,
+            });
+        }
+        attempted
+    }
+
+    /// Stops writing to the transports matching `name`, or to all of them, until
+    /// they are healed. Returns false if no transport has this name.
+    pub fn partition(&mut self, name: Option<&str>) -> bool {
+        self.set_partitioned(name, true)
+    }
+
+    /// Writes again to the partitioned transports matching `name`
+    pub fn heal(&mut self, name: Option<&str>) -> bool {
+        self.set_partitioned(name, false)
+    }
+
+    fn set_partitioned(&mut self, name: Option<&str>, partitioned: bool) -> bool {
+        let now = self.timer.now();
+        let mut found = false;
+        for link in &mut self.links {
+            if name.is_some_and(|name| name != link.output.name()) {
+                continue;
+            }
+            found = true;
+            if link.partitioned == partitioned {
+                continue;
+            }
+            link.partitioned = partitioned;
+            link.retry_at = now;
+            let state = match (partitioned, link.connected) {
+                (true, _) => State::Partitioned,
+                (false, true) => State::Connected,
+                (false, false) => State::Disconnected,
+            };
+            self.changes.push(Change {
+                name: link.output.name(),
+                state,
+            });
+        }
+        found
+    }
+
+    /// Makes the disconnected transports matching `name`, or all of them, retry
+    /// on the next tick. Returns false if no transport has this name.
+    pub fn reconnect_now(&mut self, name: Option<&str>) -> bool {
+        let now = self.timer.now();
+        let mut found = false;
+        for link in &mut self.links {
+            if name.is_none_or(|name| name == link.output.name()) {
+                found = true;
+                if !link.connected {
+                    link.retry_at = now;
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::output::Output;
+    use super::*;
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        for attempt in 1..40 {
+            let delay = backoff(attempt);
+            let exponential = BASE_DELAY * 2u32.pow((attempt - 1).min(16));
+            assert!(delay >= exponential.min(MAX_DELAY) / 2);
+            assert!(delay <= exponential.min(MAX_DELAY));
+        }
+    }
+
+    #[test]
+    fn partitioned_links_write_nothing_until_healed() {
+        let path = std::env::temp_dir().join("netchat-test-partition");
+        std::fs::File::create(&path).unwrap();
+        let mut outputs = ReconnectManager::default();
+        outputs.add(Box::new(Output::open(path.clone()).unwrap()));
+
+        assert!(!outputs.partition(Some("elsewhere")));
+        assert!(outputs.partition(None));
+        assert!(outputs.write_frame(b"lost\n", None).is_err());
+        assert!(!outputs.tick(Instant::now()), "no reconnection while cut");
+        assert!(outputs.heal(Some("netchat-test-partition")));
+        outputs.write_frame(b"sent\n", None).unwrap();
+
+        let states: Vec<_> = outputs
+            .take_changes()
+            .into_iter()
+            .map(|c| c.state)
+            .collect();
+        assert_eq!(states, vec![State::Partitioned, State::Connected]);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "sent\n");
+    }
+
+    #[test]
+    fn one_partitioned_output_holds_up_none_of_the_others() {
+        let dir = std::env::temp_dir();
+        let (cut, kept) = (dir.join("netchat-test-cut"), dir.join("netchat-test-kept"));
+        let mut outputs = ReconnectManager::default();
+        for path in [&cut, &kept] {
+            std::fs::File::create(path).unwrap();
+            outputs.add(Box::new(Output::open(path.clone()).unwrap()));
+        }
+
+        assert!(outputs.partition(Some("netchat-test-cut")));
+        outputs.write_frame(b"first\n", None).unwrap();
+        outputs.write_frame(b"second\n", None).unwrap();
+        assert!(outputs.partition(None));
+        assert!(
+            outputs.write_frame(b"lost\n", None).is_err(),
+            "none took it"
+        );
+        let (cut_read, kept_read) = (
+            std::fs::read_to_string(&cut).unwrap(),
+            std::fs::read_to_string(&kept).unwrap(),
+        );
+        std::fs::remove_file(&cut).unwrap();
+        std::fs::remove_file(&kept).unwrap();
+        assert_eq!(cut_read, "");
+        assert_eq!(kept_read, "first\nsecond\n");
+    }
+
+    /// Peer keeping what it is written, or failing once gone
+    struct Peer(&'static str, Arc<std::sync::Mutex<Vec<String>>>, bool);
+
+    impl Transport for Peer {
+        fn name(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+            if self.2 {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            let line = String::from_utf8_lossy(frame).into_owned();
+            self.1.lock().unwrap().push(format!("{} {}", self.0, line));
+            Ok(())
+        }
+
+        fn reconnect(&mut self) -> io::Result<()> {
+            Err(io::ErrorKind::NotConnected.into())
+        }
+    }
+
+    #[test]
+    fn frames_are_not_sent_back_and_gone_peers_dropped() {
+        let written = Arc::default();
+        let mut outputs = ReconnectManager::default();
+        assert!(outputs.write_frame(b"alone\n", None).is_err());
+        outputs.add_peer(Box::new(Peer("alice", Arc::clone(&written), false)));
+        outputs.add_peer(Box::new(Peer("bob", Arc::clone(&written), false)));
+        outputs.add_peer(Box::new(Peer("carol", Arc::clone(&written), true)));
+
+        outputs.write_frame(b"hi\n", Some("alice")).unwrap();
+        assert_eq!(*written.lock().unwrap(), vec!["bob hi\n"]);
+        outputs.remove_peer("alice");
+        outputs.remove_peer("bob");
+        assert!(outputs.write_frame(b"lost\n", None).is_err(), "no one left");
+
+        let gone: Vec<_> = outputs
+            .take_changes()
+            .into_iter()
+            .filter(|c| c.state == State::Left)
+            .map(|c| c.name)
+            .collect();
+        assert_eq!(gone, vec!["carol", "alice", "bob"]);
+    }
+
+    #[test]
+    fn frames_go_via_the_transports_named() {
+        let written = Arc::default();
+        let mut outputs = ReconnectManager::default();
+        outputs.add_peer(Box::new(Peer("lan", Arc::clone(&written), false)));
+        outputs.add_peer(Box::new(Peer("metered", Arc::clone(&written), false)));
+
+        let via = ["lan".to_owned()];
+        outputs.write_frame_via(b"local\n", &via).unwrap();
+        assert_eq!(*written.lock().unwrap(), vec!["lan local\n"]);
+        let unknown = outputs.write_frame_via(b"lost\n", &["wan".to_owned()]);
+        assert_eq!(unknown.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
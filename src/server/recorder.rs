@@ -0,0 +1,120 @@
+//! Recording of a session with `--record`, played back by `netchat replay`
+//!
+//! Every chat message sent or handed over, peer arrival and departure,
+//! transport change and notice is appended as a json line, with the local clock.
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use super::messages::Msg;
+use super::notice::Notice;
+use super::reconnect;
+use super::Clock;
+use crate::app::AppId;
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Step {
+    /// A chat message typed here
+    Sent(Msg),
+    /// A chat message handed over to the app
+    Delivered(Msg),
+    Joined(AppId),
+    Left(AppId),
+    Transport(reconnect::Change),
+    Notice(Notice),
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct Entry {
+    /// Milliseconds since the recording started
+    pub at_ms: u64,
+    /// Local clock right after the step, unknown for outgoing transports
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<Clock>,
+    pub step: Step,
+}
+
+struct Recording {
+    writer: Mutex<BufWriter<File>>,
+    start: Instant,
+}
+
+/// Appends the entries of a session to a file, shared by the server stages.
+/// The default one records nothing.
+#[derive(Clone, Default)]
+pub struct Recorder(Option<Arc<Recording>>);
+
+impl Recorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let writer = Mutex::new(BufWriter::new(File::create(path)?));
+        Ok(Recorder(Some(Arc::new(Recording {
+            writer,
+            start: Instant::now(),
+        }))))
+    }
+
+    pub fn record(&self, clock: Option<&Clock>, step: Step) {
+        let file = match &self.0 {
+            Some(file) => file,
+            None => return,
+        };
+        let entry = Entry {
+            at_ms: file.start.elapsed().as_millis() as u64,
+            clock: clock.cloned(),
+            step,
+        };
+        let mut writer = file.writer.lock().expect("recorder lock poisoned");
+        // Flushed at once so a crash keeps the whole session
+        let written = serde_json::to_writer(&mut *writer, &entry)
+            .map_err(io::Error::from)
+            .and_then(|_| writeln!(writer))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            log::error!("could not record the session: {}", e);
+        }
+    }
+}
+
+/// Entries of a recorded session, in order
+pub fn load(path: &Path) -> Result<Vec<Entry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for (n, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let entry = serde_json::from_str(line).map_err(|e| format!("line {}: {}", n + 1, e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recordings_load_back_in_order() {
+        let path = std::env::temp_dir().join(format!("netchat-test-{}.rec", std::process::id()));
+        let recorder = Recorder::create(&path).unwrap();
+        let clock = Clock::new("alice".to_owned());
+        recorder.record(Some(&clock), Step::Joined("bob".to_owned()));
+        recorder.record(None, Step::Left("bob".to_owned()));
+        Recorder::default().record(None, Step::Joined("carol".to_owned()));
+
+        let entries = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let steps: Vec<_> = entries.iter().map(|entry| &entry.step).collect();
+        assert_eq!(
+            steps,
+            [
+                &Step::Joined("bob".to_owned()),
+                &Step::Left("bob".to_owned())
+            ]
+        );
+        assert_eq!(entries[0].clock, Some(clock));
+        assert!(entries[0].at_ms <= entries[1].at_ms);
+    }
+}
@@ -0,0 +1,205 @@
+//! TCP transport: `--listen` waits for peers, `--connect` dials one and dials
+//! it again once the connection breaks
+//!
+//! Each connection is a peer of its own, read by the input and written by the
+//! output it comes with, which is dropped once the connection breaks.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use super::backend::{Input, Peer, Transport};
+
+/// Delay between two attempts to reach a peer with `--connect`
+const DIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// How long a peer has to take a frame before it is dropped, so one which
+/// stopped reading does not hold up the others
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Side {
+    Listen(TcpListener),
+    Connect(String),
+}
+
+pub struct Socket {
+    name: String,
+    side: Side,
+}
+
+impl Socket {
+    /// Binds `addr` now so a taken port is reported on start
+    pub fn listen(addr: &str) -> io::Result<Socket> {
+        let listener = TcpListener::bind(addr)?;
+        Ok(Socket {
+            name: listener.local_addr()?.to_string(),
+            side: Side::Listen(listener),
+        })
+    }
+
+    /// The peer at `addr` is dialed once the server runs, until it answers
+    pub fn connect(addr: &str) -> Socket {
+        Socket {
+            name: addr.to_owned(),
+            side: Side::Connect(addr.to_owned()),
+        }
+    }
+
+    pub fn input(&self) -> io::Result<TcpInput> {
+        let side = match &self.side {
+            Side::Listen(listener) => Side::Listen(listener.try_clone()?),
+            Side::Connect(addr) => Side::Connect(addr.clone()),
+        };
+        Ok(TcpInput {
+            name: self.name.clone(),
+            side,
+        })
+    }
+}
+
+impl fmt::Display for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.side {
+            Side::Listen(_) => write!(f, "listening on {}", self.name),
+            Side::Connect(_) => write!(f, "connecting to {}", self.name),
+        }
+    }
+}
+
+pub struct TcpInput {
+    name: String,
+    side: Side,
+}
+
+impl Input for TcpInput {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn open(&mut self) -> io::Result<Peer> {
+        let (name, stream) = match &self.side {
+            Side::Listen(listener) => {
+                let (stream, addr) = listener.accept()?;
+                (addr.to_string(), stream)
+            }
+            Side::Connect(addr) => loop {
+                match TcpStream::connect(addr) {
+                    Ok(stream) => break (addr.clone(), stream),
+                    Err(e) => {
+                        log::debug!("could not reach {}: {}", addr, e);
+                        thread::sleep(DIAL_DELAY);
+                    }
+                }
+            },
+        };
+        let writer = TcpOutput::new(name.clone(), &stream)?;
+        Ok(Peer {
+            name,
+            reader: Box::new(stream),
+            writer: Some(Box::new(writer)),
+        })
+    }
+
+    /// Peers keep connecting to a listening socket while others chat
+    fn concurrent(&self) -> bool {
+        matches!(self.side, Side::Listen(_))
+    }
+}
+
+/// Writing end of a connection
+pub struct TcpOutput {
+    name: String,
+    stream: Option<TcpStream>,
+}
+
+impl TcpOutput {
+    /// Writes to `stream`, giving up on a frame not taken in [`WRITE_TIMEOUT`]
+    fn new(name: String, stream: &TcpStream) -> io::Result<TcpOutput> {
+        let stream = stream.try_clone()?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        Ok(TcpOutput {
+            name,
+            stream: Some(stream),
+        })
+    }
+}
+
+impl Transport for TcpOutput {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let mut stream = self
+            .stream
+            .take()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        stream.write_all(frame)?;
+        // On error, the connection is dropped, the input opens the next one
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// A broken connection is not reopened here, the input brings the next one
+    fn reconnect(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn peers_chat_both_ways() {
+        let listening = Socket::listen("127.0.0.1:0").unwrap();
+        let connecting = Socket::connect(&listening.name);
+        let mut listener_input = listening.input().unwrap();
+        assert!(listener_input.concurrent());
+        let accepted = thread::spawn(move || listener_input.open().unwrap());
+
+        let dialed = connecting.input().unwrap().open().unwrap();
+        assert_eq!(dialed.name, listening.name);
+        dialed.writer.unwrap().write_frame(b"hi\n").unwrap();
+
+        let accepted = accepted.join().unwrap();
+        accepted.writer.unwrap().write_frame(b"hello\n").unwrap();
+
+        let mut line = String::new();
+        BufReader::new(accepted.reader)
+            .read_line(&mut line)
+            .unwrap();
+        assert_eq!(line, "hi\n");
+        line.clear();
+        BufReader::new(dialed.reader).read_line(&mut line).unwrap();
+        assert_eq!(line, "hello\n");
+    }
+
+    #[test]
+    fn peers_which_stop_reading_are_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let _stalled = listener.accept().unwrap();
+        let mut output = TcpOutput::new("stalled".to_owned(), &stream).unwrap();
+        let written = output.stream.as_ref().unwrap();
+        let timeout = written.write_timeout().unwrap();
+        // Not to wait that long here
+        let short = Duration::from_millis(50);
+        written.set_write_timeout(Some(short)).unwrap();
+
+        let frame = vec![b'x'; 64 * 1024];
+        let e = loop {
+            if let Err(e) = output.write_frame(&frame) {
+                break e;
+            }
+        };
+        let next = output.write_frame(b"hi\n").unwrap_err();
+        assert_eq!(timeout, Some(WRITE_TIMEOUT));
+        let kinds = [io::ErrorKind::WouldBlock, io::ErrorKind::TimedOut];
+        assert!(kinds.contains(&e.kind()), "{:?}", e);
+        assert_eq!(next.kind(), io::ErrorKind::NotConnected, "dropped");
+    }
+}
@@ -0,0 +1,110 @@
+#[cfg(test)]
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the server gets the time from, for heartbeats, reconnections and timeouts
+pub trait Timer: Send + Sync {
+    fn now(&self) -> Instant;
+
+    /// Blocks the calling thread until `duration` has passed
+    fn sleep(&self, duration: Duration);
+}
+
+/// The system clock
+pub struct RealTime;
+
+impl Timer for RealTime {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// Simulated time, which only moves when advanced, so tests can fast-forward
+#[cfg(test)]
+#[derive(Clone)]
+pub struct VirtualTime(Arc<(Mutex<Simulated>, Condvar)>);
+
+#[cfg(test)]
+struct Simulated {
+    now: Instant,
+    sleepers: usize, // Threads waiting for time to move
+}
+
+#[cfg(test)]
+impl Default for VirtualTime {
+    fn default() -> Self {
+        let simulated = Simulated {
+            now: Instant::now(),
+            sleepers: 0,
+        };
+        VirtualTime(Arc::new((Mutex::new(simulated), Condvar::new())))
+    }
+}
+
+#[cfg(test)]
+impl VirtualTime {
+    /// Moves time forward, waking up the sleepers whose time has come
+    pub fn advance(&self, by: Duration) {
+        let (simulated, changed) = &*self.0;
+        simulated.lock().unwrap().now += by;
+        changed.notify_all();
+    }
+
+    /// Blocks until `count` threads are sleeping, to advance time once they are
+    pub fn wait_for_sleepers(&self, count: usize) {
+        let (simulated, changed) = &*self.0;
+        let mut simulated = simulated.lock().unwrap();
+        while simulated.sleepers < count {
+            simulated = changed.wait(simulated).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+impl Timer for VirtualTime {
+    fn now(&self) -> Instant {
+        self.0 .0.lock().unwrap().now
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let (simulated, changed) = &*self.0;
+        let mut simulated = simulated.lock().unwrap();
+        let deadline = simulated.now + duration;
+        simulated.sleepers += 1;
+        changed.notify_all();
+        while simulated.now < deadline {
+            simulated = changed.wait(simulated).unwrap();
+        }
+        simulated.sleepers -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn sleepers_wake_when_time_is_advanced() {
+        let time = VirtualTime::default();
+        let start = time.now();
+        let (tx, rx) = mpsc::channel();
+        let sleeper = time.clone();
+        thread::spawn(move || {
+            sleeper.sleep(Duration::from_secs(30));
+            tx.send(()).unwrap();
+        });
+
+        time.wait_for_sleepers(1);
+        time.advance(Duration::from_secs(29));
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        time.advance(Duration::from_secs(1));
+        assert!(rx.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(time.now() - start, Duration::from_secs(30));
+    }
+}
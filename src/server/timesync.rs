@@ -0,0 +1,112 @@
+//! Coarse time-sync hints: each app stamps its `Hello` with its wall clock,
+//! and the others take the difference with their own as the offset of its
+//! clock. An app whose offset is far from the median of those known is
+//! skewed, the times of its messages are shifted by the difference where they
+//! are shown. The history keeps them as they were sent.
+
+use std::collections::HashMap;
+
+use super::messages::Msg;
+use crate::app::AppId;
+
+/// Offsets closer than this to the median are the time the hello took to
+/// come, or noise
+pub const SKEW_HINT: i64 = 60;
+
+#[derive(Default)]
+pub struct TimeSync {
+    offsets: HashMap<AppId, i64>, // Seconds the clock of each app is ahead of ours
+}
+
+impl TimeSync {
+    /// `app_id` said hello at `sent_at` by its clock, `now` by ours, both in
+    /// seconds since the Unix epoch
+    pub fn hello(&mut self, app_id: &str, sent_at: u64, now: u64) {
+        let offset = sent_at as i64 - now as i64;
+        self.offsets.insert(app_id.to_owned(), offset);
+    }
+
+    /// Median of the offsets, ours of 0 included. Between the two middle
+    /// ones, the closest to our clock.
+    fn median(&self) -> i64 {
+        let mut offsets: Vec<i64> = self.offsets.values().copied().collect();
+        offsets.push(0);
+        offsets.sort_unstable();
+        let middle = offsets.len() / 2;
+        match offsets.len() % 2 {
+            0 if offsets[middle - 1].abs() < offsets[middle].abs() => offsets[middle - 1],
+            _ => offsets[middle],
+        }
+    }
+
+    /// Seconds the clock of `app_id` is ahead of the others, behind if
+    /// negative, 0 if not by more than [`SKEW_HINT`]
+    pub fn skew(&self, app_id: &str) -> i64 {
+        let skew = match self.offsets.get(app_id) {
+            Some(offset) => offset - self.median(),
+            None => 0,
+        };
+        if skew.abs() > SKEW_HINT {
+            skew
+        } else {
+            0
+        }
+    }
+
+    /// When `msg` was sent by the clock of the others
+    pub fn sent_at(&self, msg: &Msg) -> Option<u64> {
+        let skew = self.skew(&msg.sender_id);
+        msg.sent_at
+            .map(|sent_at| (sent_at as i64 - skew).max(0) as u64)
+    }
+}
+
+/// `2 hours ahead`, `5 minutes behind`
+pub fn describe(skew: i64) -> String {
+    let secs = skew.unsigned_abs();
+    let amount = match secs {
+        7200.. => format!("{} hours", secs / 3600),
+        3600.. => "an hour".to_owned(),
+        120.. => format!("{} minutes", secs / 60),
+        _ => format!("{} seconds", secs),
+    };
+    let way = if skew > 0 { "ahead" } else { "behind" };
+    format!("{} {}", amount, way)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::messages::Channel;
+    use crate::server::messages::Header::Public;
+    use crate::server::Clock;
+
+    #[test]
+    fn skewed_clocks_are_told_by_the_median() {
+        let now = 1_792_000_000;
+        let mut sync = TimeSync::default();
+        sync.hello("bob", now + 7300, now);
+        assert_eq!(sync.skew("bob"), 7300, "ties go to our clock");
+
+        sync.hello("carol", now + 7290, now);
+        sync.hello("dave", now + 7280, now);
+        assert_eq!(sync.skew("bob"), 0, "most clocks agree with bob");
+        assert_eq!(sync.skew("dave"), 0, "within the hint");
+        sync.hello("erin", now - 20, now);
+        sync.hello("frank", now + 5, now);
+        assert_eq!(sync.skew("bob"), 7295);
+        assert_eq!(sync.skew("erin"), 0);
+        assert_eq!(sync.skew("nobody"), 0);
+
+        let mut msg = Msg::new(
+            1,
+            "bob".to_owned(),
+            Public(Channel::default(), "hi".to_owned()),
+            Clock::new("bob".to_owned()),
+        );
+        msg.sent_at = Some(now + 7300);
+        assert_eq!(sync.sent_at(&msg), Some(now + 5));
+        assert_eq!(describe(7295), "2 hours ahead");
+        assert_eq!(describe(-90), "90 seconds behind");
+    }
+}
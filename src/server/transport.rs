@@ -0,0 +1,385 @@
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use super::backend::Transport as Output;
+use super::jobs::Jobs;
+use super::messages::{self, Codec, Header, Msg};
+use super::metrics::{Metric, Metrics};
+use super::notice::Notice;
+use super::outbox::Outbox;
+use super::reconnect::ReconnectManager;
+use super::recorder::Recorder;
+use super::timer::Timer;
+use super::{report_connection, report_notice, send_to_app, AppEvent, AppSender};
+
+/// How often reconnections and queued messages are retried
+const TICK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Work for the transport stage
+pub enum Command {
+    /// A message, and the peer it came from when relayed
+    Send(Box<Msg>, Option<Arc<str>>),
+    AddPeer(Box<dyn Output>),
+    RemovePeer(String),
+    Reconnect(Option<String>),
+    Partition(Option<String>),
+    Heal(Option<String>),
+    RetryOutbox(Option<u32>),
+    CancelOutbox(u32),
+    /// How messages are encoded from now on, queued ones stay json
+    Codec(Codec),
+    /// Frames longer than this many bytes are compressed from now on, none
+    /// for None
+    Compression(Option<usize>),
+}
+
+/// Sending end of the transport stage
+pub struct Handle {
+    tx: mpsc::Sender<Command>,
+    silent: bool, // Only relays, see `silence`
+}
+
+impl Handle {
+    pub fn send(&self, msg: &Msg) {
+        if self.silent {
+            log::debug!("observing, not sent: {:?}", msg.header);
+            return;
+        }
+        self.command(Command::Send(Box::new(msg.clone()), None));
+    }
+
+    /// Drops the messages given to `send` from now on, relayed ones still go through
+    pub fn silence(&mut self) {
+        self.silent = true;
+    }
+
+    /// Sends a received message on, except back to the peer it came from
+    pub fn relay(&self, msg: &Msg, origin: Arc<str>) {
+        self.command(Command::Send(Box::new(msg.clone()), Some(origin)));
+    }
+
+    pub fn command(&self, command: Command) {
+        self.tx
+            .send(command)
+            .expect("Could not send message to the transport");
+    }
+}
+
+/// Last stage of the server: encodes messages and writes them to the outputs,
+/// queueing what cannot be written, on its own thread
+pub struct Transport {
+    outputs: ReconnectManager,
+    outbox: Outbox,
+    max_frame_len: usize,
+    app_tx: AppSender,
+    frame: Vec<u8>, // Reused for every message written
+    codec: Codec,
+    compress_above: Option<usize>,
+    packed: Vec<u8>, // The frame compressed
+    timer: Arc<dyn Timer>,
+    recorder: Recorder,
+    jobs: Jobs,
+    metrics: Arc<Metrics>,
+}
+
+impl Transport {
+    pub fn new(
+        outputs: ReconnectManager,
+        max_frame_len: usize,
+        app_tx: AppSender,
+        timer: Arc<dyn Timer>,
+        recorder: Recorder,
+        jobs: Jobs,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Transport {
+            outputs,
+            outbox: Outbox::default(),
+            max_frame_len,
+            app_tx,
+            frame: Vec::new(),
+            codec: Codec::default(),
+            compress_above: None,
+            packed: Vec::new(),
+            timer,
+            recorder,
+            jobs,
+            metrics,
+        }
+    }
+
+    /// Runs the stage until the handle is dropped and every command is handled
+    pub fn spawn(mut self) -> (Handle, thread::JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel();
+        let join_handle = thread::spawn(move || self.run(rx));
+        (Handle { tx, silent: false }, join_handle)
+    }
+
+    fn run(&mut self, commands: mpsc::Receiver<Command>) {
+        let mut pending = None;
+        loop {
+            let command = match pending.take() {
+                Some(command) => Ok(command),
+                None => commands.recv_timeout(TICK_INTERVAL),
+            };
+            match command {
+                Ok(Command::Send(mut msg, mut origin)) => {
+                    // Heartbeats piling up behind a burst are forwarded as one
+                    if let Header::Heartbeat(_) = msg.header {
+                        while let Ok(next) = commands.try_recv() {
+                            pending = match next {
+                                Command::Send(next, next_origin) => {
+                                    match msg.merge_heartbeat(*next) {
+                                        // Relayed from several peers, it goes to all of them
+                                        None if origin != next_origin => {
+                                            origin = None;
+                                            None
+                                        }
+                                        None => None,
+                                        Some(m) => Some(Command::Send(Box::new(m), next_origin)),
+                                    }
+                                }
+                                other => Some(other),
+                            };
+                            if pending.is_some() {
+                                break;
+                            }
+                        }
+                    }
+                    self.send(&msg, origin.as_deref());
+                }
+                Ok(Command::AddPeer(output)) => {
+                    self.outputs.add_peer(output);
+                    self.flush_outbox();
+                }
+                Ok(Command::RemovePeer(name)) => {
+                    self.outputs.remove_peer(&name);
+                    self.notify_connection_changes();
+                }
+                Ok(Command::Reconnect(name)) => {
+                    if !self.outputs.reconnect_now(name.as_deref()) {
+                        let name = name.unwrap_or_default();
+                        let notice = Notice::warning(
+                            "unknown-transport",
+                            format!("No transport named {}", name),
+                        );
+                        self.notify(notice.with("transport", name));
+                    }
+                }
+                Ok(Command::Partition(name)) => {
+                    self.outputs.partition(name.as_deref());
+                    self.notify_connection_changes();
+                }
+                Ok(Command::Heal(name)) => {
+                    self.outputs.heal(name.as_deref());
+                    self.flush_outbox();
+                }
+                Ok(Command::RetryOutbox(seq)) => {
+                    if self.outbox.reset(seq) {
+                        self.flush_outbox();
+                        self.show_outbox();
+                    }
+                }
+                Ok(Command::CancelOutbox(seq)) => {
+                    if self.outbox.cancel(seq) {
+                        self.show_outbox();
+                    }
+                }
+                Ok(Command::Codec(codec)) => self.codec = codec,
+                Ok(Command::Compression(above)) => self.compress_above = above,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    // The goodbye is queued behind the outbox, if anything is
+                    if !self.outbox.is_empty() {
+                        self.flush_outbox();
+                    }
+                    if !self.outbox.is_empty() {
+                        log::warn!(
+                            "Leaving with {} queued messages never sent",
+                            self.outbox.pending()
+                        );
+                    }
+                    break;
+                }
+            }
+            if self.outputs.tick(self.timer.now()) {
+                self.flush_outbox();
+            }
+        }
+    }
+
+    fn send(&mut self, msg: &Msg, origin: Option<&str>) {
+        if self.codec.encode_into(msg, &mut self.frame).is_ok() {
+            if self
+                .compress_above
+                .is_some_and(|above| self.frame.len() > above + 1)
+                && messages::compress_frame(&self.frame, &mut self.packed)
+            {
+                std::mem::swap(&mut self.frame, &mut self.packed);
+            }
+            let len = self.frame.len() - 1; // Without the newline
+            if len > self.max_frame_len {
+                let notice = Notice::error(
+                    "oversized-message",
+                    format!(
+                        "Not sent: {} is {} bytes long, over the {} bytes limit",
+                        msg.header.summary(),
+                        len,
+                        self.max_frame_len
+                    ),
+                );
+                let notice = notice
+                    .with("id", msg.id)
+                    .with("bytes", len)
+                    .with("limit", self.max_frame_len);
+                self.notify(notice);
+                return;
+            }
+            if let Some(via) = &msg.via {
+                return self.send_via(msg, via);
+            }
+            // A late heartbeat or typing tells nothing, they are never queued
+            let queue = !matches!(
+                msg.header,
+                Header::Heartbeat(_) | Header::Typing | Header::LiveLine(_)
+            );
+            // Keep the sending order while older messages are waiting
+            if !self.outbox.is_empty() {
+                if queue {
+                    self.queue(msg);
+                }
+            } else if self.outputs.write_frame(&self.frame, origin).is_ok() {
+                log::info!("sent messsage: {:?}", msg.header);
+            } else {
+                log::error!("Failed to write to output file");
+                if queue {
+                    self.notify(Notice::warning(
+                        "queued",
+                        "No one can hear you, messages are queued".to_owned(),
+                    ));
+                    self.queue(msg);
+                }
+                self.notify_connection_changes();
+            }
+        } else {
+            log::error!("Could not serialize `{:?}`", msg);
+        }
+    }
+
+    /// Writes the frame of `msg` to the transports of `via` only. It is not
+    /// queued, the outbox is flushed to every transport.
+    fn send_via(&mut self, msg: &Msg, via: &[String]) {
+        match self.outputs.write_frame_via(&self.frame, via) {
+            Ok(()) => log::info!("sent messsage via {}: {:?}", via.join(","), msg.header),
+            Err(e) => {
+                let reason = match e.kind() {
+                    io::ErrorKind::NotFound => "no transport of that name",
+                    _ => "a transport is down",
+                };
+                let notice = Notice::warning(
+                    "not-sent-via",
+                    format!(
+                        "Not sent via {}, {}: {}",
+                        via.join(", "),
+                        reason,
+                        msg.header.summary()
+                    ),
+                );
+                self.notify(notice.with("id", msg.id).with("via", via.join(",")));
+                self.notify_connection_changes();
+            }
+        }
+    }
+
+    /// Keeps a message for later, off the hot path so it gets its own line
+    fn queue(&mut self, msg: &Msg) {
+        match msg.serialize() {
+            Ok(line) => self.outbox.push(msg, line),
+            Err(e) => log::error!("Could not serialize `{:?}`: {}", msg, e),
+        }
+        self.show_outbox();
+    }
+
+    /// Lets the app and the metrics know what the outbox holds now
+    fn show_outbox(&self) {
+        let pending = self.outbox.pending() as u64;
+        self.metrics.set(Metric::Outbox, pending);
+        send_to_app(AppEvent::Outbox(self.outbox.items()), &self.app_tx);
+    }
+
+    fn flush_outbox(&mut self) {
+        // Writing a long outbox to a slow reader takes a while
+        let total = self.outbox.pending() as u64;
+        let token = (total > 1).then(|| self.jobs.start("outbox"));
+        let cancelled = || token.as_ref().is_some_and(|token| token.is_cancelled());
+        let (outputs, frame, app_tx) = (&mut self.outputs, &mut self.frame, &self.app_tx);
+        let progress = |done| {
+            if total > 1 {
+                send_to_app(
+                    AppEvent::Progress {
+                        op_id: "outbox".to_owned(),
+                        label: "Sending queued messages".to_owned(),
+                        done,
+                        total,
+                    },
+                    app_tx,
+                );
+            }
+        };
+        let mut done = 0;
+        let mut written = self.outbox.flush(|line| {
+            if cancelled() {
+                return false;
+            }
+            frame.clear();
+            frame.extend_from_slice(line.as_bytes());
+            frame.push(b'\n');
+            let written = outputs.write_frame(frame, None).is_ok();
+            if written {
+                done += 1;
+                progress(done);
+            }
+            written
+        });
+        // What was not written stays in the outbox panel, unless cancelled
+        if done < total {
+            progress(total);
+        }
+        let cancelled = cancelled();
+        if cancelled {
+            let dropped = self.outbox.drop_pending();
+            let notice = Notice::warning(
+                "outbox-cancelled",
+                format!("Cancelled, {} queued messages were dropped", dropped),
+            );
+            self.notify(notice.with("dropped", dropped));
+            written = true;
+        }
+        if token.is_some() {
+            self.jobs.finish("outbox");
+        }
+        if written {
+            if self.outbox.is_empty() && !cancelled {
+                self.notify(Notice::info(
+                    "outbox-flushed",
+                    "Queued messages were sent".to_owned(),
+                ));
+            }
+            self.show_outbox();
+        }
+        self.notify_connection_changes();
+    }
+
+    fn notify(&self, notice: Notice) {
+        report_notice(notice, None, &self.recorder, &self.app_tx);
+    }
+
+    fn notify_connection_changes(&mut self) {
+        for change in self.outputs.take_changes() {
+            report_connection(change, None, &self.recorder, &self.app_tx);
+        }
+    }
+}
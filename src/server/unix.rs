@@ -0,0 +1,233 @@
+//! Unix domain socket transport: `--socket` listens on a path for the apps of
+//! the same host, or connects to the app already listening there
+//!
+//! Unlike a named pipe, opening it never blocks, and each app connecting is a
+//! peer of its own, read by the input and written by the output it comes
+//! with. The socket file is kept to the user by the umask.
+
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use super::backend::{Input, Peer, Transport};
+
+/// Delay between two attempts to reach the app listening, once it went away
+const DIAL_DELAY: Duration = Duration::from_secs(1);
+
+/// How long an app has to take a frame before it is dropped, as over TCP
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum Side {
+    Listen(Arc<UnixListener>),
+    /// With the connection made finding out someone listens, used first
+    Connect(Arc<Mutex<Option<UnixStream>>>),
+}
+
+pub struct Socket {
+    path: PathBuf,
+    side: Side,
+}
+
+impl Socket {
+    /// Connects to the app listening at `path`, or listens there if none
+    /// does. A socket file left by an app gone is replaced.
+    pub fn open(path: &Path) -> io::Result<Socket> {
+        let side = match UnixStream::connect(path) {
+            Ok(stream) => Side::Connect(Arc::new(Mutex::new(Some(stream)))),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                // Only a socket is removed, never a file given by mistake
+                if fs::symlink_metadata(path)?.file_type().is_socket() {
+                    fs::remove_file(path)?;
+                }
+                Side::Listen(Arc::new(UnixListener::bind(path)?))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Side::Listen(Arc::new(UnixListener::bind(path)?))
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(Socket {
+            path: path.to_owned(),
+            side,
+        })
+    }
+
+    pub fn input(&self) -> UnixInput {
+        let side = match &self.side {
+            Side::Listen(listener) => Side::Listen(listener.clone()),
+            Side::Connect(first) => Side::Connect(first.clone()),
+        };
+        UnixInput {
+            path: self.path.clone(),
+            side,
+            accepted: 0,
+        }
+    }
+}
+
+impl fmt::Display for Socket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.side {
+            Side::Listen(_) => write!(f, "listening on {:?}", self.path),
+            Side::Connect(_) => write!(f, "connected to {:?}", self.path),
+        }
+    }
+}
+
+pub struct UnixInput {
+    path: PathBuf,
+    side: Side,
+    accepted: usize, // Connections get no address, they are numbered
+}
+
+impl Input for UnixInput {
+    fn name(&self) -> String {
+        self.path.display().to_string()
+    }
+
+    fn open(&mut self) -> io::Result<Peer> {
+        let (name, stream) = match &self.side {
+            Side::Listen(listener) => {
+                let (stream, _) = listener.accept()?;
+                self.accepted += 1;
+                (format!("{}#{}", self.name(), self.accepted), stream)
+            }
+            Side::Connect(first) => {
+                let first = first.lock().map_or(None, |mut first| first.take());
+                let stream = match first {
+                    Some(stream) => stream,
+                    None => loop {
+                        match UnixStream::connect(&self.path) {
+                            Ok(stream) => break stream,
+                            Err(e) => {
+                                log::debug!("could not reach {:?}: {}", self.path, e);
+                                thread::sleep(DIAL_DELAY);
+                            }
+                        }
+                    },
+                };
+                (self.name(), stream)
+            }
+        };
+        let writer = UnixOutput::new(name.clone(), &stream)?;
+        Ok(Peer {
+            name,
+            reader: Box::new(stream),
+            writer: Some(Box::new(writer)),
+        })
+    }
+
+    /// Apps keep connecting to a listening socket while others chat
+    fn concurrent(&self) -> bool {
+        matches!(self.side, Side::Listen(_))
+    }
+}
+
+/// Writing end of a connection
+pub struct UnixOutput {
+    name: String,
+    stream: Option<UnixStream>,
+}
+
+impl UnixOutput {
+    /// Writes to `stream`, giving up on a frame not taken in [`WRITE_TIMEOUT`]
+    fn new(name: String, stream: &UnixStream) -> io::Result<UnixOutput> {
+        let stream = stream.try_clone()?;
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        Ok(UnixOutput {
+            name,
+            stream: Some(stream),
+        })
+    }
+}
+
+impl Transport for UnixOutput {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        let mut stream = self
+            .stream
+            .take()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))?;
+        stream.write_all(frame)?;
+        // On error, the connection is dropped, the input opens the next one
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    /// A broken connection is not reopened here, the input brings the next one
+    fn reconnect(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::NotConnected.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn apps_share_a_socket() {
+        let path = std::env::temp_dir().join(format!("netchat-test-{}.sock", std::process::id()));
+        fs::write(&path, "").unwrap();
+        assert!(Socket::open(&path).is_err(), "not a socket, kept");
+        fs::remove_file(&path).unwrap();
+
+        let listening = Socket::open(&path).unwrap();
+        let mut listener_input = listening.input();
+        assert!(listener_input.concurrent());
+        let accepted = thread::spawn(move || {
+            let first = listener_input.open().unwrap();
+            let second = listener_input.open().unwrap();
+            (first, second)
+        });
+        let carol = Socket::open(&path).unwrap().input().open().unwrap();
+        let bob = Socket::open(&path).unwrap().input().open().unwrap();
+        let (first, second) = accepted.join().unwrap();
+        assert_ne!(first.name, second.name);
+
+        bob.writer.unwrap().write_frame(b"hi\n").unwrap();
+        carol.writer.unwrap().write_frame(b"hello\n").unwrap();
+        let mut line = String::new();
+        BufReader::new(first.reader).read_line(&mut line).unwrap();
+        BufReader::new(second.reader).read_line(&mut line).unwrap();
+        assert!(line == "hello\nhi\n" || line == "hi\nhello\n", "{:?}", line);
+
+        // The listening app went away, the next one takes its place
+        drop(listening);
+        let replacing = Socket::open(&path).unwrap();
+        assert!(replacing.to_string().starts_with("listening"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn apps_which_stop_reading_are_dropped() {
+        let (stream, _stalled) = UnixStream::pair().unwrap();
+        let mut output = UnixOutput::new("stalled".to_owned(), &stream).unwrap();
+        let written = output.stream.as_ref().unwrap();
+        let timeout = written.write_timeout().unwrap();
+        // Not to wait that long here
+        let short = Duration::from_millis(50);
+        written.set_write_timeout(Some(short)).unwrap();
+
+        let frame = vec![b'x'; 64 * 1024];
+        let e = loop {
+            if let Err(e) = output.write_frame(&frame) {
+                break e;
+            }
+        };
+        let next = output.write_frame(b"hi\n").unwrap_err();
+        assert_eq!(timeout, Some(WRITE_TIMEOUT));
+        let kinds = [io::ErrorKind::WouldBlock, io::ErrorKind::TimedOut];
+        assert!(kinds.contains(&e.kind()), "{:?}", e);
+        assert_eq!(next.kind(), io::ErrorKind::NotConnected, "dropped");
+    }
+}
@@ -0,0 +1,135 @@
+//! Simulated apps which misbehave, to see what the others resist
+//!
+//! The misbehaving app runs like the others, what it writes is then tampered
+//! with on its way to the links.
+
+use std::fmt;
+use std::str::FromStr;
+
+use netchat_core::messages;
+use netchat_core::AppId;
+
+/// Added to every date of a lying clock
+const LIE: u64 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Behaviour {
+    /// Claims to have seen far more than it did
+    LieClock,
+    /// Writes everything twice
+    Duplicate,
+    /// Only writes its own messages
+    DropRelays,
+    /// Sends its messages in the name of another app
+    Forge(AppId),
+}
+
+impl FromStr for Behaviour {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, String> {
+        match name {
+            "lie-clock" => Ok(Behaviour::LieClock),
+            "duplicate" => Ok(Behaviour::Duplicate),
+            "drop-relays" => Ok(Behaviour::DropRelays),
+            _ if name.starts_with("forge:") => Ok(Behaviour::Forge(name[6..].to_owned())),
+            _ => Err(format!(
+                "unknown behaviour {}, expected lie-clock, duplicate, drop-relays or forge:<app>",
+                name
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Behaviour {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Behaviour::LieClock => write!(f, "lie-clock"),
+            Behaviour::Duplicate => write!(f, "duplicate"),
+            Behaviour::DropRelays => write!(f, "drop-relays"),
+            Behaviour::Forge(app_id) => write!(f, "forge:{}", app_id),
+        }
+    }
+}
+
+/// What `app_id` writes instead of `frame`
+pub fn tamper(app_id: &str, behaviours: &[Behaviour], frame: Vec<u8>) -> Vec<Vec<u8>> {
+    if behaviours.is_empty() {
+        return vec![frame];
+    }
+    let mut msg = match messages::parse(&frame) {
+        Ok(msg) => msg,
+        Err(_) => return vec![frame],
+    };
+    let own = msg.sender_id == app_id;
+    let mut copies = 1;
+    for behaviour in behaviours {
+        match behaviour {
+            Behaviour::DropRelays if !own => return Vec::new(),
+            Behaviour::Forge(victim) if own => msg.sender_id = victim.clone(),
+            Behaviour::LieClock => msg.clock.values_mut().for_each(|date| *date += LIE),
+            Behaviour::Duplicate => copies = 2,
+            _ => {}
+        }
+    }
+    let mut frame = Vec::new();
+    if msg.encode_into(&mut frame).is_err() {
+        return Vec::new();
+    }
+    vec![frame; copies]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulate::{run, Scenario};
+
+    fn honest() -> Scenario {
+        let json = r#"{
+            "nodes": ["alice", "mallory", "bob"],
+            "links": [["alice", "mallory"], ["mallory", "bob"], ["bob", "alice"]],
+            "messages": [
+                {"at": 0, "from": "alice", "text": "hi"},
+                {"at": 3, "from": "mallory", "text": "send me your password"}
+            ]
+        }"#;
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn scenario(misbehave: &str) -> Scenario {
+        let mut scenario = honest();
+        scenario.misbehave(misbehave).unwrap();
+        scenario
+    }
+
+    fn violated(scenario: &Scenario) -> Vec<&'static str> {
+        let outcome = run(scenario);
+        let mut names: Vec<_> = scenario
+            .invariants()
+            .iter()
+            .flat_map(|i| i.check(&outcome))
+            .map(|v| v.invariant)
+            .collect();
+        names.dedup();
+        names
+    }
+
+    #[test]
+    fn misbehaviours_show_in_the_checks() {
+        // Duplicates are dropped by every app
+        assert!(violated(&scenario("mallory=duplicate")).is_empty());
+        assert_eq!(
+            violated(&scenario("mallory=drop-relays")),
+            vec!["causal", "eventual"]
+        );
+        assert_eq!(
+            violated(&scenario("mallory=forge:alice")),
+            vec!["authentic"],
+            "chat messages are not signed"
+        );
+        // Nothing checks clocks, the lie spreads to the honest apps
+        let lie = run(&scenario("mallory=lie-clock")).transcript;
+        assert!(lie.contains("bob received m1 from alice, clock: alice 1003"));
+        assert!(honest().misbehave("mallory=steal").is_err());
+        assert!(honest().misbehave("eve=duplicate").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+//! Invariants of the delivery logic, checked on the events of a run
+//!
+//! To check a property of your own, implement [`Invariant`] and add it to
+//! [`by_name`], a scenario then asks for it in its `checks`.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+
+use netchat_core::messages::Header;
+use netchat_core::Msg;
+
+use super::{Event, Outcome, Step};
+
+pub trait Invariant {
+    fn name(&self) -> &'static str;
+
+    fn check(&self, run: &Outcome) -> Vec<Violation>;
+}
+
+/// A broken invariant, with the events which broke it
+#[derive(Debug)]
+pub struct Violation {
+    pub invariant: &'static str,
+    pub description: String,
+    /// Transcript lines of the messages involved, in the order they happened
+    pub interleaving: Vec<String>,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} violated: {}", self.invariant, self.description)?;
+        for line in &self.interleaving {
+            writeln!(f, "    {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Names of the available invariants
+pub fn all() -> Vec<String> {
+    ["no-duplicates", "fifo", "causal", "eventual", "authentic"]
+        .iter()
+        .map(|name| (*name).to_owned())
+        .collect()
+}
+
+pub fn by_name(name: &str) -> Option<Box<dyn Invariant>> {
+    match name {
+        "no-duplicates" => Some(Box::new(NoDuplicates)),
+        "fifo" => Some(Box::new(FifoPerSender)),
+        "causal" => Some(Box::new(CausalDelivery)),
+        "eventual" => Some(Box::new(EventualDelivery)),
+        "authentic" => Some(Box::new(Authentic)),
+        _ => None,
+    }
+}
+
+/// Whether `msg` should be delivered to `node`
+fn meant_for(msg: &Msg, node: &str) -> bool {
+    msg.sender_id != node
+        && match &msg.header {
+            Header::Private(to, _) => to == node,
+            _ => true,
+        }
+}
+
+fn label(message: usize) -> String {
+    format!("m{}", message + 1)
+}
+
+/// Lines of the events concerning `messages`
+fn interleaving(run: &Outcome, messages: &[usize]) -> Vec<String> {
+    run.events
+        .iter()
+        .filter(|e| messages.contains(&e.message))
+        .map(|e| e.line.clone())
+        .collect()
+}
+
+/// Receptions of `node`, in order
+fn received<'a>(run: &'a Outcome, node: &'a str) -> impl Iterator<Item = &'a Event> {
+    run.events
+        .iter()
+        .filter(move |e| e.node == node && e.step == Step::Received)
+}
+
+/// An app delivers a message at most once
+pub struct NoDuplicates;
+
+impl Invariant for NoDuplicates {
+    fn name(&self) -> &'static str {
+        "no-duplicates"
+    }
+
+    fn check(&self, run: &Outcome) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for node in &run.nodes {
+            let mut delivered = HashSet::new();
+            for event in received(run, node) {
+                if !delivered.insert(event.message) {
+                    violations.push(Violation {
+                        invariant: self.name(),
+                        description: format!("{} received {} twice", node, label(event.message)),
+                        interleaving: interleaving(run, &[event.message]),
+                    });
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// An app delivers the messages of a sender in the order they were sent
+pub struct FifoPerSender;
+
+impl Invariant for FifoPerSender {
+    fn name(&self) -> &'static str {
+        "fifo"
+    }
+
+    fn check(&self, run: &Outcome) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for node in &run.nodes {
+            let mut last = Vec::<usize>::new(); // Latest message delivered from each sender
+            for event in received(run, node) {
+                let sender = &run.messages[event.message].sender_id;
+                let previous = last
+                    .iter_mut()
+                    .find(|m| run.messages[**m].sender_id == *sender);
+                match previous {
+                    Some(previous) if *previous > event.message => {
+                        violations.push(Violation {
+                            invariant: self.name(),
+                            description: format!(
+                                "{} received {} before {}, both sent by {}",
+                                node,
+                                label(*previous),
+                                label(event.message),
+                                sender
+                            ),
+                            interleaving: interleaving(run, &[*previous, event.message]),
+                        });
+                    }
+                    Some(previous) => *previous = event.message,
+                    None => last.push(event.message),
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// An app delivers a message only after the ones meant for it that happened before
+pub struct CausalDelivery;
+
+impl Invariant for CausalDelivery {
+    fn name(&self) -> &'static str {
+        "causal"
+    }
+
+    fn check(&self, run: &Outcome) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for node in &run.nodes {
+            let mut known = HashSet::new();
+            for event in run.events.iter().filter(|e| e.node == *node) {
+                let msg = &run.messages[event.message];
+                if event.step == Step::Received {
+                    let missing = run.messages.iter().enumerate().filter(|(i, before)| {
+                        !known.contains(i)
+                            && meant_for(before, node)
+                            && before.clock.compare(&msg.clock) == Some(Ordering::Less)
+                    });
+                    for (before, _) in missing {
+                        violations.push(Violation {
+                            invariant: self.name(),
+                            description: format!(
+                                "{} received {} before {}, which happened before it",
+                                node,
+                                label(event.message),
+                                label(before)
+                            ),
+                            interleaving: interleaving(run, &[before, event.message]),
+                        });
+                    }
+                }
+                known.insert(event.message);
+            }
+        }
+        violations
+    }
+}
+
+/// Every message reaches every app it is meant for before the end of the run
+pub struct EventualDelivery;
+
+impl Invariant for EventualDelivery {
+    fn name(&self) -> &'static str {
+        "eventual"
+    }
+
+    fn check(&self, run: &Outcome) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for (message, msg) in run.messages.iter().enumerate() {
+            for node in run.nodes.iter().filter(|n| meant_for(msg, n)) {
+                if !received(run, node).any(|e| e.message == message) {
+                    violations.push(Violation {
+                        invariant: self.name(),
+                        description: format!("{} never received {}", node, label(message)),
+                        interleaving: interleaving(run, &[message]),
+                    });
+                }
+            }
+        }
+        violations
+    }
+}
+
+/// A message comes from the app it claims to come from
+pub struct Authentic;
+
+impl Invariant for Authentic {
+    fn name(&self) -> &'static str {
+        "authentic"
+    }
+
+    fn check(&self, run: &Outcome) -> Vec<Violation> {
+        let forged = run
+            .events
+            .iter()
+            .filter(|e| e.step == Step::Received)
+            .filter(|e| e.sender != run.messages[e.message].sender_id);
+        forged
+            .map(|event| Violation {
+                invariant: self.name(),
+                description: format!(
+                    "{} received {} from {}, it was sent by {}",
+                    event.node,
+                    label(event.message),
+                    event.sender,
+                    run.messages[event.message].sender_id
+                ),
+                interleaving: interleaving(run, &[event.message]),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use netchat_core::messages::Channel;
+    use netchat_core::Clock;
+
+    fn msg(id: u64, sender: &str, clock: &[(&str, u64)]) -> Msg {
+        let clock = clock.iter().map(|(id, d)| ((*id).to_owned(), *d)).collect();
+        Msg::new(
+            id,
+            sender.to_owned(),
+            Header::Public(Channel::default(), String::new()),
+            Clock(clock),
+        )
+    }
+
+    fn event(node: &str, step: Step, message: usize) -> Event {
+        let line = format!("{} {:?} {}", node, step, label(message));
+        Event {
+            node: node.to_owned(),
+            step,
+            sender: ["alice", "bob"][message].to_owned(),
+            message,
+            line,
+        }
+    }
+
+    /// bob answers m1 with m2, carol receives the answer first and m1 twice
+    fn reordered() -> Outcome {
+        Outcome {
+            transcript: String::new(),
+            dag: String::new(),
+            nodes: vec!["alice".to_owned(), "bob".to_owned(), "carol".to_owned()],
+            messages: vec![
+                msg(1, "alice", &[("alice", 1)]),
+                msg(2, "bob", &[("alice", 1), ("bob", 2)]),
+            ],
+            events: vec![
+                event("alice", Step::Sent, 0),
+                event("bob", Step::Received, 0),
+                event("bob", Step::Sent, 1),
+                event("carol", Step::Received, 1),
+                event("carol", Step::Received, 0),
+                event("carol", Step::Received, 0),
+            ],
+        }
+    }
+
+    #[test]
+    fn violations_show_the_interleaving() {
+        let run = reordered();
+        let causal = CausalDelivery.check(&run);
+        assert_eq!(causal.len(), 1);
+        assert_eq!(
+            causal[0].description,
+            "carol received m2 before m1, which happened before it"
+        );
+        assert_eq!(causal[0].interleaving.len(), 6);
+        assert_eq!(causal[0].interleaving[3], "carol Received m2");
+
+        assert_eq!(NoDuplicates.check(&run).len(), 1);
+        // alice never got bob's answer
+        let eventual = EventualDelivery.check(&run);
+        assert_eq!(eventual[0].description, "alice never received m2");
+        assert!(FifoPerSender.check(&run).is_empty());
+    }
+}
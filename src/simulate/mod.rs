@@ -0,0 +1,459 @@
+//! `netchat simulate`: runs a scenario of simulated apps, without any pipe
+//!
+//! Time is counted in ticks, each hop on a link takes `latency` ticks. A cut
+//! link keeps what is written to it, like the outbox of an app, and delivers
+//! it once restored. The same scenario always gives the same transcript.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use netchat_core::delivery;
+use netchat_core::messages::{Channel, Header, MsgId};
+use netchat_core::node::Node;
+use netchat_core::{AppId, Clock, Msg};
+
+mod byzantine;
+use byzantine::Behaviour;
+
+pub mod checks;
+use checks::Invariant;
+
+/// Apps, links between them and what happens when, read from a json file, or
+/// a TOML one with the same keys
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    /// Message ids are drawn from it
+    #[serde(default)]
+    seed: u64,
+    nodes: Vec<AppId>,
+    /// Each app writes to its links in the order given
+    links: Vec<(AppId, AppId)>,
+    #[serde(default = "default_latency")]
+    latency: u64,
+    #[serde(default)]
+    messages: Vec<Scheduled>,
+    #[serde(default)]
+    faults: Vec<Fault>,
+    /// Delivery policy of every app, see `netchat --help`
+    #[serde(default = "default_delivery")]
+    delivery: String,
+    /// Misbehaving apps, e.g. `{"mallory": ["duplicate", "forge:alice"]}`
+    #[serde(default)]
+    misbehave: BTreeMap<AppId, Vec<String>>,
+    /// Invariants checked once the run is over, all of them by default
+    #[serde(default = "checks::all")]
+    checks: Vec<String>,
+}
+
+fn default_latency() -> u64 {
+    1
+}
+
+fn default_delivery() -> String {
+    "arrival".to_owned()
+}
+
+/// A chat message sent at a given tick, to everyone or to `to`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Scheduled {
+    at: u64,
+    from: AppId,
+    #[serde(default)]
+    to: Option<AppId>,
+    text: String,
+}
+
+/// The link `cut` is down from tick `at` until tick `until`
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Fault {
+    at: u64,
+    until: u64,
+    cut: (AppId, AppId),
+}
+
+impl Scenario {
+    /// Reads the scenario at `path`, TOML if it ends in `.toml`
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)?;
+        let scenario: Scenario = match path.extension() {
+            Some(ext) if ext == "toml" => serde_json::from_value(crate::config::document(&text)?)?,
+            _ => serde_json::from_str(&text)?,
+        };
+        scenario.check()?;
+        Ok(scenario)
+    }
+
+    /// Makes an app misbehave, `spec` is `<app>=<behaviour>`
+    pub fn misbehave(&mut self, spec: &str) -> Result<(), String> {
+        let mut parts = spec.splitn(2, '=');
+        let (app_id, behaviour) = match (parts.next(), parts.next()) {
+            (Some(app_id), Some(behaviour)) => (app_id, behaviour),
+            _ => return Err(format!("expected <app>=<behaviour>, got {}", spec)),
+        };
+        let behaviours = self.misbehave.entry(app_id.to_owned()).or_default();
+        behaviours.push(behaviour.to_owned());
+        self.check()
+    }
+
+    fn behaviours(&self, app_id: &str) -> Vec<Behaviour> {
+        let names = self.misbehave.get(app_id).into_iter().flatten();
+        names.filter_map(|name| name.parse().ok()).collect()
+    }
+
+    /// The invariants the scenario asks for
+    pub fn invariants(&self) -> Vec<Box<dyn Invariant>> {
+        self.checks
+            .iter()
+            .filter_map(|name| checks::by_name(name))
+            .collect()
+    }
+
+    fn check(&self) -> Result<(), String> {
+        if delivery::by_name(&self.delivery).is_none() {
+            return Err(format!("unknown delivery policy {}", self.delivery));
+        }
+        if let Some(name) = self.checks.iter().find(|n| checks::by_name(n).is_none()) {
+            return Err(format!("unknown check {}", name));
+        }
+        let known = |id: &AppId| {
+            if self.nodes.contains(id) {
+                Ok(())
+            } else {
+                Err(format!("unknown node {}", id))
+            }
+        };
+        for (from, to) in &self.links {
+            known(from)?;
+            known(to)?;
+        }
+        for message in &self.messages {
+            known(&message.from)?;
+            message.to.iter().try_for_each(known)?;
+        }
+        for (app_id, behaviours) in &self.misbehave {
+            known(app_id)?;
+            for behaviour in behaviours {
+                if let Behaviour::Forge(victim) = behaviour.parse()? {
+                    known(&victim)?;
+                }
+            }
+        }
+        for fault in &self.faults {
+            if !self.links.contains(&fault.cut) {
+                return Err(format!("no link from {} to {}", fault.cut.0, fault.cut.1));
+            }
+            if fault.until <= fault.at {
+                return Err(format!("fault of {:?} ends before it starts", fault.cut));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What happened during a run
+pub struct Outcome {
+    pub transcript: String,
+    /// Graphviz graph of the chat messages, an edge per direct causal dependency
+    pub dag: String,
+    pub nodes: Vec<AppId>,
+    /// Scheduled messages as sent, `m1` first
+    pub messages: Vec<Msg>,
+    /// Sends and receptions of the scheduled messages, in order
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Sent,
+    Received,
+}
+
+/// A scheduled message sent or received by an app
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub node: AppId,
+    pub step: Step,
+    /// Sender the message claims
+    pub sender: AppId,
+    /// Index in `Outcome::messages`
+    pub message: usize,
+    /// As written in the transcript
+    pub line: String,
+}
+
+struct Simulation<'a> {
+    scenario: &'a Scenario,
+    nodes: Vec<Node>,
+    in_flight: BTreeMap<u64, Vec<(usize, Vec<u8>)>>, // Frames by arrival tick, with their link
+    held: BTreeMap<usize, Vec<Vec<u8>>>,             // Frames written to a cut link
+    scheduled: HashMap<MsgId, usize>,                // Indexes of the scheduled messages
+    sent: Vec<(String, Msg)>,                        // Scheduled messages as sent
+    behaviours: Vec<Vec<Behaviour>>,                 // Of each node
+    events: Vec<Event>,
+    transcript: String,
+}
+
+/// Dates of a clock, sorted by app
+fn show(clock: &Clock) -> String {
+    let mut dates: Vec<String> = clock
+        .iter()
+        .map(|(id, d)| format!("{} {}", id, d))
+        .collect();
+    dates.sort();
+    dates.join(", ")
+}
+
+pub fn run(scenario: &Scenario) -> Outcome {
+    let nodes = scenario
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, id)| {
+            let mut node = Node::with_seed(id.clone(), scenario.seed.wrapping_add(i as u64));
+            node.set_delivery(delivery::by_name(&scenario.delivery).expect("scenario checked"));
+            node
+        })
+        .collect();
+    let mut simulation = Simulation {
+        scenario,
+        nodes,
+        in_flight: BTreeMap::new(),
+        held: BTreeMap::new(),
+        scheduled: HashMap::new(),
+        sent: Vec::new(),
+        behaviours: scenario
+            .nodes
+            .iter()
+            .map(|id| scenario.behaviours(id))
+            .collect(),
+        events: Vec::new(),
+        transcript: String::new(),
+    };
+    for (id, behaviours) in &scenario.misbehave {
+        simulation.log(0, format!("{} misbehaves: {}", id, behaviours.join(", ")));
+    }
+    let last_event = scenario
+        .messages
+        .iter()
+        .map(|m| m.at)
+        .chain(scenario.faults.iter().map(|f| f.until))
+        .max()
+        .unwrap_or(0);
+    let mut tick = 0;
+    while tick <= last_event || !simulation.in_flight.is_empty() {
+        simulation.step(tick);
+        tick += 1;
+    }
+    let dag = simulation.dag();
+    Outcome {
+        transcript: simulation.transcript,
+        dag,
+        nodes: scenario.nodes.clone(),
+        messages: simulation.sent.into_iter().map(|(_, msg)| msg).collect(),
+        events: simulation.events,
+    }
+}
+
+impl Simulation<'_> {
+    fn node_index(&self, id: &str) -> usize {
+        self.scenario
+            .nodes
+            .iter()
+            .position(|n| n == id)
+            .expect("scenario checked")
+    }
+
+    fn log(&mut self, tick: u64, line: String) -> String {
+        let line = format!("[t={}] {}", tick, line);
+        let _ = writeln!(self.transcript, "{}", line);
+        line
+    }
+
+    fn record(&mut self, node: usize, step: Step, msg: &Msg, message: usize, line: String) {
+        self.events.push(Event {
+            node: self.scenario.nodes[node].clone(),
+            step,
+            sender: msg.sender_id.clone(),
+            message,
+            line,
+        });
+    }
+
+    fn is_cut(&self, link: usize, tick: u64) -> bool {
+        let cut = &self.scenario.links[link];
+        self.scenario
+            .faults
+            .iter()
+            .any(|f| f.cut == *cut && f.at <= tick && tick < f.until)
+    }
+
+    fn step(&mut self, tick: u64) {
+        let scenario = self.scenario;
+        let latency = scenario.latency;
+
+        for fault in &scenario.faults {
+            let (from, to) = &fault.cut;
+            if fault.at == tick {
+                self.log(tick, format!("link {} -> {} is cut", from, to));
+            }
+            if fault.until == tick {
+                self.log(tick, format!("link {} -> {} is restored", from, to));
+            }
+        }
+        for (link, frames) in std::mem::take(&mut self.held) {
+            if self.is_cut(link, tick) {
+                self.held.insert(link, frames);
+            } else {
+                let arriving = self.in_flight.entry(tick + latency).or_default();
+                arriving.extend(frames.into_iter().map(|frame| (link, frame)));
+            }
+        }
+
+        for (link, frame) in self.in_flight.remove(&tick).unwrap_or_default() {
+            let to = self.node_index(&scenario.links[link].1);
+            let _ = self.nodes[to].feed(&frame);
+            while let Some(msg) = self.nodes[to].poll_event() {
+                let message = self.scheduled.get(&msg.id).copied();
+                if let Some(message) = message {
+                    let line = format!(
+                        "{} received {} from {}, clock: {}",
+                        scenario.nodes[to],
+                        self.sent[message].0,
+                        msg.sender_id,
+                        show(self.nodes[to].clock())
+                    );
+                    let line = self.log(tick, line);
+                    self.record(to, Step::Received, &msg, message, line);
+                }
+            }
+        }
+
+        for message in scenario.messages.iter().filter(|m| m.at == tick) {
+            let from = self.node_index(&message.from);
+            let header = match &message.to {
+                Some(to) => Header::Private(to.clone(), message.text.clone()),
+                None => Header::Public(Channel::default(), message.text.clone()),
+            };
+            let msg = self.nodes[from].send(header);
+            let label = format!("m{}", self.sent.len() + 1);
+            let line = format!(
+                "{} sends {} to {}: {}, clock: {}",
+                message.from,
+                label,
+                message.to.as_deref().unwrap_or("everyone"),
+                message.text,
+                show(&msg.clock)
+            );
+            let line = self.log(tick, line);
+            self.record(from, Step::Sent, &msg, self.sent.len(), line);
+            self.scheduled.insert(msg.id, self.sent.len());
+            self.sent.push((label, msg));
+        }
+
+        // What was received or sent goes out on every link of its app
+        for (from, id) in scenario.nodes.iter().enumerate() {
+            while let Some(frame) = self.nodes[from].poll_frame() {
+                for frame in byzantine::tamper(id, &self.behaviours[from], frame) {
+                    let links = scenario.links.iter().enumerate();
+                    for (link, _) in links.filter(|(_, l)| l.0 == *id) {
+                        if self.is_cut(link, tick) {
+                            self.held.entry(link).or_default().push(frame.clone());
+                        } else {
+                            let arriving = self.in_flight.entry(tick + latency).or_default();
+                            arriving.push((link, frame.clone()));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Edges from each message to those which directly depend on it
+    fn dag(&self) -> String {
+        let before = |a: &Msg, b: &Msg| a.clock.compare(&b.clock) == Some(Ordering::Less);
+        let mut dot = String::from("digraph causality {\n");
+        for (label, msg) in &self.sent {
+            let text = match &msg.header {
+                Header::Public(_, text) | Header::Private(_, text) => text.replace('"', "\\\""),
+                _ => String::new(),
+            };
+            let _ = writeln!(
+                dot,
+                "    {} [label=\"{} {}: {}\"];",
+                label, label, msg.sender_id, text
+            );
+        }
+        for (a, msg_a) in &self.sent {
+            for (b, msg_b) in &self.sent {
+                let direct = before(msg_a, msg_b)
+                    && !self
+                        .sent
+                        .iter()
+                        .any(|(_, c)| before(msg_a, c) && before(c, msg_b));
+                if direct {
+                    let _ = writeln!(dot, "    {} -> {};", a, b);
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = r#"{
+        "nodes": ["alice", "bob", "carol"],
+        "links": [["alice", "bob"], ["bob", "carol"], ["carol", "alice"]],
+        "messages": [
+            {"at": 0, "from": "alice", "text": "hi"},
+            {"at": 1, "from": "bob", "text": "cut off"},
+            {"at": 2, "from": "carol", "text": "meanwhile"},
+            {"at": 8, "from": "carol", "to": "bob", "text": "got it"}
+        ],
+        "faults": [{"at": 1, "until": 6, "cut": ["bob", "carol"]}]
+    }"#;
+
+    #[test]
+    fn scenarios_run_the_same_every_time() {
+        let scenario: Scenario = serde_json::from_str(SCENARIO).unwrap();
+        scenario.check().unwrap();
+        let outcome = run(&scenario);
+        assert_eq!(outcome.transcript, run(&scenario).transcript);
+
+        // bob's message waited for the link to be restored
+        assert!(outcome
+            .transcript
+            .contains("[t=7] carol received m2 from bob"));
+        assert!(!outcome.transcript.contains("alice received m4"));
+        assert!(outcome.dag.contains("m1 -> m2;"));
+        assert!(outcome.dag.contains("m2 -> m4;"));
+        assert!(!outcome.dag.contains("m2 -> m3;"), "concurrent messages");
+    }
+
+    #[test]
+    fn scenarios_are_read_from_toml_too() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios");
+        let json = Scenario::load(&dir.join("partition.json")).unwrap();
+        let toml = Scenario::load(&dir.join("partition.toml")).unwrap();
+        assert_eq!(run(&toml).transcript, run(&json).transcript);
+    }
+
+    #[test]
+    fn unknown_nodes_are_reported() {
+        let scenario: Scenario =
+            serde_json::from_str(r#"{"nodes": ["alice"], "links": [["alice", "bob"]]}"#).unwrap();
+        assert_eq!(scenario.check(), Err("unknown node bob".to_owned()));
+    }
+}